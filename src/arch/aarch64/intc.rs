@@ -0,0 +1,41 @@
+//! Interrupt controller of the BCM2835, for the peripherals shared with the VideoCore.
+//!
+//! Its output is the GPU interrupt of the local interrupt controller, which goes to core 0.
+
+use crate::drivers::bus::{ReadOnly, Register, WriteOnly};
+
+use super::mmio::PERIPHERALS;
+
+const INTC_BASE: usize = 0xB200;
+
+/// Pending interrupts 0 to 31, the ones from 32 to 63 are in the register after it
+const IRQ_PENDING1: Register<ReadOnly> = Register::new(INTC_BASE + 0x04);
+const IRQ_PENDING2: Register<ReadOnly> = Register::new(INTC_BASE + 0x08);
+
+/// Writing 1 to bit n enables or disables interrupt n, the same for 32 to 63 in the register after it
+const ENABLE_IRQS1: Register<WriteOnly> = Register::new(INTC_BASE + 0x10);
+const DISABLE_IRQS1: Register<WriteOnly> = Register::new(INTC_BASE + 0x1C);
+
+/// Number of interrupts of the peripherals
+pub const NUM_IRQS: u32 = 64;
+
+/// The register of `irq` in the bank starting at `reg`.
+const fn bank(reg: Register<WriteOnly>, irq: u32) -> Register<WriteOnly> {
+    Register::new(reg.index() + 4 * (irq / 32) as usize)
+}
+
+/// Masks every interrupt.
+pub fn init() {
+    for irq in (0..NUM_IRQS).step_by(32) {
+        PERIPHERALS.write(bank(DISABLE_IRQS1, irq), !0);
+    }
+}
+
+pub fn enable(irq: u32) {
+    PERIPHERALS.write(bank(ENABLE_IRQS1, irq), 1 << (irq % 32));
+}
+
+/// Bit n is set if interrupt n is pending, the devices have to be told to stop them.
+pub fn pending() -> u64 {
+    PERIPHERALS.read(IRQ_PENDING1) as u64 | (PERIPHERALS.read(IRQ_PENDING2) as u64) << 32
+}
@@ -0,0 +1,58 @@
+use core::arch::{asm, global_asm};
+use core::ptr::addr_of;
+
+use crate::drivers::dma::DmaEngine;
+use crate::drivers::gpio::Gpio;
+use crate::smp::ipi;
+
+use super::intc;
+use super::smp::{ack_ipi, irq_source, IRQ_SOURCE_GPU, IRQ_SOURCE_MAILBOX0};
+use super::{DMA, DMA_IRQS, DMA_STAT, GPIO, GPIO_IRQS, GPIO_STAT};
+
+global_asm!(include_str!("_asm/interrupt.S"));
+
+extern "C" {
+    static exception_vectors: u8;
+}
+
+/// Points VBAR_EL1 of the calling core at the exception vectors.
+pub fn init() {
+    unsafe { asm!("msr vbar_el1, {}", "isb", in(reg) addr_of!(exception_vectors), options(nostack, preserves_flags)) };
+}
+
+/// Unmasks IRQs just long enough to take the pending ones.
+pub fn take_pending() {
+    unsafe { asm!("msr daifclr, #2", "isb", "msr daifset, #2", options(nostack)) };
+}
+
+#[no_mangle]
+extern "C" fn irq_handler() {
+    let source = irq_source();
+    if source & IRQ_SOURCE_MAILBOX0 != 0 {
+        ack_ipi();
+        ipi::handle_ipi();
+    }
+
+    if source & IRQ_SOURCE_GPU != 0 {
+        handle_peripherals();
+    }
+}
+
+/// Runs the handlers of the pending peripheral interrupts.
+fn handle_peripherals() {
+    let pending = intc::pending();
+    if pending & DMA_IRQS != 0 {
+        DMA_STAT.inc();
+        DMA.handle_interrupt();
+    }
+
+    if pending & GPIO_IRQS != 0 {
+        GPIO_STAT.inc();
+        GPIO.handle_interrupt();
+    }
+}
+
+#[no_mangle]
+extern "C" fn exception_handler(esr: u64, elr: u64, far: u64) -> ! {
+    panic!("EXCEPTION: ESR {:#x} at {:#x}, FAR {:#x}", esr, elr, far);
+}
@@ -1,46 +1,47 @@
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-// TODO:
-static mut MMIO_BASE: *mut u32 = 0 as *mut _;
+use crate::drivers::bus::{Bus, RegisterBlock};
+use crate::drivers::gpio::bcm2835_gpio::Model;
+
+static MMIO_BASE: AtomicUsize = AtomicUsize::new(0x3F000000);
 
 // REVIEW:
 pub fn init() {
     let reg: u32;
     unsafe { asm!("mrs {:x}, midr_el1", out(reg) reg) }
     let part_num = (reg >> 4) & 0xFFF;
-    unsafe {
-        match part_num {
-            0xC07 | 0xD03 => MMIO_BASE = 0x3F000000 as *mut _,
-            0xD08 => MMIO_BASE = 0xFE000000 as *mut _,
-            _ => MMIO_BASE = 0x3F000000  as *mut _
-        }
-    }
-}
+    let base = match part_num {
+        0xC07 | 0xD03 => 0x3F000000,
+        0xD08 => 0xFE000000,
+        _ => 0x3F000000
+    };
 
-#[repr(transparent)]
-pub struct MmioReg {
-    v: usize
+    MMIO_BASE.store(base, Ordering::Relaxed);
 }
 
-// REVIEW: split read and write into different traits?
-impl MmioReg {
-    pub const unsafe fn new(val: usize) -> Self {
-        Self { v: val }
-    }
-
-    pub fn as_ptr(&self) -> *const u32 {
-        unsafe { MMIO_BASE.add(self.v) }
+/// The GPIO controller of the model detected by [`init`].
+pub fn gpio_model() -> Model {
+    match MMIO_BASE.load(Ordering::Relaxed) {
+        0xFE000000 => Model::Bcm2711,
+        _ => Model::Bcm2835
     }
+}
 
-    pub fn as_mut_ptr(&self) -> *mut u32 {
-        unsafe { MMIO_BASE.add(self.v) }
-    }
+/// The peripherals of the SoC, their base address depends on the model detected by [`init`].
+pub struct Peripherals;
 
-    pub fn read(&self) -> u32 {
-        unsafe { self.as_ptr().read_volatile() }
+impl Bus for Peripherals {
+    fn read(&self, offset: usize) -> u32 {
+        let addr = MMIO_BASE.load(Ordering::Relaxed) + offset;
+        unsafe { (addr as *const u32).read_volatile() }
     }
 
-    pub fn write(&self, val: u32) {
-        unsafe { self.as_mut_ptr().write_volatile(val) }
+    fn write(&self, offset: usize, value: u32) {
+        let addr = MMIO_BASE.load(Ordering::Relaxed) + offset;
+        unsafe { (addr as *mut u32).write_volatile(value) }
     }
 }
+
+/// Registers are addressed by their byte offset from the peripheral base.
+pub const PERIPHERALS: RegisterBlock<Peripherals> = RegisterBlock::new(Peripherals, 1);
@@ -1,99 +1,241 @@
+mod interrupt;
+mod intc;
 pub mod mmio;
+pub mod smp;
 
 use core::arch::asm;
 use core::fmt::{Arguments, Result, Write};
 use core::hint;
+use core::ptr::addr_of_mut;
 
+use crate::fdt;
+use crate::irq::{self, IrqStat};
 use crate::prelude::*;
-use crate::drivers::gpio::bcm2835_gpio::*;
-use crate::drivers::mailbox::bcm2835_mailbox::*;
-
-use self::mmio::MmioReg;
+use crate::drivers::gpio::bcm2835_gpio::{Bcm2835Gpio, Function, Pin};
+use crate::drivers::gpio::{Gpio, Pull};
+use crate::drivers::bus::{ReadOnly, ReadWrite, Register};
+use crate::drivers::dma::bcm2835_dma::{Bcm2835Dma, ARM_CHANNELS, DMA_BASE};
+use crate::drivers::i2c;
+use crate::drivers::i2c::bcm2835_bsc::{Bsc, BSC1_BASE};
+use crate::drivers::mailbox::bcm2835_mailbox::MailboxError;
+use crate::drivers::mailbox::bcm2835_property::*;
+use crate::drivers::serial::ByteStream;
+use crate::drivers::video::console::vga::{Color, ColorCode, ColorTracker, ScreenChar};
+use crate::drivers::video::framebuffer::console::FrameBufferConsole;
+use crate::drivers::video::framebuffer::psf::{Font, DEFAULT_FONT};
+use crate::drivers::video::framebuffer::{FrameBuffer, FrameBufferInfo};
+use crate::shell::{self, Command, CommandError};
+use crate::sync::mutex::Mutex;
+
+use self::mmio::{Peripherals, PERIPHERALS};
 
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
-    unsafe { WRITER.write_fmt(args).unwrap() };
+    write_console(args);
 }
 
 #[doc(hidden)]
 pub fn _eprint(args: Arguments) {
-    unsafe { WRITER.write_fmt(args).unwrap() };
+    // The panic handler can run while this core holds the locks in _print
+    if crate::panicking() {
+        unsafe {
+            WRITER.force_unlock();
+            FRAMEBUFFER.force_unlock();
+        }
+    }
+
+    // The serial terminal is told to switch back to the color before, not to the default one
+    let mut uart = WRITER.lock();
+    let current_color = uart.color.color_code();
+    let mut red = current_color;
+    red.set_fg_color(Color::Red);
+    red.write_sgr(&mut *uart).unwrap();
+    uart.write_fmt(args).unwrap();
+    current_color.write_sgr(&mut *uart).unwrap();
+    drop(uart);
+
+    if let Some(console) = FRAMEBUFFER.lock().as_mut() {
+        let current_color = console.color_code();
+        let mut red = current_color;
+        red.set_fg_color(Color::Red);
+        console.set_color_code(red);
+        console.write_fmt(args).unwrap();
+        console.set_color_code(current_color);
+    }
 }
 
-// TODO: make thread safe
-static mut WRITER: Uart = Uart::new();
+/// Writes to the serial port and, once it's set up, the framebuffer.
+fn write_console(args: Arguments) {
+    WRITER.lock().write_fmt(args).unwrap();
+    if let Some(console) = FRAMEBUFFER.lock().as_mut() {
+        console.write_fmt(args).unwrap();
+    }
+}
+
+static WRITER: Mutex<Uart> = Mutex::new(Uart::new());
+
+/// Size of the framebuffer asked for when the firmware didn't set one up
+const FRAMEBUFFER_WIDTH: u32 = 1024;
+const FRAMEBUFFER_HEIGHT: u32 = 768;
+const FRAMEBUFFER_DEPTH: u32 = 32;
+
+/// Most characters of the framebuffer console, enough for 1920x1200 with an 8x16 font
+const FRAMEBUFFER_CHARS: usize = 240 * 75;
+
+/// Text of the framebuffer console and a copy of what is drawn
+static mut FRAMEBUFFER_TEXT: [ScreenChar; 2 * FRAMEBUFFER_CHARS] =
+    [ScreenChar::new(b' ', ColorCode::new(Color::White, Color::Black)); 2 * FRAMEBUFFER_CHARS];
+
+static FRAMEBUFFER: Mutex<Option<FrameBufferConsole>> = Mutex::new(None);
 
 const AUX_BASE: usize = 0x215000;
 
 /// Auxiliary Interrupt status
-pub const AUX_IRQ: MmioReg = unsafe { MmioReg::new(AUX_BASE) };
+pub const AUX_IRQ: Register<ReadOnly> = Register::new(AUX_BASE);
 
 /// Auxiliary enables
-pub const AUX_ENABLES: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x4) };
+pub const AUX_ENABLES: Register<ReadWrite> = Register::new(AUX_BASE + 0x4);
 
 /// Mini Uart I/O Data
-pub const AUX_MU_IO: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x40) };
+pub const AUX_MU_IO: Register<ReadWrite> = Register::new(AUX_BASE + 0x40);
 
 /// Mini Uart Interrupt Enable
-pub const AUX_MU_IER: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x44) };
+pub const AUX_MU_IER: Register<ReadWrite> = Register::new(AUX_BASE + 0x44);
 
 /// Mini Uart Interrupt Identify
-pub const AUX_MU_IIR: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x48) };
+pub const AUX_MU_IIR: Register<ReadWrite> = Register::new(AUX_BASE + 0x48);
 
 /// Mini Uart Line Control
-pub const AUX_MU_LCR: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x4C) };
+pub const AUX_MU_LCR: Register<ReadWrite> = Register::new(AUX_BASE + 0x4C);
 
 /// Mini Uart Modem Control
-pub const AUX_MU_MCR: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x50) };
+pub const AUX_MU_MCR: Register<ReadWrite> = Register::new(AUX_BASE + 0x50);
 
 /// Mini Uart Line Status
-pub const AUX_MU_LSR: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x54) };
+pub const AUX_MU_LSR: Register<ReadOnly> = Register::new(AUX_BASE + 0x54);
 
 /// Mini Uart Modem Status
-pub const AUX_MU_MSR: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x58) };
+pub const AUX_MU_MSR: Register<ReadOnly> = Register::new(AUX_BASE + 0x58);
 
 /// Mini Uart Scratch
-pub const AUX_MU_SCRATCH: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x5C) };
+pub const AUX_MU_SCRATCH: Register<ReadWrite> = Register::new(AUX_BASE + 0x5C);
 
 /// Mini Uart Extra Control
-pub const AUX_MU_CNTL: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x60) };
+pub const AUX_MU_CNTL: Register<ReadWrite> = Register::new(AUX_BASE + 0x60);
 
 /// Mini Uart Extra Status
-pub const AUX_MU_STAT: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x64) };
+pub const AUX_MU_STAT: Register<ReadOnly> = Register::new(AUX_BASE + 0x64);
 
 /// Mini Uart Baudrate
-pub const AUX_MU_BAUD: MmioReg = unsafe { MmioReg::new(AUX_BASE + 0x68) };
+pub const AUX_MU_BAUD: Register<ReadWrite> = Register::new(AUX_BASE + 0x68);
+
+/// Power management reset control
+const PM_RSTC: Register<ReadWrite> = Register::new(0x10001C);
+
+/// Power management watchdog timer
+const PM_WDOG: Register<ReadWrite> = Register::new(0x100024);
+
+/// Has to be part of every write to the power management registers
+const PM_PASSWORD: u32 = 0x5A000000;
+const PM_RSTC_WRCFG_MASK: u32 = 0x30;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
+/// Pins of the mini UART, in alternate function 5
+const UART_TX: Pin = Pin::new(14).unwrap();
+const UART_RX: Pin = Pin::new(15).unwrap();
+
+/// SDA and SCL of BSC1 on the header, in alternate function 0
+const I2C1_SDA: Pin = Pin::new(2).unwrap();
+const I2C1_SCL: Pin = Pin::new(3).unwrap();
+
+static I2C1: Bsc<Peripherals> = Bsc::new(PERIPHERALS, BSC1_BASE);
+
+static GPIO: Bcm2835Gpio<Peripherals> = Bcm2835Gpio::new(PERIPHERALS, mmio::gpio_model);
+static GPIO_STAT: IrqStat = IrqStat::new("gpio");
+
+/// Interrupt of the first bank of pins, the second bank has the one after it
+const GPIO_IRQ: u32 = 49;
+
+/// Bit n is set for the interrupts of the GPIO banks
+const GPIO_IRQS: u64 = 0b11 << GPIO_IRQ;
+
+static DMA: Bcm2835Dma<Peripherals> = Bcm2835Dma::new(PERIPHERALS, DMA_BASE, ARM_CHANNELS);
+static DMA_STAT: IrqStat = IrqStat::new("dma");
+
+/// Interrupt of DMA channel 0, the ones up to 10 follow and 11 to 14 share the one after them
+const DMA_IRQ: u32 = 16;
+const DMA_SHARED_CHANNEL: u32 = 11;
+
+/// Bit n is set for the interrupts of the DMA channels
+const DMA_IRQS: u64 = ((1 << (DMA_SHARED_CHANNEL + 1)) - 1) << DMA_IRQ;
+
+static I2CDETECT_COMMAND: Command = Command {
+    name: "i2cdetect",
+    usage: "",
+    help: "list the devices on I2C1",
+    run: i2cdetect_command
+};
+
+/// Resets the DMA controller, transfers started with a callback get it called from the interrupt handler.
+fn init_dma() {
+    DMA.init();
+    irq::register(&DMA_STAT);
+    for channel in (0..u32::BITS).filter(|channel| ARM_CHANNELS & 1 << channel != 0) {
+        intc::enable(DMA_IRQ + channel.min(DMA_SHARED_CHANNEL));
+    }
+}
+
+/// Events enabled on the pins get their handlers called from the interrupt handler.
+fn init_gpio() {
+    irq::register(&GPIO_STAT);
+    for irq in (0..u64::BITS).filter(|irq| GPIO_IRQS & 1 << irq != 0) {
+        intc::enable(irq);
+    }
+}
 
 fn init_uart() {
-    AUX_ENABLES.write(AUX_ENABLES.read() | 1);
-    AUX_MU_CNTL.write(0);
-    AUX_MU_LCR.write(3);
-    AUX_MU_MCR.write(0);
-    AUX_MU_IER.write(0);
-    AUX_MU_IIR.write(0xc6);
-    AUX_MU_BAUD.write(270);
-    let mut r = GPFSEL1.read();
-    r &= !((7 << 12) | (7 << 15)); // gpio14, gpio15
-    r |= (2 << 12) | (2 << 15); // alt5
-    GPFSEL1.write(r);
-    GPPUD.write(0);
-    for _ in 0..150 {
-        unsafe { asm!("nop") };
-    }
-    GPPUDCLK0.write((1 << 14) | (1 << 15));
-    for _ in 0..150 {
-        unsafe { asm!("nop") };
-    }
-    GPPUDCLK0.write(0);
-    AUX_MU_CNTL.write(3);
+    PERIPHERALS.set_bits(AUX_ENABLES, 1);
+    PERIPHERALS.write(AUX_MU_CNTL, 0);
+    PERIPHERALS.write(AUX_MU_LCR, 3);
+    PERIPHERALS.write(AUX_MU_MCR, 0);
+    PERIPHERALS.write(AUX_MU_IER, 0);
+    PERIPHERALS.write(AUX_MU_IIR, 0xc6);
+    PERIPHERALS.write(AUX_MU_BAUD, 270);
+    for pin in [UART_TX, UART_RX] {
+        GPIO.set_function(pin, Function::Alt5);
+        GPIO.set_pull(pin, Pull::None);
+    }
+    PERIPHERALS.write(AUX_MU_CNTL, 3);
 }
 
 #[derive(Debug)]
-struct Uart;
+struct Uart {
+    /// Color the terminal is left with by what was written
+    color: ColorTracker
+}
 
 impl Uart {
     const fn new() -> Self {
-        Self
+        Self { color: ColorTracker::new() }
+    }
+}
+
+impl ByteStream for Uart {
+    fn read_byte(&mut self) -> Option<u8> {
+        if PERIPHERALS.read(AUX_MU_LSR) & 0x1 == 0 {
+            return None;
+        }
+
+        Some(PERIPHERALS.read(AUX_MU_IO) as u8)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while (PERIPHERALS.read(AUX_MU_LSR) & 0x20) == 0 {
+            hint::spin_loop();
+        }
+
+        PERIPHERALS.write(AUX_MU_IO, byte as u32);
+        self.color.feed(byte);
     }
 }
 
@@ -111,30 +253,145 @@ impl Write for Uart {
     }
 
     fn write_char(&mut self, c: char) -> Result {
-        while (AUX_MU_LSR.read() & 0x20) == 0 {
-            hint::spin_loop();
+        self.write(c.encode_utf8(&mut [0; 4]).as_bytes());
+
+        Ok(())
+    }
+}
+
+/// Sleeps until an interrupt is pending and takes it, IRQs stay masked the rest of the time.
+pub fn wait_for_interrupt() {
+    // wfi also wakes up for masked interrupts, so none can sneak in before it
+    unsafe { asm!("wfi", options(nomem, nostack, preserves_flags)) };
+    interrupt::take_pending();
+}
+
+/// Reads from the serial port, writes to it and the framebuffer.
+struct Console;
+
+impl ByteStream for Console {
+    fn read_byte(&mut self) -> Option<u8> {
+        WRITER.lock().read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        WRITER.lock().write_byte(byte);
+        if let Some(console) = FRAMEBUFFER.lock().as_mut() {
+            console.write_byte(byte);
         }
+    }
+}
 
-        AUX_MU_IO.write(c as u32);
+/// Gives the shell access to the console.
+pub fn with_console<R>(f: impl FnOnce(&mut dyn ByteStream) -> R) -> R {
+    f(&mut Console)
+}
 
-        Ok(())
+/// The mini UART interrupt isn't routed yet, so the shell has to poll,
+/// other interrupts get taken in between.
+pub fn wait_for_input() {
+    interrupt::take_pending();
+    hint::spin_loop();
+}
+
+/// Resets the board by letting the watchdog expire.
+pub fn reboot() -> ! {
+    PERIPHERALS.write(PM_WDOG, PM_PASSWORD | 10);
+    let rstc = PERIPHERALS.read(PM_RSTC);
+    PERIPHERALS.write(PM_RSTC, PM_PASSWORD | (rstc & !PM_RSTC_WRCFG_MASK) | PM_RSTC_WRCFG_FULL_RESET);
+    loop {
+        wait_for_interrupt();
+    }
+}
+
+fn print_board_info() -> core::result::Result<(), MailboxError> {
+    let mut msg = PropertyMessage::<48>::new();
+    let revision = msg.push(&GetBoardRevision)?;
+    let serial = msg.push(&GetBoardSerial)?;
+    let mac = msg.push(&GetMacAddress)?;
+    let arm_memory = msg.push(&GetArmMemory)?;
+    let vc_memory = msg.push(&GetVcMemory)?;
+    let arm_clock = msg.push(&GetClockRate(Clock::Arm))?;
+    let temperature = msg.push(&GetTemperature)?;
+    msg.send()?;
+
+    println!("Board revision {:#x}, serial number {:016X}", msg.response(revision)?, msg.response(serial)?);
+    let mac = msg.response(mac)?;
+    println!("MAC address {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+    let (arm_memory, vc_memory) = (msg.response(arm_memory)?, msg.response(vc_memory)?);
+    println!("ARM memory {:#x} {} MiB, VideoCore memory {:#x} {} MiB",
+        arm_memory.base, arm_memory.size >> 20, vc_memory.base, vc_memory.size >> 20);
+    let temperature = msg.response(temperature)?;
+    println!("ARM clock {} MHz, {}.{} °C", msg.response(arm_clock)? / 1_000_000, temperature / 1000, temperature % 1000 / 100);
+    Ok(())
+}
+
+/// Runs I2C1 at 100 kHz off the VideoCore core clock, the header has pull ups.
+fn init_i2c() -> core::result::Result<(), MailboxError> {
+    let mut msg = PropertyMessage::<8>::new();
+    let core_clock = msg.push(&GetClockRate(Clock::Core))?;
+    msg.send()?;
+
+    for pin in [I2C1_SDA, I2C1_SCL] {
+        GPIO.set_function(pin, Function::Alt0);
+        GPIO.set_pull(pin, Pull::None);
     }
+
+    I2C1.init(msg.response(core_clock)?, 100_000);
+    Ok(())
+}
+
+fn i2cdetect_command(_args: &[&str], out: &mut dyn Write) -> core::result::Result<(), CommandError> {
+    i2c::detect(&I2C1, out)?;
+    Ok(())
+}
+
+/// Draws the console on the framebuffer the firmware put in the device tree,
+/// or on one allocated through the mailbox if there is none.
+fn init_framebuffer() {
+    let info = match fdt::blob().and_then(|fdt| fdt.ok()).and_then(|fdt| FrameBufferInfo::from_fdt(&fdt)) {
+        Some(info) => info,
+        None => match allocate_framebuffer(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_DEPTH) {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("No framebuffer: {}", e);
+                return;
+            }
+        }
+    };
+
+    let font = Font::parse(DEFAULT_FONT).unwrap();
+    let console = FrameBufferConsole::new(unsafe { FrameBuffer::new(info) }, font, unsafe { &mut *addr_of_mut!(FRAMEBUFFER_TEXT) });
+    *FRAMEBUFFER.lock() = console;
+    println!("Framebuffer {}x{} at {:#x}", info.width, info.height, info.addr);
 }
 
 #[no_mangle]
-pub extern "C" fn kernel_main(_dtb_ptr32: u64, _x1: u64, _x2: u64, _x3: u64) -> ! {
+pub extern "C" fn kernel_main(dtb: u64, _x1: u64, _x2: u64, _x3: u64) -> ! {
+    crate::smp::cpu_online(0, smp::hw_id());
+    fdt::set_blob(dtb as usize);
     mmio::init();
+    interrupt::init();
+    intc::init();
+    init_dma();
+    init_gpio();
 
     init_uart();
+    init_framebuffer();
     println!("Hello World!");
 
-    let mut mbox: MailboxBuffer<8> = [8 * 4, MBOX_REQUEST, MBOX_TAG_GETSERIAL, 8, 8, 0, 0, MBOX_TAG_LAST].into();
-    mbox_call(Message::new(&mut mbox, Channel::PropertyTagsARMToVC)).unwrap();
-    unsafe {
-        let s1 = *(&mbox as *const _ as *const u32).offset(6);
-        let s2 = *(&mbox as *const _ as *const u32).offset(5);
-        println!("Serial number: {:X}{:X}", s1, s2);
+    if let Err(e) = print_board_info() {
+        eprintln!("Couldn't ask the firmware about the board: {}", e);
+    }
+
+    if let Err(e) = init_i2c() {
+        eprintln!("I2C setup failed: {}", e);
     }
 
-    loop { }
+    smp::enable_ipi();
+    crate::smp::init();
+
+    shell::builtins::register_builtins();
+    shell::register(&I2CDETECT_COMMAND).unwrap();
+    shell::run()
 }
@@ -0,0 +1,126 @@
+use core::arch::asm;
+use core::sync::atomic::Ordering;
+
+use crate::smp::{self, CpuData, MAX_CPUS, SECONDARY_HOLD};
+
+use super::interrupt;
+
+extern "C" {
+    fn _start() -> !;
+}
+
+/// The Raspberry Pi firmware parks the secondary cores in a spin table,
+/// core `n` jumps to the address stored at `SPIN_TABLE_BASE + n * 8`.
+const SPIN_TABLE_BASE: usize = 0xD8;
+
+// TODO: the BCM2711 has these at 0xFF800000
+/// Base of the ARM local peripherals of the BCM2836 and BCM2837
+const LOCAL_BASE: usize = 0x40000000;
+
+/// Core `n` mailbox interrupt control, at `LOCAL_MAILBOX_INT_CTRL + n * 4`
+const LOCAL_MAILBOX_INT_CTRL: usize = LOCAL_BASE + 0x50;
+
+/// Core `n` mailbox 0 write-set, at `LOCAL_MAILBOX0_SET + n * 0x10`
+const LOCAL_MAILBOX0_SET: usize = LOCAL_BASE + 0x80;
+
+/// Core `n` mailbox 0 read and write-clear, at `LOCAL_MAILBOX0_CLR + n * 0x10`
+const LOCAL_MAILBOX0_CLR: usize = LOCAL_BASE + 0xC0;
+
+/// Core `n` IRQ source, at `LOCAL_IRQ_SOURCE + n * 4`
+const LOCAL_IRQ_SOURCE: usize = LOCAL_BASE + 0x60;
+
+/// Mailbox 0 is used for inter-processor interrupts
+const IPI_MAILBOX_INT: u32 = 1 << 0;
+
+/// Mailbox 0 interrupt pending in the IRQ source register
+pub const IRQ_SOURCE_MAILBOX0: u32 = 1 << 4;
+
+/// Interrupt of the BCM2835 interrupt controller pending, only core 0 gets it
+pub const IRQ_SOURCE_GPU: u32 = 1 << 8;
+
+fn local_read(addr: usize) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+fn local_write(addr: usize, value: u32) {
+    unsafe { (addr as *mut u32).write_volatile(value) }
+}
+
+fn mpidr() -> usize {
+    let reg: u64;
+    unsafe { asm!("mrs {}, mpidr_el1", out(reg) reg, options(nomem, nostack, preserves_flags)) };
+    reg as usize
+}
+
+/// Affinity levels 0 to 2 of the MPIDR.
+pub fn hw_id() -> usize {
+    mpidr() & 0xFF_FFFF
+}
+
+fn core() -> usize {
+    mpidr() & 0xFF
+}
+
+/// Returns the per-CPU data TPIDR_EL1 points at, boot.S sets it.
+#[inline]
+pub fn cpu_data() -> *const CpuData {
+    let ptr: u64;
+    unsafe { asm!("mrs {}, tpidr_el1", out(reg) ptr, options(nomem, nostack, preserves_flags)) };
+    ptr as *const CpuData
+}
+
+/// Returns the interrupts pending on this core.
+pub fn irq_source() -> u32 {
+    local_read(LOCAL_IRQ_SOURCE + core() * 4)
+}
+
+/// Routes mailbox 0 of this core to its IRQ line.
+pub fn enable_ipi() {
+    local_write(LOCAL_MAILBOX_INT_CTRL + core() * 4, IPI_MAILBOX_INT);
+}
+
+/// Sends an inter-processor interrupt to the core with MPIDR affinity `hw_id`.
+pub fn send_ipi(hw_id: usize) {
+    unsafe { asm!("dsb sy", options(nostack, preserves_flags)) };
+    local_write(LOCAL_MAILBOX0_SET + (hw_id & 0xFF) * 0x10, 1);
+}
+
+pub fn ack_ipi() {
+    local_write(LOCAL_MAILBOX0_CLR + core() * 0x10, u32::MAX);
+}
+
+pub fn flush_tlb(addr: usize) {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) addr >> 12,
+            options(nostack, preserves_flags));
+    }
+}
+
+pub fn flush_tlb_all() {
+    unsafe { asm!("dsb ishst", "tlbi vmalle1is", "dsb ish", "isb", options(nostack, preserves_flags)) };
+}
+
+/// Releases the secondary cores, both the ones parked by the firmware and the
+/// ones that entered `_start` themselves, like under QEMU.
+pub fn start_secondary_cpus() {
+    for core in 1..MAX_CPUS {
+        unsafe { ((SPIN_TABLE_BASE + core * 8) as *mut u64).write_volatile(_start as *const () as u64) };
+    }
+
+    SECONDARY_HOLD.store(0, Ordering::Release);
+
+    unsafe { asm!("dsb sy", "sev", options(nostack, preserves_flags)) };
+}
+
+#[no_mangle]
+extern "C" fn secondary_start(id: usize) -> ! {
+    smp::cpu_online(id, hw_id());
+    interrupt::init();
+    enable_ipi();
+    smp::secondary_main(id)
+}
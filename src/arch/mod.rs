@@ -9,13 +9,31 @@ pub mod x86_64;
 pub use aarch64::_print;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::_eprint;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::smp::*;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::wait_for_interrupt;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::{reboot, wait_for_input, with_console};
 
 #[cfg(target_arch = "riscv64")]
 pub use riscv64::_print;
 #[cfg(target_arch = "riscv64")]
 pub use riscv64::_eprint;
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::smp::*;
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::wait_for_interrupt;
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::{reboot, wait_for_input, with_console};
 
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::_print;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::_eprint;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::smp::*;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::wait_for_interrupt;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::{reboot, wait_for_input, with_console};
@@ -0,0 +1,60 @@
+//! DRAM initialisation payload, linked into SRAM with dram_boot.ld.
+//!
+//! The boot ROM runs it in FEL mode before the kernel is written to DRAM,
+//! in place of `xfel ddr d1`, and gets control back once it's done.
+
+use core::arch::global_asm;
+
+use crate::drivers::dram::sun20i_d1_dram::DramConfig;
+use crate::prelude::*;
+
+use super::{init_uart, CCU, DRAM};
+
+global_asm!(
+    ".section .text.dram_boot",
+    ".global _dram_boot_start",
+    "_dram_boot_start:",
+    // keep what the boot ROM needs to continue after the return
+    "    la      t0, {fel_context}",
+    "    sd      sp, 0(t0)",
+    "    sd      ra, 8(t0)",
+    "    sd      gp, 16(t0)",
+    "    sd      tp, 24(t0)",
+    // zero bss
+    "    la      t0, __bss_start",
+    "    la      t1, __bss_end_exclusive",
+    "1:",
+    "    bgeu    t0, t1, 2f",
+    "    sd      zero, 0(t0)",
+    "    addi    t0, t0, 8",
+    "    j       1b",
+    "2:",
+    "    la      sp, __boot_core_stack_end_exclusive",
+    "    la      tp, CPUS",
+    "    call    {main}",
+    "    la      t0, {fel_context}",
+    "    ld      sp, 0(t0)",
+    "    ld      ra, 8(t0)",
+    "    ld      gp, 16(t0)",
+    "    ld      tp, 24(t0)",
+    "    ret",
+    fel_context = sym FEL_CONTEXT,
+    main = sym dram_boot_main
+);
+
+/// sp, ra, gp and tp of the boot ROM, not in .bss as that's cleared after saving them
+#[link_section = ".data.fel_context"]
+static mut FEL_CONTEXT: [usize; 4] = [0; 4];
+
+extern "C" fn dram_boot_main() {
+    let clocks = CCU.init();
+    init_uart();
+    if let Err(e) = clocks {
+        eprintln!("Clock setup failed: {}", e);
+    }
+
+    match DRAM.init(&CCU, &DramConfig::NEZHA) {
+        Ok(size) => println!("DRAM {} MiB", size >> 20),
+        Err(e) => eprintln!("DRAM setup failed: {}", e)
+    }
+}
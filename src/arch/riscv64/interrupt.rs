@@ -0,0 +1,69 @@
+use core::arch::{asm, global_asm};
+
+use crate::drivers::dma::DmaEngine;
+use crate::drivers::gpio::Gpio;
+use crate::prelude::*;
+use crate::smp::ipi;
+
+use super::plic;
+use super::smp::ack_ipi;
+use super::{DMA, DMA_IRQ, DMA_STAT, PIO, PIO_IRQS, PIO_STAT};
+
+global_asm!(include_str!("_asm/trap.S"));
+
+extern "C" {
+    fn trap_vector();
+}
+
+/// Set in mcause for interrupts, the rest is the cause
+const MCAUSE_INTERRUPT: usize = 1 << 63;
+
+/// Machine software interrupt, used for inter-processor interrupts
+const IRQ_MACHINE_SOFTWARE: usize = 3;
+
+/// Machine external interrupt, the PLIC tells which device it is
+const IRQ_MACHINE_EXTERNAL: usize = 11;
+
+/// Points mtvec of the calling hart at the trap vector.
+pub fn init() {
+    unsafe { asm!("csrw mtvec, {}", in(reg) trap_vector as *const () as usize, options(nomem, nostack, preserves_flags)) };
+}
+
+/// Sets mstatus.MIE just long enough to take the pending interrupts.
+pub fn take_pending() {
+    unsafe { asm!("csrsi mstatus, 8", "csrci mstatus, 8", options(nostack)) };
+}
+
+#[no_mangle]
+extern "C" fn trap_handler(mcause: usize, mepc: usize, mtval: usize) {
+    match mcause {
+        c if c == MCAUSE_INTERRUPT | IRQ_MACHINE_SOFTWARE => {
+            ack_ipi();
+            ipi::handle_ipi();
+        },
+        c if c == MCAUSE_INTERRUPT | IRQ_MACHINE_EXTERNAL => handle_external(),
+        c if c & MCAUSE_INTERRUPT != 0 => eprintln!("Unexpected interrupt {}", c & !MCAUSE_INTERRUPT),
+        c => panic!("EXCEPTION: mcause {} at {:#x}, mtval {:#x}", c, mepc, mtval)
+    }
+}
+
+/// Runs the handlers of all pending device interrupts.
+fn handle_external() {
+    loop {
+        let irq = plic::claim();
+        match irq {
+            0 => return,
+            DMA_IRQ => {
+                DMA_STAT.inc();
+                DMA.handle_interrupt();
+            },
+            irq if PIO_IRQS.contains(&irq) => {
+                PIO_STAT.inc();
+                PIO.handle_interrupt();
+            },
+            irq => eprintln!("Unexpected IRQ {}", irq)
+        }
+
+        plic::complete(irq);
+    }
+}
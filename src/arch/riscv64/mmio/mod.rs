@@ -1,7 +1,12 @@
+use crate::drivers::bus::{Bus, Mmio32};
+
+// Physical memory is identity mapped
+const PHYS: Mmio32 = unsafe { Mmio32::new(0) };
+
 pub fn write32(addr: usize, value: u32) {
-    unsafe { (addr as *mut u32).write_volatile(value) }
+    PHYS.write(addr, value)
 }
 
 pub fn read32(addr: usize) -> u32 {
-    unsafe { (addr as *const u32).read_volatile() }
+    PHYS.read(addr)
 }
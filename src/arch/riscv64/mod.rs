@@ -1,91 +1,299 @@
-mod clk;
+mod dram_boot;
+mod interrupt;
 pub mod mmio;
+mod plic;
+pub mod smp;
 
 use core::arch::asm;
-use core::fmt::{Arguments, Write};
+use core::fmt::{self, Arguments, Write};
+use core::hint;
 
-use crate::drivers::serial::ns16550::NS16550;
+use crate::drivers::bus::{Mmio8, Mmio32, RegisterBlock};
+use crate::drivers::clk::d1_ccu::{Ccu, Clock, Reset, D1_CCU_BASE};
+use crate::drivers::dma::{DmaPort, Width};
+use crate::drivers::dma::sun6i_dma::{Dma, D1_DMA_BASE, DRQ_UART0};
+use crate::drivers::dram::sun20i_d1_dram::{Dram, D1_DRAM_BASE, D1_MCTL_BASE};
+use crate::drivers::gpio::sun20i_d1_pio::{Function, Pin, Pio, Port, D1_PIO_BASE};
+use crate::drivers::i2c;
+use crate::drivers::i2c::sun20i_twi::{Twi, D1_TWI2_BASE};
+use crate::drivers::serial::ByteStream;
+use crate::drivers::serial::ns16550::{NS16550, SerialConfig};
+use crate::drivers::spi::Mode;
+use crate::drivers::spi::spi_nor::{FlashError, SpiNor};
+use crate::drivers::spi::sun20i_spi::{Spi, D1_SPI0_BASE};
+use crate::drivers::video::console::vga::{Color, ColorTracker};
+use crate::drivers::virtio::{self, Transport};
+use crate::drivers::virtio::mmio::MmioTransport;
+use crate::fdt;
+use crate::irq::{self, IrqStat};
 use crate::prelude::*;
+use crate::shell::{self, Command, CommandError};
+use crate::sync::mutex::Mutex;
 
-use self::clk::init_clock;
-use self::mmio::{read32, write32};
+use self::mmio::write32;
 
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
-    unsafe { WRITER.write_fmt(args).unwrap() };
+    WRITER.lock().write_fmt(args).unwrap();
 }
 
 #[doc(hidden)]
 pub fn _eprint(args: Arguments) {
-    unsafe { WRITER.write_fmt(args).unwrap() };
+    // The panic handler can run while this core holds the lock in _print
+    if crate::panicking() {
+        unsafe { WRITER.force_unlock(); }
+    }
+
+    // The terminal is told to switch back to the color before, not to the default one
+    let mut serial = WRITER.lock();
+    let current_color = serial.color.color_code();
+    let mut red = current_color;
+    red.set_fg_color(Color::Red);
+    red.write_sgr(&mut *serial).unwrap();
+    serial.write_fmt(args).unwrap();
+    current_color.write_sgr(&mut *serial).unwrap();
+}
+
+/// Writing this to the watchdog config register resets the system
+const WDOG_RESET: u32 = 0x16AA0001;
+const WDOG_CONFIG: usize = 0x020500A8;
+
+static CCU: Ccu<Mmio32> = Ccu::new(RegisterBlock::new(unsafe { Mmio32::new(D1_CCU_BASE) }, 1), sdelay);
+
+static DRAM: Dram<Mmio32, Mmio32> =
+    Dram::new(RegisterBlock::new(unsafe { Mmio32::new(D1_MCTL_BASE) }, 1), unsafe { Mmio32::new(D1_DRAM_BASE) }, sdelay);
+
+static PIO: Pio<Mmio32> = Pio::new(RegisterBlock::new(unsafe { Mmio32::new(D1_PIO_BASE) }, 1));
+static PIO_STAT: IrqStat = IrqStat::new("pio");
+
+/// PLIC sources of the ports B to G, the secure interrupt of each port is the one after it
+const PIO_IRQS: [u32; 6] = [85, 87, 89, 91, 93, 95];
+
+static TWI2: Twi<Mmio32> = Twi::new(RegisterBlock::new(unsafe { Mmio32::new(D1_TWI2_BASE) }, 1), 2);
+
+static I2CDETECT_COMMAND: Command = Command {
+    name: "i2cdetect",
+    usage: "",
+    help: "list the devices on TWI2",
+    run: i2cdetect_command
+};
+
+static VIRTIO_COMMAND: Command = Command {
+    name: "virtio",
+    usage: "",
+    help: "list the VirtIO devices in the device tree",
+    run: virtio_command
+};
+
+static DMA: Dma<Mmio32> = Dma::new(RegisterBlock::new(unsafe { Mmio32::new(D1_DMA_BASE) }, 1));
+static DMA_STAT: IrqStat = IrqStat::new("dma");
+
+/// PLIC source of the DMA controller
+const DMA_IRQ: u32 = 66;
+
+static SPI0: Spi<Mmio32, Mmio8> =
+    Spi::new(RegisterBlock::new(unsafe { Mmio32::new(D1_SPI0_BASE) }, 1), RegisterBlock::new(unsafe { Mmio8::new(D1_SPI0_BASE) }, 1), D1_SPI0_BASE, 0, Some(&DMA));
+
+const UART0_BASE: usize = 0x02500000;
+
+static WRITER: Mutex<Serial> = Mutex::new(Serial {
+    uart: NS16550::new(RegisterBlock::new(unsafe { Mmio32::new(UART0_BASE) }, 4)),
+    color: ColorTracker::new()
+});
+
+/// UART0, along with the color its terminal is left with by what was written.
+struct Serial {
+    uart: NS16550<Mmio32>,
+    color: ColorTracker
 }
 
-// TODO: make thread safe
-static mut WRITER: NS16550 = NS16550::new(0x02500000);
+impl ByteStream for Serial {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.uart.read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.uart.write_byte(byte);
+        self.color.feed(byte);
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        // Passed on as a whole so long writes can still use DMA
+        self.uart.write(buf);
+        buf.iter().for_each(|&byte| self.color.feed(byte));
+    }
+
+    fn flush(&mut self) {
+        self.uart.flush();
+    }
+}
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.uart.write_str(s)?;
+        s.bytes().for_each(|byte| self.color.feed(byte));
+        Ok(())
+    }
+}
+
+/// Sleeps until an interrupt is pending and takes it, mstatus.MIE stays clear the rest of the time.
+pub fn wait_for_interrupt() {
+    // wfi also wakes up for disabled interrupts, so none can sneak in before it
+    unsafe { asm!("wfi", options(nomem, nostack, preserves_flags)) };
+    interrupt::take_pending();
+}
+
+/// Gives the shell access to the console, the serial port here.
+pub fn with_console<R>(f: impl FnOnce(&mut dyn ByteStream) -> R) -> R {
+    f(&mut *WRITER.lock())
+}
+
+/// The UART interrupt isn't routed yet, so the shell has to poll,
+/// other interrupts get taken in between.
+pub fn wait_for_input() {
+    interrupt::take_pending();
+    hint::spin_loop();
+}
+
+pub fn reboot() -> ! {
+    write32(WDOG_CONFIG, WDOG_RESET);
+    loop {
+        wait_for_interrupt();
+    }
+}
 
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
-    init_clock();
+pub extern "C" fn kernel_main(_hart_id: usize, dtb: usize) -> ! {
+    crate::smp::cpu_online(0, smp::hart_id());
+    interrupt::init();
+    plic::init();
+    fdt::set_blob(dtb);
+    smp::init_clint();
+    let clocks = CCU.init();
     init_jtag();
     init_uart();
+    init_dma();
+    init_pio();
 
     println!("Hello World!");
+    if let Err(e) = clocks {
+        eprintln!("Clock setup failed: {}", e);
+    }
+
+    init_dram();
+    init_flash();
+    init_i2c();
+
+    smp::enable_ipi();
+    crate::smp::init();
+
+    shell::builtins::register_builtins();
+    shell::register(&I2CDETECT_COMMAND).unwrap();
+    shell::register(&VIRTIO_COMMAND).unwrap();
+    shell::run()
+}
+
+/// JTAG pins, in function 4
+const JTAG_PINS: [Pin; 4] = [
+    Pin::new(Port::PF, 0).unwrap(),
+    Pin::new(Port::PF, 1).unwrap(),
+    Pin::new(Port::PF, 3).unwrap(),
+    Pin::new(Port::PF, 5).unwrap()
+];
+
+/// TX and RX of UART0, in function 6
+const UART0_PINS: [Pin; 2] = [Pin::new(Port::PB, 8).unwrap(), Pin::new(Port::PB, 9).unwrap()];
+
+/// CLK, CS0, MOSI, MISO, WP and HOLD of SPI0, in function 2
+const SPI0_PINS: [Pin; 6] = [
+    Pin::new(Port::PC, 2).unwrap(),
+    Pin::new(Port::PC, 3).unwrap(),
+    Pin::new(Port::PC, 4).unwrap(),
+    Pin::new(Port::PC, 5).unwrap(),
+    Pin::new(Port::PC, 6).unwrap(),
+    Pin::new(Port::PC, 7).unwrap()
+];
+
+/// SCK and SDA of TWI2, in function 4
+const TWI2_PINS: [Pin; 2] = [Pin::new(Port::PB, 0).unwrap(), Pin::new(Port::PB, 1).unwrap()];
+
+fn init_jtag() {
+    PIO.set_functions(&JTAG_PINS, Function::Function4);
+}
+
+fn init_uart() {
+    PIO.set_functions(&UART0_PINS, Function::Function6);
+    CCU.enable(Clock::Uart(0)).unwrap();
+    CCU.deassert_reset(Reset::Uart(0)).unwrap();
+
+    let clock = CCU.rate(Clock::Uart(0)).unwrap();
+    WRITER.lock().uart.init(clock as u32, SerialConfig::new(115200)).unwrap();
+}
+
+/// Events enabled on the pins get their handlers called from the interrupt handler.
+fn init_pio() {
+    irq::register(&PIO_STAT);
+    for irq in PIO_IRQS {
+        plic::enable(irq);
+    }
+}
+
+/// Resets the DMA controller and hands it to UART0, SPI0 has it from the start.
+/// Transfers started with a callback get it called from the interrupt handler.
+fn init_dma() {
+    DMA.init();
+    irq::register(&DMA_STAT);
+    plic::enable(DMA_IRQ);
+    let port = DmaPort { addr: UART0_BASE, drq: DRQ_UART0, width: Width::Byte };
+    WRITER.lock().uart.set_dma(&DMA, port);
+}
+
+/// The kernel runs from DRAM, so the DRAM boot payload (dram_boot.rs) already
+/// trained it and only the size is read back.
+fn init_dram() {
+    println!("DRAM {} MiB", DRAM.size() >> 20);
+}
+
+/// Looks for a NOR flash on SPI0, boards without one are fine.
+fn init_flash() {
+    PIO.set_functions(&SPI0_PINS, Function::Function2);
+    if let Err(e) = SPI0.init(&CCU, Mode::Mode0, 50_000_000) {
+        eprintln!("SPI0 setup failed: {}", e);
+        return;
+    }
+
+    match SpiNor::probe(&SPI0, 0) {
+        Ok(flash) => println!("SPI flash {}, {} KiB", flash.id(), flash.size() >> 10),
+        Err(FlashError::NoDevice) => {},
+        Err(e) => eprintln!("{}", e)
+    }
+}
+
+/// Sets up TWI2, which has the GPIO expander on the Nezha.
+fn init_i2c() {
+    PIO.set_functions(&TWI2_PINS, Function::Function4);
+    if let Err(e) = TWI2.init(&CCU, 100_000) {
+        eprintln!("TWI2 setup failed: {}", e);
+    }
+}
+
+fn i2cdetect_command(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    i2c::detect(&TWI2, out)?;
+    Ok(())
+}
+
+fn virtio_command(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let fdt = fdt::blob().and_then(|fdt| fdt.ok()).ok_or(CommandError::Failed("no device tree"))?;
+    let mut result = Ok(());
+    virtio::mmio::devices(&fdt, |addr| {
+        let regs = RegisterBlock::new(unsafe { Mmio32::new(addr as usize) }, 1);
+        if let Some(transport) = MmioTransport::probe(regs).ok().filter(|_| result.is_ok()) {
+            result = writeln!(out, "{:#x} {} device, features {:#018x}", addr, transport.device_type(), transport.device_features());
+        }
+    });
 
-    loop { }
-}
-
-fn init_jtag()
-{
-    /* Config GPIOF0, GPIOF1, GPIOF3 and GPIOF5 to JTAG mode */
-    let addr = 0x020000f0;
-    let mut val = read32(addr);
-    val &= !(0xf << ((0 & 0x7) << 2));
-    val |= (0x4 & 0xf) << ((0 & 0x7) << 2);
-    write32(addr, val);
-
-    val = read32(addr);
-    val &= !(0xf << ((1 & 0x7) << 2));
-    val |= (0x4 & 0xf) << ((1 & 0x7) << 2);
-    write32(addr, val);
-
-    val = read32(addr);
-    val &= !(0xf << ((3 & 0x7) << 2));
-    val |= (0x4 & 0xf) << ((3 & 0x7) << 2);
-    write32(addr, val);
-
-    val = read32(addr);
-    val &= !(0xf << ((5 & 0x7) << 2));
-    val |= (0x4 & 0xf) << ((5 & 0x7) << 2);
-    write32(addr, val);
-}
-
-fn init_uart()
-{
-    /* Config GPIOB8 and GPIOB9 to txd0 and rxd0 */
-    let mut addr = 0x02000030 + 0x04;
-    let mut val = read32(addr);
-    val &= !(0xf << ((8 & 0x7) << 2));
-    val |= (0x6 & 0xf) << ((8 & 0x7) << 2);
-    write32(addr, val);
-
-    val = read32(addr);
-    val &= !(0xf << ((9 & 0x7) << 2));
-    val |= (0x6 & 0xf) << ((9 & 0x7) << 2);
-    write32(addr, val);
-
-    /* Open the clock gate for uart0 */
-    addr = 0x0200190c;
-    val = read32(addr);
-    val |= 1 << 0;
-    write32(addr, val);
-
-    /* Deassert uart0 reset */
-    addr = 0x0200190c;
-    val = read32(addr);
-    val |= 1 << 16;
-    write32(addr, val);
-
-    unsafe { WRITER.init(24000000, 115200); }
+    result?;
+    Ok(())
 }
 
 fn counter() -> u64 {
@@ -0,0 +1,49 @@
+//! Platform-level interrupt controller of the D1, device interrupts go to the machine mode context of hart 0.
+
+use core::arch::asm;
+
+use super::mmio::{read32, write32};
+
+const PLIC_BASE: usize = 0x10000000;
+
+/// Sources of the D1, the first one is reserved
+const NUM_SOURCES: usize = 256;
+
+/// Priority of source `n`, at `PLIC_PRIORITY + n * 4`, 0 never interrupts
+const PLIC_PRIORITY: usize = PLIC_BASE;
+
+/// Enable bits of the sources for the machine mode context of hart 0
+const PLIC_MENABLE: usize = PLIC_BASE + 0x2000;
+
+/// Priority threshold and claim/complete register of the machine mode context of hart 0
+const PLIC_MTHRESHOLD: usize = PLIC_BASE + 0x200000;
+const PLIC_MCLAIM: usize = PLIC_BASE + 0x200004;
+
+/// Machine external interrupt enable in mie
+const MIE_MEIE: usize = 1 << 11;
+
+/// Masks all sources and lets the enabled ones through to the calling hart, which has to be hart 0.
+pub fn init() {
+    for i in 0..NUM_SOURCES / 32 {
+        write32(PLIC_MENABLE + i * 4, 0);
+    }
+
+    write32(PLIC_MTHRESHOLD, 0);
+    unsafe { asm!("csrs mie, {}", in(reg) MIE_MEIE, options(nomem, nostack, preserves_flags)) };
+}
+
+pub fn enable(irq: u32) {
+    write32(PLIC_PRIORITY + irq as usize * 4, 1);
+    let reg = PLIC_MENABLE + irq as usize / 32 * 4;
+    write32(reg, read32(reg) | 1 << (irq % 32));
+}
+
+/// Takes the highest priority pending interrupt, 0 if there is none.
+pub fn claim() -> u32 {
+    read32(PLIC_MCLAIM)
+}
+
+/// Signals the end of the handler of `irq`, which was claimed.
+pub fn complete(irq: u32) {
+    write32(PLIC_MCLAIM, irq);
+}
@@ -0,0 +1,95 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fdt;
+use crate::smp::{self, CpuData, MAX_CPUS, SECONDARY_HOLD};
+
+use super::interrupt;
+use super::mmio::write32;
+
+/// Base of the CLINT of the C906 core in the D1, used without a device tree
+const D1_CLINT_BASE: usize = 0x14000000;
+
+/// Compatible strings of the CLINTs of the D1 and the QEMU virt machine
+const CLINT_COMPATIBLE: [&str; 2] = ["thead,c900-clint", "riscv,clint0"];
+
+/// Base of the CLINT, the machine software interrupt pending register of hart `n` is at `n * 4`
+static CLINT_BASE: AtomicUsize = AtomicUsize::new(D1_CLINT_BASE);
+
+/// Takes the CLINT from the device tree, if there is one, before any IPI is sent.
+pub fn init_clint() {
+    if let Some(Ok(fdt)) = fdt::blob() {
+        let mut base = None;
+        fdt.compatible_nodes(&CLINT_COMPATIBLE, |addr| base = base.or(Some(addr as usize)));
+        if let Some(base) = base {
+            CLINT_BASE.store(base, Ordering::Relaxed);
+        }
+    }
+}
+
+fn clint_msip(hart: usize) -> usize {
+    CLINT_BASE.load(Ordering::Relaxed) + hart * 4
+}
+
+pub fn hart_id() -> usize {
+    let id: usize;
+    unsafe { asm!("csrr {}, mhartid", out(reg) id, options(nomem, nostack, preserves_flags)) };
+    id
+}
+
+/// Returns the per-CPU data tp points at, boot.S sets it. Rust doesn't use tp without thread locals.
+#[inline]
+pub fn cpu_data() -> *const CpuData {
+    let ptr: *const CpuData;
+    unsafe { asm!("mv {}, tp", out(reg) ptr, options(nomem, nostack, preserves_flags)) };
+    ptr
+}
+
+/// Sends a machine software interrupt to `hart`.
+pub fn send_msip(hart: usize) {
+    write32(clint_msip(hart), 1);
+}
+
+pub fn clear_msip(hart: usize) {
+    write32(clint_msip(hart), 0);
+}
+
+/// Enables the machine software interrupt, it's taken whenever mstatus.MIE gets set.
+pub fn enable_ipi() {
+    unsafe { asm!("csrs mie, {}", in(reg) 1 << 3, options(nomem, nostack, preserves_flags)) };
+}
+
+/// Sends an inter-processor interrupt to `hart`.
+pub fn send_ipi(hart: usize) {
+    send_msip(hart);
+}
+
+pub fn ack_ipi() {
+    clear_msip(hart_id());
+}
+
+pub fn flush_tlb(addr: usize) {
+    unsafe { asm!("sfence.vma {}, zero", in(reg) addr, options(nostack, preserves_flags)) };
+}
+
+pub fn flush_tlb_all() {
+    unsafe { asm!("sfence.vma", options(nostack, preserves_flags)) };
+}
+
+/// Wakes up the secondary harts, they're waiting in `_start` for a software interrupt.
+pub fn start_secondary_cpus() {
+    SECONDARY_HOLD.store(0, Ordering::Release);
+
+    for hart in 1..MAX_CPUS {
+        send_msip(hart);
+    }
+}
+
+#[no_mangle]
+extern "C" fn secondary_start(id: usize) -> ! {
+    clear_msip(id);
+    smp::cpu_online(id, id);
+    interrupt::init();
+    enable_ipi();
+    smp::secondary_main(id)
+}
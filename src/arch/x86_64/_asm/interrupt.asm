@@ -1,8 +1,14 @@
 .extern double_fault_handler
 .extern page_fault_handler
+.extern ipi_interrupt_handler
+.extern com1_interrupt_handler
+.extern keyboard_interrupt_handler
 
 .global double_fault
 .global page_fault
+.global ipi_interrupt
+.global com1_interrupt
+.global keyboard_interrupt
 
 .section .text
 
@@ -41,3 +47,79 @@ page_fault:
     add rsp, 8
 
     iretq
+
+ipi_interrupt:
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+
+    # the stack is 16 byte aligned again after pushing 9 registers on the 5 entry stack frame
+    call ipi_interrupt_handler
+
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+
+    iretq
+
+com1_interrupt:
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+
+    call com1_interrupt_handler
+
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+
+    iretq
+
+keyboard_interrupt:
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+
+    call keyboard_interrupt_handler
+
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+
+    iretq
@@ -0,0 +1,150 @@
+//! Just enough ACPI to find the system description tables, relies on the low memory being identity mapped.
+
+use core::ptr;
+use core::slice;
+
+/// Segment of the extended BIOS data area is stored here
+const EBDA_SEGMENT_PTR: usize = 0x40E;
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;
+const SDT_HEADER_SIZE: usize = 36;
+
+/// The MCFG has 8 reserved bytes after the header, then 16 byte entries
+const MCFG_ENTRIES: usize = SDT_HEADER_SIZE + 8;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    addr: usize
+}
+
+impl Rsdp {
+    /// Looks for the RSDP in the first KiB of the EBDA and in the BIOS area.
+    pub fn find() -> Option<Self> {
+        let ebda = unsafe { ptr::read_volatile(EBDA_SEGMENT_PTR as *const u16) } as usize * 16;
+        let ebda = if ebda != 0 { scan(ebda, ebda + 1024) } else { None };
+        ebda.or_else(|| scan(BIOS_AREA_START, BIOS_AREA_END))
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    fn bytes(&self) -> &'static [u8] {
+        let len = if self.revision() >= 2 { self.read_u32(20) as usize } else { RSDP_V1_SIZE };
+        unsafe { slice::from_raw_parts(self.addr as *const u8, len) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_unaligned((self.addr + offset) as *const u32) }
+    }
+
+    pub fn revision(&self) -> u8 {
+        unsafe { *((self.addr + 15) as *const u8) }
+    }
+
+    pub fn oem_id(&self) -> &'static [u8] {
+        &self.bytes()[9..15]
+    }
+
+    /// Calls `f` with the address of every table listed in the XSDT, or the RSDT on ACPI 1.0.
+    pub fn for_each_table<F: FnMut(SdtHeader)>(&self, mut f: F) {
+        let (root, entry_size) = if self.revision() >= 2 {
+            (unsafe { ptr::read_unaligned((self.addr + 24) as *const u64) } as usize, 8)
+        } else {
+            (self.read_u32(16) as usize, 4)
+        };
+
+        let root = SdtHeader { addr: root };
+        let entries = (root.length() as usize).saturating_sub(SDT_HEADER_SIZE) / entry_size;
+        for i in 0..entries {
+            let entry = root.addr + SDT_HEADER_SIZE + i * entry_size;
+            let addr = unsafe {
+                if entry_size == 8 {
+                    ptr::read_unaligned(entry as *const u64) as usize
+                } else {
+                    ptr::read_unaligned(entry as *const u32) as usize
+                }
+            };
+
+            f(SdtHeader { addr });
+        }
+    }
+
+    /// The first table with `signature` and a valid checksum.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<SdtHeader> {
+        let mut found = None;
+        self.for_each_table(|table| {
+            if found.is_none() && &table.signature() == signature && table.valid() {
+                found = Some(table);
+            }
+        });
+
+        found
+    }
+}
+
+/// Header every system description table starts with.
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    addr: usize
+}
+
+impl SdtHeader {
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        unsafe { ptr::read_unaligned(self.addr as *const [u8; 4]) }
+    }
+
+    pub fn length(&self) -> u32 {
+        unsafe { ptr::read_unaligned((self.addr + 4) as *const u32) }
+    }
+
+    pub fn valid(&self) -> bool {
+        checksum(unsafe { slice::from_raw_parts(self.addr as *const u8, self.length() as usize) })
+    }
+}
+
+/// ECAM region of a PCI segment group, an entry of the MCFG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8
+}
+
+/// The entries of `mcfg`, which has to be the MCFG.
+pub fn mcfg_entries(mcfg: SdtHeader) -> impl Iterator<Item = McfgEntry> {
+    let entries = (mcfg.length() as usize).saturating_sub(MCFG_ENTRIES) / MCFG_ENTRY_SIZE;
+    (0..entries).map(move |i| {
+        let entry = mcfg.addr + MCFG_ENTRIES + i * MCFG_ENTRY_SIZE;
+        unsafe {
+            McfgEntry {
+                base: ptr::read_unaligned(entry as *const u64),
+                segment: ptr::read_unaligned((entry + 8) as *const u16),
+                start_bus: *((entry + 10) as *const u8),
+                end_bus: *((entry + 11) as *const u8)
+            }
+        }
+    })
+}
+
+/// The RSDP is 16 byte aligned.
+fn scan(start: usize, end: usize) -> Option<Rsdp> {
+    (start..end).step_by(16).find_map(|addr| {
+        let bytes = unsafe { slice::from_raw_parts(addr as *const u8, RSDP_V1_SIZE) };
+        (&bytes[..8] == RSDP_SIGNATURE && checksum(bytes)).then_some(Rsdp { addr })
+    })
+}
+
+/// All bytes of an ACPI structure add up to 0.
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
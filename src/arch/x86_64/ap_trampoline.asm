@@ -0,0 +1,128 @@
+; Entry point of the application processors.
+; This code doesn't run in place, smp::start_secondary_cpus copies it to
+; TRAMPOLINE_BASE (below 1MiB) and points the startup IPI at it.
+
+TRAMPOLINE_BASE equ 0x8000
+
+; Address of label x once the trampoline is copied
+%define REL(x) (x - ap_trampoline_start + TRAMPOLINE_BASE)
+
+CODE64_SEG equ 0x08
+DATA_SEG equ 0x10
+CODE32_SEG equ 0x18
+
+IA32_GS_BASE equ 0xC0000101
+
+global ap_trampoline_start
+global ap_trampoline_data
+global ap_trampoline_end
+
+section .rodata
+align 4096
+bits 16
+ap_trampoline_start:
+    cli
+    cld
+    xor ax, ax
+    mov ds, ax
+
+    lgdt [REL(ap_gdt.pointer)]
+
+    ; enable protected mode
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+
+    jmp CODE32_SEG:REL(ap_protected_mode)
+
+bits 32
+ap_protected_mode:
+    mov ax, DATA_SEG
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; use the page tables of the boot processor
+    mov eax, [REL(ap_trampoline_data.cr3)]
+    mov cr3, eax
+
+    ; enable PAE-flag in cr4 (Physical Address Extension)
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    ; set the long mode bit in the EFER MSR (model specific register)
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, 1 << 8
+    wrmsr
+
+    ; enable paging in the cr0 register
+    mov eax, cr0
+    or eax, 1 << 31
+    mov cr0, eax
+
+    jmp CODE64_SEG:REL(ap_long_mode)
+
+bits 64
+ap_long_mode:
+    xor eax, eax
+    mov ss, ax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+
+    ; take the next free cpu id
+    mov eax, 1
+    lock xadd [REL(ap_trampoline_data.next_cpu)], eax
+    cmp eax, [REL(ap_trampoline_data.max_cpus)]
+    jae .halt
+
+    ; stack top = stacks + (cpu id + 1) * stack size
+    mov rdi, rax
+    lea rax, [rax + 1]
+    mul qword [REL(ap_trampoline_data.stack_size)]
+    add rax, [REL(ap_trampoline_data.stacks)]
+    mov rsp, rax
+
+    ; point the GS base at cpus + cpu id * cpu data size, before any rust code runs
+    mov rax, rdi
+    mul qword [REL(ap_trampoline_data.cpu_data_size)]
+    add rax, [REL(ap_trampoline_data.cpus)]
+    mov rdx, rax
+    shr rdx, 32
+    mov ecx, IA32_GS_BASE
+    wrmsr
+
+    ; jump to rust with the cpu id as argument, should not return
+    mov rax, [REL(ap_trampoline_data.entry)]
+    call rax
+
+.halt:
+    cli
+    hlt
+    jmp .halt
+
+align 16
+ap_gdt:
+    dq 0 ; zero entry
+    dq 0x00209A0000000000 ; 64-bit code, same selector as the boot GDT
+    dq 0x00CF92000000FFFF ; flat data
+    dq 0x00CF9A000000FFFF ; flat 32-bit code
+.pointer:
+    dw $ - ap_gdt - 1
+    dd REL(ap_gdt)
+
+; Filled in by smp::start_secondary_cpus, layout must match TrampolineData
+align 8
+ap_trampoline_data:
+.cr3: dq 0
+.entry: dq 0
+.stacks: dq 0
+.stack_size: dq 0
+.cpus: dq 0
+.cpu_data_size: dq 0
+.next_cpu: dd 0
+.max_cpus: dd 0
+ap_trampoline_end:
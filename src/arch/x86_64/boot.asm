@@ -33,6 +33,8 @@ gdt64:
 section .text
 start:
     mov esp, stack_top
+    ; keep the multiboot information pointer for kernel_main, cpuid overwrites ebx
+    mov edi, ebx
 
     call check_multiboot
     call check_cpuid
@@ -127,23 +129,33 @@ set_up_page_tables:
     or eax, PAGE_PRESENT | PAGE_WRITABLE
     mov [pml4_table], eax
 
-    ; map first PDP entry to PD table
-    mov eax, pd_table
+    ; map the first 4 PDP entries to the PD tables
+    xor ecx, ecx         ; counter variable
+
+.map_pdp_table:
+    mov eax, 4096
+    mul ecx
+    add eax, pd_table
     or eax, PAGE_PRESENT | PAGE_WRITABLE
-    mov [pdp_table], eax
+    mov [pdp_table + ecx * 8], eax
+
+    inc ecx
+    cmp ecx, 4
+    jne .map_pdp_table
 
     xor ecx, ecx         ; counter variable
 
-.map_pdp_table:
+.map_pd_table:
     ; map ecx-th PD entry to a huge page that starts at address 2MiB*ecx
+    ; this identity maps the first 4GiB, including the local APIC
     mov eax, 0x200000  ; 2MiB
     mul ecx            ; start address of ecx-th page
     or eax, PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE
-    mov [pdp_table + ecx * 8], eax ; map ecx-th entry
+    mov [pd_table + ecx * 8], eax ; map ecx-th entry
 
     inc ecx            ; increase counter
-    cmp ecx, 512       ; if counter == 512, the whole PD table is mapped
-    jne .map_pdp_table  ; else map the next entry
+    cmp ecx, 4 * 512   ; if counter == 2048, all 4 PD tables are mapped
+    jne .map_pd_table  ; else map the next entry
 
     ret
 
@@ -181,7 +193,7 @@ pml4_table:
 pdp_table:
     resb 4096
 pd_table:
-    resb 4096
+    resb 4 * 4096
 stack_bottom:
     resb 5 * 4096
 stack_top:
@@ -3,8 +3,13 @@ use core::cell::OnceCell;
 use core::mem;
 
 use crate::prelude::*;
+use crate::smp::ipi;
 use crate::sync::mutex::Mutex;
 
+use super::pic::{self, PIC1_OFFSET};
+use super::smp::{lapic_eoi, IPI_VECTOR};
+use super::{handle_keyboard, COM1, COM1_IRQ, COM1_STAT, KEYBOARD_IRQ, KEYBOARD_STAT};
+
 static IDT: Mutex<OnceCell<InterruptDescriptorTable>> = Mutex::new(OnceCell::new());
 
 global_asm!(include_str!("_asm/interrupt.asm"));
@@ -12,6 +17,9 @@ global_asm!(include_str!("_asm/interrupt.asm"));
 extern "C" {
     fn double_fault() -> !;
     fn page_fault() -> !;
+    fn ipi_interrupt();
+    fn com1_interrupt();
+    fn keyboard_interrupt();
 }
 
 #[derive(Default, Clone, Copy)]
@@ -150,6 +158,12 @@ impl Default for Int {
 }
 
 impl InterruptDescriptorTable {
+    /// Sets the entry of one of the interrupts after the exceptions.
+    pub fn set_interrupt(&mut self, vector: u8, entry: IDTEnrty) {
+        assert!(vector >= 32, "Vector {} is an exception", vector);
+        self.interrupts.0[vector as usize - 32] = entry;
+    }
+
     // REVIEW: unsafe
     pub fn load(&self) {
         let ptr = InterruptDescriptorTablePtr {
@@ -166,12 +180,20 @@ pub fn init_idt() {
     idt_lock.set(InterruptDescriptorTable::default()).unwrap();
     let idt = idt_lock.get_mut().unwrap();
 
-    idt.double_fault = IDTEnrty::new(double_fault as u64);
-    idt.page_fault = IDTEnrty::new(page_fault as u64);
+    idt.double_fault = IDTEnrty::new(double_fault as *const () as u64);
+    idt.page_fault = IDTEnrty::new(page_fault as *const () as u64);
+    idt.set_interrupt(IPI_VECTOR, IDTEnrty::new(ipi_interrupt as *const () as u64));
+    idt.set_interrupt(PIC1_OFFSET + COM1_IRQ, IDTEnrty::new(com1_interrupt as *const () as u64));
+    idt.set_interrupt(PIC1_OFFSET + KEYBOARD_IRQ, IDTEnrty::new(keyboard_interrupt as *const () as u64));
 
     idt.load();
 }
 
+/// Loads the IDT set up by [`init_idt`] on the calling CPU.
+pub fn load_idt() {
+    IDT.lock().get().unwrap().load();
+}
+
 #[derive(Debug)]
 #[repr(C)]
 struct ExceptionStackFrame {
@@ -194,6 +216,26 @@ extern "C" fn page_fault_handler(stack_frame: &ExceptionStackFrame, error_code:
     eprintln!("EXCEPTION: page fault, code: {}\n{:#?}", error_code, stack_frame);
 }
 
+#[no_mangle]
+extern "C" fn ipi_interrupt_handler() {
+    ipi::handle_ipi();
+    lapic_eoi();
+}
+
+#[no_mangle]
+extern "C" fn com1_interrupt_handler() {
+    COM1_STAT.inc();
+    COM1.lock().handle_interrupt();
+    pic::eoi(COM1_IRQ);
+}
+
+#[no_mangle]
+extern "C" fn keyboard_interrupt_handler() {
+    KEYBOARD_STAT.inc();
+    handle_keyboard();
+    pic::eoi(KEYBOARD_IRQ);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
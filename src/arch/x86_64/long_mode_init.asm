@@ -1,6 +1,9 @@
 bits 64
 
+IA32_GS_BASE equ 0xC0000101
+
 global long_mode_start
+extern CPUS
 
 section .text
 long_mode_start:
@@ -12,7 +15,17 @@ long_mode_start:
     mov fs, ax
     mov gs, ax
 
-    ; call the rust main
+    ; point the GS base at the per-CPU data of the boot cpu (smp::CPUS[0]),
+    ; locks and prints use it from the start
+    mov rax, CPUS
+    mov rdx, rax
+    shr rdx, 32
+    mov ecx, IA32_GS_BASE
+    wrmsr
+
+    ; call the rust main with the multiboot information pointer, the upper
+    ; half of rdi is undefined after the switch to long mode
+    mov edi, edi
     extern kernel_main
     call kernel_main
 
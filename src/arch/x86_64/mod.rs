@@ -1,18 +1,205 @@
+pub mod acpi;
 mod gdt;
 mod interrupt;
 pub mod io;
+mod multiboot2;
+mod pic;
+pub mod smp;
 
+use core::arch::asm;
 use core::cell::OnceCell;
 use core::fmt::{Arguments, Write};
-use core::ptr;
+use core::ptr::{self, addr_of_mut};
 
-use crate::drivers::serial::ns16550::NS16550;
-use crate::drivers::video::console::vga::{Writer, ScreenChar, Color};
+use crate::drivers::bus::{PortIo, RegisterBlock};
+use crate::drivers::input::keyboard::Keyboard;
+use crate::drivers::input::keymap::{self, US};
+use crate::drivers::input::ps2::Ps2Controller;
+use crate::drivers::input::scancode::ScancodeSet;
+use crate::drivers::input::KeyCode;
+use crate::drivers::pci::{self, Bar, ConfigSpace, DeviceId, PciError};
+use crate::drivers::pci::ecam::Ecam;
+use crate::drivers::pci::legacy::LegacyConfig;
+use crate::drivers::serial::ByteStream;
+use crate::drivers::serial::ns16550::{NS16550, SerialConfig};
+use crate::drivers::video::console::vga::{Color, ColorCode, Crtc, Writer, ScreenChar};
+use crate::drivers::video::framebuffer::console::FrameBufferConsole;
+use crate::drivers::video::framebuffer::psf::{Font, DEFAULT_FONT};
+use crate::drivers::video::framebuffer::{FrameBuffer, FrameBufferInfo};
+use crate::drivers::virtio::{self, Transport};
+use crate::drivers::virtio::pci::PciTransport;
+use crate::irq::{self, IrqStat};
+use crate::shell::{self, Command, CommandError};
 use crate::sync::mutex::Mutex;
+use crate::tty::LineDiscipline;
 use crate::prelude::*;
 
+use self::multiboot2::BootInfo;
+
+/// IRQ line of the first serial port
+const COM1_IRQ: u8 = 4;
+
+/// IRQ line of the PS/2 keyboard
+const KEYBOARD_IRQ: u8 = 1;
+
+/// PS/2 controller data port, the command port is 4 above it
+const PS2_BASE: u16 = 0x60;
+
+/// PS/2 controller command port, also used to pulse the reset line
+const PS2_COMMAND: u16 = 0x64;
+
+static COM1: Mutex<NS16550<PortIo>> = Mutex::new(NS16550::new(RegisterBlock::new(unsafe { PortIo::new(0x3F8) }, 1)));
+static COM1_STAT: IrqStat = IrqStat::new("com1");
+
+static PS2: Ps2Controller<PortIo> = Ps2Controller::new(RegisterBlock::new(unsafe { PortIo::new(PS2_BASE) }, 1));
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(ScancodeSet::Set2, &US));
+static KEYBOARD_STAT: IrqStat = IrqStat::new("keyboard");
+
+/// Input typed on the keyboard, echoed on the screen
+static TTY: Mutex<LineDiscipline> = Mutex::new(LineDiscipline::new());
+
+static KEYMAP_COMMAND: Command = Command {
+    name: "keymap",
+    usage: "[us|be]",
+    help: "show or change the keyboard layout",
+    run: keymap_command
+};
+
+static LSPCI_COMMAND: Command = Command {
+    name: "lspci",
+    usage: "[-v]",
+    help: "list the PCI devices",
+    run: lspci_command
+};
+
+static VIRTIO_COMMAND: Command = Command {
+    name: "virtio",
+    usage: "",
+    help: "list the VirtIO devices",
+    run: virtio_command
+};
+
+static VIRTIO_DRIVER: pci::Driver = pci::Driver {
+    name: "virtio",
+    ids: &[DeviceId::vendor(virtio::pci::VENDOR_ID)],
+    probe: virtio_probe
+};
+
+/// Most VirtIO devices [`virtio_probe`] takes
+const MAX_VIRTIO_DEVICES: usize = 8;
+
+/// The VirtIO devices bound to [`VIRTIO_DRIVER`] and their transports
+static VIRTIO_DEVICES: Mutex<[Option<(pci::Address, PciTransport)>; MAX_VIRTIO_DEVICES]> =
+    Mutex::new([const { None }; MAX_VIRTIO_DEVICES]);
+
+const VGA_LINES: usize = 25;
+const VGA_COLUMNS: usize = 80;
+
+/// Lines of VGA history kept for Shift+PgUp, fewer on a wider framebuffer console
+const SCROLLBACK_LINES: usize = 500;
+
+/// Scrollback history followed by room for a copy of the screen
+static mut SCROLLBACK: [ScreenChar; (SCROLLBACK_LINES + VGA_LINES) * VGA_COLUMNS] =
+    [ScreenChar::new(b' ', ColorCode::new(Color::White, Color::Black)); (SCROLLBACK_LINES + VGA_LINES) * VGA_COLUMNS];
+
+/// Most characters of a framebuffer console, enough for 1920x1200 with an 8x16 font
+const FRAMEBUFFER_CHARS: usize = 240 * 75;
+
+/// Memory boot.asm identity maps
+const IDENTITY_MAPPED: usize = 4 << 30;
+
+/// Text of the framebuffer console and a copy of what is drawn
+static mut FRAMEBUFFER_TEXT: [ScreenChar; 2 * FRAMEBUFFER_CHARS] =
+    [ScreenChar::new(b' ', ColorCode::new(Color::White, Color::Black)); 2 * FRAMEBUFFER_CHARS];
+
+/// Where output shows up, VGA text mode or the framebuffer a UEFI boot leaves behind
+// Only ever lives in WRITER, so the size of the variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum Screen {
+    Text(Writer<PortIo>),
+    FrameBuffer(FrameBufferConsole)
+}
+
+impl Screen {
+    fn write_byte(&mut self, byte: u8) {
+        match self {
+            Screen::Text(writer) => writer.write_byte(byte),
+            Screen::FrameBuffer(console) => console.write_byte(byte)
+        }
+    }
+
+    fn color_code(&self) -> ColorCode {
+        match self {
+            Screen::Text(writer) => writer.color_code(),
+            Screen::FrameBuffer(console) => console.color_code()
+        }
+    }
+
+    fn set_color_code(&mut self, color: ColorCode) {
+        match self {
+            Screen::Text(writer) => writer.set_color_code(color),
+            Screen::FrameBuffer(console) => console.set_color_code(color)
+        }
+    }
+
+    fn num_lines(&self) -> usize {
+        match self {
+            Screen::Text(writer) => writer.num_lines(),
+            Screen::FrameBuffer(console) => console.num_lines()
+        }
+    }
+
+    fn scroll_view_up(&mut self, lines: usize) {
+        match self {
+            Screen::Text(writer) => writer.scroll_view_up(lines),
+            Screen::FrameBuffer(console) => console.scroll_view_up(lines)
+        }
+    }
+
+    fn scroll_view_down(&mut self, lines: usize) {
+        match self {
+            Screen::Text(writer) => writer.scroll_view_down(lines),
+            Screen::FrameBuffer(console) => console.scroll_view_down(lines)
+        }
+    }
+}
+
+impl Write for Screen {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        match self {
+            Screen::Text(writer) => writer.write_str(s),
+            Screen::FrameBuffer(console) => console.write_str(s)
+        }
+    }
+}
+
 // TODO: replace once lazy type is stabilized
-static WRITER: Mutex<OnceCell<Writer>> = Mutex::new(OnceCell::new());
+static WRITER: Mutex<OnceCell<Screen>> = Mutex::new(OnceCell::new());
+
+/// How PCI configuration space is reached, ECAM if the firmware has an MCFG for it
+enum PciConfig {
+    Legacy(LegacyConfig),
+    Ecam(Ecam)
+}
+
+impl ConfigSpace for PciConfig {
+    fn read(&self, addr: pci::Address, offset: u16) -> u32 {
+        match self {
+            PciConfig::Legacy(config) => config.read(addr, offset),
+            PciConfig::Ecam(config) => config.read(addr, offset)
+        }
+    }
+
+    fn write(&self, addr: pci::Address, offset: u16, value: u32) {
+        match self {
+            PciConfig::Legacy(config) => config.write(addr, offset, value),
+            PciConfig::Ecam(config) => config.write(addr, offset, value)
+        }
+    }
+}
+
+static PCI: Mutex<OnceCell<PciConfig>> = Mutex::new(OnceCell::new());
 
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
@@ -22,34 +209,241 @@ pub fn _print(args: Arguments) {
 
 #[doc(hidden)]
 pub fn _eprint(args: Arguments) {
+    // The panic handler can run while this core holds the lock in _print
+    if crate::panicking() {
+        unsafe { WRITER.force_unlock(); }
+    }
+
     let mut lock = WRITER.lock();
     let writer = lock.get_mut().unwrap();
     let current_color = writer.color_code();
-    writer.set_fg_color(Color::Red);
+    let mut red = current_color;
+    red.set_fg_color(Color::Red);
+    writer.set_color_code(red);
     writer.write_fmt(args).unwrap();
     writer.set_color_code(current_color);
 }
 
+/// Enables interrupts just long enough to wait for one.
+pub fn wait_for_interrupt() {
+    // sti only takes effect after the next instruction, so no interrupt can sneak in before the hlt
+    unsafe { asm!("sti", "hlt", "cli", options(nomem, nostack)); }
+}
+
+/// Reads from the keyboard and COM1, writes to COM1 and the screen.
+struct Console<'a> {
+    com1: &'a mut NS16550<PortIo>
+}
+
+impl ByteStream for Console<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let byte = TTY.lock().read_byte();
+        byte.or_else(|| self.com1.read_byte())
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.com1.write_byte(byte);
+        WRITER.lock().get_mut().unwrap().write_byte(byte);
+    }
+}
+
+/// Gives the shell access to the console.
+pub fn with_console<R>(f: impl FnOnce(&mut dyn ByteStream) -> R) -> R {
+    f(&mut Console { com1: &mut COM1.lock() })
+}
+
+/// Sleeps until a byte could have arrived, the keyboard and COM1 are interrupt driven.
+pub fn wait_for_input() {
+    wait_for_interrupt();
+}
+
+pub fn reboot() -> ! {
+    // Pulse the CPU reset line through the keyboard controller
+    unsafe { io::outb(PS2_COMMAND, 0xFE) };
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Feeds everything the keyboard sent to the line discipline.
+fn handle_keyboard() {
+    while let Some(byte) = PS2.read_data() {
+        let Some(key) = KEYBOARD.lock().feed(byte) else {
+            continue;
+        };
+
+        let mut writer = WRITER.lock();
+        let writer = writer.get_mut().unwrap();
+        if key.modifiers.shift() && matches!(key.code, KeyCode::PageUp | KeyCode::PageDown) {
+            let page = writer.num_lines() / 2;
+            if key.code == KeyCode::PageUp {
+                writer.scroll_view_up(page);
+            } else {
+                writer.scroll_view_down(page);
+            }
+
+            continue;
+        }
+
+        TTY.lock().receive_key(&key, writer);
+    }
+}
+
+fn keymap_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    match args {
+        [_] => writeln!(out, "{}", KEYBOARD.lock().keymap().name)?,
+        [_, name] => {
+            let keymap = keymap::find(name).ok_or(CommandError::Failed("unknown keymap"))?;
+            KEYBOARD.lock().set_keymap(keymap);
+        },
+        _ => return Err(CommandError::Usage)
+    }
+
+    Ok(())
+}
+
+fn lspci_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let verbose = match args {
+        [_] => false,
+        [_, "-v"] => true,
+        _ => return Err(CommandError::Usage)
+    };
+
+    let pci = PCI.lock();
+    let config = pci.get().ok_or(CommandError::Failed("PCI not set up"))?;
+    pci::list(config, out, verbose)?;
+    Ok(())
+}
+
+fn virtio_command(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    for (address, transport) in VIRTIO_DEVICES.lock().iter().flatten() {
+        writeln!(out, "{} {} device, features {:#018x}", address, transport.device_type(), transport.device_features())?;
+    }
+
+    Ok(())
+}
+
+/// Takes the modern VirtIO devices whose BARs are identity mapped, and leaves
+/// them reset and acknowledged for the drivers of their type, which find the
+/// transport in [`VIRTIO_DEVICES`].
+fn virtio_probe(function: pci::Function<'_>) -> Result<(), PciError> {
+    if !PciTransport::is_virtio(&function) {
+        return Err(PciError::Unsupported);
+    }
+
+    let unmapped = function.bars().any(|(_, bar)| {
+        matches!(bar, Bar::Memory { addr, size, .. } if addr + size > IDENTITY_MAPPED as u64)
+    });
+    if unmapped {
+        return Err(PciError::ProbeFailed("BAR above the identity map"));
+    }
+
+    let mut devices = VIRTIO_DEVICES.lock();
+    let slot = devices.iter_mut().find(|slot| slot.is_none()).ok_or(PciError::ProbeFailed("too many VirtIO devices"))?;
+    let transport = unsafe { PciTransport::new(&function) }.map_err(|_| PciError::ProbeFailed("no modern VirtIO interface"))?;
+    transport.set_status(0);
+    transport.set_status(virtio::STATUS_ACKNOWLEDGE);
+    *slot = Some((function.address(), transport));
+    Ok(())
+}
+
+/// Picks how to reach PCI configuration space and binds the drivers.
+fn init_pci() {
+    let ecam = acpi::Rsdp::find()
+        .and_then(|rsdp| rsdp.find_table(b"MCFG"))
+        .and_then(|mcfg| acpi::mcfg_entries(mcfg).find(|e| e.segment == 0 && e.start_bus == 0))
+        .filter(|e| e.base as usize + Ecam::size(e.start_bus, e.end_bus) <= IDENTITY_MAPPED);
+
+    let config = match ecam {
+        Some(e) => {
+            println!("PCI ECAM at {:#x}, buses {}-{}", e.base, e.start_bus, e.end_bus);
+            PciConfig::Ecam(unsafe { Ecam::new(e.base as usize, e.start_bus, e.end_bus) })
+        },
+        None => PciConfig::Legacy(unsafe { LegacyConfig::new() })
+    };
+
+    pci::register_driver(&VIRTIO_DRIVER).unwrap();
+    let pci = PCI.lock();
+    let config = pci.get_or_init(|| config);
+    let mut functions = 0;
+    pci::enumerate(config, |_| functions += 1);
+    let bound = pci::probe(config);
+    println!("PCI: {} functions, {} bound to drivers", functions, bound);
+}
+
+/// Sets up a console on the framebuffer the boot loader left, unless it's in text mode.
+fn framebuffer_console(multiboot_info: usize) -> Option<FrameBufferConsole> {
+    let boot_info = unsafe { BootInfo::from_ptr(multiboot_info) };
+    let info = FrameBufferInfo::from_multiboot2(boot_info.find(multiboot2::TAG_FRAMEBUFFER)?)?;
+    if info.addr + info.size() > IDENTITY_MAPPED {
+        return None;
+    }
+
+    let font = Font::parse(DEFAULT_FONT).ok()?;
+    FrameBufferConsole::new(unsafe { FrameBuffer::new(info) }, font, unsafe { &mut *addr_of_mut!(FRAMEBUFFER_TEXT) })
+}
+
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
+pub extern "C" fn kernel_main(multiboot_info: usize) -> ! {
+    crate::smp::cpu_online(0, smp::lapic_id());
 
-    let mut writer = Writer::new(
-        unsafe { &mut *ptr::slice_from_raw_parts_mut(0xb8000 as *mut ScreenChar, 80 * 25) },
-        25,
-        80);
-    writer.clear(); // Clear screen
+    let scrollback = unsafe { &mut *addr_of_mut!(SCROLLBACK) };
+    let screen = match framebuffer_console(multiboot_info) {
+        Some(mut console) => {
+            console.set_scrollback(scrollback);
+            Screen::FrameBuffer(console)
+        },
+        None => {
+            let crtc = Crtc::new(RegisterBlock::new(unsafe { PortIo::new(0x3D4) }, 1));
+            crtc.enable_cursor(14, 15);
+            let mut writer = Writer::new(
+                unsafe { &mut *ptr::slice_from_raw_parts_mut(0xb8000 as *mut ScreenChar, VGA_COLUMNS * VGA_LINES) },
+                VGA_LINES,
+                VGA_COLUMNS,
+                Some(crtc));
+            writer.set_scrollback(scrollback);
+            writer.clear(); // Clear screen
+            Screen::Text(writer)
+        }
+    };
 
-    WRITER.lock().set(writer.into()).unwrap();
+    WRITER.lock().set(screen).unwrap();
 
     println!("Hello World!");
 
+    pic::init();
     interrupt::init_idt();
 
     println!("Interrupts set up");
 
-    let mut w = NS16550::new(0x3F8);
-    unsafe { w.init(1843200, 115200); }
-    w.write_str("Hello COM1!\n").unwrap();
+    crate::smp::init();
+
+    {
+        let mut com1 = COM1.lock();
+        com1.init(1843200, SerialConfig::default()).unwrap();
+        com1.enable_interrupts();
+        com1.write_str("Hello COM1!\n").unwrap();
+    }
+    irq::register(&COM1_STAT);
+    pic::unmask(COM1_IRQ);
+
+    init_pci();
+
+    match PS2.init(ScancodeSet::Set2) {
+        Ok(()) => {
+            // The shell does its own line editing and echoing
+            let mut tty = TTY.lock();
+            tty.set_canonical(false);
+            tty.set_echo(false);
+            irq::register(&KEYBOARD_STAT);
+            pic::unmask(KEYBOARD_IRQ);
+        },
+        Err(e) => eprintln!("No PS/2 keyboard: {}", e)
+    }
 
-    loop {}
+    shell::builtins::register_builtins();
+    shell::register(&KEYMAP_COMMAND).unwrap();
+    shell::register(&LSPCI_COMMAND).unwrap();
+    shell::register(&VIRTIO_COMMAND).unwrap();
+    shell::run()
 }
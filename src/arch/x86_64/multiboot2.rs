@@ -0,0 +1,44 @@
+//! Boot information handed over by a multiboot2 boot loader.
+
+use core::slice;
+
+/// Framebuffer info tag
+pub const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_END: u32 = 0;
+
+/// Size of the boot information header and of a tag header
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    data: &'static [u8]
+}
+
+impl BootInfo {
+    /// # Safety
+    ///
+    /// `addr` has to point to the boot information, which has to stay valid and unmodified.
+    pub unsafe fn from_ptr(addr: usize) -> Self {
+        let total_size = (addr as *const u32).read();
+        Self { data: slice::from_raw_parts(addr as *const u8, total_size as usize) }
+    }
+
+    /// Returns the first tag of type `tag_type`, header included.
+    pub fn find(&self, tag_type: u32) -> Option<&'static [u8]> {
+        let u32_at = |offset: usize| self.data.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+        let mut offset = HEADER_SIZE;
+        loop {
+            let (ty, size) = (u32_at(offset)?, u32_at(offset + 4)? as usize);
+            if ty == TAG_END || size < HEADER_SIZE {
+                return None;
+            }
+
+            if ty == tag_type {
+                return self.data.get(offset..offset + size);
+            }
+
+            // Tags are 8 byte aligned
+            offset += size.next_multiple_of(8);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Legacy 8259 programmable interrupt controllers.
+
+use super::io::{inb, outb};
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+/// Initialization, ICW4 needed
+const ICW1_INIT: u8 = 0x11;
+/// 8086 mode
+const ICW4_8086: u8 = 0x01;
+
+/// Vector of IRQ 0, the first 32 vectors are used by exceptions.
+pub const PIC1_OFFSET: u8 = 0x20;
+/// Vector of IRQ 8
+pub const PIC2_OFFSET: u8 = 0x28;
+
+/// Moves the IRQs out of the way of the exceptions and masks all of them.
+pub fn init() {
+    unsafe {
+        outb(PIC1_COMMAND, ICW1_INIT);
+        outb(PIC2_COMMAND, ICW1_INIT);
+        outb(PIC1_DATA, PIC1_OFFSET);
+        outb(PIC2_DATA, PIC2_OFFSET);
+        // PIC2 is cascaded on IRQ 2
+        outb(PIC1_DATA, 1 << 2);
+        outb(PIC2_DATA, 2);
+        outb(PIC1_DATA, ICW4_8086);
+        outb(PIC2_DATA, ICW4_8086);
+
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+/// End of interrupt command
+const EOI: u8 = 0x20;
+
+fn data_port(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    }
+}
+
+pub fn unmask(irq: u8) {
+    let (port, bit) = data_port(irq);
+    unsafe { outb(port, inb(port) & !(1 << bit)); }
+}
+
+pub fn mask(irq: u8) {
+    let (port, bit) = data_port(irq);
+    unsafe { outb(port, inb(port) | 1 << bit); }
+}
+
+/// Signals the end of the handler of `irq`, both controllers need it for IRQs on PIC2.
+pub fn eoi(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(PIC2_COMMAND, EOI);
+        }
+
+        outb(PIC1_COMMAND, EOI);
+    }
+}
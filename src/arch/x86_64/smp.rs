@@ -0,0 +1,162 @@
+use core::arch::asm;
+use core::ptr::{self, addr_of, addr_of_mut};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::smp::{self, CpuData, CPUS, CPU_DATA_SIZE, CPU_STACKS, MAX_CPUS, STACK_SIZE};
+
+use super::interrupt;
+use super::io::outb;
+
+const LAPIC_BASE: usize = 0xFEE00000;
+
+/// Local APIC ID Register
+const LAPIC_ID: usize = 0x20;
+
+/// EOI Register
+const LAPIC_EOI: usize = 0xB0;
+
+/// Spurious Interrupt Vector Register
+const LAPIC_SVR: usize = 0xF0;
+
+/// Interrupt Command Register, low and high half
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+const SPURIOUS_VECTOR: u32 = 0xFF;
+
+const ICR_DELIVERY_FIXED: u32 = 0b000 << 8;
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_STATUS: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Vector used for inter-processor interrupts
+pub const IPI_VECTOR: u8 = 0xF0;
+
+/// Where the trampoline is copied to, must match TRAMPOLINE_BASE in ap_trampoline.asm
+const TRAMPOLINE_BASE: usize = 0x8000;
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Layout must match ap_trampoline_data in ap_trampoline.asm
+#[repr(C)]
+struct TrampolineData {
+    cr3: u64,
+    entry: u64,
+    stacks: u64,
+    stack_size: u64,
+    cpus: u64,
+    cpu_data_size: u64,
+    next_cpu: u32,
+    max_cpus: u32
+}
+
+pub fn lapic_read(reg: usize) -> u32 {
+    unsafe { ((LAPIC_BASE + reg) as *const u32).read_volatile() }
+}
+
+pub fn lapic_write(reg: usize, value: u32) {
+    unsafe { ((LAPIC_BASE + reg) as *mut u32).write_volatile(value) }
+}
+
+pub fn lapic_id() -> usize {
+    (lapic_read(LAPIC_ID) >> 24) as usize
+}
+
+/// Returns the per-CPU data GS points at, long_mode_init.asm and the trampoline set the GS base.
+#[inline]
+pub fn cpu_data() -> *const CpuData {
+    let ptr: *const CpuData;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags)) };
+    ptr
+}
+
+pub fn lapic_eoi() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Sends an inter-processor interrupt to the CPU with local APIC ID `apic_id`.
+pub fn send_ipi(apic_id: usize) {
+    send_ipi_raw((apic_id as u32) << 24, ICR_DELIVERY_FIXED | IPI_VECTOR as u32);
+}
+
+/// The interrupt handler sends the EOI, an IPI handled by polling stays pending in the
+/// local APIC and finds nothing to do once it's taken.
+pub fn ack_ipi() {}
+
+pub fn flush_tlb(addr: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) addr, options(nostack, preserves_flags)) };
+}
+
+pub fn flush_tlb_all() {
+    unsafe {
+        asm!(
+            "mov {tmp}, cr3",
+            "mov cr3, {tmp}",
+            tmp = out(reg) _,
+            options(nostack, preserves_flags));
+    }
+}
+
+/// Busy waits for roughly `us` microseconds, every write to port 0x80 takes about 1us.
+fn udelay(us: usize) {
+    for _ in 0..us {
+        unsafe { outb(0x80, 0) };
+    }
+}
+
+fn send_ipi_raw(icr_high: u32, icr_low: u32) {
+    lapic_write(LAPIC_ICR_HIGH, icr_high);
+    lapic_write(LAPIC_ICR_LOW, icr_low);
+    while lapic_read(LAPIC_ICR_LOW) & ICR_DELIVERY_STATUS != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Starts the application processors with the INIT-SIPI-SIPI sequence.
+pub fn start_secondary_cpus() {
+    lapic_write(LAPIC_SVR, lapic_read(LAPIC_SVR) | SVR_APIC_ENABLE | SPURIOUS_VECTOR);
+
+    unsafe {
+        let start = addr_of!(ap_trampoline_start);
+        let len = addr_of!(ap_trampoline_end) as usize - start as usize;
+        ptr::copy_nonoverlapping(start, TRAMPOLINE_BASE as *mut u8, len);
+
+        let cr3: u64;
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+
+        let data = (TRAMPOLINE_BASE + (addr_of!(ap_trampoline_data) as usize - start as usize)) as *mut TrampolineData;
+        data.write_volatile(TrampolineData {
+            cr3,
+            entry: ap_main as *const () as u64,
+            stacks: addr_of_mut!(CPU_STACKS) as u64,
+            stack_size: STACK_SIZE as u64,
+            cpus: addr_of!(CPUS) as u64,
+            cpu_data_size: CPU_DATA_SIZE as u64,
+            next_cpu: 1,
+            max_cpus: MAX_CPUS as u32
+        });
+    }
+
+    fence(Ordering::SeqCst);
+
+    let vector = (TRAMPOLINE_BASE >> 12) as u32;
+    send_ipi_raw(0, ICR_ALL_EXCLUDING_SELF | ICR_LEVEL_ASSERT | ICR_DELIVERY_INIT);
+    udelay(10_000);
+    send_ipi_raw(0, ICR_ALL_EXCLUDING_SELF | ICR_DELIVERY_STARTUP | vector);
+    udelay(200);
+    send_ipi_raw(0, ICR_ALL_EXCLUDING_SELF | ICR_DELIVERY_STARTUP | vector);
+}
+
+extern "C" fn ap_main(id: usize) -> ! {
+    lapic_write(LAPIC_SVR, lapic_read(LAPIC_SVR) | SVR_APIC_ENABLE | SPURIOUS_VECTOR);
+    smp::cpu_online(id, lapic_id());
+    interrupt::load_idt();
+    smp::secondary_main(id)
+}
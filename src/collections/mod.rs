@@ -0,0 +1,3 @@
+pub mod ring_buffer;
+
+pub use self::ring_buffer::RingBuffer;
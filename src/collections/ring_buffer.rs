@@ -0,0 +1,179 @@
+use core::mem::MaybeUninit;
+
+/// Fixed capacity FIFO queue.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    head: usize,
+    len: usize
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            len: 0
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends `value` at the back, hands it back if the buffer is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+
+        self.buf[(self.head + self.len) % N].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends `value` at the back, dropping the oldest element if the buffer is full.
+    ///
+    /// Returns the dropped element.
+    pub fn push_overwrite(&mut self, value: T) -> Option<T> {
+        let dropped = if self.is_full() { self.pop() } else { None };
+        // Can't fail after making room
+        let _ = self.push(value);
+        dropped
+    }
+
+    /// Removes the oldest element.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // SAFETY: all elements between head and head + len are initialized
+        let value = unsafe { self.buf[self.head].assume_init() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Removes the newest element.
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        // SAFETY: all elements between head and head + len are initialized
+        Some(unsafe { self.buf[(self.head + self.len) % N].assume_init() })
+    }
+
+    /// Returns the element at `index`, counting from the oldest one.
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index >= self.len {
+            return None;
+        }
+
+        // SAFETY: all elements between head and head + len are initialized
+        Some(unsafe { self.buf[(self.head + index) % N].assume_init() })
+    }
+
+    /// Returns a mutable reference to the element at `index`, counting from the oldest one.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+
+        // SAFETY: all elements between head and head + len are initialized
+        Some(unsafe { self.buf[(self.head + index) % N].assume_init_mut() })
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Iterates from the oldest to the newest element.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = T> + '_ {
+        (0..self.len).map(move |i| unsafe { self.buf[(self.head + i) % N].assume_init() })
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_pop() {
+        let mut buf = RingBuffer::<u8, 4>::new();
+        assert!(buf.is_empty());
+        buf.push(1).unwrap();
+        buf.push(2).unwrap();
+        assert_eq!(buf.len(), 2);
+        assert_eq!(buf.pop(), Some(1));
+        assert_eq!(buf.pop(), Some(2));
+        assert_eq!(buf.pop(), None);
+    }
+
+    #[test]
+    fn full() {
+        let mut buf = RingBuffer::<u8, 2>::new();
+        buf.push(1).unwrap();
+        buf.push(2).unwrap();
+        assert!(buf.is_full());
+        assert_eq!(buf.push(3), Err(3));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut buf = RingBuffer::<u8, 3>::new();
+        for i in 0..10 {
+            buf.push(i).unwrap();
+            buf.push(i + 1).unwrap();
+            assert_eq!(buf.pop(), Some(i));
+            assert_eq!(buf.pop(), Some(i + 1));
+        }
+    }
+
+    #[test]
+    fn overwrite() {
+        let mut buf = RingBuffer::<u8, 3>::new();
+        for i in 0..3 {
+            assert_eq!(buf.push_overwrite(i), None);
+        }
+
+        assert_eq!(buf.push_overwrite(3), Some(0));
+        assert!(buf.iter().eq([1, 2, 3]));
+    }
+
+    #[test]
+    fn pop_back_and_get() {
+        let mut buf = RingBuffer::<u8, 3>::new();
+        buf.push(1).unwrap();
+        buf.push(2).unwrap();
+        buf.push(3).unwrap();
+        assert_eq!(buf.get(0), Some(1));
+        assert_eq!(buf.get(2), Some(3));
+        assert_eq!(buf.get(3), None);
+        assert_eq!(buf.pop_back(), Some(3));
+        assert_eq!(buf.get(2), None);
+        *buf.get_mut(1).unwrap() = 5;
+        assert_eq!(buf.pop(), Some(1));
+        assert_eq!(buf.pop(), Some(5));
+    }
+}
@@ -0,0 +1,131 @@
+//! Fake bus for host tests, records every access and can script register contents.
+
+extern crate std;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::vec::Vec;
+
+use super::Bus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read(usize, u32),
+    Write(usize, u32)
+}
+
+#[derive(Default)]
+struct State {
+    values: BTreeMap<usize, u32>,
+    scripted: BTreeMap<usize, VecDeque<u32>>,
+    read_bits: BTreeMap<usize, u32>,
+    write_clears: BTreeMap<usize, u32>,
+    log: Vec<Access>
+}
+
+/// Registers backed by plain memory, reads return the last value written unless scripted otherwise.
+#[derive(Default)]
+pub struct MockBus {
+    state: RefCell<State>
+}
+
+impl MockBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the contents of a register without logging an access.
+    pub fn set(&self, offset: usize, value: u32) {
+        self.state.borrow_mut().values.insert(offset, value);
+    }
+
+    /// Returns the contents of a register without logging an access.
+    pub fn get(&self, offset: usize) -> u32 {
+        self.state.borrow().values.get(&offset).copied().unwrap_or(0)
+    }
+
+    /// Makes the next reads of a register return `values`, in order.
+    pub fn script(&self, offset: usize, values: impl IntoIterator<Item = u32>) {
+        self.state.borrow_mut().scripted.entry(offset).or_default().extend(values);
+    }
+
+    /// Makes all reads of a register that aren't scripted have `bits` set, like status bits set by hardware.
+    pub fn set_read_bits(&self, offset: usize, bits: u32) {
+        self.state.borrow_mut().read_bits.insert(offset, bits);
+    }
+
+    /// Makes writing 1 to `bits` of a register clear them instead of setting them, like status bits.
+    pub fn set_write_clears(&self, offset: usize, bits: u32) {
+        self.state.borrow_mut().write_clears.insert(offset, bits);
+    }
+
+    pub fn log(&self) -> Vec<Access> {
+        self.state.borrow().log.clone()
+    }
+
+    pub fn clear_log(&self) {
+        self.state.borrow_mut().log.clear();
+    }
+
+    /// Returns the values written to a register, oldest first.
+    pub fn writes(&self, offset: usize) -> Vec<u32> {
+        self.state.borrow().log.iter()
+            .filter_map(|a| match *a {
+                Access::Write(o, v) if o == offset => Some(v),
+                _ => None
+            })
+            .collect()
+    }
+}
+
+impl Bus for MockBus {
+    fn read(&self, offset: usize) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let value = match state.scripted.get_mut(&offset).and_then(|s| s.pop_front()) {
+            Some(value) => value,
+            None => {
+                let bits = state.read_bits.get(&offset).copied().unwrap_or(0);
+                state.values.get(&offset).copied().unwrap_or(0) | bits
+            }
+        };
+
+        state.log.push(Access::Read(offset, value));
+        value
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        let mut state = self.state.borrow_mut();
+        let clears = state.write_clears.get(&offset).copied().unwrap_or(0);
+        let old = state.values.get(&offset).copied().unwrap_or(0);
+        state.values.insert(offset, old & clears & !value | value & !clears);
+        state.log.push(Access::Write(offset, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_reads() {
+        let bus = MockBus::new();
+        bus.write(4, 1);
+        bus.script(4, [7, 8]);
+        bus.set_read_bits(4, 0x10);
+        assert_eq!(bus.read(4), 7);
+        assert_eq!(bus.read(4), 8);
+        assert_eq!(bus.read(4), 0x11);
+        assert_eq!(bus.log(), [Access::Write(4, 1), Access::Read(4, 7), Access::Read(4, 8), Access::Read(4, 0x11)]);
+        assert_eq!(bus.writes(4), [1]);
+    }
+
+    #[test]
+    fn write_clears() {
+        let bus = MockBus::new();
+        bus.set(8, 0x13);
+        bus.set_write_clears(8, 0x0F);
+        bus.write(8, 0x21);
+        assert_eq!(bus.get(8), 0x22);
+        assert_eq!(bus.writes(8), [0x21]);
+    }
+}
@@ -0,0 +1,210 @@
+//! Access to device registers independent of how the device is attached.
+//!
+//! A [`Bus`] reads and writes raw registers at byte offsets, a [`RegisterBlock`]
+//! puts typed [`Register`]s on top of it, so a driver only deals with register
+//! indices and doesn't care whether the device sits behind port I/O or MMIO.
+
+#[cfg(test)]
+pub mod mock;
+
+use core::marker::PhantomData;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::io::{inb, outb};
+
+/// Raw register access at byte offsets from the base of a device.
+pub trait Bus {
+    /// Reads the register at `offset`, narrower buses zero extend the value.
+    fn read(&self, offset: usize) -> u32;
+
+    /// Writes the register at `offset`, narrower buses truncate the value.
+    fn write(&self, offset: usize, value: u32);
+}
+
+impl<B: Bus> Bus for &B {
+    fn read(&self, offset: usize) -> u32 {
+        (**self).read(offset)
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        (**self).write(offset, value)
+    }
+}
+
+/// 8 bit wide I/O ports.
+#[cfg(target_arch = "x86_64")]
+pub struct PortIo {
+    base: u16
+}
+
+#[cfg(target_arch = "x86_64")]
+impl PortIo {
+    /// # Safety
+    ///
+    /// The ports starting at `base` have to belong to the device.
+    pub const unsafe fn new(base: u16) -> Self {
+        Self { base }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Bus for PortIo {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { inb(self.base + offset as u16) as u32 }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { outb(self.base + offset as u16, value as u8) }
+    }
+}
+
+/// 8 bit wide memory mapped registers.
+pub struct Mmio8 {
+    base: usize
+}
+
+impl Mmio8 {
+    /// # Safety
+    ///
+    /// `base` has to be the mapped address of the device.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+}
+
+impl Bus for Mmio8 {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u8).read_volatile() as u32 }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u8).write_volatile(value as u8) }
+    }
+}
+
+/// 32 bit wide memory mapped registers.
+pub struct Mmio32 {
+    base: usize
+}
+
+impl Mmio32 {
+    /// # Safety
+    ///
+    /// `base` has to be the mapped address of the device.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+}
+
+impl Bus for Mmio32 {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+}
+
+pub struct ReadOnly;
+pub struct WriteOnly;
+pub struct ReadWrite;
+
+pub trait Readable {}
+pub trait Writable {}
+
+impl Readable for ReadOnly {}
+impl Readable for ReadWrite {}
+impl Writable for WriteOnly {}
+impl Writable for ReadWrite {}
+
+/// A register of a [`RegisterBlock`], `A` restricts whether it can be read, written or both.
+pub struct Register<A> {
+    index: usize,
+    _access: PhantomData<A>
+}
+
+impl<A> Register<A> {
+    pub const fn new(index: usize) -> Self {
+        Self {
+            index,
+            _access: PhantomData
+        }
+    }
+
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<A> Clone for Register<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A> Copy for Register<A> {}
+
+/// The registers of a device on a [`Bus`], register `n` is at byte offset `n * stride`.
+pub struct RegisterBlock<B: Bus> {
+    bus: B,
+    stride: usize
+}
+
+impl<B: Bus> RegisterBlock<B> {
+    pub const fn new(bus: B, stride: usize) -> Self {
+        Self { bus, stride }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn read<A: Readable>(&self, reg: Register<A>) -> u32 {
+        self.bus.read(reg.index * self.stride)
+    }
+
+    pub fn write<A: Writable>(&self, reg: Register<A>, value: u32) {
+        self.bus.write(reg.index * self.stride, value)
+    }
+
+    /// Read-modify-write of `reg`.
+    pub fn modify(&self, reg: Register<ReadWrite>, f: impl FnOnce(u32) -> u32) {
+        self.write(reg, f(self.read(reg)))
+    }
+
+    pub fn set_bits(&self, reg: Register<ReadWrite>, bits: u32) {
+        self.modify(reg, |v| v | bits)
+    }
+
+    pub fn clear_bits(&self, reg: Register<ReadWrite>, bits: u32) {
+        self.modify(reg, |v| v & !bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REG: Register<ReadWrite> = Register::new(2);
+
+    #[test]
+    fn stride() {
+        let mut mem = [0u32; 8];
+        let regs = RegisterBlock::new(unsafe { Mmio32::new(mem.as_mut_ptr() as usize) }, 8);
+        regs.write(REG, 0x1234);
+        assert_eq!(mem[4], 0x1234);
+        regs.set_bits(REG, 1);
+        regs.clear_bits(REG, 0x1000);
+        assert_eq!(regs.read(REG), 0x0235);
+    }
+
+    #[test]
+    fn narrow_bus_truncates() {
+        let mut mem = [0u8; 4];
+        let regs = RegisterBlock::new(unsafe { Mmio8::new(mem.as_mut_ptr() as usize) }, 1);
+        regs.write(REG, 0x1FF);
+        assert_eq!(mem, [0, 0, 0xFF, 0]);
+        assert_eq!(regs.read(REG), 0xFF);
+    }
+}
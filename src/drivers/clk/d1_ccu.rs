@@ -0,0 +1,744 @@
+//! Clock control unit of the Allwinner D1.
+//!
+//! The clock tree is modelled as fixed oscillators, PLLs, composite clocks
+//! (a parent mux followed by dividers and a gate) and bus clock gates, which
+//! come with a reset line each. Only the parts the kernel drives are in it,
+//! the audio and video PLLs are turned on but their rates aren't known.
+
+use core::{fmt, hint};
+
+use crate::drivers::bus::{Bus, ReadWrite, Register, RegisterBlock};
+
+pub const D1_CCU_BASE: usize = 0x02001000; //D1 CCU
+const CCU_PLL_CPU_CTRL_REG: Register<ReadWrite> = Register::new(0x000);
+const CCU_PLL_DDR_CTRL_REG: Register<ReadWrite> = Register::new(0x010);
+const CCU_PLL_PERI0_CTRL_REG: Register<ReadWrite> = Register::new(0x020);
+const CCU_PLL_VIDEO0_CTRL_REG: Register<ReadWrite> = Register::new(0x040);
+const CCU_PLL_VIDEO1_CTRL_REG: Register<ReadWrite> = Register::new(0x048);
+const CCU_PLL_VE_CTRL: Register<ReadWrite> = Register::new(0x058);
+const CCU_PLL_AUDIO0_CTRL_REG: Register<ReadWrite> = Register::new(0x078);
+const CCU_PLL_AUDIO1_CTRL_REG: Register<ReadWrite> = Register::new(0x080);
+const CCU_PSI_CLK_REG: Register<ReadWrite> = Register::new(0x510);
+const CCU_APB0_CLK_REG: Register<ReadWrite> = Register::new(0x520);
+const CCU_APB1_CLK_REG: Register<ReadWrite> = Register::new(0x524);
+const CCU_MBUS_CLK_REG: Register<ReadWrite> = Register::new(0x540);
+const CCU_DMA_BGR_REG: Register<ReadWrite> = Register::new(0x70c);
+const CCU_DRAM_CLK_REG: Register<ReadWrite> = Register::new(0x800);
+const CCU_DRAM_BGR_REG: Register<ReadWrite> = Register::new(0x80c);
+const CCU_UART_BGR_REG: Register<ReadWrite> = Register::new(0x90C);
+const CCU_TWI_BGR_REG: Register<ReadWrite> = Register::new(0x91C);
+const CCU_SPI0_CLK_REG: Register<ReadWrite> = Register::new(0x940);
+const CCU_SPI1_CLK_REG: Register<ReadWrite> = Register::new(0x944);
+const CCU_SPI_BGR_REG: Register<ReadWrite> = Register::new(0x96C);
+const CCU_RISCV_CLK_REG: Register<ReadWrite> = Register::new(0xd00);
+
+const PLL_ENABLE: u32 = 1 << 31;
+const PLL_LDO_ENABLE: u32 = 1 << 30;
+const PLL_LOCK_ENABLE: u32 = 1 << 29;
+const PLL_LOCK: u32 = 1 << 28;
+const PLL_OUTPUT_GATE: u32 = 1 << 27;
+
+/// Factors of the PLLs, output divider P, multiplier N and input divider M
+const PLL_P_MASK: u32 = 0x3 << 16;
+const PLL_N_SHIFT: u32 = 8;
+const PLL_N_MASK: u32 = 0xFF << PLL_N_SHIFT;
+const PLL_M_MASK: u32 = 0x3;
+
+/// Gate of the composite clocks that have one
+const CLK_GATE: u32 = 1 << 31;
+
+/// The new factors of the DRAM clock only take effect once this is written
+const DRAM_CLK_UPDATE: u32 = 1 << 27;
+
+/// Bus clock gate registers have the reset of gate bit n in bit 16 + n
+const BGR_RESET_SHIFT: u32 = 16;
+
+/// Mbus reset, released when set
+const MBUS_RESET: u32 = 1 << 30;
+
+/// Polls of the lock bit before giving up on a PLL
+const PLL_LOCK_TIMEOUT: usize = 1_000_000;
+
+/// Rate of the crystal oscillator
+pub const HOSC_RATE: u64 = 24_000_000;
+const RTC32K_RATE: u64 = 32_768;
+const RC16M_RATE: u64 = 16_000_000;
+
+const NUM_UARTS: u8 = 6;
+const NUM_TWIS: u8 = 4;
+const NUM_SPIS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// 24 MHz crystal
+    Hosc,
+    Rtc32k,
+    Rc16m,
+    PllCpu,
+    PllDdr,
+    /// Twice the rate of [`Clock::PllPeri0x1`], 1.2 GHz normally
+    PllPeri0x2,
+    PllPeri0x1,
+    /// The output divided by P1, 800 MHz normally
+    PllPeri0P1,
+    /// CPU core clock
+    Riscv,
+    /// AHB
+    Psi,
+    Apb0,
+    Apb1,
+    Dram,
+    Spi(u8),
+    /// Bus clock gates
+    Uart(u8),
+    Twi(u8),
+    SpiBus(u8),
+    Dma,
+    DramBus
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reset {
+    Uart(u8),
+    Twi(u8),
+    Spi(u8),
+    Dma,
+    Dram,
+    Mbus
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockError {
+    /// The controller number is out of range
+    NoSuchClock,
+    /// The operation isn't possible on the clock, or its parent isn't modelled
+    Unsupported,
+    /// No parent and divider gets at or below the rate
+    RateUnreachable,
+    /// A PLL didn't lock
+    Timeout
+}
+
+impl fmt::Display for ClockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchClock => f.write_str("no such clock"),
+            Self::Unsupported => f.write_str("not supported by the clock"),
+            Self::RateUnreachable => f.write_str("rate can't be reached"),
+            Self::Timeout => f.write_str("PLL didn't lock")
+        }
+    }
+}
+
+/// A clock with a parent mux at bit 24, an optional power of two divider N
+/// in bits 9:8 and a divider M + 1 in the lowest bits.
+struct Composite {
+    reg: Register<ReadWrite>,
+    /// By mux value, `None` for parents that aren't modelled
+    parents: &'static [Option<Clock>],
+    mux_bits: u32,
+    has_n: bool,
+    m_bits: u32,
+    gate: bool,
+    update: u32
+}
+
+const MUX_SHIFT: u32 = 24;
+const N_SHIFT: u32 = 8;
+const M_SHIFT: u32 = 0;
+
+/// Values of the N field, which divides by a power of 2
+const N_DIV_1: u32 = 0;
+const N_DIV_2: u32 = 1;
+
+/// Values of the mux field of the RISC-V clock
+const RISCV_SRC_HOSC: u32 = 0;
+const RISCV_SRC_PLL_CPU: u32 = 5;
+
+/// Values of the mux field of the PSI and APB clocks
+const PSI_SRC_HOSC: u32 = 0;
+const PSI_SRC_PLL_PERI0: u32 = 3;
+const APB_SRC_PLL_PERI0: u32 = 3;
+
+impl Composite {
+    const fn mask(&self) -> u32 {
+        let n = if self.has_n { 0x3 << N_SHIFT } else { 0 };
+        ((1 << self.mux_bits) - 1) << MUX_SHIFT | n | ((1 << self.m_bits) - 1)
+    }
+
+    const fn max_m(&self) -> u64 {
+        1 << self.m_bits
+    }
+}
+
+const RISCV: Composite = Composite {
+    reg: CCU_RISCV_CLK_REG,
+    parents: &[Some(Clock::Hosc), Some(Clock::Rtc32k), Some(Clock::Rc16m), Some(Clock::PllPeri0P1),
+        Some(Clock::PllPeri0x1), Some(Clock::PllCpu), None],
+    mux_bits: 3,
+    has_n: false,
+    m_bits: 5,
+    gate: false,
+    update: 0
+};
+
+const PSI: Composite = Composite {
+    reg: CCU_PSI_CLK_REG,
+    parents: &[Some(Clock::Hosc), Some(Clock::Rtc32k), Some(Clock::Rc16m), Some(Clock::PllPeri0x1)],
+    mux_bits: 2,
+    has_n: true,
+    m_bits: 2,
+    gate: false,
+    update: 0
+};
+
+const APB_PARENTS: &[Option<Clock>] = &[Some(Clock::Hosc), Some(Clock::Rtc32k), Some(Clock::Psi), Some(Clock::PllPeri0x1)];
+
+const APB0: Composite = Composite {
+    reg: CCU_APB0_CLK_REG,
+    parents: APB_PARENTS,
+    mux_bits: 2,
+    has_n: true,
+    m_bits: 5,
+    gate: false,
+    update: 0
+};
+
+const APB1: Composite = Composite { reg: CCU_APB1_CLK_REG, ..APB0 };
+
+const DRAM: Composite = Composite {
+    reg: CCU_DRAM_CLK_REG,
+    parents: &[Some(Clock::PllDdr), None, Some(Clock::PllPeri0x2), Some(Clock::PllPeri0P1)],
+    mux_bits: 3,
+    has_n: true,
+    m_bits: 2,
+    gate: true,
+    update: DRAM_CLK_UPDATE
+};
+
+const SPI0: Composite = Composite {
+    reg: CCU_SPI0_CLK_REG,
+    parents: &[Some(Clock::Hosc), Some(Clock::PllPeri0x1), Some(Clock::PllPeri0x2), None, None],
+    mux_bits: 3,
+    has_n: true,
+    m_bits: 4,
+    gate: true,
+    update: 0
+};
+
+const SPI1: Composite = Composite { reg: CCU_SPI1_CLK_REG, ..SPI0 };
+
+/// What kind of clock a [`Clock`] is.
+enum Kind {
+    Fixed(u64),
+    Pll(Register<ReadWrite>),
+    Composite(&'static Composite),
+    /// Register, bit and parent of a bus clock gate
+    Gate(Register<ReadWrite>, u32, Clock)
+}
+
+impl Clock {
+    fn kind(self) -> Result<Kind, ClockError> {
+        let check = |n: u8, count: u8| if n < count { Ok(n as u32) } else { Err(ClockError::NoSuchClock) };
+        Ok(match self {
+            Clock::Hosc => Kind::Fixed(HOSC_RATE),
+            Clock::Rtc32k => Kind::Fixed(RTC32K_RATE),
+            Clock::Rc16m => Kind::Fixed(RC16M_RATE),
+            Clock::PllCpu => Kind::Pll(CCU_PLL_CPU_CTRL_REG),
+            Clock::PllDdr => Kind::Pll(CCU_PLL_DDR_CTRL_REG),
+            Clock::PllPeri0x2 | Clock::PllPeri0x1 | Clock::PllPeri0P1 => Kind::Pll(CCU_PLL_PERI0_CTRL_REG),
+            Clock::Riscv => Kind::Composite(&RISCV),
+            Clock::Psi => Kind::Composite(&PSI),
+            Clock::Apb0 => Kind::Composite(&APB0),
+            Clock::Apb1 => Kind::Composite(&APB1),
+            Clock::Dram => Kind::Composite(&DRAM),
+            Clock::Spi(n) => Kind::Composite(if check(n, NUM_SPIS)? == 0 { &SPI0 } else { &SPI1 }),
+            Clock::Uart(n) => Kind::Gate(CCU_UART_BGR_REG, check(n, NUM_UARTS)?, Clock::Apb1),
+            Clock::Twi(n) => Kind::Gate(CCU_TWI_BGR_REG, check(n, NUM_TWIS)?, Clock::Apb1),
+            Clock::SpiBus(n) => Kind::Gate(CCU_SPI_BGR_REG, check(n, NUM_SPIS)?, Clock::Psi),
+            Clock::Dma => Kind::Gate(CCU_DMA_BGR_REG, 0, Clock::Psi),
+            Clock::DramBus => Kind::Gate(CCU_DRAM_BGR_REG, 0, Clock::Psi)
+        })
+    }
+}
+
+impl Reset {
+    /// Register and bit of the reset, the device runs while the bit is set.
+    fn bit(self) -> Result<(Register<ReadWrite>, u32), ClockError> {
+        let gate = match self {
+            Reset::Uart(n) => Clock::Uart(n),
+            Reset::Twi(n) => Clock::Twi(n),
+            Reset::Spi(n) => Clock::SpiBus(n),
+            Reset::Dma => Clock::Dma,
+            Reset::Dram => Clock::DramBus,
+            Reset::Mbus => return Ok((CCU_MBUS_CLK_REG, MBUS_RESET))
+        };
+
+        match gate.kind()? {
+            Kind::Gate(reg, bit, _) => Ok((reg, 1 << (bit + BGR_RESET_SHIFT))),
+            _ => unreachable!()
+        }
+    }
+}
+
+/// Value of the `bits` wide field at `shift` plus one, which is how most factors are stored.
+fn factor(value: u32, shift: u32, bits: u32) -> u64 {
+    ((value >> shift) & ((1 << bits) - 1)) as u64 + 1
+}
+
+/// Busy waits for the given number of microseconds.
+pub type Delay = fn(u64);
+
+pub struct Ccu<B: Bus> {
+    regs: RegisterBlock<B>,
+    delay: Delay
+}
+
+impl<B: Bus> Ccu<B> {
+    pub const fn new(regs: RegisterBlock<B>, delay: Delay) -> Self {
+        Self { regs, delay }
+    }
+
+    fn wait_lock(&self, reg: Register<ReadWrite>) -> Result<(), ClockError> {
+        for _ in 0..PLL_LOCK_TIMEOUT {
+            if self.regs.read(reg) & PLL_LOCK != 0 {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(ClockError::Timeout)
+    }
+
+    fn set_pll_cpux_axi(&self) -> Result<(), ClockError> {
+        let mut val;
+
+        /* Select cpux clock src to osc24m, axi divide ratio is 3, system apb clk ratio is 4 */
+        self.regs.write(
+            CCU_RISCV_CLK_REG,
+            (RISCV_SRC_HOSC << MUX_SHIFT) | (3 << N_SHIFT) | (1 << M_SHIFT),
+        );
+        (self.delay)(1);
+
+        /* Disable pll gating */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val &= !PLL_OUTPUT_GATE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+
+        /* Enable pll ldo */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val |= PLL_LDO_ENABLE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+        (self.delay)(5);
+
+        /* Set default clk to 1008mhz */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val &= !(PLL_P_MASK | PLL_N_MASK | PLL_M_MASK);
+        val |= 41 << PLL_N_SHIFT;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+
+        /* Lock enable */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val |= PLL_LOCK_ENABLE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+
+        /* Enable pll */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val |= PLL_ENABLE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+
+        /* Wait pll stable */
+        self.wait_lock(CCU_PLL_CPU_CTRL_REG)?;
+        (self.delay)(20);
+
+        /* Enable pll gating */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val |= PLL_OUTPUT_GATE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+
+        /* Lock disable */
+        val = self.regs.read(CCU_PLL_CPU_CTRL_REG);
+        val &= !PLL_LOCK_ENABLE;
+        self.regs.write(CCU_PLL_CPU_CTRL_REG, val);
+        (self.delay)(1);
+
+        /* Set and change cpu clk src */
+        val = self.regs.read(CCU_RISCV_CLK_REG);
+        val &= !(0x07 << MUX_SHIFT | 0x3 << N_SHIFT | 0xf << M_SHIFT);
+        val |= RISCV_SRC_PLL_CPU << MUX_SHIFT | 0x1 << N_SHIFT;
+        self.regs.write(CCU_RISCV_CLK_REG, val);
+        (self.delay)(1);
+        Ok(())
+    }
+
+    fn set_pll_periph0(&self) -> Result<(), ClockError> {
+        /* Periph0 has been enabled */
+        if self.regs.read(CCU_PLL_PERI0_CTRL_REG) & PLL_ENABLE != 0 {
+            return Ok(());
+        }
+
+        /* Change psi src to osc24m */
+        let mut val = self.regs.read(CCU_PSI_CLK_REG);
+        val &= !(0x3 << MUX_SHIFT);
+        val |= PSI_SRC_HOSC << MUX_SHIFT;
+        self.regs.write(CCU_PSI_CLK_REG, val);
+
+        /* Set default val, then enable pll 600m(1x) 1200m(2x) */
+        self.regs.write(CCU_PLL_PERI0_CTRL_REG, 0x63 << PLL_N_SHIFT);
+        self.enable_pll(CCU_PLL_PERI0_CTRL_REG)
+    }
+
+    fn set_ahb(&self) {
+        self.regs.write(CCU_PSI_CLK_REG, (2 << M_SHIFT) | (N_DIV_1 << N_SHIFT));
+        self.regs.write(
+            CCU_PSI_CLK_REG,
+            self.regs.read(CCU_PSI_CLK_REG) | (PSI_SRC_PLL_PERI0 << MUX_SHIFT),
+        );
+        (self.delay)(1);
+    }
+
+    fn set_apb(&self) {
+        self.regs.write(CCU_APB0_CLK_REG, (2 << M_SHIFT) | (N_DIV_2 << N_SHIFT));
+        self.regs.write(
+            CCU_APB0_CLK_REG,
+            (APB_SRC_PLL_PERI0 << MUX_SHIFT) | self.regs.read(CCU_APB0_CLK_REG),
+        );
+        (self.delay)(1);
+    }
+
+    fn set_dma(&self) -> Result<(), ClockError> {
+        self.deassert_reset(Reset::Dma)?;
+        (self.delay)(20);
+        self.enable(Clock::Dma)
+    }
+
+    /// Turns the PLL at `reg` on and waits for it to lock, unless it's already running.
+    fn enable_pll(&self, reg: Register<ReadWrite>) -> Result<(), ClockError> {
+        if self.regs.read(reg) & PLL_ENABLE != 0 {
+            return Ok(());
+        }
+
+        self.regs.set_bits(reg, PLL_ENABLE | PLL_LDO_ENABLE);
+        self.regs.set_bits(reg, PLL_LOCK_ENABLE);
+        self.wait_lock(reg)?;
+        (self.delay)(20);
+        self.regs.clear_bits(reg, PLL_LOCK_ENABLE);
+        Ok(())
+    }
+
+    /// Sets up the PLLs and bus clocks.
+    pub fn init(&self) -> Result<(), ClockError> {
+        self.set_pll_cpux_axi()?;
+        self.set_pll_periph0()?;
+        self.set_ahb();
+        self.set_apb();
+        self.set_dma()?;
+        self.deassert_reset(Reset::Mbus)?;
+        (self.delay)(1);
+        for pll in [
+            CCU_PLL_PERI0_CTRL_REG,
+            CCU_PLL_VIDEO0_CTRL_REG,
+            CCU_PLL_VIDEO1_CTRL_REG,
+            CCU_PLL_VE_CTRL,
+            CCU_PLL_AUDIO0_CTRL_REG,
+            CCU_PLL_AUDIO1_CTRL_REG
+        ] {
+            self.enable_pll(pll)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rate of `clk` in Hz, as programmed, whether it runs or not.
+    pub fn rate(&self, clk: Clock) -> Result<u64, ClockError> {
+        match clk.kind()? {
+            Kind::Fixed(rate) => Ok(rate),
+            Kind::Pll(reg) => {
+                let v = self.regs.read(reg);
+                let n = factor(v, 8, 8);
+                Ok(match clk {
+                    // The output divider P is a power of two
+                    Clock::PllCpu => (HOSC_RATE * n / factor(v, 0, 2)) >> ((v >> 16) & 0x3),
+                    Clock::PllDdr => HOSC_RATE * n / factor(v, 1, 1) / factor(v, 0, 1),
+                    Clock::PllPeri0x2 => HOSC_RATE * n / factor(v, 1, 1) / factor(v, 16, 3),
+                    Clock::PllPeri0x1 => HOSC_RATE * n / factor(v, 1, 1) / factor(v, 16, 3) / 2,
+                    _ => HOSC_RATE * n / factor(v, 1, 1) / factor(v, 20, 3)
+                })
+            },
+            Kind::Composite(c) => {
+                let v = self.regs.read(c.reg);
+                let mux = (v >> MUX_SHIFT) & ((1 << c.mux_bits) - 1);
+                let parent = c.parents.get(mux as usize).copied().flatten().ok_or(ClockError::Unsupported)?;
+                let n = if c.has_n { (v >> N_SHIFT) & 0x3 } else { 0 };
+                Ok((self.rate(parent)? >> n) / factor(v, 0, c.m_bits))
+            },
+            Kind::Gate(_, _, parent) => self.rate(parent)
+        }
+    }
+
+    /// Sets `clk` as close to `rate` as it gets without going above it and returns the new rate.
+    ///
+    /// Composite clocks pick the best of their parents, PLL_DDR is set in steps
+    /// of 24 MHz. The other PLLs and the bus clock gates can't be changed.
+    pub fn set_rate(&self, clk: Clock, rate: u64) -> Result<u64, ClockError> {
+        match clk.kind()? {
+            Kind::Composite(c) => self.set_composite_rate(c, rate),
+            Kind::Pll(reg) if clk == Clock::PllDdr => {
+                let n = (rate / HOSC_RATE).clamp(1, 256);
+                if HOSC_RATE * n > rate {
+                    return Err(ClockError::RateUnreachable);
+                }
+
+                // N, with the M dividers at 1
+                self.regs.modify(reg, |v| v & !(PLL_N_MASK | PLL_M_MASK) | ((n - 1) as u32) << PLL_N_SHIFT);
+                if self.regs.read(reg) & PLL_ENABLE != 0 {
+                    self.regs.set_bits(reg, PLL_LOCK_ENABLE);
+                    self.wait_lock(reg)?;
+                    (self.delay)(20);
+                    self.regs.clear_bits(reg, PLL_LOCK_ENABLE);
+                }
+
+                Ok(HOSC_RATE * n)
+            },
+            _ => Err(ClockError::Unsupported)
+        }
+    }
+
+    fn set_composite_rate(&self, c: &Composite, rate: u64) -> Result<u64, ClockError> {
+        if rate == 0 {
+            return Err(ClockError::RateUnreachable);
+        }
+
+        // Best rate and the fields that give it
+        let mut best: Option<(u64, u32)> = None;
+        for (mux, parent) in c.parents.iter().enumerate() {
+            let Some(parent_rate) = parent.and_then(|p| self.rate(p).ok()) else {
+                continue;
+            };
+
+            for n in 0..if c.has_n { 4 } else { 1 } {
+                let m = (parent_rate >> n).div_ceil(rate).clamp(1, c.max_m());
+                let r = (parent_rate >> n) / m;
+                if r <= rate && best.is_none_or(|(b, _)| r > b) {
+                    let fields = (mux as u32) << MUX_SHIFT | if c.has_n { n << N_SHIFT } else { 0 } | (m - 1) as u32;
+                    best = Some((r, fields));
+                }
+            }
+        }
+
+        let (r, fields) = best.ok_or(ClockError::RateUnreachable)?;
+        self.regs.modify(c.reg, |v| v & !c.mask() | fields | c.update);
+        Ok(r)
+    }
+
+    /// Ungates `clk`, PLLs are turned on and waited for.
+    pub fn enable(&self, clk: Clock) -> Result<(), ClockError> {
+        match clk.kind()? {
+            Kind::Fixed(_) => Ok(()),
+            Kind::Pll(reg) => {
+                self.enable_pll(reg)?;
+                self.regs.set_bits(reg, PLL_OUTPUT_GATE);
+                Ok(())
+            },
+            Kind::Composite(c) if c.gate => {
+                self.regs.set_bits(c.reg, CLK_GATE);
+                Ok(())
+            },
+            Kind::Composite(_) => Ok(()),
+            Kind::Gate(reg, bit, _) => {
+                self.regs.set_bits(reg, 1 << bit);
+                Ok(())
+            }
+        }
+    }
+
+    /// Gates `clk`, clocks without a gate can't be stopped.
+    pub fn disable(&self, clk: Clock) -> Result<(), ClockError> {
+        match clk.kind()? {
+            Kind::Pll(reg) => self.regs.clear_bits(reg, PLL_ENABLE),
+            Kind::Composite(c) if c.gate => self.regs.clear_bits(c.reg, CLK_GATE),
+            Kind::Gate(reg, bit, _) => self.regs.clear_bits(reg, 1 << bit),
+            _ => return Err(ClockError::Unsupported)
+        }
+
+        Ok(())
+    }
+
+    /// Holds the device in reset.
+    pub fn assert_reset(&self, reset: Reset) -> Result<(), ClockError> {
+        let (reg, bit) = reset.bit()?;
+        self.regs.clear_bits(reg, bit);
+        Ok(())
+    }
+
+    /// Lets the device run.
+    pub fn deassert_reset(&self, reset: Reset) -> Result<(), ClockError> {
+        let (reg, bit) = reset.bit()?;
+        self.regs.set_bits(reg, bit);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    /// Reset value of PLL_PERI0, 1.2 GHz, 600 MHz and 800 MHz
+    const PERI0_DEFAULT: u32 = 0x48216300;
+
+    fn no_delay(_: u64) {}
+
+    fn ccu(bus: &MockBus) -> Ccu<&MockBus> {
+        // The PLLs lock immediately
+        for reg in [
+            CCU_PLL_CPU_CTRL_REG,
+            CCU_PLL_DDR_CTRL_REG,
+            CCU_PLL_PERI0_CTRL_REG,
+            CCU_PLL_VIDEO0_CTRL_REG,
+            CCU_PLL_VIDEO1_CTRL_REG,
+            CCU_PLL_VE_CTRL,
+            CCU_PLL_AUDIO0_CTRL_REG,
+            CCU_PLL_AUDIO1_CTRL_REG
+        ] {
+            bus.set_read_bits(reg.index(), PLL_LOCK);
+        }
+
+        Ccu::new(RegisterBlock::new(bus, 1), no_delay)
+    }
+
+    #[test]
+    fn init_cpu_pll() {
+        let bus = MockBus::new();
+        let ccu = ccu(&bus);
+        ccu.init().unwrap();
+
+        let pll = bus.get(CCU_PLL_CPU_CTRL_REG.index());
+        assert_eq!(pll & PLL_N_MASK, 41 << PLL_N_SHIFT);
+        assert_eq!(pll & (PLL_ENABLE | PLL_LDO_ENABLE | PLL_LOCK_ENABLE | PLL_OUTPUT_GATE), PLL_ENABLE | PLL_LDO_ENABLE | PLL_OUTPUT_GATE);
+        assert_eq!(bus.get(CCU_RISCV_CLK_REG.index()), RISCV_SRC_PLL_CPU << MUX_SHIFT | 1 << N_SHIFT);
+        assert_eq!(ccu.rate(Clock::PllCpu), Ok(1_008_000_000));
+        assert_eq!(ccu.rate(Clock::Riscv), Ok(1_008_000_000));
+    }
+
+    #[test]
+    fn init_enables_module_plls() {
+        let bus = MockBus::new();
+        ccu(&bus).init().unwrap();
+
+        for reg in [CCU_PLL_VIDEO0_CTRL_REG, CCU_PLL_AUDIO1_CTRL_REG] {
+            let writes = bus.writes(reg.index());
+            assert_eq!(writes.len(), 3);
+            // Lock enable is set while waiting for the PLL and cleared afterwards
+            assert_ne!(writes[1] & PLL_LOCK_ENABLE, 0);
+            assert_eq!(writes[2] & (PLL_ENABLE | PLL_LDO_ENABLE | PLL_LOCK_ENABLE), PLL_ENABLE | PLL_LDO_ENABLE);
+        }
+
+        assert_eq!(bus.get(CCU_DMA_BGR_REG.index()), 1 << 16 | 1);
+        assert_eq!(bus.get(CCU_MBUS_CLK_REG.index()), MBUS_RESET);
+    }
+
+    #[test]
+    fn init_periph0() {
+        let bus = MockBus::new();
+        ccu(&bus).init().unwrap();
+
+        let pll = bus.get(CCU_PLL_PERI0_CTRL_REG.index());
+        assert_eq!(pll & PLL_N_MASK, 0x63 << PLL_N_SHIFT);
+        assert_eq!(pll & (PLL_ENABLE | PLL_LDO_ENABLE | PLL_LOCK_ENABLE), PLL_ENABLE | PLL_LDO_ENABLE);
+    }
+
+    #[test]
+    fn init_keeps_running_periph0() {
+        let bus = MockBus::new();
+        bus.set(CCU_PLL_PERI0_CTRL_REG.index(), PLL_ENABLE);
+        ccu(&bus).init().unwrap();
+        assert!(bus.writes(CCU_PLL_PERI0_CTRL_REG.index()).is_empty());
+    }
+
+    #[test]
+    fn pll_timeout() {
+        let bus = MockBus::new();
+        let ccu = Ccu::new(RegisterBlock::new(&bus, 1), no_delay);
+        assert_eq!(ccu.init(), Err(ClockError::Timeout));
+    }
+
+    #[test]
+    fn bus_rates() {
+        let bus = MockBus::new();
+        let ccu = ccu(&bus);
+        bus.set(CCU_PLL_PERI0_CTRL_REG.index(), PERI0_DEFAULT);
+        assert_eq!(ccu.rate(Clock::PllPeri0x2), Ok(1_200_000_000));
+        assert_eq!(ccu.rate(Clock::PllPeri0x1), Ok(600_000_000));
+        assert_eq!(ccu.rate(Clock::PllPeri0P1), Ok(800_000_000));
+
+        // Reset values run the buses off the crystal
+        assert_eq!(ccu.rate(Clock::Uart(0)), Ok(HOSC_RATE));
+
+        // PLL_PERI0(1X) / 3 for AHB, / 2 / 3 for APB0
+        bus.set(CCU_PSI_CLK_REG.index(), PSI_SRC_PLL_PERI0 << MUX_SHIFT | 2);
+        bus.set(CCU_APB0_CLK_REG.index(), 2 << MUX_SHIFT | N_DIV_2 << N_SHIFT | 2);
+        assert_eq!(ccu.rate(Clock::Dma), Ok(200_000_000));
+        assert_eq!(ccu.rate(Clock::Apb0), Ok(33_333_333));
+
+        assert_eq!(ccu.rate(Clock::Uart(6)), Err(ClockError::NoSuchClock));
+        bus.set(CCU_SPI0_CLK_REG.index(), 3 << MUX_SHIFT);
+        assert_eq!(ccu.rate(Clock::Spi(0)), Err(ClockError::Unsupported));
+    }
+
+    #[test]
+    fn set_composite_rate() {
+        let bus = MockBus::new();
+        let ccu = ccu(&bus);
+        bus.set(CCU_PLL_PERI0_CTRL_REG.index(), PERI0_DEFAULT);
+        bus.set(CCU_SPI0_CLK_REG.index(), CLK_GATE);
+
+        // 600 MHz / 6
+        assert_eq!(ccu.set_rate(Clock::Spi(0), 100_000_000), Ok(100_000_000));
+        assert_eq!(bus.get(CCU_SPI0_CLK_REG.index()), CLK_GATE | 1 << MUX_SHIFT | 5);
+        assert_eq!(ccu.rate(Clock::Spi(0)), Ok(100_000_000));
+
+        // 24 MHz / 2 / 11 is the closest below 1.1 MHz
+        assert_eq!(ccu.set_rate(Clock::Spi(1), 1_100_000), Ok(1_090_909));
+        assert_eq!(bus.get(CCU_SPI1_CLK_REG.index()), N_DIV_2 << N_SHIFT | 10);
+
+        assert_eq!(ccu.set_rate(Clock::Spi(0), 1000), Err(ClockError::RateUnreachable));
+        assert_eq!(ccu.set_rate(Clock::Uart(0), 1000), Err(ClockError::Unsupported));
+    }
+
+    #[test]
+    fn set_dram_rate() {
+        let bus = MockBus::new();
+        let ccu = ccu(&bus);
+        assert_eq!(ccu.set_rate(Clock::PllDdr, 1_584_000_000), Ok(1_584_000_000));
+        assert_eq!(bus.get(CCU_PLL_DDR_CTRL_REG.index()) & !PLL_LOCK, 65 << PLL_N_SHIFT);
+
+        // PLL_DDR / 2 beats both PLL_PERI0 outputs / 2
+        bus.set(CCU_PLL_PERI0_CTRL_REG.index(), PERI0_DEFAULT);
+        assert_eq!(ccu.set_rate(Clock::Dram, 792_000_000), Ok(792_000_000));
+        assert_eq!(bus.get(CCU_DRAM_CLK_REG.index()), DRAM_CLK_UPDATE | 1);
+    }
+
+    #[test]
+    fn gates_and_resets() {
+        let bus = MockBus::new();
+        let ccu = ccu(&bus);
+        ccu.enable(Clock::Uart(2)).unwrap();
+        ccu.deassert_reset(Reset::Uart(2)).unwrap();
+        assert_eq!(bus.get(CCU_UART_BGR_REG.index()), 1 << 2 | 1 << 18);
+
+        ccu.enable(Clock::Twi(1)).unwrap();
+        ccu.deassert_reset(Reset::Twi(1)).unwrap();
+        ccu.assert_reset(Reset::Twi(1)).unwrap();
+        assert_eq!(bus.get(CCU_TWI_BGR_REG.index()), 1 << 1);
+
+        ccu.enable(Clock::Spi(1)).unwrap();
+        assert_eq!(bus.get(CCU_SPI1_CLK_REG.index()), CLK_GATE);
+        ccu.disable(Clock::Spi(1)).unwrap();
+        assert_eq!(bus.get(CCU_SPI1_CLK_REG.index()), 0);
+
+        assert_eq!(ccu.disable(Clock::Psi), Err(ClockError::Unsupported));
+        assert_eq!(ccu.deassert_reset(Reset::Spi(2)), Err(ClockError::NoSuchClock));
+    }
+}
@@ -0,0 +1,2 @@
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod d1_ccu;
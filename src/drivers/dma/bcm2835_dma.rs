@@ -0,0 +1,335 @@
+//! DMA controller of the BCM2835 and the legacy one of the BCM2711.
+//!
+//! Channels walk chains of 32 byte aligned control blocks. The controller sits
+//! behind the VideoCore MMU, so every address it is given, control blocks
+//! included, is a bus address: [`bus_address`] turns RAM into one and
+//! [`peripheral_bus_address`] a register.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{self, Ordering};
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock};
+use crate::sync::mutex::Mutex;
+
+use super::{Callback, Channels, DmaEngine, DmaError, Flow, Transfer, Width, MAX_SEGMENTS};
+
+/// Offset from the peripheral base
+pub const DMA_BASE: usize = 0x7000;
+
+/// Channels the firmware leaves to the ARM, as in the upstream device trees
+pub const ARM_CHANNELS: u32 = 0x7F35;
+
+/// Request lines of the devices
+pub const DREQ_UART0_TX: u8 = 12;
+pub const DREQ_UART0_RX: u8 = 14;
+pub const DREQ_SPI0_TX: u8 = 6;
+pub const DREQ_SPI0_RX: u8 = 7;
+
+/// Registers of channel n start at `n * CHAN_SIZE`, channel 15 is elsewhere and not used
+const CHAN_SIZE: usize = 0x100;
+const CHAN_CS: usize = 0x00;
+const CHAN_CONBLK_AD: usize = 0x04;
+
+/// Bit n is set while channel n has an interrupt
+const DMA_INT_STATUS: Register<ReadOnly> = Register::new(0xFE0);
+const DMA_ENABLE: Register<ReadWrite> = Register::new(0xFF0);
+
+const NUM_CHANNELS: usize = 15;
+
+const CS_ACTIVE: u32 = 1 << 0;
+/// Set once the chain is done, cleared by writing 1
+const CS_END: u32 = 1 << 1;
+/// Cleared by writing 1
+const CS_INT: u32 = 1 << 2;
+const CS_ERROR: u32 = 1 << 8;
+const CS_WAIT_FOR_OUTSTANDING_WRITES: u32 = 1 << 28;
+const CS_RESET: u32 = 1 << 31;
+
+const TI_INTEN: u32 = 1 << 0;
+const TI_WAIT_RESP: u32 = 1 << 3;
+const TI_DEST_INC: u32 = 1 << 4;
+const TI_DEST_DREQ: u32 = 1 << 6;
+const TI_SRC_INC: u32 = 1 << 8;
+const TI_SRC_DREQ: u32 = 1 << 10;
+const TI_PERMAP_SHIFT: u32 = 16;
+const PERMAP_MAX: u8 = 0x1F;
+
+/// Channels from 7 on are lite ones, with a 16 bit length
+const FIRST_LITE: usize = 7;
+const LITE_MAX_LEN: usize = 0xFFFF;
+const MAX_LEN: usize = 0x3FFF_FFFF;
+
+/// Alias of RAM that skips the L2 cache of the VideoCore
+const RAM_BUS_BASE: u32 = 0xC000_0000;
+const PERIPHERAL_BUS_BASE: usize = 0x7E00_0000;
+
+/// Bus address of the RAM at `phys`, which has to be in the first GiB.
+pub const fn bus_address(phys: usize) -> usize {
+    (phys as u32 | RAM_BUS_BASE) as usize
+}
+
+/// Bus address of the peripheral register at `offset` from the peripheral base.
+pub const fn peripheral_bus_address(offset: usize) -> usize {
+    PERIPHERAL_BUS_BASE + offset
+}
+
+/// Control block as read by the controller.
+#[repr(C, align(32))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ControlBlock {
+    ti: u32,
+    src: u32,
+    dst: u32,
+    len: u32,
+    stride: u32,
+    next: u32,
+    reserved: [u32; 2]
+}
+
+/// Control blocks of one channel, only touched by whoever requested the channel.
+struct ControlBlocks(UnsafeCell<[ControlBlock; MAX_SEGMENTS]>);
+
+unsafe impl Sync for ControlBlocks {}
+
+/// Transfer information of the control blocks of a transfer, without the interrupt.
+fn transfer_info(flow: Flow) -> Result<u32, DmaError> {
+    let ti = match flow {
+        Flow::MemToMem => TI_SRC_INC | TI_DEST_INC,
+        // The controller reads and writes devices 32 bits at a time
+        Flow::MemToDev(port) if port.width == Width::Word && port.drq <= PERMAP_MAX =>
+            TI_SRC_INC | TI_DEST_DREQ | (port.drq as u32) << TI_PERMAP_SHIFT,
+        Flow::DevToMem(port) if port.width == Width::Word && port.drq <= PERMAP_MAX =>
+            TI_DEST_INC | TI_SRC_DREQ | (port.drq as u32) << TI_PERMAP_SHIFT,
+        _ => return Err(DmaError::Unsupported)
+    };
+
+    Ok(ti | TI_WAIT_RESP)
+}
+
+pub struct Bcm2835Dma<B: Bus> {
+    regs: RegisterBlock<B>,
+    /// Offset of the registers on the bus
+    base: usize,
+    channels: Mutex<Channels<NUM_CHANNELS>>,
+    blocks: [ControlBlocks; NUM_CHANNELS]
+}
+
+impl<B: Bus> Bcm2835Dma<B> {
+    /// Only the channels in `available` are handed out.
+    pub const fn new(regs: RegisterBlock<B>, base: usize, available: u32) -> Self {
+        Self {
+            regs,
+            base,
+            channels: Mutex::new(Channels::new(available & ((1 << NUM_CHANNELS) - 1))),
+            blocks: [const { ControlBlocks(UnsafeCell::new([ControlBlock { ti: 0, src: 0, dst: 0, len: 0, stride: 0, next: 0, reserved: [0; 2] }; MAX_SEGMENTS])) }; NUM_CHANNELS]
+        }
+    }
+
+    /// Enables and resets the available channels.
+    pub fn init(&self) {
+        let available = self.channels.lock().available;
+        self.regs.set_bits(self.reg(DMA_ENABLE), available);
+        for channel in 0..NUM_CHANNELS {
+            if available & 1 << channel != 0 {
+                self.regs.write(self.chan_reg(channel, CHAN_CS), CS_RESET);
+            }
+        }
+    }
+
+    fn reg<A>(&self, reg: Register<A>) -> Register<A> {
+        Register::new(self.base + reg.index())
+    }
+
+    fn chan_reg(&self, channel: usize, offset: usize) -> Register<ReadWrite> {
+        Register::new(self.base + channel * CHAN_SIZE + offset)
+    }
+}
+
+impl<B: Bus> DmaEngine for Bcm2835Dma<B> {
+    fn request_channel(&self) -> Result<u8, DmaError> {
+        self.channels.lock().request()
+    }
+
+    fn release_channel(&self, channel: u8) {
+        self.stop(channel);
+        self.channels.lock().release(channel);
+    }
+
+    fn start(&self, channel: u8, transfer: &Transfer<'_>, callback: Option<Callback>) -> Result<(), DmaError> {
+        let segments = transfer.segments;
+        if segments.is_empty() || segments.len() > MAX_SEGMENTS {
+            return Err(DmaError::BadChain);
+        }
+
+        let ti = transfer_info(transfer.flow)?;
+        let mut channels = self.channels.lock();
+        let n = channels.check(channel)?;
+        let max_len = if n >= FIRST_LITE { LITE_MAX_LEN } else { MAX_LEN };
+        if segments.iter().any(|s| s.len > max_len) {
+            return Err(DmaError::TooLong);
+        }
+
+        let cs = self.chan_reg(n, CHAN_CS);
+        if self.regs.read(cs) & CS_ACTIVE != 0 {
+            return Err(DmaError::Busy);
+        }
+
+        let blocks = self.blocks[n].0.get() as *mut ControlBlock;
+        for (i, segment) in segments.iter().enumerate() {
+            let (src, dst) = transfer.addrs(segment);
+            let last = i + 1 == segments.len();
+            let ti = if last && callback.is_some() { ti | TI_INTEN } else { ti };
+            let next = if last { 0 } else { bus_address(unsafe { blocks.add(i + 1) as usize }) as u32 };
+            let block = ControlBlock { ti, src: src as u32, dst: dst as u32, len: segment.len as u32, stride: 0, next, reserved: [0; 2] };
+            unsafe { blocks.add(i).write_volatile(block) };
+        }
+
+        channels.callbacks[n] = callback;
+
+        // The control blocks have to be in memory before the controller goes looking
+        atomic::fence(Ordering::SeqCst);
+        self.regs.write(cs, CS_END | CS_INT);
+        self.regs.write(self.chan_reg(n, CHAN_CONBLK_AD), bus_address(blocks as usize) as u32);
+        self.regs.write(cs, CS_WAIT_FOR_OUTSTANDING_WRITES | CS_ACTIVE);
+        Ok(())
+    }
+
+    fn is_busy(&self, channel: u8) -> Result<bool, DmaError> {
+        let n = self.channels.lock().check(channel)?;
+        let cs = self.regs.read(self.chan_reg(n, CHAN_CS));
+        if cs & CS_ERROR != 0 {
+            return Err(DmaError::Failed);
+        }
+
+        Ok(cs & CS_ACTIVE != 0)
+    }
+
+    fn stop(&self, channel: u8) {
+        if (channel as usize) < NUM_CHANNELS {
+            self.regs.write(self.chan_reg(channel as usize, CHAN_CS), CS_RESET);
+        }
+    }
+
+    fn handle_interrupt(&self) {
+        let mut done = [None; NUM_CHANNELS];
+        {
+            let channels = self.channels.lock();
+            let pending = self.regs.read(self.reg(DMA_INT_STATUS)) & channels.available;
+            for (channel, callback) in done.iter_mut().enumerate() {
+                if pending & 1 << channel != 0 {
+                    // Clearing ACTIVE would pause the channel
+                    self.regs.write(self.chan_reg(channel, CHAN_CS), CS_INT | CS_ACTIVE);
+                    *callback = channels.callbacks[channel];
+                }
+            }
+        }
+
+        // Outside of the lock, so callbacks can start the next transfer
+        for (channel, callback) in done.iter().enumerate() {
+            if let Some(callback) = callback {
+                callback(channel as u8);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicU8;
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+    use crate::drivers::dma::{DmaPort, Segment};
+
+    fn blocks(dma: &Bcm2835Dma<&MockBus>, channel: usize) -> [ControlBlock; MAX_SEGMENTS] {
+        unsafe { *dma.blocks[channel].0.get() }
+    }
+
+    #[test]
+    fn memory_copy() {
+        let regs = MockBus::new();
+        let dma = Bcm2835Dma::new(RegisterBlock::new(&regs, 1), DMA_BASE, ARM_CHANNELS);
+        dma.init();
+        assert_eq!(regs.get(DMA_BASE + DMA_ENABLE.index()), ARM_CHANNELS);
+        assert_eq!(regs.writes(DMA_BASE + 0x200), [CS_RESET]);
+        assert!(regs.writes(DMA_BASE + 0x100).is_empty());
+        regs.clear_log();
+
+        let channel = dma.request_channel().unwrap();
+        assert_eq!(channel, 0);
+        let segments = [Segment { src: 0xC100_0000, dst: 0xC200_0000, len: 0x1000 }, Segment { src: 0xC100_2000, dst: 0xC200_1000, len: 3 }];
+        dma.start(channel, &Transfer::new(Flow::MemToMem, &segments), None).unwrap();
+
+        let blocks = blocks(&dma, 0);
+        let first = bus_address(dma.blocks[0].0.get() as usize) as u32;
+        let ti = TI_SRC_INC | TI_DEST_INC | TI_WAIT_RESP;
+        assert_eq!(blocks[0], ControlBlock { ti, src: 0xC100_0000, dst: 0xC200_0000, len: 0x1000, stride: 0, next: first + 32, reserved: [0; 2] });
+        assert_eq!(blocks[1], ControlBlock { ti, src: 0xC100_2000, dst: 0xC200_1000, len: 3, stride: 0, next: 0, reserved: [0; 2] });
+        assert_eq!(regs.get(DMA_BASE + CHAN_CONBLK_AD), first);
+        assert_eq!(regs.writes(DMA_BASE + CHAN_CS), [CS_END | CS_INT, CS_WAIT_FOR_OUTSTANDING_WRITES | CS_ACTIVE]);
+
+        assert_eq!(dma.is_busy(channel), Ok(true));
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::MemToMem, &segments), None), Err(DmaError::Busy));
+        regs.set(DMA_BASE + CHAN_CS, CS_ERROR);
+        assert_eq!(dma.is_busy(channel), Err(DmaError::Failed));
+    }
+
+    #[test]
+    fn device_transfers() {
+        let regs = MockBus::new();
+        let dma = Bcm2835Dma::new(RegisterBlock::new(&regs, 1), DMA_BASE, ARM_CHANNELS);
+        let fifo = DmaPort { addr: peripheral_bus_address(0x201000), drq: DREQ_UART0_TX, width: Width::Word };
+        let channel = dma.request_channel().unwrap();
+
+        let segments = [Segment { src: 0xC000_1000, dst: 0, len: 16 }, Segment { src: 0xC000_3000, dst: 0, len: 16 }];
+        dma.start(channel, &Transfer::new(Flow::MemToDev(fifo), &segments), Some(|_| ())).unwrap();
+        let blocks = blocks(&dma, 0);
+        let ti = TI_SRC_INC | TI_DEST_DREQ | 12 << TI_PERMAP_SHIFT | TI_WAIT_RESP;
+        assert_eq!((blocks[0].ti, blocks[0].dst), (ti, 0x7E20_1000));
+        assert_eq!((blocks[1].ti, blocks[1].dst), (ti | TI_INTEN, 0x7E20_1000));
+
+        let byte = DmaPort { width: Width::Byte, ..fifo };
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::DevToMem(byte), &segments), None), Err(DmaError::Unsupported));
+    }
+
+    #[test]
+    fn channels() {
+        let regs = MockBus::new();
+        let dma = Bcm2835Dma::new(RegisterBlock::new(&regs, 1), DMA_BASE, ARM_CHANNELS);
+        let channels: [u8; 11] = core::array::from_fn(|_| dma.request_channel().unwrap());
+        assert_eq!(channels, [0, 2, 4, 5, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(dma.request_channel(), Err(DmaError::NoFreeChannel));
+        assert_eq!(dma.start(1, &Transfer::new(Flow::MemToMem, &[]), None), Err(DmaError::BadChain));
+        assert_eq!(dma.is_busy(1), Err(DmaError::NoSuchChannel));
+
+        // Lite channels move at most 64 KiB at once
+        let segments = [Segment { src: 0, dst: 0, len: 0x10000 }];
+        assert_eq!(dma.start(8, &Transfer::new(Flow::MemToMem, &segments), None), Err(DmaError::TooLong));
+        assert_eq!(dma.start(5, &Transfer::new(Flow::MemToMem, &segments), None), Ok(()));
+
+        dma.release_channel(5);
+        assert_eq!(regs.get(DMA_BASE + 0x500), CS_RESET);
+        assert_eq!(dma.request_channel(), Ok(5));
+    }
+
+    #[test]
+    fn completion_callback() {
+        static DONE: AtomicU8 = AtomicU8::new(0xFF);
+
+        let regs = MockBus::new();
+        let dma = Bcm2835Dma::new(RegisterBlock::new(&regs, 1), DMA_BASE, ARM_CHANNELS);
+        dma.request_channel().unwrap();
+        let channel = dma.request_channel().unwrap();
+
+        let segments = [Segment { src: 0, dst: 0x100, len: 0x100 }];
+        dma.start(channel, &Transfer::new(Flow::MemToMem, &segments), Some(|channel| DONE.store(channel, Ordering::Relaxed))).unwrap();
+        regs.clear_log();
+
+        // Channel 1 belongs to the firmware
+        regs.set(DMA_BASE + DMA_INT_STATUS.index(), 1 << 2 | 1 << 1);
+        dma.handle_interrupt();
+        assert_eq!(DONE.load(Ordering::Relaxed), 2);
+        assert_eq!(regs.writes(DMA_BASE + 0x200), [CS_INT | CS_ACTIVE]);
+        assert!(regs.writes(DMA_BASE + 0x100).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Fake DMA engine for host tests, transfers are done as soon as they start.
+
+extern crate std;
+
+use std::vec::Vec;
+
+use crate::sync::mutex::Mutex;
+
+use super::{Callback, DmaEngine, DmaError, Flow, Segment, Transfer};
+
+/// Records the transfers started on it, one entry per segment.
+pub struct MockDma {
+    transfers: Mutex<Vec<(Flow, Segment)>>,
+    error: Mutex<Option<DmaError>>
+}
+
+impl MockDma {
+    pub const fn new() -> Self {
+        Self { transfers: Mutex::new(Vec::new()), error: Mutex::new(None) }
+    }
+
+    /// The segments started so far with the flow of their transfer.
+    pub fn transfers(&self) -> Vec<(Flow, Segment)> {
+        self.transfers.lock().clone()
+    }
+
+    pub fn clear(&self) {
+        self.transfers.lock().clear();
+    }
+
+    /// Makes the transfers started from now on run into `error`, or succeed again with `None`.
+    pub fn fail_with(&self, error: Option<DmaError>) {
+        *self.error.lock() = error;
+    }
+}
+
+impl DmaEngine for MockDma {
+    fn request_channel(&self) -> Result<u8, DmaError> {
+        Ok(0)
+    }
+
+    fn release_channel(&self, _channel: u8) {}
+
+    fn start(&self, _channel: u8, transfer: &Transfer<'_>, _callback: Option<Callback>) -> Result<(), DmaError> {
+        self.transfers.lock().extend(transfer.segments.iter().map(|&s| (transfer.flow, s)));
+        Ok(())
+    }
+
+    fn is_busy(&self, _channel: u8) -> Result<bool, DmaError> {
+        match *self.error.lock() {
+            Some(error) => Err(error),
+            None => Ok(false)
+        }
+    }
+
+    fn stop(&self, _channel: u8) {}
+
+    fn handle_interrupt(&self) {}
+}
@@ -0,0 +1,228 @@
+//! DMA controllers behind the [`DmaEngine`] trait.
+//!
+//! Addresses are the ones the controller sees, which are the physical ones
+//! except on the BCM2835, and buffers have to be coherent with it, the
+//! kernel does no cache maintenance.
+
+#[cfg(any(target_arch = "aarch64", test))]
+pub mod bcm2835_dma;
+#[cfg(test)]
+pub mod mock;
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod sun6i_dma;
+
+use core::{fmt, hint};
+
+/// Longest a chain of descriptors can be
+pub const MAX_SEGMENTS: usize = 8;
+
+/// Polls of a channel before [`DmaEngine::run`] gives up, bulk transfers to slow devices take seconds
+const TIMEOUT: usize = 100_000_000;
+
+/// Width of the accesses to a device register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    HalfWord,
+    Word
+}
+
+/// A device register data is moved to or from, paced by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaPort {
+    pub addr: usize,
+    /// DMA request line of the device
+    pub drq: u8,
+    pub width: Width
+}
+
+/// Which sides of a transfer are devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    MemToMem,
+    /// The destination is `port`, which is the same for all segments
+    MemToDev(DmaPort),
+    /// The source is `port`
+    DevToMem(DmaPort)
+}
+
+/// A contiguous piece of a transfer, the addresses of a device side are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub src: usize,
+    pub dst: usize,
+    pub len: usize
+}
+
+/// A chain of segments run one after the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer<'a> {
+    pub flow: Flow,
+    pub segments: &'a [Segment]
+}
+
+impl<'a> Transfer<'a> {
+    pub const fn new(flow: Flow, segments: &'a [Segment]) -> Self {
+        Self { flow, segments }
+    }
+
+    /// Source and destination of a segment, with the device side filled in.
+    fn addrs(&self, segment: &Segment) -> (usize, usize) {
+        match self.flow {
+            Flow::MemToMem => (segment.src, segment.dst),
+            Flow::MemToDev(port) => (segment.src, port.addr),
+            Flow::DevToMem(port) => (port.addr, segment.dst)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    /// All channels are taken
+    NoFreeChannel,
+    /// The channel doesn't exist or wasn't requested
+    NoSuchChannel,
+    /// The channel is still running a transfer
+    Busy,
+    /// More than [`MAX_SEGMENTS`] segments, or none
+    BadChain,
+    /// A segment is longer than the controller can move at once
+    TooLong,
+    /// The controller can't do that width or request line
+    Unsupported,
+    /// The controller reported an error
+    Failed,
+    Timeout
+}
+
+impl fmt::Display for DmaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoFreeChannel => f.write_str("no free DMA channel"),
+            Self::NoSuchChannel => f.write_str("no such DMA channel"),
+            Self::Busy => f.write_str("DMA channel busy"),
+            Self::BadChain => f.write_str("bad number of DMA segments"),
+            Self::TooLong => f.write_str("DMA segment too long"),
+            Self::Unsupported => f.write_str("DMA transfer not supported by the controller"),
+            Self::Failed => f.write_str("DMA transfer failed"),
+            Self::Timeout => f.write_str("DMA transfer timed out")
+        }
+    }
+}
+
+/// Called from [`DmaEngine::handle_interrupt`] with the channel whose transfer completed.
+pub type Callback = fn(u8);
+
+/// A DMA controller with independent channels.
+pub trait DmaEngine {
+    /// Reserves a free channel.
+    fn request_channel(&self) -> Result<u8, DmaError>;
+
+    /// Stops the channel and makes it free again.
+    fn release_channel(&self, channel: u8);
+
+    /// Starts `transfer` on `channel`, `callback` is called once all of it is done.
+    ///
+    /// The buffers have to stay valid until then.
+    fn start(&self, channel: u8, transfer: &Transfer<'_>, callback: Option<Callback>) -> Result<(), DmaError>;
+
+    /// Whether the transfer started on `channel` is still going, fails if it ran into an error.
+    fn is_busy(&self, channel: u8) -> Result<bool, DmaError>;
+
+    fn stop(&self, channel: u8);
+
+    /// Acknowledges completed transfers and calls their callbacks.
+    ///
+    /// The caller is responsible for routing the interrupt of the controller here.
+    fn handle_interrupt(&self);
+
+    /// Waits for the transfer started on `channel`, stopping it if it takes too long.
+    fn wait(&self, channel: u8) -> Result<(), DmaError> {
+        for _ in 0..TIMEOUT {
+            if !self.is_busy(channel)? {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        self.stop(channel);
+        Err(DmaError::Timeout)
+    }
+
+    /// Runs `transfer` on a channel of its own and waits for it.
+    fn run(&self, transfer: &Transfer<'_>) -> Result<(), DmaError> {
+        let channel = self.request_channel()?;
+        let result = self.start(channel, transfer, None).and_then(|()| self.wait(channel));
+        self.release_channel(channel);
+        result
+    }
+}
+
+impl<D: DmaEngine> DmaEngine for &D {
+    fn request_channel(&self) -> Result<u8, DmaError> {
+        (**self).request_channel()
+    }
+
+    fn release_channel(&self, channel: u8) {
+        (**self).release_channel(channel)
+    }
+
+    fn start(&self, channel: u8, transfer: &Transfer<'_>, callback: Option<Callback>) -> Result<(), DmaError> {
+        (**self).start(channel, transfer, callback)
+    }
+
+    fn is_busy(&self, channel: u8) -> Result<bool, DmaError> {
+        (**self).is_busy(channel)
+    }
+
+    fn stop(&self, channel: u8) {
+        (**self).stop(channel)
+    }
+
+    fn handle_interrupt(&self) {
+        (**self).handle_interrupt()
+    }
+}
+
+/// Channels that are free and callbacks of the running transfers, shared by the drivers.
+struct Channels<const N: usize> {
+    /// Bit n is set if channel n is taken
+    used: u32,
+    /// Channels that can be handed out
+    available: u32,
+    callbacks: [Option<Callback>; N]
+}
+
+impl<const N: usize> Channels<N> {
+    const fn new(available: u32) -> Self {
+        Self { used: 0, available, callbacks: [None; N] }
+    }
+
+    fn request(&mut self) -> Result<u8, DmaError> {
+        let free = self.available & !self.used;
+        if free == 0 {
+            return Err(DmaError::NoFreeChannel);
+        }
+
+        let channel = free.trailing_zeros();
+        self.used |= 1 << channel;
+        Ok(channel as u8)
+    }
+
+    fn release(&mut self, channel: u8) {
+        if (channel as usize) < N {
+            self.used &= !(1 << channel);
+            self.callbacks[channel as usize] = None;
+        }
+    }
+
+    /// Checks that `channel` was requested.
+    fn check(&self, channel: u8) -> Result<usize, DmaError> {
+        if (channel as usize) < N && self.used & 1 << channel != 0 {
+            Ok(channel as usize)
+        } else {
+            Err(DmaError::NoSuchChannel)
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! DMA controller of the Allwinner D1, the sun6i design with 16 channels.
+//!
+//! A channel walks a linked list of descriptors in memory, one per segment.
+//! The descriptors of a channel live in the driver, so the driver has to stay
+//! where it is while transfers are running, which a static does.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{self, Ordering};
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::sync::mutex::Mutex;
+
+use super::{Callback, Channels, DmaEngine, DmaError, Flow, Segment, Transfer, Width, MAX_SEGMENTS};
+
+pub const D1_DMA_BASE: usize = 0x03002000;
+
+/// Request lines of the devices, memory has one too
+pub const DRQ_SDRAM: u8 = 1;
+pub const DRQ_UART0: u8 = 14;
+pub const DRQ_SPI0: u8 = 22;
+pub const DRQ_SPI1: u8 = 23;
+
+/// Interrupt enables and pending interrupts, 4 bits per channel, channels 8 to 15 in the second register
+const DMA_IRQ_EN0: Register<ReadWrite> = Register::new(0x00);
+const DMA_IRQ_PEND0: Register<ReadWrite> = Register::new(0x10);
+const DMA_AUTO_GATE: Register<WriteOnly> = Register::new(0x28);
+
+/// Bit n is set while channel n is running
+const DMA_STA: Register<ReadOnly> = Register::new(0x30);
+
+/// Registers of channel n start at `CHAN_BASE + n * CHAN_SIZE`
+const CHAN_BASE: usize = 0x100;
+const CHAN_SIZE: usize = 0x40;
+const CHAN_EN: usize = 0x00;
+const CHAN_DESC_ADDR: usize = 0x08;
+
+const NUM_CHANNELS: usize = 16;
+const IRQ_BITS: u32 = 4;
+const IRQ_CHANNELS_PER_REG: u8 = 8;
+
+/// Raised once the last descriptor is done
+const IRQ_QUEUE_END: u32 = 1 << 2;
+
+/// Keeps the clock of the memory interface running, as the vendor code does
+const AUTO_GATE_DISABLE: u32 = 1 << 2;
+
+/// Half of the configuration of a descriptor, the source in the low and the destination in the high half
+const CFG_BURST_SHIFT: u32 = 6;
+/// The address stays the same, for device registers
+const CFG_IO_MODE: u32 = 1 << 8;
+const CFG_WIDTH_SHIFT: u32 = 9;
+const CFG_DST_SHIFT: u32 = 16;
+const CFG_DRQ_MAX: u8 = 0x3F;
+
+/// Burst of 8, for copies in memory
+const BURST_8: u32 = 2;
+const BURST_1: u32 = 0;
+
+/// Waits for the device between bursts, the value of the vendor code
+const PARA_NORMAL_WAIT: u32 = 8;
+const LINK_END: u32 = 0xFFFFF800;
+
+/// Longest segment, the byte counter has 25 bits
+const MAX_LEN: usize = 0x1FF_FFFF;
+
+/// Descriptor as read by the controller.
+#[repr(C, align(4))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Lli {
+    cfg: u32,
+    src: u32,
+    dst: u32,
+    len: u32,
+    para: u32,
+    next: u32
+}
+
+/// Descriptors of one channel, only touched by whoever requested the channel.
+struct Descriptors(UnsafeCell<[Lli; MAX_SEGMENTS]>);
+
+unsafe impl Sync for Descriptors {}
+
+const fn width_code(width: Width) -> u32 {
+    match width {
+        Width::Byte => 0,
+        Width::HalfWord => 1,
+        Width::Word => 2
+    }
+}
+
+/// One half of the configuration of a descriptor.
+const fn side(drq: u8, burst: u32, io: bool, width: Width) -> u32 {
+    drq as u32 | burst << CFG_BURST_SHIFT | if io { CFG_IO_MODE } else { 0 } | width_code(width) << CFG_WIDTH_SHIFT
+}
+
+/// Configuration of the descriptors of a transfer.
+fn config(flow: Flow, segments: &[Segment]) -> Result<u32, DmaError> {
+    let (src, dst) = match flow {
+        Flow::MemToMem => {
+            // Copies go in words when everything lines up
+            let aligned = segments.iter().all(|s| (s.src | s.dst | s.len).is_multiple_of(4));
+            let width = if aligned { Width::Word } else { Width::Byte };
+            (side(DRQ_SDRAM, BURST_8, false, width), side(DRQ_SDRAM, BURST_8, false, width))
+        },
+        Flow::MemToDev(port) if port.drq <= CFG_DRQ_MAX =>
+            (side(DRQ_SDRAM, BURST_1, false, port.width), side(port.drq, BURST_1, true, port.width)),
+        Flow::DevToMem(port) if port.drq <= CFG_DRQ_MAX =>
+            (side(port.drq, BURST_1, true, port.width), side(DRQ_SDRAM, BURST_1, false, port.width)),
+        _ => return Err(DmaError::Unsupported)
+    };
+
+    Ok(src | dst << CFG_DST_SHIFT)
+}
+
+pub struct Dma<B: Bus> {
+    regs: RegisterBlock<B>,
+    channels: Mutex<Channels<NUM_CHANNELS>>,
+    descriptors: [Descriptors; NUM_CHANNELS]
+}
+
+impl<B: Bus> Dma<B> {
+    pub const fn new(regs: RegisterBlock<B>) -> Self {
+        Self {
+            regs,
+            channels: Mutex::new(Channels::new((1 << NUM_CHANNELS) - 1)),
+            descriptors: [const { Descriptors(UnsafeCell::new([Lli { cfg: 0, src: 0, dst: 0, len: 0, para: 0, next: 0 }; MAX_SEGMENTS])) }; NUM_CHANNELS]
+        }
+    }
+
+    /// Masks and clears all interrupts, the CCU ungates the controller when it sets up the bus clocks.
+    pub fn init(&self) {
+        self.regs.write(DMA_AUTO_GATE, AUTO_GATE_DISABLE);
+        for i in 0..2 {
+            self.regs.write(Self::irq_reg(DMA_IRQ_EN0, i * IRQ_CHANNELS_PER_REG), 0);
+            self.regs.write(Self::irq_reg(DMA_IRQ_PEND0, i * IRQ_CHANNELS_PER_REG), !0);
+        }
+    }
+
+    fn chan_reg(channel: usize, offset: usize) -> Register<ReadWrite> {
+        Register::new(CHAN_BASE + channel * CHAN_SIZE + offset)
+    }
+
+    /// The interrupt register of `channel` following `reg`.
+    fn irq_reg(reg: Register<ReadWrite>, channel: u8) -> Register<ReadWrite> {
+        Register::new(reg.index() + 4 * (channel / IRQ_CHANNELS_PER_REG) as usize)
+    }
+
+    fn irq_bit(channel: u8) -> u32 {
+        IRQ_QUEUE_END << (IRQ_BITS * (channel % IRQ_CHANNELS_PER_REG) as u32)
+    }
+
+    fn running(&self, channel: usize) -> bool {
+        self.regs.read(DMA_STA) & 1 << channel != 0
+    }
+}
+
+impl<B: Bus> DmaEngine for Dma<B> {
+    fn request_channel(&self) -> Result<u8, DmaError> {
+        self.channels.lock().request()
+    }
+
+    fn release_channel(&self, channel: u8) {
+        self.stop(channel);
+        if (channel as usize) < NUM_CHANNELS {
+            self.regs.clear_bits(Self::irq_reg(DMA_IRQ_EN0, channel), Self::irq_bit(channel));
+        }
+
+        self.channels.lock().release(channel);
+    }
+
+    fn start(&self, channel: u8, transfer: &Transfer<'_>, callback: Option<Callback>) -> Result<(), DmaError> {
+        let segments = transfer.segments;
+        if segments.is_empty() || segments.len() > MAX_SEGMENTS {
+            return Err(DmaError::BadChain);
+        }
+
+        if segments.iter().any(|s| s.len > MAX_LEN) {
+            return Err(DmaError::TooLong);
+        }
+
+        let cfg = config(transfer.flow, segments)?;
+        let mut channels = self.channels.lock();
+        let n = channels.check(channel)?;
+        if self.running(n) {
+            return Err(DmaError::Busy);
+        }
+
+        let llis = self.descriptors[n].0.get() as *mut Lli;
+        for (i, segment) in segments.iter().enumerate() {
+            let (src, dst) = transfer.addrs(segment);
+            let next = if i + 1 < segments.len() { unsafe { llis.add(i + 1) as usize as u32 } } else { LINK_END };
+            let lli = Lli { cfg, src: src as u32, dst: dst as u32, len: segment.len as u32, para: PARA_NORMAL_WAIT, next };
+            unsafe { llis.add(i).write_volatile(lli) };
+        }
+
+        channels.callbacks[n] = callback;
+        let (irq_en, bit) = (Self::irq_reg(DMA_IRQ_EN0, channel), Self::irq_bit(channel));
+        if callback.is_some() {
+            self.regs.set_bits(irq_en, bit);
+        } else {
+            self.regs.clear_bits(irq_en, bit);
+        }
+
+        // The descriptors have to be in memory before the controller goes looking
+        atomic::fence(Ordering::SeqCst);
+        self.regs.write(Self::chan_reg(n, CHAN_DESC_ADDR), llis as usize as u32);
+        self.regs.write(Self::chan_reg(n, CHAN_EN), 1);
+        Ok(())
+    }
+
+    fn is_busy(&self, channel: u8) -> Result<bool, DmaError> {
+        let n = self.channels.lock().check(channel)?;
+        Ok(self.running(n))
+    }
+
+    fn stop(&self, channel: u8) {
+        if (channel as usize) < NUM_CHANNELS {
+            self.regs.write(Self::chan_reg(channel as usize, CHAN_EN), 0);
+        }
+    }
+
+    fn handle_interrupt(&self) {
+        let mut done = [None; NUM_CHANNELS];
+        {
+            let channels = self.channels.lock();
+            for first in [0, IRQ_CHANNELS_PER_REG] {
+                let reg = Self::irq_reg(DMA_IRQ_PEND0, first);
+                let pending = self.regs.read(reg);
+                self.regs.write(reg, pending);
+
+                for channel in first..first + IRQ_CHANNELS_PER_REG {
+                    if pending & Self::irq_bit(channel) != 0 {
+                        done[channel as usize] = channels.callbacks[channel as usize];
+                    }
+                }
+            }
+        }
+
+        // Outside of the lock, so callbacks can start the next transfer
+        for (channel, callback) in done.iter().enumerate() {
+            if let Some(callback) = callback {
+                callback(channel as u8);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicU8;
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+    use crate::drivers::dma::DmaPort;
+
+    fn llis(dma: &Dma<&MockBus>, channel: usize) -> [Lli; MAX_SEGMENTS] {
+        unsafe { *dma.descriptors[channel].0.get() }
+    }
+
+    #[test]
+    fn memory_copy() {
+        let regs = MockBus::new();
+        let dma = Dma::new(RegisterBlock::new(&regs, 1));
+        let channel = dma.request_channel().unwrap();
+        assert_eq!(channel, 0);
+
+        let segments = [Segment { src: 0x4000_0000, dst: 0x4100_0000, len: 0x1000 }, Segment { src: 0x4000_2000, dst: 0x4100_1000, len: 64 }];
+        dma.start(channel, &Transfer::new(Flow::MemToMem, &segments), None).unwrap();
+
+        let llis = llis(&dma, 0);
+        let first = dma.descriptors[0].0.get() as usize as u32;
+        // 32 bit bursts of 8 from and to memory
+        let cfg = 0x0481_0481;
+        assert_eq!(llis[0], Lli { cfg, src: 0x4000_0000, dst: 0x4100_0000, len: 0x1000, para: 8, next: first + 24 });
+        assert_eq!(llis[1], Lli { cfg, src: 0x4000_2000, dst: 0x4100_1000, len: 64, para: 8, next: LINK_END });
+        assert_eq!(regs.get(0x108), first);
+        assert_eq!(regs.get(0x100), 1);
+
+        regs.set(DMA_STA.index(), 1);
+        assert_eq!(dma.is_busy(channel), Ok(true));
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::MemToMem, &segments), None), Err(DmaError::Busy));
+    }
+
+    #[test]
+    fn device_transfers() {
+        let regs = MockBus::new();
+        let dma = Dma::new(RegisterBlock::new(&regs, 1));
+        let uart = DmaPort { addr: 0x02500000, drq: DRQ_UART0, width: Width::Byte };
+        let channel = dma.request_channel().unwrap();
+
+        let segments = [Segment { src: 0x4000_0003, dst: 0, len: 5 }];
+        dma.start(channel, &Transfer::new(Flow::MemToDev(uart), &segments), None).unwrap();
+        let lli = llis(&dma, 0)[0];
+        assert_eq!((lli.cfg, lli.src, lli.dst), ((DRQ_UART0 as u32 | CFG_IO_MODE) << 16 | 1, 0x4000_0003, 0x02500000));
+
+        let spi = DmaPort { addr: 0x04025300, drq: DRQ_SPI0, width: Width::Byte };
+        let segments = [Segment { src: 0, dst: 0x4000_0000, len: 5 }];
+        dma.start(channel, &Transfer::new(Flow::DevToMem(spi), &segments), None).unwrap();
+        let lli = llis(&dma, 0)[0];
+        assert_eq!((lli.cfg, lli.src, lli.dst), (1 << 16 | CFG_IO_MODE | DRQ_SPI0 as u32, 0x04025300, 0x4000_0000));
+
+        let bad = DmaPort { drq: 64, ..spi };
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::DevToMem(bad), &segments), None), Err(DmaError::Unsupported));
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::MemToMem, &[]), None), Err(DmaError::BadChain));
+        let long = [Segment { src: 0, dst: 0, len: MAX_LEN + 1 }];
+        assert_eq!(dma.start(channel, &Transfer::new(Flow::MemToMem, &long), None), Err(DmaError::TooLong));
+        assert_eq!(dma.start(3, &Transfer::new(Flow::MemToMem, &segments), None), Err(DmaError::NoSuchChannel));
+    }
+
+    #[test]
+    fn channels() {
+        let regs = MockBus::new();
+        let dma = Dma::new(RegisterBlock::new(&regs, 1));
+        for i in 0..16 {
+            assert_eq!(dma.request_channel(), Ok(i));
+        }
+        assert_eq!(dma.request_channel(), Err(DmaError::NoFreeChannel));
+
+        dma.release_channel(5);
+        assert_eq!(regs.get(0x100 + 5 * 0x40), 0);
+        assert_eq!(dma.request_channel(), Ok(5));
+
+        // The channel is released after running
+        dma.release_channel(5);
+        let segments = [Segment { src: 0, dst: 0x100, len: 0x100 }];
+        assert_eq!(dma.run(&Transfer::new(Flow::MemToMem, &segments)), Ok(()));
+        assert_eq!(dma.request_channel(), Ok(5));
+    }
+
+    #[test]
+    fn completion_callback() {
+        static DONE: AtomicU8 = AtomicU8::new(0xFF);
+
+        let regs = MockBus::new();
+        let dma = Dma::new(RegisterBlock::new(&regs, 1));
+        for _ in 0..10 {
+            dma.request_channel().unwrap();
+        }
+
+        let segments = [Segment { src: 0, dst: 0x100, len: 0x100 }];
+        dma.start(9, &Transfer::new(Flow::MemToMem, &segments), Some(|channel| DONE.store(channel, Ordering::Relaxed))).unwrap();
+        assert_eq!(regs.get(0x04), IRQ_QUEUE_END << 4);
+
+        regs.set(0x14, IRQ_QUEUE_END << 4 | 1);
+        dma.handle_interrupt();
+        assert_eq!(DONE.load(Ordering::Relaxed), 9);
+        assert_eq!(regs.writes(0x14), [IRQ_QUEUE_END << 4 | 1]);
+    }
+}
@@ -0,0 +1,4 @@
+//! DRAM controllers that have to be brought up by the kernel.
+
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod sun20i_d1_dram;
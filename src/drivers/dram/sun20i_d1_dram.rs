@@ -0,0 +1,588 @@
+//! DRAM controller (MCTL) and PHY of the Allwinner D1.
+//!
+//! Follows the sequence of the boot0 DRAM driver for DDR3: clocks and resets,
+//! controller mode, timings, PHY initialisation with ZQ calibration and DQS gate
+//! training, and finally a scan of the address lines to find rows, banks and
+//! columns. Only one rank is scanned, a second one is taken to be the same.
+
+use core::{fmt, hint};
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::drivers::clk::d1_ccu::{Ccu, Clock, ClockError, Delay, Reset};
+
+pub const D1_MCTL_BASE: usize = 0x03102000;
+
+/// Where the DRAM shows up
+pub const D1_DRAM_BASE: usize = 0x40000000;
+
+/// Controller work mode of rank 0, rank 1 follows
+const MC_WORK_MODE0: Register<ReadWrite> = Register::new(0x000);
+const MC_WORK_MODE1: Register<ReadWrite> = Register::new(0x004);
+const MC_DBGCR: Register<ReadWrite> = Register::new(0x008);
+const MC_CLKEN: Register<ReadWrite> = Register::new(0x00C);
+
+/// Master enables, the CPU port can't be turned off
+const MC_MAER0: Register<WriteOnly> = Register::new(0x020);
+const MC_MAER1: Register<WriteOnly> = Register::new(0x024);
+const MC_MAER2: Register<WriteOnly> = Register::new(0x028);
+
+/// PHY initialisation
+const PIR: Register<ReadWrite> = Register::new(0x1000);
+const PGSR0: Register<ReadOnly> = Register::new(0x1010);
+const STATR: Register<ReadOnly> = Register::new(0x1018);
+const DRAM_MR0: usize = 0x1030;
+const PTR3: Register<WriteOnly> = Register::new(0x1050);
+const PTR4: Register<WriteOnly> = Register::new(0x1054);
+const DRAMTMG0: usize = 0x1058;
+const DRAMTMG8: Register<ReadWrite> = Register::new(0x1078);
+const PITMG0: Register<WriteOnly> = Register::new(0x1080);
+const RFSHTMG: Register<WriteOnly> = Register::new(0x1090);
+const RFSHCTL1: Register<WriteOnly> = Register::new(0x1094);
+const ODTMAP: Register<WriteOnly> = Register::new(0x1120);
+const ZQCR: Register<ReadWrite> = Register::new(0x1140);
+
+/// General configuration of the two byte lanes
+const DX0GCR0: Register<ReadWrite> = Register::new(0x1344);
+const DX1GCR0: Register<ReadWrite> = Register::new(0x13C4);
+
+const PIR_INIT: u32 = 1 << 0;
+const PIR_ZCAL: u32 = 1 << 1;
+const PIR_PLLINIT: u32 = 1 << 4;
+const PIR_DCAL: u32 = 1 << 5;
+const PIR_PHYRST: u32 = 1 << 6;
+const PIR_DRAMRST: u32 = 1 << 7;
+const PIR_DRAMINIT: u32 = 1 << 8;
+const PIR_QSGATE: u32 = 1 << 10;
+
+const PGSR0_IDONE: u32 = 1 << 0;
+/// ZQ calibration and training errors
+const PGSR0_ERRORS: u32 = 0x0FF0_0000;
+const STATR_NORMAL: u32 = 1 << 0;
+
+/// Turns the on die termination of a byte lane off
+const DXGCR0_ODT_DISABLE: u32 = 1 << 5;
+
+const WORK_MODE_DDR3: u32 = 3 << 16;
+const WORK_MODE_FULL_WIDTH: u32 = 1 << 12;
+const WORK_MODE_UNKNOWN: u32 = 1 << 22;
+const WORK_MODE_GEOMETRY: u32 = 0xFFF;
+
+/// Polls of a PHY status bit before giving up
+const PHY_TIMEOUT: usize = 1_000_000;
+
+/// Words written at the start of the DRAM to recognize aliases
+const SCAN_WORDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DramError {
+    /// The configuration is out of what the controller supports
+    BadConfig,
+    Clock(ClockError),
+    /// The PHY didn't finish initialising
+    Timeout,
+    /// Calibration or training failed, with the PHY status
+    TrainingFailed(u32),
+    /// The memory doesn't keep what is written to it
+    NoMemory
+}
+
+impl fmt::Display for DramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadConfig => f.write_str("unsupported DRAM configuration"),
+            Self::Clock(e) => write!(f, "DRAM clock: {}", e),
+            Self::Timeout => f.write_str("DRAM PHY timed out"),
+            Self::TrainingFailed(status) => write!(f, "DRAM training failed (PGSR0 {:#x})", status),
+            Self::NoMemory => f.write_str("no working DRAM found")
+        }
+    }
+}
+
+/// Board specific settings of DDR3 memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DramConfig {
+    /// DDR clock in MHz, the PLL runs at twice that and the controller at half
+    pub clock_mhz: u32,
+    /// Impedance of the ZQ calibration
+    pub zq: u32,
+    /// On die termination
+    pub odt: bool,
+    /// Only 8 of the 16 data lines are connected
+    pub half_width: bool,
+    /// 1 or 2
+    pub ranks: u32,
+    /// Output drive and termination strength, the other mode registers follow from the clock
+    pub mr1: u32
+}
+
+impl DramConfig {
+    /// DDR3 at 792 MHz, as on the Nezha and MangoPi MQ-Pro boards
+    pub const NEZHA: DramConfig = DramConfig {
+        clock_mhz: 792,
+        zq: 0x7B7BFB,
+        odt: true,
+        half_width: false,
+        ranks: 1,
+        mr1: 0x42
+    };
+
+    /// Address bits of a byte within a column
+    const fn byte_bits(&self) -> u32 {
+        if self.half_width { 0 } else { 1 }
+    }
+}
+
+/// Address bits of a rank, the controller maps addresses as row, bank, column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub rows: u32,
+    pub banks: u32,
+    pub columns: u32
+}
+
+impl Geometry {
+    /// The largest geometry, to scan with, DDR3 always has 8 banks
+    const MAX: Geometry = Geometry { rows: 16, banks: 3, columns: 9 };
+
+    /// Geometry bits of the work mode register.
+    const fn work_mode(&self) -> u32 {
+        let bank8 = if self.banks == 3 { 1 << 2 } else { 0 };
+        bank8 | (self.rows - 1) << 4 | (self.columns - 2) << 8
+    }
+
+    const fn from_work_mode(mode: u32) -> Self {
+        Geometry {
+            rows: ((mode >> 4) & 0xF) + 1,
+            banks: if mode & (1 << 2) != 0 { 3 } else { 2 },
+            columns: ((mode >> 8) & 0xF) + 2
+        }
+    }
+}
+
+/// Timings in controller clock cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Timing {
+    mr0: u32,
+    mr2: u32,
+    tcl: u32,
+    tcwl: u32,
+    trfc: u32,
+    trefi: u32,
+    trrd: u32,
+    txp: u32,
+    tfaw: u32,
+    trcd: u32,
+    trp: u32,
+    tras: u32,
+    trc: u32,
+    trtp: u32,
+    trasmax: u32,
+    twtp: u32,
+    twr2rd: u32,
+    trd2wr: u32,
+    /// Power up and reset waits in DDR clock cycles
+    tdinit: [u32; 4]
+}
+
+/// DDR3 mode register constants: burst length 8, no additive latency
+const DDR3_TMRD: u32 = 2;
+const DDR3_TMOD: u32 = 6;
+const DDR3_TCCD: u32 = 2;
+const DDR3_TCKE: u32 = 3;
+const DDR3_TCKESR: u32 = 4;
+const DDR3_TCKSRE: u32 = 5;
+const DDR3_TCKSRX: u32 = 5;
+
+impl Timing {
+    fn ddr3(clock_mhz: u32) -> Self {
+        let t = |ns: u32| (clock_mhz.div_ceil(2) * ns).div_ceil(1000);
+        let slow = clock_mhz <= 800;
+        let (tcl, tcwl, mr0, mr2) = if slow { (6, 4, 0x1C70, 0x18) } else { (7, 5, 0x1E14, 0x20) };
+        let twtr = t(8) + 2;
+        let twr = t(15).max(2);
+
+        Timing {
+            mr0,
+            mr2,
+            tcl,
+            tcwl,
+            trfc: t(350),
+            trefi: t(7800) / 32 + 1,
+            trrd: t(10).max(2),
+            txp: t(10).max(2),
+            tfaw: t(if slow { 50 } else { 35 }),
+            trcd: t(if slow { 15 } else { 14 }),
+            trp: t(if slow { 15 } else { 14 }),
+            tras: t(if slow { 38 } else { 34 }),
+            trc: t(if slow { 53 } else { 48 }),
+            trtp: t(8).max(2),
+            // 9 refresh intervals, in units of 1024 cycles
+            trasmax: t(9 * 7800) / 1024,
+            twtp: tcwl + 2 + twr,
+            twr2rd: tcwl + 2 + twtr,
+            trd2wr: tcl + 2 + 1 - tcwl,
+            // 500 us, tXPR, 200 us and 1 us
+            tdinit: [500 * clock_mhz + 1, 360 * clock_mhz / 1000 + 1, 200 * clock_mhz + 1, clock_mhz + 1]
+        }
+    }
+}
+
+pub struct Dram<B: Bus, M: Bus> {
+    regs: RegisterBlock<B>,
+    /// The DRAM itself
+    mem: M,
+    delay: Delay
+}
+
+impl<B: Bus, M: Bus> Dram<B, M> {
+    pub const fn new(regs: RegisterBlock<B>, mem: M, delay: Delay) -> Self {
+        Self { regs, mem, delay }
+    }
+
+    /// Brings the DRAM up and returns its size in bytes.
+    pub fn init<C: Bus>(&self, ccu: &Ccu<C>, config: &DramConfig) -> Result<u64, DramError> {
+        if !(200..=936).contains(&config.clock_mhz) || !(1..=2).contains(&config.ranks) {
+            return Err(DramError::BadConfig);
+        }
+
+        self.sys_init(ccu, config).map_err(DramError::Clock)?;
+        self.core_init(config, Geometry::MAX)?;
+        let geometry = self.scan(config)?;
+        self.core_init(config, geometry)?;
+        self.enable_all_masters();
+        Ok(self.size())
+    }
+
+    /// Size in bytes as the controller is set up, by [`Dram::init`] or a boot loader.
+    pub fn size(&self) -> u64 {
+        let mode = self.regs.read(MC_WORK_MODE0);
+        let geometry = Geometry::from_work_mode(mode);
+        let byte_bits = (mode & WORK_MODE_FULL_WIDTH != 0) as u32;
+        let ranks = (mode & 0x3) as u64 + 1;
+        (1 << (byte_bits + geometry.columns + geometry.banks + geometry.rows)) * ranks
+    }
+
+    fn disable_all_masters(&self) {
+        self.regs.write(MC_MAER0, 1);
+        self.regs.write(MC_MAER1, 0);
+        self.regs.write(MC_MAER2, 0);
+        (self.delay)(10);
+    }
+
+    fn enable_all_masters(&self) {
+        self.regs.write(MC_MAER0, 0xFFFF_FFFF);
+        self.regs.write(MC_MAER1, 0xFF);
+        self.regs.write(MC_MAER2, 0xFFFF);
+        (self.delay)(10);
+    }
+
+    /// Runs the controller off PLL_DDR at twice the DDR clock and takes it out of reset.
+    fn sys_init<C: Bus>(&self, ccu: &Ccu<C>, config: &DramConfig) -> Result<(), ClockError> {
+        ccu.assert_reset(Reset::Mbus)?;
+        ccu.disable(Clock::DramBus)?;
+        ccu.assert_reset(Reset::Dram)?;
+        ccu.disable(Clock::Dram)?;
+        (self.delay)(10);
+
+        let rate = 2 * config.clock_mhz as u64 * 1_000_000;
+        ccu.set_rate(Clock::PllDdr, rate)?;
+        ccu.enable(Clock::PllDdr)?;
+        if ccu.set_rate(Clock::Dram, rate)? != rate {
+            return Err(ClockError::RateUnreachable);
+        }
+
+        (self.delay)(100);
+        self.disable_all_masters();
+
+        ccu.deassert_reset(Reset::Dram)?;
+        ccu.deassert_reset(Reset::Mbus)?;
+        (self.delay)(5);
+        ccu.enable(Clock::DramBus)?;
+        ccu.enable(Clock::Dram)?;
+        (self.delay)(5);
+
+        self.regs.write(MC_CLKEN, 0x8000);
+        (self.delay)(10);
+        Ok(())
+    }
+
+    fn core_init(&self, config: &DramConfig, geometry: Geometry) -> Result<(), DramError> {
+        self.com_init(config, geometry);
+        self.set_timing(config, &Timing::ddr3(config.clock_mhz));
+        self.phy_init(config)
+    }
+
+    /// Memory type, width and geometry.
+    fn com_init(&self, config: &DramConfig, geometry: Geometry) {
+        self.regs.modify(MC_DBGCR, |v| v & !0x3F00 | 0x2000);
+
+        let width = if config.half_width { 0 } else { WORK_MODE_FULL_WIDTH };
+        let mode = WORK_MODE_DDR3 | width | WORK_MODE_UNKNOWN | (config.ranks - 1) | geometry.work_mode();
+        self.regs.modify(MC_WORK_MODE0, |v| v & !(0x00FF_F000 | WORK_MODE_GEOMETRY) | mode);
+        if config.ranks == 2 {
+            self.regs.modify(MC_WORK_MODE1, |v| v & !WORK_MODE_GEOMETRY | (config.ranks - 1) | geometry.work_mode());
+        }
+
+        self.regs.write(ODTMAP, if config.ranks == 2 { 0x303 } else { 0x201 });
+        if config.half_width {
+            self.regs.write(DX1GCR0, 0);
+        }
+    }
+
+    fn set_timing(&self, config: &DramConfig, t: &Timing) {
+        let mr = [t.mr0, config.mr1, t.mr2, 0];
+        for (i, value) in mr.into_iter().enumerate() {
+            let reg: Register<WriteOnly> = Register::new(DRAM_MR0 + i * 4);
+            self.regs.write(reg, value);
+        }
+
+        let tmg = [
+            t.twtp << 24 | t.tfaw << 16 | t.trasmax << 8 | t.tras,
+            t.txp << 16 | t.trtp << 8 | t.trc,
+            t.tcwl << 24 | t.tcl << 16 | t.trd2wr << 8 | t.twr2rd,
+            DDR3_TMRD << 12 | DDR3_TMOD,
+            t.trcd << 24 | DDR3_TCCD << 16 | t.trrd << 8 | t.trp,
+            DDR3_TCKSRX << 24 | DDR3_TCKSRE << 16 | DDR3_TCKESR << 8 | DDR3_TCKE
+        ];
+        for (i, value) in tmg.into_iter().enumerate() {
+            let reg: Register<WriteOnly> = Register::new(DRAMTMG0 + i * 4);
+            self.regs.write(reg, value);
+        }
+
+        // Dual rank timing
+        self.regs.modify(DRAMTMG8, |v| v & !0xF000_FFFF | 0xF000_6610);
+
+        // Read data enable and write latency of the PHY interface
+        self.regs.write(PITMG0, 2 << 24 | (t.tcl - 2) << 16 | 1 << 8 | (t.tcwl - 2));
+        self.regs.write(PTR3, t.tdinit[0] | t.tdinit[1] << 20);
+        self.regs.write(PTR4, t.tdinit[2] | t.tdinit[3] << 20);
+
+        self.regs.write(RFSHTMG, t.trefi << 16 | t.trfc);
+        self.regs.write(RFSHCTL1, (t.trefi << 15) & 0x0FFF_0000);
+    }
+
+    /// Calibrates the PHY, resets and initialises the memory and trains the DQS gates.
+    fn phy_init(&self, config: &DramConfig) -> Result<(), DramError> {
+        self.regs.modify(MC_CLKEN, |v| v & !0xFFF | (config.clock_mhz / 2 - 1));
+
+        let odt = if config.odt { 0 } else { DXGCR0_ODT_DISABLE };
+        self.regs.modify(DX0GCR0, |v| v & !0xF03E | odt);
+        if !config.half_width {
+            self.regs.modify(DX1GCR0, |v| v & !0xF03E | odt);
+        }
+
+        self.regs.modify(ZQCR, |v| v & !0x00FF_FFFF | (config.zq & 0x00FF_FFFF));
+
+        let pir = PIR_ZCAL | PIR_PLLINIT | PIR_DCAL | PIR_PHYRST | PIR_DRAMRST | PIR_DRAMINIT | PIR_QSGATE;
+        self.regs.write(PIR, pir | PIR_INIT);
+        (self.delay)(10);
+        self.wait(PGSR0, PGSR0_IDONE)?;
+
+        let status = self.regs.read(PGSR0);
+        if status & PGSR0_ERRORS != 0 {
+            return Err(DramError::TrainingFailed(status));
+        }
+
+        self.wait(STATR, STATR_NORMAL)
+    }
+
+    fn wait(&self, reg: Register<ReadOnly>, bit: u32) -> Result<(), DramError> {
+        for _ in 0..PHY_TIMEOUT {
+            if self.regs.read(reg) & bit != 0 {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(DramError::Timeout)
+    }
+
+    fn pattern(i: usize) -> u32 {
+        let value = (D1_DRAM_BASE + 4 * i) as u32;
+        if i.is_multiple_of(2) { value } else { !value }
+    }
+
+    /// True if the memory at `offset` is the same as at the start.
+    fn aliases(&self, offset: usize) -> bool {
+        (0..SCAN_WORDS).all(|i| self.mem.read(offset + 4 * i) == Self::pattern(i))
+    }
+
+    /// Finds the address lines of the memory by looking for the lowest one
+    /// that wraps around, one kind of line at a time.
+    fn scan(&self, config: &DramConfig) -> Result<Geometry, DramError> {
+        let byte_bits = config.byte_bits();
+        let set = |geometry: Geometry| {
+            self.regs.modify(MC_WORK_MODE0, |v| v & !WORK_MODE_GEOMETRY | (config.ranks - 1) | geometry.work_mode());
+            (self.delay)(1);
+        };
+
+        for i in 0..SCAN_WORDS {
+            self.mem.write(4 * i, Self::pattern(i));
+        }
+
+        if !self.aliases(0) {
+            return Err(DramError::NoMemory);
+        }
+
+        set(Geometry::MAX);
+        let rows = (11..16)
+            .find(|row| self.aliases(1 << (byte_bits + Geometry::MAX.columns + Geometry::MAX.banks + row)))
+            .unwrap_or(16);
+
+        let eight_banks = Geometry { rows: 11, banks: 3, columns: 9 };
+        set(eight_banks);
+        let banks = if self.aliases(1 << (byte_bits + eight_banks.columns + 2)) { 2 } else { 3 };
+
+        set(Geometry { rows: 11, banks: 2, columns: 12 });
+        let columns = (8..12).find(|column| self.aliases(1 << (byte_bits + column))).unwrap_or(12);
+
+        Ok(Geometry { rows, banks, columns })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    fn no_delay(_: u64) {}
+
+    /// DDR3 chips as their datasheet describes them: `rows`, `banks` and `columns`
+    /// address lines, 16 data lines. Lines a chip doesn't have are ignored, so
+    /// higher addresses wrap around.
+    #[derive(Debug, Clone, Copy)]
+    struct Part {
+        rows: u32,
+        banks: u32,
+        columns: u32
+    }
+
+    /// MT41K64M16, 128 MiB: 8K rows, 8 banks, 1K columns
+    const MT41K64M16: Part = Part { rows: 13, banks: 3, columns: 10 };
+    /// MT41K256M16, 512 MiB: 32K rows, 8 banks, 1K columns
+    const MT41K256M16: Part = Part { rows: 15, banks: 3, columns: 10 };
+    /// MT41K512M16, 1 GiB: 64K rows, 8 banks, 1K columns
+    const MT41K512M16: Part = Part { rows: 16, banks: 3, columns: 10 };
+
+    /// `part` behind the controller at `regs`.
+    ///
+    /// The controller splits the address into byte, column, bank and row lines
+    /// from the lowest bit up, with the widths of MC_WORK_MODE0: rows - 1 in
+    /// bits 7:4, 8 banks in bit 2 and the page size in bits 11:8, 7 for 1 KiB
+    /// pages up to 10 for 8 KiB pages, that's 9 to 12 columns on 16 data lines.
+    struct Chips<'a> {
+        regs: &'a MockBus,
+        part: Part,
+        cells: RefCell<BTreeMap<(usize, usize, usize), u32>>
+    }
+
+    impl Chips<'_> {
+        fn cell(&self, offset: usize) -> (usize, usize, usize) {
+            let mode = self.regs.get(0x000) as usize;
+            let row_lines = ((mode >> 4) & 0xF) + 1;
+            let bank_lines = if mode & (1 << 2) != 0 { 3 } else { 2 };
+            let column_lines = ((mode >> 8) & 0xF) + 2;
+
+            let lines = |offset: usize, count: usize| offset & ((1 << count) - 1);
+            let column = offset >> 1;
+            let bank = column >> column_lines;
+            let row = bank >> bank_lines;
+            (
+                lines(lines(row, row_lines), self.part.rows as usize),
+                lines(lines(bank, bank_lines), self.part.banks as usize),
+                lines(lines(column, column_lines), self.part.columns as usize)
+            )
+        }
+    }
+
+    impl Bus for Chips<'_> {
+        fn read(&self, offset: usize) -> u32 {
+            self.cells.borrow().get(&self.cell(offset)).copied().unwrap_or(0)
+        }
+
+        fn write(&self, offset: usize, value: u32) {
+            self.cells.borrow_mut().insert(self.cell(offset), value);
+        }
+    }
+
+    fn setup() -> (MockBus, MockBus) {
+        let regs = MockBus::new();
+        regs.set_read_bits(PGSR0.index(), PGSR0_IDONE);
+        regs.set_read_bits(STATR.index(), STATR_NORMAL);
+        let ccu = MockBus::new();
+        // PLL_DDR locks immediately
+        ccu.set_read_bits(0x010, 1 << 28);
+        (regs, ccu)
+    }
+
+    fn init(regs: &MockBus, ccu: &MockBus, part: Part) -> Result<u64, DramError> {
+        let chips = Chips { regs, part, cells: RefCell::new(BTreeMap::new()) };
+        let dram = Dram::new(RegisterBlock::new(regs, 1), chips, no_delay);
+        dram.init(&Ccu::new(RegisterBlock::new(ccu, 1), no_delay), &DramConfig::NEZHA)
+    }
+
+    #[test]
+    fn detects_size() {
+        // 64K rows, 8 banks and 2 KiB pages
+        let (regs, ccu) = setup();
+        assert_eq!(init(&regs, &ccu, MT41K512M16), Ok(1 << 30));
+        assert_eq!(regs.get(MC_WORK_MODE0.index()) & WORK_MODE_GEOMETRY, 0x8F4);
+        assert_eq!(regs.get(MC_WORK_MODE0.index()) & !WORK_MODE_GEOMETRY, WORK_MODE_UNKNOWN | WORK_MODE_DDR3 | WORK_MODE_FULL_WIDTH);
+
+        let (regs, ccu) = setup();
+        assert_eq!(init(&regs, &ccu, MT41K256M16), Ok(512 << 20));
+        assert_eq!(regs.get(MC_WORK_MODE0.index()) & WORK_MODE_GEOMETRY, 0x8E4);
+
+        let (regs, ccu) = setup();
+        assert_eq!(init(&regs, &ccu, MT41K64M16), Ok(128 << 20));
+        assert_eq!(regs.get(MC_WORK_MODE0.index()) & WORK_MODE_GEOMETRY, 0x8C4);
+    }
+
+    #[test]
+    fn sequence() {
+        let (regs, ccu) = setup();
+        init(&regs, &ccu, MT41K64M16).unwrap();
+
+        // PLL_DDR at 1584 MHz feeds the DRAM clock undivided
+        assert_eq!(ccu.get(0x010) & (0xFF << 8), 65 << 8);
+        assert_eq!(ccu.get(0x800) & 0x0700_0303, 0);
+        assert_eq!(ccu.get(0x80C), 1 << 16 | 1);
+
+        // The PHY was set up once to scan and once for the memory found
+        assert_eq!(regs.writes(PIR.index()), [0x5F3, 0x5F3]);
+        assert_eq!(regs.get(MC_CLKEN.index()), 0x8000 | 395);
+        assert_eq!(regs.writes(MC_MAER0.index()), [1, 0xFFFF_FFFF]);
+        assert_eq!([regs.get(0x1030), regs.get(0x1034), regs.get(0x1038)], [0x1C70, 0x42, 0x18]);
+        assert_eq!(regs.get(ZQCR.index()), 0x7B7BFB);
+    }
+
+    #[test]
+    fn failures() {
+        let (regs, ccu) = setup();
+        regs.set_read_bits(PGSR0.index(), PGSR0_IDONE | 1 << 22);
+        assert_eq!(init(&regs, &ccu, MT41K512M16), Err(DramError::TrainingFailed(PGSR0_IDONE | 1 << 22)));
+
+        let regs = MockBus::new();
+        assert_eq!(init(&regs, &ccu, MT41K512M16), Err(DramError::Timeout));
+
+        let config = DramConfig { clock_mhz: 1200, ..DramConfig::NEZHA };
+        let dram = Dram::new(RegisterBlock::new(&regs, 1), MockBus::new(), no_delay);
+        assert_eq!(dram.init(&Ccu::new(RegisterBlock::new(&ccu, 1), no_delay), &config), Err(DramError::BadConfig));
+    }
+
+    #[test]
+    fn ddr3_timing() {
+        let t = Timing::ddr3(792);
+        // Controller clock of 396 MHz
+        assert_eq!((t.trfc, t.trefi, t.trcd, t.tras, t.trc), (139, 97, 6, 16, 21));
+        assert_eq!((t.tcl, t.tcwl, t.mr0), (6, 4, 0x1C70));
+        assert_eq!(t.tdinit, [396_001, 286, 158_401, 793]);
+        assert_eq!(Timing::ddr3(912).mr0, 0x1E14);
+    }
+}
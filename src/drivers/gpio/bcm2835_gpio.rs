@@ -1,11 +1,363 @@
-use crate::arch::aarch64::mmio::MmioReg;
+//! GPIO controller of the BCM2835 and its successors up to the BCM2711.
+
+use core::hint;
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::sync::mutex::Mutex;
+
+use super::{Gpio, Level, Pull, Trigger};
 
 const GPIO_BASE: usize = 0x200000;
 
-pub const GPFSEL1: MmioReg = unsafe { MmioReg::new(GPIO_BASE + 0x04) };
+/// Function select, 3 bits for each of 10 pins per register
+const GPFSEL0: usize = GPIO_BASE;
+
+/// Pin output set, one bit per pin in two registers
+const GPSET0: usize = GPIO_BASE + 0x1C;
+
+/// Pin output clear
+const GPCLR0: usize = GPIO_BASE + 0x28;
+
+/// Pin level
+const GPLEV0: usize = GPIO_BASE + 0x34;
+
+/// Event detect status, write 1 to clear
+const GPEDS0: usize = GPIO_BASE + 0x40;
+
+/// Rising edge detect enable
+const GPREN0: usize = GPIO_BASE + 0x4C;
+
+/// Falling edge detect enable
+const GPFEN0: usize = GPIO_BASE + 0x58;
+
+/// High level detect enable
+const GPHEN0: usize = GPIO_BASE + 0x64;
+
+/// Low level detect enable
+const GPLEN0: usize = GPIO_BASE + 0x70;
+
+/// Controls actuation of pull up/down to ALL GPIO pins, BCM2835 only.
+const GPPUD: Register<ReadWrite> = Register::new(GPIO_BASE + 0x94);
+
+/// Clocks the value of [`GPPUD`] into the pins whose bit is set
+const GPPUDCLK0: usize = GPIO_BASE + 0x98;
+
+/// Pull up/down of the BCM2711, 2 bits for each of 16 pins per register
+const GPIO_PUP_PDN_CNTRL_REG0: usize = GPIO_BASE + 0xE4;
+
+/// Cycles to wait for the pull up/down control to set up and hold
+const PULL_SETUP_CYCLES: usize = 150;
+
+pub const NUM_PINS: usize = 54;
+
+/// Registers with one bit per pin hold this many pins
+const PINS_PER_BANK: usize = 32;
+
+/// A GPIO pin, 0 to 53.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin(u8);
+
+impl Pin {
+    pub const fn new(n: u8) -> Option<Self> {
+        if (n as usize) < NUM_PINS {
+            Some(Self(n))
+        } else {
+            None
+        }
+    }
+
+    pub const fn number(&self) -> u8 {
+        self.0
+    }
+
+    /// Register `offset` of the bank with one bit per pin that has this pin.
+    const fn bank<A>(&self, offset: usize) -> Register<A> {
+        Register::new(offset + self.0 as usize / PINS_PER_BANK * 4)
+    }
+
+    const fn bit(&self) -> u32 {
+        1 << (self.0 as usize % PINS_PER_BANK)
+    }
+}
+
+/// Pin functions, the alternate functions are numbered as in the datasheet but not encoded in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010
+}
+
+impl Function {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b111 {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alt0,
+            0b101 => Function::Alt1,
+            0b110 => Function::Alt2,
+            0b111 => Function::Alt3,
+            0b011 => Function::Alt4,
+            _ => Function::Alt5
+        }
+    }
+}
+
+/// The pull up/down control changed with the BCM2711 of the Raspberry Pi 4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// BCM2835 to BCM2837, pulls are clocked into the pins through [`GPPUD`]
+    Bcm2835,
+    /// Pulls are set directly per pin
+    Bcm2711
+}
+
+/// Event handlers of the pins
+type Handlers = [Option<fn(Pin)>; NUM_PINS];
+
+pub struct Bcm2835Gpio<B: Bus> {
+    regs: RegisterBlock<B>,
+    model: fn() -> Model,
+    handlers: Mutex<Handlers>
+}
+
+impl<B: Bus> Bcm2835Gpio<B> {
+    /// `regs` are the peripherals, registers are at their offset from the peripheral base.
+    /// `model` is only asked once a pin is configured, so a static can be made before the SoC is detected.
+    pub const fn new(regs: RegisterBlock<B>, model: fn() -> Model) -> Self {
+        Self {
+            regs,
+            model,
+            handlers: Mutex::new([None; NUM_PINS])
+        }
+    }
+
+    pub fn set_function(&self, pin: Pin, function: Function) {
+        let n = pin.number() as usize;
+        let reg = Register::new(GPFSEL0 + n / 10 * 4);
+        let shift = n % 10 * 3;
+        self.regs.modify(reg, |v| v & !(0b111 << shift) | (function as u32) << shift);
+    }
+
+    pub fn function(&self, pin: Pin) -> Function {
+        let n = pin.number() as usize;
+        let reg: Register<ReadOnly> = Register::new(GPFSEL0 + n / 10 * 4);
+        Function::from_bits(self.regs.read(reg) >> (n % 10 * 3))
+    }
+
+    fn wait_cycles() {
+        for _ in 0..PULL_SETUP_CYCLES {
+            hint::spin_loop();
+        }
+    }
+
+    /// Enables `trigger` on `pin` and disables the other triggers.
+    fn set_trigger(&self, pin: Pin, trigger: Option<Trigger>) {
+        let enables = [
+            (GPREN0, matches!(trigger, Some(Trigger::RisingEdge | Trigger::BothEdges))),
+            (GPFEN0, matches!(trigger, Some(Trigger::FallingEdge | Trigger::BothEdges))),
+            (GPHEN0, trigger == Some(Trigger::HighLevel)),
+            (GPLEN0, trigger == Some(Trigger::LowLevel))
+        ];
+
+        for (offset, enable) in enables {
+            if enable {
+                self.regs.set_bits(pin.bank(offset), pin.bit());
+            } else {
+                self.regs.clear_bits(pin.bank(offset), pin.bit());
+            }
+        }
+    }
+}
+
+impl<B: Bus> Gpio for Bcm2835Gpio<B> {
+    type Pin = Pin;
+
+    fn set_input(&self, pin: Pin) {
+        self.set_function(pin, Function::Input);
+    }
+
+    fn set_output(&self, pin: Pin, level: Level) {
+        self.write(pin, level);
+        self.set_function(pin, Function::Output);
+    }
+
+    fn write(&self, pin: Pin, level: Level) {
+        let reg: Register<WriteOnly> = pin.bank(if level == Level::High { GPSET0 } else { GPCLR0 });
+        self.regs.write(reg, pin.bit());
+    }
+
+    fn read(&self, pin: Pin) -> Level {
+        let reg: Register<ReadOnly> = pin.bank(GPLEV0);
+        Level::from(self.regs.read(reg) & pin.bit() != 0)
+    }
+
+    fn set_pull(&self, pin: Pin, pull: Pull) {
+        match (self.model)() {
+            Model::Bcm2835 => {
+                let control = match pull {
+                    Pull::None => 0,
+                    Pull::Down => 1,
+                    Pull::Up => 2
+                };
+                let clock: Register<ReadWrite> = pin.bank(GPPUDCLK0);
+                self.regs.write(GPPUD, control);
+                Self::wait_cycles();
+                self.regs.write(clock, pin.bit());
+                Self::wait_cycles();
+                self.regs.write(GPPUD, 0);
+                self.regs.write(clock, 0);
+            },
+            Model::Bcm2711 => {
+                let control = match pull {
+                    Pull::None => 0,
+                    Pull::Up => 1,
+                    Pull::Down => 2
+                };
+                let n = pin.number() as usize;
+                let reg = Register::new(GPIO_PUP_PDN_CNTRL_REG0 + n / 16 * 4);
+                let shift = n % 16 * 2;
+                self.regs.modify(reg, |v| v & !(0b11 << shift) | control << shift);
+            }
+        }
+    }
+
+    fn enable_event(&self, pin: Pin, trigger: Trigger, handler: fn(Pin)) {
+        self.handlers.lock()[pin.number() as usize] = Some(handler);
+        self.set_trigger(pin, Some(trigger));
+    }
+
+    fn disable_event(&self, pin: Pin) {
+        self.set_trigger(pin, None);
+        let status: Register<ReadWrite> = pin.bank(GPEDS0);
+        self.regs.write(status, pin.bit());
+        self.handlers.lock()[pin.number() as usize] = None;
+    }
+
+    /// Level events are raised again right away as long as the level holds.
+    fn handle_interrupt(&self) {
+        for bank in 0..NUM_PINS.div_ceil(PINS_PER_BANK) {
+            let status: Register<ReadWrite> = Register::new(GPEDS0 + bank * 4);
+            let pending = self.regs.read(status);
+            if pending == 0 {
+                continue;
+            }
+
+            self.regs.write(status, pending);
+            for bit in (0..PINS_PER_BANK).filter(|bit| pending & (1 << bit) != 0) {
+                let Some(pin) = Pin::new((bank * PINS_PER_BANK + bit) as u8) else {
+                    continue;
+                };
+
+                let handler = self.handlers.lock()[pin.number() as usize];
+                if let Some(handler) = handler {
+                    handler(pin);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    fn pin(n: u8) -> Pin {
+        Pin::new(n).unwrap()
+    }
+
+    #[test]
+    fn pins() {
+        assert_eq!(Pin::new(53).map(|p| p.number()), Some(53));
+        assert_eq!(Pin::new(54), None);
+        assert_eq!(pin(33).bank::<ReadOnly>(GPLEV0).index(), GPLEV0 + 4);
+        assert_eq!(pin(33).bit(), 1 << 1);
+    }
+
+    #[test]
+    fn functions() {
+        let bus = MockBus::new();
+        let gpio = Bcm2835Gpio::new(RegisterBlock::new(&bus, 1), || Model::Bcm2835);
+        bus.set(GPFSEL0 + 4, 0xFFFF_FFFF);
+        gpio.set_function(pin(14), Function::Alt5);
+        gpio.set_function(pin(15), Function::Alt5);
+        assert_eq!(bus.get(GPFSEL0 + 4), !(0o55 << 12));
+        assert_eq!(gpio.function(pin(14)), Function::Alt5);
+
+        gpio.set_function(pin(53), Function::Alt4);
+        assert_eq!(bus.get(GPFSEL0 + 20), 0b011 << 9);
+        assert_eq!(gpio.function(pin(53)), Function::Alt4);
+    }
+
+    #[test]
+    fn levels() {
+        let bus = MockBus::new();
+        let gpio = Bcm2835Gpio::new(RegisterBlock::new(&bus, 1), || Model::Bcm2835);
+        gpio.set_output(pin(40), Level::High);
+        gpio.write(pin(2), Level::Low);
+        assert_eq!(bus.writes(GPSET0 + 4), [1 << 8]);
+        assert_eq!(bus.writes(GPCLR0), [1 << 2]);
+        assert_eq!(gpio.function(pin(40)), Function::Output);
+
+        bus.set(GPLEV0 + 4, 1 << 8);
+        assert_eq!(gpio.read(pin(40)), Level::High);
+        assert_eq!(gpio.read(pin(41)), Level::Low);
+    }
+
+    #[test]
+    fn pulls() {
+        let bus = MockBus::new();
+        let gpio = Bcm2835Gpio::new(RegisterBlock::new(&bus, 1), || Model::Bcm2835);
+        gpio.set_pull(pin(35), Pull::Up);
+        assert_eq!(bus.writes(GPPUD.index()), [2, 0]);
+        assert_eq!(bus.writes(GPPUDCLK0 + 4), [1 << 3, 0]);
+
+        let bus = MockBus::new();
+        let gpio = Bcm2835Gpio::new(RegisterBlock::new(&bus, 1), || Model::Bcm2711);
+        bus.set(GPIO_PUP_PDN_CNTRL_REG0 + 4, 0xFFFF_FFFF);
+        gpio.set_pull(pin(17), Pull::Up);
+        gpio.set_pull(pin(18), Pull::None);
+        assert_eq!(bus.get(GPIO_PUP_PDN_CNTRL_REG0 + 4), 0xFFFF_FFC7);
+        assert!(bus.writes(GPPUD.index()).is_empty());
+    }
+
+    #[test]
+    fn events() {
+        static SEEN: AtomicU32 = AtomicU32::new(0);
+        fn handler(pin: Pin) {
+            SEEN.fetch_or(1 << (pin.number() - 30), Ordering::Relaxed);
+        }
+
+        let bus = MockBus::new();
+        let gpio = Bcm2835Gpio::new(RegisterBlock::new(&bus, 1), || Model::Bcm2835);
+        gpio.enable_event(pin(33), Trigger::BothEdges, handler);
+        gpio.enable_event(pin(31), Trigger::LowLevel, handler);
+        assert_eq!(bus.get(GPREN0 + 4), 1 << 1);
+        assert_eq!(bus.get(GPFEN0 + 4), 1 << 1);
+        assert_eq!(bus.get(GPLEN0), 1 << 31);
+
+        gpio.enable_event(pin(33), Trigger::RisingEdge, handler);
+        assert_eq!(bus.get(GPFEN0 + 4), 0);
 
-/// Controls actuation of pull up/down to ALL GPIO pins.
-pub const GPPUD: MmioReg = unsafe { MmioReg::new(GPIO_BASE + 0x94) };
+        // Pin 34 has no handler
+        bus.script(GPEDS0, [1 << 31]);
+        bus.script(GPEDS0 + 4, [1 << 1 | 1 << 2]);
+        gpio.handle_interrupt();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 1 << 1 | 1 << 3);
+        assert_eq!(bus.writes(GPEDS0), [1 << 31]);
+        assert_eq!(bus.writes(GPEDS0 + 4), [1 << 1 | 1 << 2]);
 
-/// Controls actuation of pull up/down for specific GPIO pin.
-pub const GPPUDCLK0: MmioReg = unsafe { MmioReg::new(GPIO_BASE + 0x98) };
+        gpio.disable_event(pin(31));
+        assert_eq!(bus.get(GPLEN0), 0);
+        assert!(gpio.handlers.lock()[31].is_none());
+    }
+}
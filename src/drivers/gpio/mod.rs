@@ -1,2 +1,64 @@
-#[cfg(target_arch = "aarch64")]
+//! General purpose I/O pins, one driver per pin controller behind the [`Gpio`] trait.
+
+#[cfg(any(target_arch = "aarch64", test))]
 pub mod bcm2835_gpio;
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod sun20i_d1_pio;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High
+}
+
+impl From<bool> for Level {
+    fn from(high: bool) -> Self {
+        if high { Level::High } else { Level::Low }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down
+}
+
+/// Condition that raises an event on an input pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    /// Keeps raising events as long as the pin is high
+    HighLevel,
+    LowLevel
+}
+
+/// A pin controller.
+pub trait Gpio {
+    /// Identifies a pin, only valid pins can be constructed.
+    type Pin: Copy;
+
+    fn set_input(&self, pin: Self::Pin);
+
+    /// Drives `pin` at `level`, which is set before switching the pin to an output so it doesn't glitch.
+    fn set_output(&self, pin: Self::Pin, level: Level);
+
+    fn write(&self, pin: Self::Pin, level: Level);
+
+    fn read(&self, pin: Self::Pin) -> Level;
+
+    fn set_pull(&self, pin: Self::Pin, pull: Pull);
+
+    /// Calls `handler` from [`Gpio::handle_interrupt`] whenever `trigger` happens on `pin`.
+    fn enable_event(&self, pin: Self::Pin, trigger: Trigger, handler: fn(Self::Pin));
+
+    fn disable_event(&self, pin: Self::Pin);
+
+    /// Acknowledges the pending events and calls their handlers.
+    ///
+    /// The caller is responsible for routing the interrupt of the controller here.
+    /// Handlers run without the controller locked, so they can change events themselves.
+    fn handle_interrupt(&self);
+}
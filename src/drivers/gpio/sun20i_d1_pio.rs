@@ -0,0 +1,373 @@
+//! Pin controller (PIO) of the Allwinner D1.
+
+use crate::drivers::bus::{Bus, ReadWrite, Register, RegisterBlock};
+use crate::sync::mutex::Mutex;
+
+use super::{Gpio, Level, Pull, Trigger};
+
+pub const D1_PIO_BASE: usize = 0x02000000;
+
+/// Registers of port n start at `n * PORT_SIZE`, port A doesn't exist
+const PORT_SIZE: usize = 0x30;
+
+/// Function select, 4 bits for each of 8 pins per register
+const CFG0: usize = 0x00;
+const DAT: usize = 0x10;
+
+/// Drive strength, 4 bits for each of 8 pins per register
+const DRV0: usize = 0x14;
+
+/// Pull up/down, 2 bits for each of 16 pins per register
+const PULL0: usize = 0x24;
+
+/// External interrupt registers of port n start at `EINT_BASE + n * EINT_SIZE`
+const EINT_BASE: usize = 0x200;
+const EINT_SIZE: usize = 0x20;
+
+/// Trigger condition, 4 bits for each of 8 pins per register
+const EINT_CFG0: usize = 0x00;
+const EINT_CTL: usize = 0x10;
+
+/// Pending interrupts, write 1 to clear
+const EINT_STATUS: usize = 0x14;
+
+const EINT_POSITIVE_EDGE: u32 = 0;
+const EINT_NEGATIVE_EDGE: u32 = 1;
+const EINT_HIGH_LEVEL: u32 = 2;
+const EINT_LOW_LEVEL: u32 = 3;
+const EINT_DOUBLE_EDGE: u32 = 4;
+
+/// Registers with one bit per pin have room for this many pins
+const PINS_PER_PORT: usize = 32;
+
+const NUM_PORTS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    PB = 1,
+    PC = 2,
+    PD = 3,
+    PE = 4,
+    PF = 5,
+    PG = 6
+}
+
+impl Port {
+    pub const fn num_pins(&self) -> u8 {
+        match self {
+            Port::PB => 13,
+            Port::PC => 8,
+            Port::PD => 23,
+            Port::PE => 18,
+            Port::PF => 7,
+            Port::PG => 19
+        }
+    }
+
+    /// Register `offset` of the port.
+    const fn reg(&self, offset: usize) -> Register<ReadWrite> {
+        Register::new(*self as usize * PORT_SIZE + offset)
+    }
+
+    /// Register `offset` of the external interrupts of the port.
+    const fn eint_reg(&self, offset: usize) -> Register<ReadWrite> {
+        Register::new(EINT_BASE + *self as usize * EINT_SIZE + offset)
+    }
+
+    /// Index of the port in per port tables.
+    const fn index(&self) -> usize {
+        *self as usize - 1
+    }
+}
+
+/// A pin of one of the ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    port: Port,
+    n: u8
+}
+
+impl Pin {
+    pub const fn new(port: Port, n: u8) -> Option<Self> {
+        if n < port.num_pins() {
+            Some(Self { port, n })
+        } else {
+            None
+        }
+    }
+
+    pub const fn port(&self) -> Port {
+        self.port
+    }
+
+    pub const fn number(&self) -> u8 {
+        self.n
+    }
+
+    /// Offset from the first of the registers with `bits` bits per pin to
+    /// the one that has the pin, and the shift of the pin in it.
+    const fn field(&self, bits: usize) -> (usize, usize) {
+        let per_reg = 32 / bits;
+        let n = self.n as usize;
+        (n / per_reg * 4, n % per_reg * bits)
+    }
+
+    const fn bit(&self) -> u32 {
+        1 << self.n
+    }
+}
+
+/// Pin functions, what the peripheral functions 2 to 8 are depends on the pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Function {
+    Input = 0,
+    Output = 1,
+    Function2 = 2,
+    Function3 = 3,
+    Function4 = 4,
+    Function5 = 5,
+    Function6 = 6,
+    Function7 = 7,
+    Function8 = 8,
+    /// External interrupt input
+    Eint = 14,
+    /// Reset state of most pins
+    Disabled = 15
+}
+
+impl Function {
+    fn from_bits(bits: u32) -> Option<Self> {
+        Some(match bits & 0xF {
+            0 => Function::Input,
+            1 => Function::Output,
+            2 => Function::Function2,
+            3 => Function::Function3,
+            4 => Function::Function4,
+            5 => Function::Function5,
+            6 => Function::Function6,
+            7 => Function::Function7,
+            8 => Function::Function8,
+            14 => Function::Eint,
+            15 => Function::Disabled,
+            _ => return None
+        })
+    }
+}
+
+/// Output drive strength, from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DriveStrength {
+    Level0 = 0,
+    Level1 = 1,
+    Level2 = 2,
+    Level3 = 3
+}
+
+/// Event handlers of the pins of one port
+type PortHandlers = [Option<fn(Pin)>; PINS_PER_PORT];
+
+pub struct Pio<B: Bus> {
+    regs: RegisterBlock<B>,
+    handlers: Mutex<[PortHandlers; NUM_PORTS]>
+}
+
+impl<B: Bus> Pio<B> {
+    pub const fn new(regs: RegisterBlock<B>) -> Self {
+        Self {
+            regs,
+            handlers: Mutex::new([[None; PINS_PER_PORT]; NUM_PORTS])
+        }
+    }
+
+    pub fn set_function(&self, pin: Pin, function: Function) {
+        let (offset, shift) = pin.field(4);
+        let reg = pin.port.reg(CFG0 + offset);
+        self.regs.modify(reg, |v| v & !(0xF << shift) | (function as u32) << shift);
+    }
+
+    /// Muxes all of `pins` to `function`.
+    pub fn set_functions(&self, pins: &[Pin], function: Function) {
+        for &pin in pins {
+            self.set_function(pin, function);
+        }
+    }
+
+    /// Returns `None` for the reserved function numbers.
+    pub fn function(&self, pin: Pin) -> Option<Function> {
+        let (offset, shift) = pin.field(4);
+        let reg = pin.port.reg(CFG0 + offset);
+        Function::from_bits(self.regs.read(reg) >> shift)
+    }
+
+    pub fn set_drive_strength(&self, pin: Pin, strength: DriveStrength) {
+        let (offset, shift) = pin.field(4);
+        let reg = pin.port.reg(DRV0 + offset);
+        self.regs.modify(reg, |v| v & !(0x3 << shift) | (strength as u32) << shift);
+    }
+
+    fn set_trigger(&self, pin: Pin, trigger: Trigger) {
+        let mode = match trigger {
+            Trigger::RisingEdge => EINT_POSITIVE_EDGE,
+            Trigger::FallingEdge => EINT_NEGATIVE_EDGE,
+            Trigger::BothEdges => EINT_DOUBLE_EDGE,
+            Trigger::HighLevel => EINT_HIGH_LEVEL,
+            Trigger::LowLevel => EINT_LOW_LEVEL
+        };
+        let (offset, shift) = pin.field(4);
+        let reg = pin.port.eint_reg(EINT_CFG0 + offset);
+        self.regs.modify(reg, |v| v & !(0xF << shift) | mode << shift);
+    }
+}
+
+impl<B: Bus> Gpio for Pio<B> {
+    type Pin = Pin;
+
+    fn set_input(&self, pin: Pin) {
+        self.set_function(pin, Function::Input);
+    }
+
+    fn set_output(&self, pin: Pin, level: Level) {
+        self.write(pin, level);
+        self.set_function(pin, Function::Output);
+    }
+
+    fn write(&self, pin: Pin, level: Level) {
+        let reg = pin.port.reg(DAT);
+        match level {
+            Level::High => self.regs.set_bits(reg, pin.bit()),
+            Level::Low => self.regs.clear_bits(reg, pin.bit())
+        }
+    }
+
+    fn read(&self, pin: Pin) -> Level {
+        Level::from(self.regs.read(pin.port.reg(DAT)) & pin.bit() != 0)
+    }
+
+    fn set_pull(&self, pin: Pin, pull: Pull) {
+        let control = match pull {
+            Pull::None => 0,
+            Pull::Up => 1,
+            Pull::Down => 2
+        };
+        let (offset, shift) = pin.field(2);
+        let reg = pin.port.reg(PULL0 + offset);
+        self.regs.modify(reg, |v| v & !(0x3 << shift) | control << shift);
+    }
+
+    /// Also muxes `pin` to [`Function::Eint`].
+    fn enable_event(&self, pin: Pin, trigger: Trigger, handler: fn(Pin)) {
+        self.handlers.lock()[pin.port.index()][pin.n as usize] = Some(handler);
+        self.set_trigger(pin, trigger);
+        self.set_function(pin, Function::Eint);
+        self.regs.write(pin.port.eint_reg(EINT_STATUS), pin.bit());
+        self.regs.set_bits(pin.port.eint_reg(EINT_CTL), pin.bit());
+    }
+
+    /// Makes `pin` an input again.
+    fn disable_event(&self, pin: Pin) {
+        self.regs.clear_bits(pin.port.eint_reg(EINT_CTL), pin.bit());
+        self.regs.write(pin.port.eint_reg(EINT_STATUS), pin.bit());
+        self.set_function(pin, Function::Input);
+        self.handlers.lock()[pin.port.index()][pin.n as usize] = None;
+    }
+
+    /// Every port has its own interrupt, all of them can be routed here.
+    fn handle_interrupt(&self) {
+        for port in [Port::PB, Port::PC, Port::PD, Port::PE, Port::PF, Port::PG] {
+            let pending = self.regs.read(port.eint_reg(EINT_STATUS));
+            if pending == 0 {
+                continue;
+            }
+
+            self.regs.write(port.eint_reg(EINT_STATUS), pending);
+            for n in (0..port.num_pins()).filter(|n| pending & (1 << n) != 0) {
+                let handler = self.handlers.lock()[port.index()][n as usize];
+                if let Some(handler) = handler {
+                    handler(Pin { port, n });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    fn pin(port: Port, n: u8) -> Pin {
+        Pin::new(port, n).unwrap()
+    }
+
+    #[test]
+    fn pins() {
+        assert!(Pin::new(Port::PB, 12).is_some());
+        assert_eq!(Pin::new(Port::PB, 13), None);
+        assert_eq!(Pin::new(Port::PF, 7), None);
+        assert_eq!(Port::PF.reg(CFG0).index(), 0xF0);
+        assert_eq!(Port::PB.eint_reg(EINT_CFG0).index(), 0x220);
+    }
+
+    #[test]
+    fn functions() {
+        let bus = MockBus::new();
+        let pio = Pio::new(RegisterBlock::new(&bus, 1));
+        bus.set(0x34, 0xFFFF_FFFF);
+        pio.set_functions(&[pin(Port::PB, 8), pin(Port::PB, 9)], Function::Function6);
+        assert_eq!(bus.get(0x34), 0xFFFF_FF66);
+        assert_eq!(pio.function(pin(Port::PB, 9)), Some(Function::Function6));
+
+        pio.set_function(pin(Port::PD, 22), Function::Output);
+        assert_eq!(bus.get(0x90 + 0x08), 1 << 24);
+        bus.set(0x90, 0xA);
+        assert_eq!(pio.function(pin(Port::PD, 0)), None);
+    }
+
+    #[test]
+    fn data_drive_and_pulls() {
+        let bus = MockBus::new();
+        let pio = Pio::new(RegisterBlock::new(&bus, 1));
+        pio.set_output(pin(Port::PC, 1), Level::High);
+        assert_eq!(bus.get(0x60 + DAT), 1 << 1);
+        assert_eq!(pio.read(pin(Port::PC, 1)), Level::High);
+        pio.write(pin(Port::PC, 1), Level::Low);
+        assert_eq!(pio.read(pin(Port::PC, 1)), Level::Low);
+
+        pio.set_drive_strength(pin(Port::PE, 9), DriveStrength::Level3);
+        assert_eq!(bus.get(0xC0 + DRV0 + 4), 3 << 4);
+
+        pio.set_pull(pin(Port::PG, 17), Pull::Down);
+        pio.set_pull(pin(Port::PG, 0), Pull::Up);
+        assert_eq!(bus.get(0x120 + PULL0 + 4), 2 << 2);
+        assert_eq!(bus.get(0x120 + PULL0), 1);
+    }
+
+    #[test]
+    fn events() {
+        static SEEN: AtomicU32 = AtomicU32::new(0);
+        fn handler(pin: Pin) {
+            SEEN.fetch_or(1 << pin.number(), Ordering::Relaxed);
+        }
+
+        let bus = MockBus::new();
+        let pio = Pio::new(RegisterBlock::new(&bus, 1));
+        pio.enable_event(pin(Port::PE, 10), Trigger::BothEdges, handler);
+        assert_eq!(pio.function(pin(Port::PE, 10)), Some(Function::Eint));
+        assert_eq!(bus.get(0x280 + EINT_CFG0 + 4), EINT_DOUBLE_EDGE << 8);
+        assert_eq!(bus.get(0x280 + EINT_CTL), 1 << 10);
+
+        // PE11 has no handler
+        bus.script(0x280 + EINT_STATUS, [1 << 10 | 1 << 11]);
+        pio.handle_interrupt();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 1 << 10);
+        assert_eq!(bus.writes(0x280 + EINT_STATUS).last(), Some(&(1 << 10 | 1 << 11)));
+
+        pio.disable_event(pin(Port::PE, 10));
+        assert_eq!(bus.get(0x280 + EINT_CTL), 0);
+        assert_eq!(pio.function(pin(Port::PE, 10)), Some(Function::Input));
+    }
+}
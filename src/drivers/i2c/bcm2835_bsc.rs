@@ -0,0 +1,205 @@
+//! Broadcom Serial Controller (BSC), the I2C master of the BCM2835 and BCM2711.
+//!
+//! The controller runs whole messages by itself out of a 16 byte FIFO. It has
+//! no way to ask for a repeated start, but starting the next message while a
+//! write is still going on gets one, which is what `write_read` relies on.
+
+use core::hint;
+
+use crate::drivers::bus::{Bus, ReadWrite, Register, RegisterBlock};
+
+use super::{I2cBus, I2cError, Op};
+
+/// Offsets from the peripheral base, BSC0 is on the pins of the HAT ID EEPROM
+pub const BSC0_BASE: usize = 0x205000;
+pub const BSC1_BASE: usize = 0x804000;
+
+const BSC_C: Register<ReadWrite> = Register::new(0x00);
+
+/// Status, the error and done bits are cleared by writing 1
+const BSC_S: Register<ReadWrite> = Register::new(0x04);
+const BSC_DLEN: Register<ReadWrite> = Register::new(0x08);
+const BSC_A: Register<ReadWrite> = Register::new(0x0C);
+const BSC_FIFO: Register<ReadWrite> = Register::new(0x10);
+
+/// The core clock is divided by this, rounded down to an even number
+const BSC_DIV: Register<ReadWrite> = Register::new(0x14);
+
+const C_READ: u32 = 1 << 0;
+const C_CLEAR: u32 = 0x3 << 4;
+const C_ST: u32 = 1 << 7;
+const C_I2CEN: u32 = 1 << 15;
+
+/// Transfer active
+const S_TA: u32 = 1 << 0;
+const S_DONE: u32 = 1 << 1;
+/// The FIFO has room
+const S_TXD: u32 = 1 << 4;
+/// The FIFO has data
+const S_RXD: u32 = 1 << 5;
+/// The address or a byte wasn't acknowledged
+const S_ERR: u32 = 1 << 8;
+/// A device held the clock low for too long
+const S_CLKT: u32 = 1 << 9;
+
+const DIV_MAX: u32 = 0xFFFE;
+
+/// Polls of the status before giving up
+const TIMEOUT: usize = 1_000_000;
+
+pub struct Bsc<B: Bus> {
+    regs: RegisterBlock<B>,
+    /// Offset of the registers on the bus
+    base: usize
+}
+
+impl<B: Bus> Bsc<B> {
+    pub const fn new(regs: RegisterBlock<B>, base: usize) -> Self {
+        Self { regs, base }
+    }
+
+    /// Sets SCL as close to `rate` as it gets without going above it and returns it,
+    /// `core_clock` is the rate of the VideoCore clock that feeds the controller.
+    pub fn init(&self, core_clock: u32, rate: u32) -> u32 {
+        let div = core_clock.div_ceil(rate).next_multiple_of(2).clamp(2, DIV_MAX);
+        self.regs.write(self.reg(BSC_DIV), div);
+        self.regs.write(self.reg(BSC_C), C_I2CEN | C_CLEAR);
+        self.regs.write(self.reg(BSC_S), S_CLKT | S_ERR | S_DONE);
+        core_clock / div
+    }
+
+    fn reg<A>(&self, reg: Register<A>) -> Register<A> {
+        Register::new(self.base + reg.index())
+    }
+
+    /// Waits for one of the status `bits`, failing early on errors.
+    fn wait(&self, bits: u32) -> Result<(), I2cError> {
+        for _ in 0..TIMEOUT {
+            let status = self.regs.read(self.reg(BSC_S));
+            if status & S_ERR != 0 {
+                return Err(I2cError::Nack);
+            }
+            if status & S_CLKT != 0 {
+                return Err(I2cError::Timeout);
+            }
+            if status & bits != 0 {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(I2cError::Timeout)
+    }
+
+    fn message(&self, op: &mut Op<'_>, last: bool) -> Result<(), I2cError> {
+        self.regs.write(self.reg(BSC_S), S_DONE);
+        match op {
+            Op::Write(data) => {
+                self.regs.write(self.reg(BSC_DLEN), data.len() as u32);
+                self.regs.write(self.reg(BSC_C), C_I2CEN | C_ST);
+                for &byte in data.iter() {
+                    self.wait(S_TXD)?;
+                    self.regs.write(self.reg(BSC_FIFO), byte as u32);
+                }
+
+                // The next message has to be started while this one is active to get a repeated start
+                if !last {
+                    self.wait(S_TA | S_DONE)?;
+                }
+            },
+            Op::Read(buf) => {
+                self.regs.write(self.reg(BSC_DLEN), buf.len() as u32);
+                self.regs.write(self.reg(BSC_C), C_I2CEN | C_ST | C_READ);
+                for byte in buf.iter_mut() {
+                    self.wait(S_RXD)?;
+                    *byte = self.regs.read(self.reg(BSC_FIFO)) as u8;
+                }
+            }
+        }
+
+        if last {
+            self.wait(S_DONE)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: Bus> I2cBus for Bsc<B> {
+    fn transfer(&self, addr: u8, ops: &mut [Op<'_>]) -> Result<(), I2cError> {
+        if addr > 0x7F {
+            return Err(I2cError::InvalidAddress);
+        }
+
+        self.regs.write(self.reg(BSC_C), C_I2CEN | C_CLEAR);
+        self.regs.write(self.reg(BSC_S), S_CLKT | S_ERR | S_DONE);
+        self.regs.write(self.reg(BSC_A), addr as u32);
+
+        let len = ops.len();
+        let result = ops.iter_mut()
+            .enumerate()
+            .try_for_each(|(i, op)| self.message(op, i + 1 == len));
+
+        // The controller sends the stop by itself, even after an error
+        self.regs.write(self.reg(BSC_C), C_I2CEN | C_CLEAR);
+        self.regs.write(self.reg(BSC_S), S_CLKT | S_ERR | S_DONE);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    fn reg<A>(reg: Register<A>) -> usize {
+        BSC1_BASE + reg.index()
+    }
+
+    #[test]
+    fn init() {
+        let regs = MockBus::new();
+        let bsc = Bsc::new(RegisterBlock::new(&regs, 1), BSC1_BASE);
+
+        // 250 MHz / 2500, and / 626 for 400 kHz since odd dividers get rounded down
+        assert_eq!(bsc.init(250_000_000, 100_000), 100_000);
+        assert_eq!(regs.get(reg(BSC_DIV)), 2500);
+        assert_eq!(bsc.init(250_000_000, 400_000), 399_361);
+        assert_eq!(regs.get(reg(BSC_DIV)), 626);
+        assert_eq!(bsc.init(250_000_000, 1000), 3814);
+        assert_eq!(regs.get(reg(BSC_C)), C_I2CEN | C_CLEAR);
+    }
+
+    #[test]
+    fn write_read() {
+        let regs = MockBus::new();
+        regs.set_write_clears(reg(BSC_S), S_CLKT | S_ERR | S_DONE);
+        // Room for the register number, still active, two bytes in and done
+        regs.script(reg(BSC_S), [S_TXD, S_TA, S_RXD, S_RXD, S_DONE]);
+        regs.script(reg(BSC_FIFO), [0x12, 0x34]);
+        let bsc = Bsc::new(RegisterBlock::new(&regs, 1), BSC1_BASE);
+
+        let mut buf = [0; 2];
+        bsc.write_read(0x68, &[0x05], &mut buf).unwrap();
+        assert_eq!(buf, [0x12, 0x34]);
+        assert_eq!(regs.writes(reg(BSC_A)), [0x68]);
+        assert_eq!(regs.writes(reg(BSC_DLEN)), [1, 2]);
+        assert_eq!(regs.writes(reg(BSC_FIFO)), [0x05]);
+        assert_eq!(regs.writes(reg(BSC_C)), [C_I2CEN | C_CLEAR, C_I2CEN | C_ST, C_I2CEN | C_ST | C_READ, C_I2CEN | C_CLEAR]);
+    }
+
+    #[test]
+    fn errors() {
+        let regs = MockBus::new();
+        let bsc = Bsc::new(RegisterBlock::new(&regs, 1), BSC0_BASE);
+        regs.set_write_clears(BSC0_BASE + BSC_S.index(), S_CLKT | S_ERR | S_DONE);
+        regs.set_read_bits(BSC0_BASE + BSC_S.index(), S_ERR);
+        assert_eq!(bsc.read(0x50, &mut [0]), Err(I2cError::Nack));
+        regs.set_read_bits(BSC0_BASE + BSC_S.index(), S_CLKT);
+        assert_eq!(bsc.read(0x50, &mut [0]), Err(I2cError::Timeout));
+        regs.set_read_bits(BSC0_BASE + BSC_S.index(), 0);
+        assert_eq!(bsc.write(0x50, &[]), Err(I2cError::Timeout));
+        assert_eq!(bsc.write(0x80, &[]), Err(I2cError::InvalidAddress));
+    }
+}
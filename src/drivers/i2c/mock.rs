@@ -0,0 +1,126 @@
+//! Fake I2C bus for host tests, with devices that have 256 byte registers.
+
+extern crate std;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+use super::{I2cBus, I2cError, Op};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Write(u8, Vec<u8>),
+    /// Address and number of bytes
+    Read(u8, usize)
+}
+
+/// A device like most sensors, EEPROMs and RTCs: the first byte written
+/// selects a register, reads and further writes go on from there.
+struct Device {
+    regs: [u8; 256],
+    pointer: u8
+}
+
+#[derive(Default)]
+struct State {
+    devices: BTreeMap<u8, Device>,
+    log: Vec<Message>
+}
+
+/// Devices backed by plain memory, addresses without a device don't acknowledge.
+#[derive(Default)]
+pub struct MockI2c {
+    state: RefCell<State>
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_device(&self, addr: u8) {
+        self.state.borrow_mut().devices.insert(addr, Device { regs: [0; 256], pointer: 0 });
+    }
+
+    /// Sets registers of a device starting at `reg`, without logging a message.
+    pub fn set(&self, addr: u8, reg: u8, values: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let device = state.devices.get_mut(&addr).expect("no such device");
+        for (i, &value) in values.iter().enumerate() {
+            device.regs[reg.wrapping_add(i as u8) as usize] = value;
+        }
+    }
+
+    /// Returns a register of a device without logging a message.
+    pub fn get(&self, addr: u8, reg: u8) -> u8 {
+        self.state.borrow().devices.get(&addr).expect("no such device").regs[reg as usize]
+    }
+
+    pub fn log(&self) -> Vec<Message> {
+        self.state.borrow().log.clone()
+    }
+
+    pub fn clear_log(&self) {
+        self.state.borrow_mut().log.clear();
+    }
+}
+
+impl I2cBus for MockI2c {
+    fn transfer(&self, addr: u8, ops: &mut [Op<'_>]) -> Result<(), I2cError> {
+        if addr > 0x7F {
+            return Err(I2cError::InvalidAddress);
+        }
+
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let device = state.devices.get_mut(&addr).ok_or(I2cError::Nack)?;
+        for op in ops {
+            match op {
+                Op::Write(data) => {
+                    state.log.push(Message::Write(addr, data.to_vec()));
+                    if let Some((&reg, values)) = data.split_first() {
+                        device.pointer = reg;
+                        for &value in values {
+                            device.regs[device.pointer as usize] = value;
+                            device.pointer = device.pointer.wrapping_add(1);
+                        }
+                    }
+                },
+                Op::Read(buf) => {
+                    state.log.push(Message::Read(addr, buf.len()));
+                    for byte in buf.iter_mut() {
+                        *byte = device.regs[device.pointer as usize];
+                        device.pointer = device.pointer.wrapping_add(1);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_pointer() {
+        let bus = MockI2c::new();
+        bus.add_device(0x68);
+        bus.set(0x68, 0xFF, &[1, 2]);
+        assert_eq!(bus.get(0x68, 0), 2);
+
+        let mut buf = [0; 3];
+        bus.write_read(0x68, &[0xFE], &mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2]);
+
+        // Reads go on where the last one stopped
+        bus.read(0x68, &mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 0);
+        assert_eq!(bus.log(), [Message::Write(0x68, std::vec![0xFE]), Message::Read(0x68, 3), Message::Read(0x68, 1)]);
+
+        assert_eq!(bus.write(0x80, &[]), Err(I2cError::InvalidAddress));
+    }
+}
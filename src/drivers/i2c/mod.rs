@@ -0,0 +1,147 @@
+//! I2C controllers behind the [`I2cBus`] trait.
+
+#[cfg(any(target_arch = "aarch64", test))]
+pub mod bcm2835_bsc;
+#[cfg(test)]
+pub mod mock;
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod sun20i_twi;
+
+use core::fmt;
+
+/// Addresses below and above are reserved, scanning them can confuse devices
+const SCAN_FIRST: u8 = 0x08;
+const SCAN_LAST: u8 = 0x77;
+
+/// One message of an [`I2cBus::transfer`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Op<'a> {
+    Write(&'a [u8]),
+    Read(&'a mut [u8])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cError {
+    /// Addresses have 7 bits
+    InvalidAddress,
+    /// The address or a byte written wasn't acknowledged, there might be no device
+    Nack,
+    /// Another master took over the bus
+    ArbitrationLost,
+    /// The controller didn't finish, or a device held the clock low for too long
+    Timeout,
+    /// The controller got into a state it shouldn't be in, with its status
+    UnexpectedState(u32)
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAddress => f.write_str("invalid I2C address"),
+            Self::Nack => f.write_str("no acknowledge from the I2C device"),
+            Self::ArbitrationLost => f.write_str("I2C arbitration lost"),
+            Self::Timeout => f.write_str("I2C transfer timed out"),
+            Self::UnexpectedState(status) => write!(f, "unexpected I2C controller state ({:#x})", status)
+        }
+    }
+}
+
+/// An I2C controller in master mode.
+pub trait I2cBus {
+    /// Runs `ops` with the device at the 7 bit address `addr`.
+    ///
+    /// Each message starts with a (repeated) start condition, a stop ends the transfer.
+    fn transfer(&self, addr: u8, ops: &mut [Op<'_>]) -> Result<(), I2cError>;
+
+    fn read(&self, addr: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        self.transfer(addr, &mut [Op::Read(buf)])
+    }
+
+    fn write(&self, addr: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.transfer(addr, &mut [Op::Write(data)])
+    }
+
+    /// Writes `data`, usually a register number, and reads the answer after a repeated start.
+    fn write_read(&self, addr: u8, data: &[u8], buf: &mut [u8]) -> Result<(), I2cError> {
+        self.transfer(addr, &mut [Op::Write(data), Op::Read(buf)])
+    }
+}
+
+impl<I: I2cBus> I2cBus for &I {
+    fn transfer(&self, addr: u8, ops: &mut [Op<'_>]) -> Result<(), I2cError> {
+        (**self).transfer(addr, ops)
+    }
+}
+
+/// Returns the addresses that answer a one byte read, as a bitmap.
+pub fn scan(bus: &impl I2cBus) -> u128 {
+    (SCAN_FIRST..=SCAN_LAST)
+        .filter(|&addr| bus.read(addr, &mut [0]).is_ok())
+        .fold(0, |found, addr| found | 1 << addr)
+}
+
+/// Prints the devices on `bus` as a table, like `i2cdetect`.
+pub fn detect(bus: &impl I2cBus, out: &mut dyn fmt::Write) -> fmt::Result {
+    let found = scan(bus);
+    write!(out, "   ")?;
+    for column in 0..16 {
+        write!(out, " {:2x}", column)?;
+    }
+
+    for addr in 0..128u8 {
+        if addr % 16 == 0 {
+            write!(out, "\n{:02x}:", addr)?;
+        }
+
+        if found & 1 << addr != 0 {
+            write!(out, " {:02x}", addr)?;
+        } else if (SCAN_FIRST..=SCAN_LAST).contains(&addr) {
+            write!(out, " --")?;
+        } else {
+            write!(out, "   ")?;
+        }
+    }
+
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+    use super::mock::MockI2c;
+
+    #[test]
+    fn scan_and_detect() {
+        let bus = MockI2c::new();
+        bus.add_device(0x38);
+        bus.add_device(0x68);
+        // Reserved addresses aren't scanned
+        bus.add_device(0x03);
+        assert_eq!(scan(&bus), 1 << 0x38 | 1 << 0x68);
+
+        let mut out = String::new();
+        detect(&bus, &mut out).unwrap();
+        let lines: std::vec::Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+        assert_eq!(lines[1], "00:                         -- -- -- -- -- -- -- --");
+        assert_eq!(lines[4], "30: -- -- -- -- -- -- -- -- 38 -- -- -- -- -- -- --");
+        assert_eq!(lines[8], "70: -- -- -- -- -- -- -- --                        ");
+    }
+
+    #[test]
+    fn write_read() {
+        let bus = MockI2c::new();
+        bus.add_device(0x50);
+        bus.write(0x50, &[0x10, 0xAA, 0xBB]).unwrap();
+
+        let mut buf = [0; 2];
+        bus.write_read(0x50, &[0x10], &mut buf).unwrap();
+        assert_eq!(buf, [0xAA, 0xBB]);
+        assert_eq!(bus.read(0x51, &mut buf), Err(I2cError::Nack));
+    }
+}
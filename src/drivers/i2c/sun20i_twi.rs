@@ -0,0 +1,234 @@
+//! TWI controller of the Allwinner D1, a Marvell mv64xxx derivative.
+//!
+//! The controller steps through the I2C protocol one event at a time: each
+//! action sets the interrupt flag once done, with a status code telling what
+//! happened. Unlike on the Marvell parts, the flag is cleared by writing 1.
+
+use core::hint;
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::drivers::clk::d1_ccu::{Ccu, Clock, ClockError, Reset};
+
+use super::{I2cBus, I2cError, Op};
+
+pub const D1_TWI0_BASE: usize = 0x02502000;
+pub const D1_TWI1_BASE: usize = 0x02502400;
+pub const D1_TWI2_BASE: usize = 0x02502800;
+pub const D1_TWI3_BASE: usize = 0x02502C00;
+
+const TWI_DATA: Register<ReadWrite> = Register::new(0x08);
+const TWI_CNTR: Register<ReadWrite> = Register::new(0x0C);
+const TWI_STAT: Register<ReadOnly> = Register::new(0x10);
+const TWI_CCR: Register<WriteOnly> = Register::new(0x14);
+const TWI_SRST: Register<WriteOnly> = Register::new(0x18);
+
+/// Acknowledges received bytes
+const CNTR_A_ACK: u32 = 1 << 2;
+const CNTR_INT_FLAG: u32 = 1 << 3;
+/// Cleared by the controller once the stop condition is sent
+const CNTR_M_STP: u32 = 1 << 4;
+const CNTR_M_STA: u32 = 1 << 5;
+const CNTR_BUS_EN: u32 = 1 << 6;
+
+const STAT_START: u32 = 0x08;
+const STAT_REPEATED_START: u32 = 0x10;
+const STAT_ADDR_WRITE_ACK: u32 = 0x18;
+const STAT_ADDR_WRITE_NACK: u32 = 0x20;
+const STAT_DATA_WRITE_ACK: u32 = 0x28;
+const STAT_DATA_WRITE_NACK: u32 = 0x30;
+const STAT_ARBITRATION_LOST: u32 = 0x38;
+const STAT_ADDR_READ_ACK: u32 = 0x40;
+const STAT_ADDR_READ_NACK: u32 = 0x48;
+const STAT_DATA_READ_ACK: u32 = 0x50;
+const STAT_DATA_READ_NACK: u32 = 0x58;
+
+/// M in bits 6:3, N in bits 2:0, SCL is the bus clock / (2^N * (M + 1) * 10)
+const CCR_M_SHIFT: u32 = 3;
+const CCR_M_MAX: u64 = 0xF;
+const CCR_N_MAX: u32 = 0x7;
+
+/// Polls of the interrupt flag before giving up
+const TIMEOUT: usize = 1_000_000;
+
+pub struct Twi<B: Bus> {
+    regs: RegisterBlock<B>,
+    /// Which of the four controllers this is, for the clocks
+    index: u8
+}
+
+impl<B: Bus> Twi<B> {
+    pub const fn new(regs: RegisterBlock<B>, index: u8) -> Self {
+        Self { regs, index }
+    }
+
+    /// Resets the controller and returns the SCL rate, as close to `rate` as it gets without going above it.
+    pub fn init<C: Bus>(&self, ccu: &Ccu<C>, rate: u64) -> Result<u64, ClockError> {
+        ccu.assert_reset(Reset::Twi(self.index))?;
+        ccu.enable(Clock::Twi(self.index))?;
+        ccu.deassert_reset(Reset::Twi(self.index))?;
+        let bus_rate = ccu.rate(Clock::Twi(self.index))?;
+
+        // The smallest N keeps M, and so the rate, the most accurate
+        let (m, n) = (0..=CCR_N_MAX)
+            .map(|n| ((bus_rate >> n).div_ceil(10 * rate).max(1), n))
+            .find(|&(m, _)| m <= CCR_M_MAX + 1)
+            .ok_or(ClockError::RateUnreachable)?;
+
+        self.regs.write(TWI_SRST, 1);
+        self.regs.write(TWI_CCR, ((m - 1) as u32) << CCR_M_SHIFT | n);
+        self.regs.write(TWI_CNTR, CNTR_BUS_EN);
+
+        Ok((bus_rate >> n) / (m * 10))
+    }
+
+    /// Clears the interrupt flag along with `bits`, which starts the next step, and waits for it.
+    fn step(&self, bits: u32) -> Result<u32, I2cError> {
+        self.regs.write(TWI_CNTR, CNTR_BUS_EN | CNTR_INT_FLAG | bits);
+        for _ in 0..TIMEOUT {
+            if self.regs.read(TWI_CNTR) & CNTR_INT_FLAG != 0 {
+                return Ok(self.regs.read(TWI_STAT));
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(I2cError::Timeout)
+    }
+
+    /// Does a step and checks that it ended in `expected`.
+    fn expect(&self, bits: u32, expected: u32) -> Result<(), I2cError> {
+        match self.step(bits)? {
+            status if status == expected => Ok(()),
+            STAT_ADDR_WRITE_NACK | STAT_ADDR_READ_NACK | STAT_DATA_WRITE_NACK => Err(I2cError::Nack),
+            STAT_ARBITRATION_LOST => Err(I2cError::ArbitrationLost),
+            status => Err(I2cError::UnexpectedState(status))
+        }
+    }
+
+    fn message(&self, addr: u8, op: &mut Op<'_>, first: bool) -> Result<(), I2cError> {
+        self.expect(CNTR_M_STA, if first { STAT_START } else { STAT_REPEATED_START })?;
+
+        match op {
+            Op::Write(data) => {
+                self.regs.write(TWI_DATA, (addr as u32) << 1);
+                self.expect(0, STAT_ADDR_WRITE_ACK)?;
+                for &byte in data.iter() {
+                    self.regs.write(TWI_DATA, byte as u32);
+                    self.expect(0, STAT_DATA_WRITE_ACK)?;
+                }
+            },
+            Op::Read(buf) => {
+                self.regs.write(TWI_DATA, (addr as u32) << 1 | 1);
+                self.expect(0, STAT_ADDR_READ_ACK)?;
+
+                // The last byte isn't acknowledged, so the device lets go of the bus
+                let len = buf.len();
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    if i + 1 < len {
+                        self.expect(CNTR_A_ACK, STAT_DATA_READ_ACK)?;
+                    } else {
+                        self.expect(0, STAT_DATA_READ_NACK)?;
+                    }
+
+                    *byte = self.regs.read(TWI_DATA) as u8;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), I2cError> {
+        self.regs.write(TWI_CNTR, CNTR_BUS_EN | CNTR_INT_FLAG | CNTR_M_STP);
+        for _ in 0..TIMEOUT {
+            if self.regs.read(TWI_CNTR) & CNTR_M_STP == 0 {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(I2cError::Timeout)
+    }
+}
+
+impl<B: Bus> I2cBus for Twi<B> {
+    fn transfer(&self, addr: u8, ops: &mut [Op<'_>]) -> Result<(), I2cError> {
+        if addr > 0x7F {
+            return Err(I2cError::InvalidAddress);
+        }
+
+        let result = ops.iter_mut()
+            .enumerate()
+            .try_for_each(|(i, op)| self.message(addr, op, i == 0));
+
+        // The bus is released even after an error
+        let stopped = self.stop();
+        result.and(stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    fn no_delay(_: u64) {}
+
+    #[test]
+    fn init() {
+        let regs = MockBus::new();
+        let twi = Twi::new(RegisterBlock::new(&regs, 1), 2);
+        let ccu = MockBus::new();
+        let ccu = Ccu::new(RegisterBlock::new(&ccu, 1), no_delay);
+
+        // APB1 runs off the 24 MHz crystal after reset, / 2 / 12 / 10
+        assert_eq!(twi.init(&ccu, 100_000), Ok(100_000));
+        assert_eq!(regs.get(TWI_CCR.index()), 11 << CCR_M_SHIFT | 1);
+        assert_eq!(regs.get(TWI_CNTR.index()), CNTR_BUS_EN);
+
+        // / 1 / 6 / 10
+        assert_eq!(twi.init(&ccu, 400_000), Ok(400_000));
+        assert_eq!(regs.get(TWI_CCR.index()), 5 << CCR_M_SHIFT);
+
+        assert_eq!(twi.init(&ccu, 10), Err(ClockError::RateUnreachable));
+    }
+
+    #[test]
+    fn write_read() {
+        let regs = MockBus::new();
+        // Each step finishes at once, and so does the stop
+        regs.script(TWI_CNTR.index(), [CNTR_INT_FLAG; 7].into_iter().chain([0]));
+        regs.script(TWI_STAT.index(), [STAT_START, STAT_ADDR_WRITE_ACK, STAT_DATA_WRITE_ACK,
+            STAT_REPEATED_START, STAT_ADDR_READ_ACK, STAT_DATA_READ_ACK, STAT_DATA_READ_NACK]);
+        regs.script(TWI_DATA.index(), [0x12, 0x34]);
+        let twi = Twi::new(RegisterBlock::new(&regs, 1), 0);
+
+        let mut buf = [0; 2];
+        twi.write_read(0x38, &[0x05], &mut buf).unwrap();
+        assert_eq!(buf, [0x12, 0x34]);
+        assert_eq!(regs.writes(TWI_DATA.index()), [0x70, 0x05, 0x71]);
+
+        let base = CNTR_BUS_EN | CNTR_INT_FLAG;
+        assert_eq!(regs.writes(TWI_CNTR.index()), [base | CNTR_M_STA, base, base,
+            base | CNTR_M_STA, base, base | CNTR_A_ACK, base, base | CNTR_M_STP]);
+    }
+
+    #[test]
+    fn errors() {
+        let regs = MockBus::new();
+        regs.set_read_bits(TWI_CNTR.index(), CNTR_INT_FLAG);
+        regs.script(TWI_STAT.index(), [STAT_START, STAT_ADDR_READ_NACK, STAT_START, STAT_ARBITRATION_LOST, 0xF8]);
+        let twi = Twi::new(RegisterBlock::new(&regs, 1), 0);
+
+        assert_eq!(twi.read(0x50, &mut [0]), Err(I2cError::Nack));
+        assert_eq!(twi.write(0x50, &[0]), Err(I2cError::ArbitrationLost));
+        assert_eq!(twi.write(0x50, &[0]), Err(I2cError::UnexpectedState(0xF8)));
+        assert_eq!(twi.write(0x80, &[0]), Err(I2cError::InvalidAddress));
+
+        let regs = MockBus::new();
+        regs.set_write_clears(TWI_CNTR.index(), CNTR_INT_FLAG);
+        let twi = Twi::new(RegisterBlock::new(&regs, 1), 0);
+        assert_eq!(twi.write(0x50, &[0]), Err(I2cError::Timeout));
+    }
+}
@@ -0,0 +1,139 @@
+//! Turns keyboard bytes into key presses with their characters.
+
+use super::keymap::Keymap;
+use super::scancode::{Decoder, ScancodeSet};
+use super::{KeyCode, Modifiers};
+
+/// A key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    /// Modifiers held when the key was pressed
+    pub modifiers: Modifiers,
+    /// Character the key produces in the current layout
+    pub ch: Option<char>
+}
+
+pub struct Keyboard {
+    decoder: Decoder,
+    modifiers: Modifiers,
+    keymap: &'static Keymap,
+    /// Lock keys that are held down, to ignore their typematic repeats
+    locks_held: u8
+}
+
+impl Keyboard {
+    pub const fn new(set: ScancodeSet, keymap: &'static Keymap) -> Self {
+        Self {
+            decoder: Decoder::new(set),
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                alt: false,
+                alt_gr: false,
+                caps_lock: false,
+                num_lock: true,
+                scroll_lock: false
+            },
+            keymap,
+            locks_held: 0
+        }
+    }
+
+    pub fn modifiers(&self) -> &Modifiers {
+        &self.modifiers
+    }
+
+    pub fn keymap(&self) -> &'static Keymap {
+        self.keymap
+    }
+
+    pub fn set_keymap(&mut self, keymap: &'static Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Switches to another scancode set, the keyboard has to be told separately.
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.decoder = Decoder::new(set);
+    }
+
+    /// Handles one byte from the keyboard, returns the key once one is pressed.
+    ///
+    /// Modifier keys and releases only update the state.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let event = self.decoder.feed(byte)?;
+
+        let lock = match event.code {
+            KeyCode::CapsLock => 1 << 0,
+            KeyCode::NumLock => 1 << 1,
+            KeyCode::ScrollLock => 1 << 2,
+            _ => 0
+        };
+        if lock != 0 {
+            let repeat = event.pressed && self.locks_held & lock != 0;
+            if event.pressed {
+                self.locks_held |= lock;
+            } else {
+                self.locks_held &= !lock;
+            }
+
+            if repeat {
+                return None;
+            }
+        }
+
+        if self.modifiers.update(event) || !event.pressed {
+            return None;
+        }
+
+        Some(Key {
+            code: event.code,
+            modifiers: self.modifiers,
+            ch: self.keymap.map(event.code, &self.modifiers)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::drivers::input::keymap::{BE, US};
+
+    fn chars(keyboard: &mut Keyboard, bytes: &[u8]) -> Vec<Option<char>> {
+        bytes.iter().filter_map(|&b| keyboard.feed(b)).map(|k| k.ch).collect()
+    }
+
+    #[test]
+    fn shift_and_caps_lock() {
+        let mut keyboard = Keyboard::new(ScancodeSet::Set2, &US);
+        // a, shift+a, caps lock (held long enough to repeat), a, caps lock, a
+        let typed = chars(&mut keyboard, &[
+            0x1C, 0xF0, 0x1C,
+            0x12, 0x1C, 0xF0, 0x1C, 0xF0, 0x12,
+            0x58, 0x58, 0xF0, 0x58, 0x1C, 0xF0, 0x1C,
+            0x58, 0xF0, 0x58, 0x1C
+        ]);
+        assert_eq!(typed, [Some('a'), Some('A'), Some('A'), Some('a')]);
+    }
+
+    #[test]
+    fn special_keys() {
+        let mut keyboard = Keyboard::new(ScancodeSet::Set1, &BE);
+        // ctrl+a on a BE keyboard is ctrl+q
+        let key = [0x1D, 0x1E].iter().find_map(|&b| keyboard.feed(b)).unwrap();
+        assert_eq!(key.code, KeyCode::A);
+        assert_eq!(key.ch, Some('q'));
+        assert!(key.modifiers.ctrl());
+
+        let key = [0x9E, 0x9D, 0xE0, 0x4B].iter().find_map(|&b| keyboard.feed(b)).unwrap();
+        assert_eq!(key.code, KeyCode::Left);
+        assert_eq!(key.ch, None);
+        assert!(!key.modifiers.ctrl());
+    }
+}
@@ -0,0 +1,229 @@
+//! Keyboard layouts.
+
+use super::{KeyCode, Modifiers};
+
+/// Characters printed on a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    normal: char,
+    shift: char,
+    alt_gr: Option<char>
+}
+
+const fn key(normal: char, shift: char) -> Option<Mapping> {
+    Some(Mapping { normal, shift, alt_gr: None })
+}
+
+const fn key3(normal: char, shift: char, alt_gr: char) -> Option<Mapping> {
+    Some(Mapping { normal, shift, alt_gr: Some(alt_gr) })
+}
+
+pub struct Keymap {
+    pub name: &'static str,
+    /// Characters of the keys that differ between layouts
+    lookup: fn(KeyCode) -> Option<Mapping>
+}
+
+/// US QWERTY
+pub static US: Keymap = Keymap { name: "us", lookup: us };
+
+/// Belgian AZERTY, the dead keys produce their character straight away
+pub static BE: Keymap = Keymap { name: "be", lookup: be };
+
+pub static KEYMAPS: [&Keymap; 2] = [&US, &BE];
+
+/// Looks up a layout by name.
+pub fn find(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS.iter().copied().find(|k| k.name == name)
+}
+
+impl Keymap {
+    /// Returns the character `code` produces with `modifiers` held, if any.
+    ///
+    /// Control characters aren't handled here, the result is the same with or without ctrl.
+    pub fn map(&self, code: KeyCode, modifiers: &Modifiers) -> Option<char> {
+        if let Some(mapping) = (self.lookup)(code) {
+            if modifiers.alt_gr {
+                return mapping.alt_gr;
+            }
+
+            // Caps lock only affects letters
+            let shift = modifiers.shift() ^ (modifiers.caps_lock && mapping.normal.is_ascii_alphabetic());
+            return Some(if shift { mapping.shift } else { mapping.normal });
+        }
+
+        keypad(code, modifiers.num_lock && !modifiers.shift())
+    }
+}
+
+/// Keys that are the same on every layout.
+fn keypad(code: KeyCode, num_lock: bool) -> Option<char> {
+    use KeyCode::*;
+
+    Some(match code {
+        Space => ' ',
+        KpStar => '*',
+        KpMinus => '-',
+        KpPlus => '+',
+        KpSlash => '/',
+        Kp0 if num_lock => '0',
+        Kp1 if num_lock => '1',
+        Kp2 if num_lock => '2',
+        Kp3 if num_lock => '3',
+        Kp4 if num_lock => '4',
+        Kp5 if num_lock => '5',
+        Kp6 if num_lock => '6',
+        Kp7 if num_lock => '7',
+        Kp8 if num_lock => '8',
+        Kp9 if num_lock => '9',
+        KpPeriod if num_lock => '.',
+        _ => return None
+    })
+}
+
+/// Letters are at the same place on the US and BE layouts, apart from these
+fn letter(code: KeyCode) -> Option<Mapping> {
+    use KeyCode::*;
+
+    match code {
+        A => key('a', 'A'),
+        B => key('b', 'B'),
+        C => key('c', 'C'),
+        D => key('d', 'D'),
+        E => key('e', 'E'),
+        F => key('f', 'F'),
+        G => key('g', 'G'),
+        H => key('h', 'H'),
+        I => key('i', 'I'),
+        J => key('j', 'J'),
+        K => key('k', 'K'),
+        L => key('l', 'L'),
+        M => key('m', 'M'),
+        N => key('n', 'N'),
+        O => key('o', 'O'),
+        P => key('p', 'P'),
+        Q => key('q', 'Q'),
+        R => key('r', 'R'),
+        S => key('s', 'S'),
+        T => key('t', 'T'),
+        U => key('u', 'U'),
+        V => key('v', 'V'),
+        W => key('w', 'W'),
+        X => key('x', 'X'),
+        Y => key('y', 'Y'),
+        Z => key('z', 'Z'),
+        _ => None
+    }
+}
+
+fn us(code: KeyCode) -> Option<Mapping> {
+    use KeyCode::*;
+
+    match code {
+        Backtick => key('`', '~'),
+        Key1 => key('1', '!'),
+        Key2 => key('2', '@'),
+        Key3 => key('3', '#'),
+        Key4 => key('4', '$'),
+        Key5 => key('5', '%'),
+        Key6 => key('6', '^'),
+        Key7 => key('7', '&'),
+        Key8 => key('8', '*'),
+        Key9 => key('9', '('),
+        Key0 => key('0', ')'),
+        Minus => key('-', '_'),
+        Equals => key('=', '+'),
+        LeftBracket => key('[', '{'),
+        RightBracket => key(']', '}'),
+        Backslash | NonUsBackslash => key('\\', '|'),
+        Semicolon => key(';', ':'),
+        Quote => key('\'', '"'),
+        Comma => key(',', '<'),
+        Period => key('.', '>'),
+        Slash => key('/', '?'),
+        _ => letter(code)
+    }
+}
+
+fn be(code: KeyCode) -> Option<Mapping> {
+    use KeyCode::*;
+
+    match code {
+        Backtick => key('²', '³'),
+        Key1 => key3('&', '1', '|'),
+        Key2 => key3('é', '2', '@'),
+        Key3 => key3('"', '3', '#'),
+        Key4 => key('\'', '4'),
+        Key5 => key('(', '5'),
+        Key6 => key3('§', '6', '^'),
+        Key7 => key('è', '7'),
+        Key8 => key('!', '8'),
+        Key9 => key3('ç', '9', '{'),
+        Key0 => key3('à', '0', '}'),
+        Minus => key(')', '°'),
+        Equals => key('-', '_'),
+        A => key('q', 'Q'),
+        Q => key('a', 'A'),
+        W => key('z', 'Z'),
+        Z => key('w', 'W'),
+        E => key3('e', 'E', '€'),
+        M => key(',', '?'),
+        LeftBracket => key3('^', '¨', '['),
+        RightBracket => key3('$', '*', ']'),
+        Semicolon => key('m', 'M'),
+        Quote => key3('ù', '%', '´'),
+        Backslash => key3('µ', '£', '`'),
+        NonUsBackslash => key3('<', '>', '\\'),
+        Comma => key(';', '.'),
+        Period => key(':', '/'),
+        Slash => key3('=', '+', '~'),
+        _ => letter(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn us_layout() {
+        let mut modifiers = Modifiers::default();
+        assert_eq!(US.map(KeyCode::A, &modifiers), Some('a'));
+        assert_eq!(US.map(KeyCode::Key2, &modifiers), Some('2'));
+        modifiers.left_shift = true;
+        assert_eq!(US.map(KeyCode::A, &modifiers), Some('A'));
+        assert_eq!(US.map(KeyCode::Key2, &modifiers), Some('@'));
+        modifiers.caps_lock = true;
+        assert_eq!(US.map(KeyCode::A, &modifiers), Some('a'));
+        assert_eq!(US.map(KeyCode::Key2, &modifiers), Some('@'));
+        assert_eq!(US.map(KeyCode::Enter, &modifiers), None);
+    }
+
+    #[test]
+    fn be_layout() {
+        let mut modifiers = Modifiers::default();
+        assert_eq!(BE.map(KeyCode::A, &modifiers), Some('q'));
+        assert_eq!(BE.map(KeyCode::Q, &modifiers), Some('a'));
+        assert_eq!(BE.map(KeyCode::Semicolon, &modifiers), Some('m'));
+        assert_eq!(BE.map(KeyCode::Key2, &modifiers), Some('é'));
+        modifiers.caps_lock = true;
+        assert_eq!(BE.map(KeyCode::Q, &modifiers), Some('A'));
+        assert_eq!(BE.map(KeyCode::Key2, &modifiers), Some('é'));
+        modifiers.caps_lock = false;
+        modifiers.alt_gr = true;
+        assert_eq!(BE.map(KeyCode::Key2, &modifiers), Some('@'));
+        assert_eq!(BE.map(KeyCode::E, &modifiers), Some('€'));
+        assert_eq!(BE.map(KeyCode::A, &modifiers), None);
+    }
+
+    #[test]
+    fn keypad_keys() {
+        let mut modifiers = Modifiers::default();
+        assert_eq!(US.map(KeyCode::Kp7, &modifiers), None);
+        assert_eq!(US.map(KeyCode::KpPlus, &modifiers), Some('+'));
+        modifiers.num_lock = true;
+        assert_eq!(BE.map(KeyCode::Kp7, &modifiers), Some('7'));
+        assert_eq!(find("be").map(|k| k.name), Some("be"));
+        assert!(find("dvorak").is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! Keyboard input: the PS/2 controller, scancode decoding and keymaps.
+//!
+//! Keys are named after their position on a US keyboard, a [`keymap::Keymap`]
+//! turns them into the characters printed on the keycaps of a layout.
+
+pub mod keyboard;
+pub mod keymap;
+pub mod ps2;
+pub mod scancode;
+
+/// A physical key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    A, B, C, D, E, F, G, H, I, J, K, L, M,
+    N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    Backtick,
+    Minus,
+    Equals,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    Semicolon,
+    Quote,
+    Comma,
+    Period,
+    Slash,
+    /// The extra key next to left shift on ISO keyboards
+    NonUsBackslash,
+    Space,
+    Tab,
+    Enter,
+    Backspace,
+    Escape,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    /// AltGr on most non US layouts
+    RightAlt,
+    LeftMeta,
+    RightMeta,
+    Menu,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    PrintScreen,
+    Pause,
+    Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9,
+    KpPeriod,
+    KpPlus,
+    KpMinus,
+    KpStar,
+    KpSlash,
+    KpEnter
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// False when the key is released
+    pub pressed: bool
+}
+
+impl KeyEvent {
+    pub const fn new(code: KeyCode, pressed: bool) -> Self {
+        Self { code, pressed }
+    }
+}
+
+/// State of the modifier and lock keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool
+}
+
+impl Modifiers {
+    pub const fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub const fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Updates the state for `event`, returns false if it isn't a modifier or lock key.
+    pub fn update(&mut self, event: KeyEvent) -> bool {
+        let pressed = event.pressed;
+        match event.code {
+            KeyCode::LeftShift => self.left_shift = pressed,
+            KeyCode::RightShift => self.right_shift = pressed,
+            KeyCode::LeftCtrl => self.left_ctrl = pressed,
+            KeyCode::RightCtrl => self.right_ctrl = pressed,
+            KeyCode::LeftAlt => self.alt = pressed,
+            KeyCode::RightAlt => self.alt_gr = pressed,
+            // Locks toggle on press, [`keyboard::Keyboard`] drops the repeats of a held key
+            KeyCode::CapsLock if pressed => self.caps_lock = !self.caps_lock,
+            KeyCode::NumLock if pressed => self.num_lock = !self.num_lock,
+            KeyCode::ScrollLock if pressed => self.scroll_lock = !self.scroll_lock,
+            KeyCode::CapsLock | KeyCode::NumLock | KeyCode::ScrollLock => (),
+            _ => return false
+        }
+
+        true
+    }
+}
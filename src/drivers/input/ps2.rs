@@ -0,0 +1,347 @@
+//! 8042 PS/2 controller with a keyboard on the first port.
+
+use core::fmt;
+use core::hint;
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+
+use super::scancode::ScancodeSet;
+use super::Modifiers;
+
+const DATA: Register<ReadWrite> = Register::new(0);
+const STATUS: Register<ReadOnly> = Register::new(4);
+const COMMAND: Register<WriteOnly> = Register::new(4);
+
+/// Data waiting to be read
+const STATUS_OUTPUT_FULL: u32 = 1;
+/// The controller hasn't picked up the last write yet
+const STATUS_INPUT_FULL: u32 = 1 << 1;
+
+const CMD_READ_CONFIG: u32 = 0x20;
+const CMD_WRITE_CONFIG: u32 = 0x60;
+const CMD_DISABLE_PORT2: u32 = 0xA7;
+const CMD_SELF_TEST: u32 = 0xAA;
+const CMD_TEST_PORT1: u32 = 0xAB;
+const CMD_DISABLE_PORT1: u32 = 0xAD;
+const CMD_ENABLE_PORT1: u32 = 0xAE;
+
+/// Interrupt on IRQ 1 when the keyboard sends data
+const CONFIG_PORT1_IRQ: u8 = 1;
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+/// Translate set 2 scancodes to set 1
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const KBD_SET_LEDS: u8 = 0xED;
+const KBD_SCANCODE_SET: u8 = 0xF0;
+const KBD_ENABLE_SCANNING: u8 = 0xF4;
+const KBD_RESET: u8 = 0xFF;
+
+const KBD_ACK: u8 = 0xFA;
+const KBD_RESEND: u8 = 0xFE;
+const KBD_SELF_TEST_PASSED: u8 = 0xAA;
+
+const LED_SCROLL_LOCK: u8 = 1;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Number of status polls before giving up on the controller
+const TIMEOUT: usize = 1_000_000;
+/// Number of times a command is sent when the keyboard asks for a resend
+const RETRIES: usize = 3;
+/// Most bytes there can be left in the output buffer
+const FLUSH_LIMIT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    /// The controller self test returned something other than 0x55
+    ControllerTestFailed(u8),
+    /// The interface test of the keyboard port returned an error code
+    PortTestFailed(u8),
+    /// The keyboard didn't pass its self test after a reset
+    KeyboardTestFailed(u8),
+    /// The keyboard answered a command with something other than an ack
+    NoAck(u8)
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("controller timed out"),
+            Self::ControllerTestFailed(v) => write!(f, "controller self test failed ({:#x})", v),
+            Self::PortTestFailed(v) => write!(f, "keyboard port test failed ({:#x})", v),
+            Self::KeyboardTestFailed(v) => write!(f, "keyboard self test failed ({:#x})", v),
+            Self::NoAck(v) => write!(f, "keyboard didn't acknowledge a command ({:#x})", v)
+        }
+    }
+}
+
+pub struct Ps2Controller<B: Bus> {
+    regs: RegisterBlock<B>
+}
+
+impl<B: Bus> Ps2Controller<B> {
+    /// `regs` has the data port at index 0 and the status and command port at index 4.
+    pub const fn new(regs: RegisterBlock<B>) -> Self {
+        Self { regs }
+    }
+
+    /// Resets the controller and the keyboard, then enables keyboard interrupts.
+    ///
+    /// The keyboard is always put in set 2, for set 1 the controller translates.
+    pub fn init(&self, set: ScancodeSet) -> Result<(), Ps2Error> {
+        self.command(CMD_DISABLE_PORT1)?;
+        self.command(CMD_DISABLE_PORT2)?;
+        self.flush();
+
+        let mut config = self.read_config()?;
+        config &= !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ | CONFIG_TRANSLATION);
+        self.write_config(config)?;
+
+        self.command(CMD_SELF_TEST)?;
+        match self.read()? {
+            SELF_TEST_PASSED => (),
+            v => return Err(Ps2Error::ControllerTestFailed(v))
+        }
+
+        // Some controllers reset on a self test
+        self.write_config(config)?;
+
+        self.command(CMD_TEST_PORT1)?;
+        match self.read()? {
+            PORT_TEST_PASSED => (),
+            v => return Err(Ps2Error::PortTestFailed(v))
+        }
+
+        self.command(CMD_ENABLE_PORT1)?;
+
+        self.keyboard_command(KBD_RESET)?;
+        match self.read()? {
+            KBD_SELF_TEST_PASSED => (),
+            v => return Err(Ps2Error::KeyboardTestFailed(v))
+        }
+
+        self.keyboard_command(KBD_SCANCODE_SET)?;
+        self.keyboard_command(2)?;
+        self.keyboard_command(KBD_ENABLE_SCANNING)?;
+
+        config |= CONFIG_PORT1_IRQ;
+        if set == ScancodeSet::Set1 {
+            config |= CONFIG_TRANSLATION;
+        }
+
+        self.write_config(config)
+    }
+
+    /// Returns the next byte from the keyboard, if there is one.
+    pub fn read_data(&self) -> Option<u8> {
+        if self.regs.read(STATUS) & STATUS_OUTPUT_FULL == 0 {
+            return None;
+        }
+
+        Some(self.regs.read(DATA) as u8)
+    }
+
+    /// Turns the lock LEDs on or off to match `modifiers`.
+    ///
+    /// Has to be called with the keyboard interrupt masked, as the keyboard answers through the data port.
+    pub fn set_leds(&self, modifiers: &Modifiers) -> Result<(), Ps2Error> {
+        let mut leds = 0;
+        if modifiers.scroll_lock {
+            leds |= LED_SCROLL_LOCK;
+        }
+
+        if modifiers.num_lock {
+            leds |= LED_NUM_LOCK;
+        }
+
+        if modifiers.caps_lock {
+            leds |= LED_CAPS_LOCK;
+        }
+
+        self.keyboard_command(KBD_SET_LEDS)?;
+        self.keyboard_command(leds)
+    }
+
+    fn flush(&self) {
+        for _ in 0..FLUSH_LIMIT {
+            if self.read_data().is_none() {
+                break;
+            }
+        }
+    }
+
+    fn wait(&self, mask: u32, set: bool) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if (self.regs.read(STATUS) & mask != 0) == set {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(Ps2Error::Timeout)
+    }
+
+    fn read(&self) -> Result<u8, Ps2Error> {
+        self.wait(STATUS_OUTPUT_FULL, true)?;
+        Ok(self.regs.read(DATA) as u8)
+    }
+
+    fn write(&self, value: u8) -> Result<(), Ps2Error> {
+        self.wait(STATUS_INPUT_FULL, false)?;
+        self.regs.write(DATA, value as u32);
+        Ok(())
+    }
+
+    fn command(&self, command: u32) -> Result<(), Ps2Error> {
+        self.wait(STATUS_INPUT_FULL, false)?;
+        self.regs.write(COMMAND, command);
+        Ok(())
+    }
+
+    fn read_config(&self) -> Result<u8, Ps2Error> {
+        self.command(CMD_READ_CONFIG)?;
+        self.read()
+    }
+
+    fn write_config(&self, config: u8) -> Result<(), Ps2Error> {
+        self.command(CMD_WRITE_CONFIG)?;
+        self.write(config)
+    }
+
+    /// Sends a byte to the keyboard and waits for the ack.
+    fn keyboard_command(&self, value: u8) -> Result<(), Ps2Error> {
+        let mut response = KBD_RESEND;
+        for _ in 0..RETRIES {
+            self.write(value)?;
+            response = self.read()?;
+            if response != KBD_RESEND {
+                break;
+            }
+        }
+
+        match response {
+            KBD_ACK => Ok(()),
+            v => Err(Ps2Error::NoAck(v))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Emulates a controller with a keyboard attached.
+    #[derive(Default)]
+    struct Fake8042 {
+        output: RefCell<VecDeque<u8>>,
+        config: RefCell<u8>,
+        /// Command waiting for its data byte
+        pending: RefCell<Option<u8>>,
+        /// Bytes the keyboard got
+        keyboard: RefCell<Vec<u8>>,
+        /// Number of times the keyboard asks for a resend before acking
+        resends: RefCell<usize>
+    }
+
+    impl Bus for Fake8042 {
+        fn read(&self, offset: usize) -> u32 {
+            match offset {
+                0 => self.output.borrow_mut().pop_front().unwrap_or(0) as u32,
+                4 => !self.output.borrow().is_empty() as u32,
+                _ => unreachable!()
+            }
+        }
+
+        fn write(&self, offset: usize, value: u32) {
+            let value = value as u8;
+            let mut output = self.output.borrow_mut();
+            if offset == 4 {
+                match value {
+                    0x20 => output.push_back(*self.config.borrow()),
+                    0xAA => output.push_back(SELF_TEST_PASSED),
+                    0xAB => output.push_back(PORT_TEST_PASSED),
+                    _ => ()
+                }
+
+                if value == 0x60 {
+                    *self.pending.borrow_mut() = Some(value);
+                }
+
+                return;
+            }
+
+            if self.pending.borrow_mut().take() == Some(0x60) {
+                *self.config.borrow_mut() = value;
+                return;
+            }
+
+            if *self.resends.borrow() > 0 {
+                *self.resends.borrow_mut() -= 1;
+                output.push_back(KBD_RESEND);
+                return;
+            }
+
+            self.keyboard.borrow_mut().push(value);
+            output.push_back(KBD_ACK);
+            if value == KBD_RESET {
+                output.push_back(KBD_SELF_TEST_PASSED);
+            }
+        }
+    }
+
+    #[test]
+    fn init() {
+        let bus = Fake8042::default();
+        *bus.config.borrow_mut() = 0x47;
+        bus.output.borrow_mut().push_back(0x1C);
+        let ps2 = Ps2Controller::new(RegisterBlock::new(&bus, 1));
+        ps2.init(ScancodeSet::Set2).unwrap();
+
+        assert_eq!(*bus.config.borrow(), 0x05);
+        assert_eq!(*bus.keyboard.borrow(), [KBD_RESET, KBD_SCANCODE_SET, 2, KBD_ENABLE_SCANNING]);
+        assert_eq!(ps2.read_data(), None);
+
+        ps2.init(ScancodeSet::Set1).unwrap();
+        assert_eq!(*bus.config.borrow(), 0x45);
+    }
+
+    #[test]
+    fn resend_and_leds() {
+        let bus = Fake8042::default();
+        *bus.resends.borrow_mut() = 2;
+        let ps2 = Ps2Controller::new(RegisterBlock::new(&bus, 1));
+        let modifiers = Modifiers { caps_lock: true, num_lock: true, ..Default::default() };
+        ps2.set_leds(&modifiers).unwrap();
+        assert_eq!(*bus.keyboard.borrow(), [KBD_SET_LEDS, LED_NUM_LOCK | LED_CAPS_LOCK]);
+
+        *bus.resends.borrow_mut() = RETRIES;
+        assert_eq!(ps2.set_leds(&modifiers), Err(Ps2Error::NoAck(KBD_RESEND)));
+    }
+
+    #[test]
+    fn timeout() {
+        struct Dead;
+
+        impl Bus for Dead {
+            fn read(&self, _offset: usize) -> u32 {
+                STATUS_INPUT_FULL
+            }
+
+            fn write(&self, _offset: usize, _value: u32) {}
+        }
+
+        let ps2 = Ps2Controller::new(RegisterBlock::new(Dead, 1));
+        assert_eq!(ps2.init(ScancodeSet::Set2), Err(Ps2Error::Timeout));
+    }
+}
@@ -0,0 +1,429 @@
+//! Decoding of the bytes a PS/2 keyboard sends into key events.
+
+use super::{KeyCode, KeyEvent};
+
+/// Prefix of the extended keys
+const EXTENDED: u8 = 0xE0;
+/// Prefix of the pause key, the only key without a release code
+const PAUSE: u8 = 0xE1;
+/// Set 2 prefix of a release code
+const RELEASE: u8 = 0xF0;
+/// Set 1 release codes have this bit set
+const SET1_RELEASE: u8 = 0x80;
+
+/// Bytes following the set 1 pause prefix: 1D 45 E1 9D C5
+const SET1_PAUSE_LEN: u8 = 5;
+/// Bytes following the set 2 pause prefix: 14 77 E1 F0 14 F0 77
+const SET2_PAUSE_LEN: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// What the controller translates to by default, also used by XT keyboards
+    Set1,
+    /// What AT keyboards send by default
+    Set2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Extended,
+    Release,
+    ExtendedRelease,
+    /// Skipping the rest of the pause sequence, with the number of bytes left
+    Pause(u8)
+}
+
+/// Turns the byte stream of a keyboard into key events.
+#[derive(Debug)]
+pub struct Decoder {
+    set: ScancodeSet,
+    state: State
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            state: State::Start
+        }
+    }
+
+    pub const fn set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// Handles one byte from the keyboard, returns an event once a scancode is complete.
+    ///
+    /// Unknown scancodes are dropped.
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        match self.set {
+            ScancodeSet::Set1 => self.feed_set1(byte),
+            ScancodeSet::Set2 => self.feed_set2(byte)
+        }
+    }
+
+    fn feed_set1(&mut self, byte: u8) -> Option<KeyEvent> {
+        let pressed = byte & SET1_RELEASE == 0;
+        let code = byte & !SET1_RELEASE;
+        match self.state {
+            State::Pause(left) => self.skip_pause(left),
+            State::Extended => {
+                self.state = State::Start;
+                set1_extended(code).map(|key| KeyEvent::new(key, pressed))
+            },
+            _ => match byte {
+                EXTENDED => {
+                    self.state = State::Extended;
+                    None
+                },
+                PAUSE => {
+                    self.state = State::Pause(SET1_PAUSE_LEN);
+                    None
+                },
+                _ => set1(code).map(|key| KeyEvent::new(key, pressed))
+            }
+        }
+    }
+
+    fn feed_set2(&mut self, byte: u8) -> Option<KeyEvent> {
+        match (self.state, byte) {
+            (State::Pause(left), _) => self.skip_pause(left),
+            (State::Start, EXTENDED) => {
+                self.state = State::Extended;
+                None
+            },
+            (State::Start, PAUSE) => {
+                self.state = State::Pause(SET2_PAUSE_LEN);
+                None
+            },
+            (State::Start, RELEASE) => {
+                self.state = State::Release;
+                None
+            },
+            (State::Extended, RELEASE) => {
+                self.state = State::ExtendedRelease;
+                None
+            },
+            (State::Start, _) => set2(byte).map(|key| KeyEvent::new(key, true)),
+            (State::Release, _) => {
+                self.state = State::Start;
+                set2(byte).map(|key| KeyEvent::new(key, false))
+            },
+            (State::Extended, _) => {
+                self.state = State::Start;
+                set2_extended(byte).map(|key| KeyEvent::new(key, true))
+            },
+            (State::ExtendedRelease, _) => {
+                self.state = State::Start;
+                set2_extended(byte).map(|key| KeyEvent::new(key, false))
+            }
+        }
+    }
+
+    fn skip_pause(&mut self, left: u8) -> Option<KeyEvent> {
+        if left > 1 {
+            self.state = State::Pause(left - 1);
+            return None;
+        }
+
+        self.state = State::Start;
+        Some(KeyEvent::new(KeyCode::Pause, true))
+    }
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0A => Key9,
+        0x0B => Key0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => LeftBracket,
+        0x1B => RightBracket,
+        0x1C => Enter,
+        0x1D => LeftCtrl,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Backtick,
+        0x2A => LeftShift,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KpStar,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Kp7,
+        0x48 => Kp8,
+        0x49 => Kp9,
+        0x4A => KpMinus,
+        0x4B => Kp4,
+        0x4C => Kp5,
+        0x4D => Kp6,
+        0x4E => KpPlus,
+        0x4F => Kp1,
+        0x50 => Kp2,
+        0x51 => Kp3,
+        0x52 => Kp0,
+        0x53 => KpPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None
+    })
+}
+
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    // The fake shifts (2A and 36) that come with some extended keys are dropped
+    Some(match code {
+        0x1C => KpEnter,
+        0x1D => RightCtrl,
+        0x35 => KpSlash,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4B => Left,
+        0x4D => Right,
+        0x4F => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => LeftMeta,
+        0x5C => RightMeta,
+        0x5D => Menu,
+        _ => return None
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Backtick,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x14 => LeftCtrl,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Space,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Key7,
+        0x3E => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Period,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => Semicolon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Quote,
+        0x54 => LeftBracket,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5A => Enter,
+        0x5B => RightBracket,
+        0x5D => Backslash,
+        0x61 => NonUsBackslash,
+        0x66 => Backspace,
+        0x69 => Kp1,
+        0x6B => Kp4,
+        0x6C => Kp7,
+        0x70 => Kp0,
+        0x71 => KpPeriod,
+        0x72 => Kp2,
+        0x73 => Kp5,
+        0x74 => Kp6,
+        0x75 => Kp8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KpPlus,
+        0x7A => Kp3,
+        0x7B => KpMinus,
+        0x7C => KpStar,
+        0x7D => Kp9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None
+    })
+}
+
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    // The fake shifts (12 and 59) that come with some extended keys are dropped
+    Some(match code {
+        0x11 => RightAlt,
+        0x14 => RightCtrl,
+        0x1F => LeftMeta,
+        0x27 => RightMeta,
+        0x2F => Menu,
+        0x4A => KpSlash,
+        0x5A => KpEnter,
+        0x69 => End,
+        0x6B => Left,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => Down,
+        0x74 => Right,
+        0x75 => Up,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn decode(set: ScancodeSet, bytes: &[u8]) -> Vec<KeyEvent> {
+        let mut decoder = Decoder::new(set);
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    #[test]
+    fn set1() {
+        assert_eq!(decode(ScancodeSet::Set1, &[0x1E, 0x9E, 0xE0, 0x48, 0xE0, 0xC8]), [
+            KeyEvent::new(KeyCode::A, true),
+            KeyEvent::new(KeyCode::A, false),
+            KeyEvent::new(KeyCode::Up, true),
+            KeyEvent::new(KeyCode::Up, false)
+        ]);
+    }
+
+    #[test]
+    fn set2() {
+        assert_eq!(decode(ScancodeSet::Set2, &[0x1C, 0xF0, 0x1C, 0xE0, 0x75, 0xE0, 0xF0, 0x75]), [
+            KeyEvent::new(KeyCode::A, true),
+            KeyEvent::new(KeyCode::A, false),
+            KeyEvent::new(KeyCode::Up, true),
+            KeyEvent::new(KeyCode::Up, false)
+        ]);
+    }
+
+    #[test]
+    fn fake_shifts_and_pause() {
+        // Print screen followed by pause
+        assert_eq!(decode(ScancodeSet::Set1, &[0xE0, 0x2A, 0xE0, 0x37, 0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5, 0x10]), [
+            KeyEvent::new(KeyCode::PrintScreen, true),
+            KeyEvent::new(KeyCode::Pause, true),
+            KeyEvent::new(KeyCode::Q, true)
+        ]);
+        assert_eq!(decode(ScancodeSet::Set2, &[0xE0, 0x12, 0xE0, 0x7C, 0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77, 0x15]), [
+            KeyEvent::new(KeyCode::PrintScreen, true),
+            KeyEvent::new(KeyCode::Pause, true),
+            KeyEvent::new(KeyCode::Q, true)
+        ]);
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(decode(ScancodeSet::Set2, &[0x00, 0xF0, 0x02, 0x1C]), [KeyEvent::new(KeyCode::A, true)]);
+    }
+}
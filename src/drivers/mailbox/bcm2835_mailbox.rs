@@ -1,10 +1,33 @@
-use core::{hint, marker::PhantomData};
+use core::{fmt, hint, marker::PhantomData};
+use core::sync::atomic::{self, Ordering};
 
-use crate::arch::aarch64::mmio::MmioReg;
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::mmio::PERIPHERALS;
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
 
 #[repr(C, align(16))]
 pub struct MailboxBuffer<const N: usize>([u32; N]);
 
+impl<const N: usize> MailboxBuffer<N> {
+    pub const fn new() -> Self {
+        Self([0; N])
+    }
+
+    pub fn words(&self) -> &[u32; N] {
+        &self.0
+    }
+
+    pub fn words_mut(&mut self) -> &mut [u32; N] {
+        &mut self.0
+    }
+}
+
+impl<const N: usize> Default for MailboxBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const N: usize> From<[u32; N]> for MailboxBuffer<N> {
     fn from(v: [u32; N]) -> MailboxBuffer<N> {
         MailboxBuffer(v)
@@ -12,27 +35,57 @@ impl<const N: usize> From<[u32; N]> for MailboxBuffer<N> {
 }
 
 const MAIL_BASE: usize = 0xB880;
-const MBOX_READ: MmioReg = unsafe { MmioReg::new(MAIL_BASE) };
-const MBOX_POLL: MmioReg = unsafe { MmioReg::new(MAIL_BASE + 0x10) };
-const MBOX_SENDER: MmioReg = unsafe { MmioReg::new(MAIL_BASE + 0x14) };
-const MBOX_STATUS: MmioReg = unsafe { MmioReg::new(MAIL_BASE + 0x18) };
-const MBOX_CONFIG: MmioReg = unsafe { MmioReg::new(MAIL_BASE + 0x1C) };
-const MBOX_WRITE: MmioReg = unsafe { MmioReg::new(MAIL_BASE + 0x20) };
+const MBOX_READ: Register<ReadOnly> = Register::new(MAIL_BASE);
+const MBOX_POLL: Register<ReadOnly> = Register::new(MAIL_BASE + 0x10);
+const MBOX_SENDER: Register<ReadOnly> = Register::new(MAIL_BASE + 0x14);
+const MBOX_STATUS: Register<ReadOnly> = Register::new(MAIL_BASE + 0x18);
+const MBOX_CONFIG: Register<ReadWrite> = Register::new(MAIL_BASE + 0x1C);
+const MBOX_WRITE: Register<WriteOnly> = Register::new(MAIL_BASE + 0x20);
 
 const MBOX_FULL: u32 = 0x80000000;
 const MBOX_EMPTY: u32 = 0x40000000;
 
-pub const MBOX_REQUEST: u32 = 0;
+/// Spins to wait for the VideoCore before giving up
+const MBOX_TIMEOUT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxError {
+    /// The VideoCore didn't answer in time
+    Timeout,
+    /// The tags don't fit in the buffer
+    BufferFull,
+    /// The VideoCore couldn't parse the buffer
+    RequestFailed,
+    /// The buffer came back with neither the success nor the error code
+    InvalidResponse(u32),
+    /// The firmware didn't answer the tag, it doesn't know it
+    TagNotAnswered(u32),
+    /// The answer is shorter than the response of the tag
+    TruncatedResponse(u32),
+    /// The answer to the tag makes no sense
+    BadValue(u32)
+}
 
-pub const MBOX_TAG_GETSERIAL: u32 = 0x10004;
-pub const MBOX_TAG_LAST: u32 = 0;
+impl fmt::Display for MailboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("mailbox timed out"),
+            Self::BufferFull => f.write_str("tags don't fit in the buffer"),
+            Self::RequestFailed => f.write_str("firmware couldn't parse the request"),
+            Self::InvalidResponse(v) => write!(f, "invalid response code ({:#x})", v),
+            Self::TagNotAnswered(tag) => write!(f, "tag {:#x} not answered", tag),
+            Self::TruncatedResponse(tag) => write!(f, "response to tag {:#x} too short", tag),
+            Self::BadValue(tag) => write!(f, "bad value in response to tag {:#x}", tag)
+        }
+    }
+}
 
 #[repr(u8)]
 pub enum Channel {
     PowerManagement = 0,
     FrameBuffer = 1,
     VirtualUART = 2,
-    VCHIQ = 3,
+    Vchiq = 3,
     LEDs = 4,
     Buttons = 5,
     TouchScreen = 6,
@@ -62,21 +115,66 @@ impl<'a> Message<'a> {
     }
 }
 
-// TODO:
-pub fn mbox_call(msg: Message) -> Result<(), ()> {
-    while MBOX_STATUS.read() & MBOX_FULL != 0 {
+/// Sends `msg` and waits for the answer, property messages are checked by
+/// [`PropertyMessage::send`](super::bcm2835_property::PropertyMessage::send).
+#[cfg(target_arch = "aarch64")]
+pub fn mbox_call(msg: Message) -> Result<(), MailboxError> {
+    mbox_call_on(&PERIPHERALS, msg)
+}
+
+/// Spins until `done` returns true, at most [`MBOX_TIMEOUT`] times.
+fn wait(mut done: impl FnMut() -> bool) -> Result<(), MailboxError> {
+    for _ in 0..MBOX_TIMEOUT {
+        if done() {
+            return Ok(());
+        }
+
         hint::spin_loop();
     }
 
-    MBOX_WRITE.write(msg.v);
+    Err(MailboxError::Timeout)
+}
 
-    loop {
-        while MBOX_STATUS.read() & MBOX_EMPTY != 0 {
-            hint::spin_loop();
-        }
+/// Sends `msg` through the mailbox at `regs` and waits for the answer.
+fn mbox_call_on<B: Bus>(regs: &RegisterBlock<B>, msg: Message) -> Result<(), MailboxError> {
+    wait(|| regs.read(MBOX_STATUS) & MBOX_FULL == 0)?;
 
-        if MBOX_READ.read() == msg.v {
-            return Ok(())
-        }
+    // The buffer has to be written before the VideoCore gets to see it, and read after it answered
+    atomic::fence(Ordering::SeqCst);
+    regs.write(MBOX_WRITE, msg.v);
+
+    // Answers to other messages are dropped
+    wait(|| regs.read(MBOX_STATUS) & MBOX_EMPTY == 0 && regs.read(MBOX_READ) == msg.v)?;
+    atomic::fence(Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+
+    #[test]
+    fn mbox_call_waits_for_answer() {
+        let bus = MockBus::new();
+        let regs = RegisterBlock::new(&bus, 1);
+        // Host addresses don't fit into 32 bits, so skip Message::new
+        let msg = Message { v: 0x1000 | Channel::PropertyTagsARMToVC as u32, _lifetime: PhantomData };
+
+        bus.script(MBOX_STATUS.index(), [MBOX_FULL, 0, MBOX_EMPTY, 0, 0]);
+        // The first answer belongs to someone else
+        bus.script(MBOX_READ.index(), [0x2000 | Channel::PropertyTagsARMToVC as u32, msg.v]);
+        assert_eq!(mbox_call_on(&regs, msg), Ok(()));
+        assert_eq!(bus.writes(MBOX_WRITE.index()), [0x1008]);
+    }
+
+    #[test]
+    fn mbox_call_times_out() {
+        let bus = MockBus::new();
+        let regs = RegisterBlock::new(&bus, 1);
+        let msg = Message { v: 0x1000 | Channel::PropertyTagsARMToVC as u32, _lifetime: PhantomData };
+
+        bus.set(MBOX_STATUS.index(), MBOX_EMPTY);
+        assert_eq!(mbox_call_on(&regs, msg), Err(MailboxError::Timeout));
     }
 }
@@ -0,0 +1,760 @@
+//! Property tags, the requests the ARM sends the VideoCore firmware through the mailbox.
+//!
+//! A [`PropertyMessage`] is built from typed [`Tag`]s, and every tag hands back a
+//! [`TagSlot`] to read its response with once the message went through.
+
+use core::marker::PhantomData;
+
+#[cfg(target_arch = "aarch64")]
+use super::bcm2835_mailbox::{mbox_call, Channel, Message};
+use super::bcm2835_mailbox::{MailboxBuffer, MailboxError};
+use crate::drivers::video::framebuffer::FrameBufferInfo;
+
+pub const MBOX_REQUEST: u32 = 0;
+/// Request code of a buffer the VideoCore handled
+pub const MBOX_RESPONSE_SUCCESS: u32 = 0x80000000;
+/// Request code of a buffer the VideoCore couldn't parse
+pub const MBOX_RESPONSE_ERROR: u32 = 0x80000001;
+/// Set in the request code of a tag the VideoCore answered, with the length of the answer in the other bits
+const MBOX_TAG_RESPONSE: u32 = 0x80000000;
+pub const MBOX_TAG_LAST: u32 = 0;
+
+/// Words of a tag before its value: the id, the value buffer size and the request code
+const TAG_HEADER_WORDS: usize = 3;
+
+/// Words of the message [`query`] sends
+#[cfg(target_arch = "aarch64")]
+const QUERY_WORDS: usize = 16;
+
+/// Words of the message [`allocate_framebuffer`] sends
+const FRAMEBUFFER_WORDS: usize = 36;
+
+/// Alignment asked for the framebuffer
+const FRAMEBUFFER_ALIGNMENT: u32 = 4096;
+
+/// A property tag, the struct holds the request.
+pub trait Tag {
+    const ID: u32;
+    /// Size of the value buffer in words, which has to fit the request and the response
+    const WORDS: usize;
+    /// Bytes the firmware answers with
+    const RESPONSE_LEN: usize;
+
+    type Response;
+
+    /// Writes the request to the start of the zeroed value buffer.
+    fn encode(&self, _value: &mut [u32]) {}
+
+    fn decode(value: &[u32]) -> Self::Response;
+}
+
+/// Devices of the power state tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Device {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8
+}
+
+/// Clocks of the clock rate tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+    M2mc = 13,
+    PixelBvb = 14
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerState {
+    pub on: bool,
+    /// False if the board doesn't have the device
+    pub exists: bool
+}
+
+impl PowerState {
+    const ON: u32 = 1 << 0;
+    /// Set in the answer if the device doesn't exist
+    const NO_DEVICE: u32 = 1 << 1;
+    /// Set in the request to wait for the power to become stable
+    const WAIT: u32 = 1 << 1;
+
+    fn decode(state: u32) -> Self {
+        Self {
+            on: state & Self::ON != 0,
+            exists: state & Self::NO_DEVICE == 0
+        }
+    }
+}
+
+/// Part of the memory given to the ARM or the VideoCore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PixelOrder {
+    Bgr = 0,
+    Rgb = 1
+}
+
+/// Framebuffer handed out by the VideoCore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// Bus address, with the cache alias in the top bits
+    pub bus_addr: u32,
+    pub size: u32
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetBoardModel;
+
+impl Tag for GetBoardModel {
+    const ID: u32 = 0x00010001;
+    const WORDS: usize = 1;
+    const RESPONSE_LEN: usize = 4;
+    type Response = u32;
+
+    fn decode(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetBoardRevision;
+
+impl Tag for GetBoardRevision {
+    const ID: u32 = 0x00010002;
+    const WORDS: usize = 1;
+    const RESPONSE_LEN: usize = 4;
+    type Response = u32;
+
+    fn decode(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetMacAddress;
+
+impl Tag for GetMacAddress {
+    const ID: u32 = 0x00010003;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 6;
+    /// In network byte order
+    type Response = [u8; 6];
+
+    fn decode(value: &[u32]) -> [u8; 6] {
+        let (low, high) = (value[0].to_le_bytes(), value[1].to_le_bytes());
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetBoardSerial;
+
+impl Tag for GetBoardSerial {
+    const ID: u32 = 0x00010004;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u64;
+
+    fn decode(value: &[u32]) -> u64 {
+        (value[1] as u64) << 32 | value[0] as u64
+    }
+}
+
+/// Memory left to the ARM by the split with the VideoCore
+#[derive(Debug, Clone, Copy)]
+pub struct GetArmMemory;
+
+impl Tag for GetArmMemory {
+    const ID: u32 = 0x00010005;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = MemoryRegion;
+
+    fn decode(value: &[u32]) -> MemoryRegion {
+        MemoryRegion { base: value[0], size: value[1] }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetVcMemory;
+
+impl Tag for GetVcMemory {
+    const ID: u32 = 0x00010006;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = MemoryRegion;
+
+    fn decode(value: &[u32]) -> MemoryRegion {
+        MemoryRegion { base: value[0], size: value[1] }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetPowerState(pub Device);
+
+impl Tag for GetPowerState {
+    const ID: u32 = 0x00020001;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = PowerState;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode(value: &[u32]) -> PowerState {
+        PowerState::decode(value[1])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SetPowerState {
+    pub device: Device,
+    pub on: bool,
+    /// Answer once the power is stable
+    pub wait: bool
+}
+
+impl Tag for SetPowerState {
+    const ID: u32 = 0x00028001;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = PowerState;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.device as u32;
+        value[1] = if self.on { PowerState::ON } else { 0 } | if self.wait { PowerState::WAIT } else { 0 };
+    }
+
+    fn decode(value: &[u32]) -> PowerState {
+        PowerState::decode(value[1])
+    }
+}
+
+/// Rate of a clock in Hz, 0 if the clock doesn't exist
+#[derive(Debug, Clone, Copy)]
+pub struct GetClockRate(pub Clock);
+
+impl Tag for GetClockRate {
+    const ID: u32 = 0x00030002;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetMaxClockRate(pub Clock);
+
+impl Tag for GetMaxClockRate {
+    const ID: u32 = 0x00030004;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GetMinClockRate(pub Clock);
+
+impl Tag for GetMinClockRate {
+    const ID: u32 = 0x00030007;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+/// Answers with the rate the clock got, in Hz.
+#[derive(Debug, Clone, Copy)]
+pub struct SetClockRate {
+    pub clock: Clock,
+    pub rate: u32,
+    /// Don't raise the voltage along with the ARM clock
+    pub skip_turbo: bool
+}
+
+impl Tag for SetClockRate {
+    const ID: u32 = 0x00038002;
+    const WORDS: usize = 3;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.clock as u32;
+        value[1] = self.rate;
+        value[2] = self.skip_turbo as u32;
+    }
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+/// SoC temperature in thousandths of a degree Celsius
+#[derive(Debug, Clone, Copy)]
+pub struct GetTemperature;
+
+impl Tag for GetTemperature {
+    const ID: u32 = 0x00030006;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+/// Temperature at which the firmware starts throttling, in thousandths of a degree Celsius
+#[derive(Debug, Clone, Copy)]
+pub struct GetMaxTemperature;
+
+impl Tag for GetMaxTemperature {
+    const ID: u32 = 0x0003000A;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = u32;
+
+    fn decode(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocateBuffer {
+    pub alignment: u32
+}
+
+impl Tag for AllocateBuffer {
+    const ID: u32 = 0x00040001;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = Allocation;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.alignment;
+    }
+
+    fn decode(value: &[u32]) -> Allocation {
+        Allocation { bus_addr: value[0], size: value[1] }
+    }
+}
+
+/// Bytes per line of the framebuffer
+#[derive(Debug, Clone, Copy)]
+pub struct GetPitch;
+
+impl Tag for GetPitch {
+    const ID: u32 = 0x00040008;
+    const WORDS: usize = 1;
+    const RESPONSE_LEN: usize = 4;
+    type Response = u32;
+
+    fn decode(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+/// Size of the display, answers with the size the firmware picked.
+#[derive(Debug, Clone, Copy)]
+pub struct SetPhysicalSize(pub Size);
+
+impl Tag for SetPhysicalSize {
+    const ID: u32 = 0x00048003;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = Size;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0.width;
+        value[1] = self.0.height;
+    }
+
+    fn decode(value: &[u32]) -> Size {
+        Size { width: value[0], height: value[1] }
+    }
+}
+
+/// Size of the framebuffer, which can be larger than the display to pan around in.
+#[derive(Debug, Clone, Copy)]
+pub struct SetVirtualSize(pub Size);
+
+impl Tag for SetVirtualSize {
+    const ID: u32 = 0x00048004;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = Size;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0.width;
+        value[1] = self.0.height;
+    }
+
+    fn decode(value: &[u32]) -> Size {
+        Size { width: value[0], height: value[1] }
+    }
+}
+
+/// Bits per pixel, answers with the depth the firmware picked.
+#[derive(Debug, Clone, Copy)]
+pub struct SetDepth(pub u32);
+
+impl Tag for SetDepth {
+    const ID: u32 = 0x00048005;
+    const WORDS: usize = 1;
+    const RESPONSE_LEN: usize = 4;
+    type Response = u32;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0;
+    }
+
+    fn decode(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+/// Answers with the order the firmware picked, which can differ from the one asked.
+#[derive(Debug, Clone, Copy)]
+pub struct SetPixelOrder(pub PixelOrder);
+
+impl Tag for SetPixelOrder {
+    const ID: u32 = 0x00048006;
+    const WORDS: usize = 1;
+    const RESPONSE_LEN: usize = 4;
+    type Response = PixelOrder;
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode(value: &[u32]) -> PixelOrder {
+        if value[0] == PixelOrder::Rgb as u32 { PixelOrder::Rgb } else { PixelOrder::Bgr }
+    }
+}
+
+/// Position of the display within the framebuffer.
+#[derive(Debug, Clone, Copy)]
+pub struct SetVirtualOffset {
+    pub x: u32,
+    pub y: u32
+}
+
+impl Tag for SetVirtualOffset {
+    const ID: u32 = 0x00048009;
+    const WORDS: usize = 2;
+    const RESPONSE_LEN: usize = 8;
+    type Response = (u32, u32);
+
+    fn encode(&self, value: &mut [u32]) {
+        value[0] = self.x;
+        value[1] = self.y;
+    }
+
+    fn decode(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+/// Where the response of a tag in a [`PropertyMessage`] is.
+#[derive(Debug)]
+pub struct TagSlot<T: Tag> {
+    /// Word of the tag id
+    offset: usize,
+    _tag: PhantomData<T>
+}
+
+/// Property message of at most `N` words.
+pub struct PropertyMessage<const N: usize> {
+    buffer: MailboxBuffer<N>,
+    /// Words used, up to and including the end tag
+    len: usize
+}
+
+impl<const N: usize> PropertyMessage<N> {
+    pub fn new() -> Self {
+        let mut buffer = MailboxBuffer::new();
+        let words = buffer.words_mut();
+        words[0] = 3 * 4;
+        words[1] = MBOX_REQUEST;
+        words[2] = MBOX_TAG_LAST;
+        Self { buffer, len: 3 }
+    }
+
+    /// Adds `tag` to the message.
+    pub fn push<T: Tag>(&mut self, tag: &T) -> Result<TagSlot<T>, MailboxError> {
+        // The new tag takes the place of the end tag
+        let offset = self.len - 1;
+        let end = offset + TAG_HEADER_WORDS + T::WORDS;
+        if end >= N {
+            return Err(MailboxError::BufferFull);
+        }
+
+        let words = self.buffer.words_mut();
+        words[offset] = T::ID;
+        words[offset + 1] = (T::WORDS * 4) as u32;
+        words[offset + 2] = MBOX_REQUEST;
+        let value = &mut words[offset + TAG_HEADER_WORDS..end];
+        value.fill(0);
+        tag.encode(value);
+        words[end] = MBOX_TAG_LAST;
+
+        self.len = end + 1;
+        words[0] = (self.len * 4) as u32;
+        Ok(TagSlot { offset, _tag: PhantomData })
+    }
+
+    /// Sends the message to the firmware and checks it was handled.
+    #[cfg(target_arch = "aarch64")]
+    pub fn send(&mut self) -> Result<(), MailboxError> {
+        mbox_call(Message::new(&mut self.buffer, Channel::PropertyTagsARMToVC))?;
+        self.check()
+    }
+
+    /// Checks the request code of the answered message.
+    fn check(&self) -> Result<(), MailboxError> {
+        match self.buffer.words()[1] {
+            MBOX_RESPONSE_SUCCESS => Ok(()),
+            MBOX_RESPONSE_ERROR => Err(MailboxError::RequestFailed),
+            code => Err(MailboxError::InvalidResponse(code))
+        }
+    }
+
+    /// Reads the answer to a tag of this message.
+    pub fn response<T: Tag>(&self, slot: TagSlot<T>) -> Result<T::Response, MailboxError> {
+        let words = self.buffer.words();
+        let code = words[slot.offset + 2];
+        if code & MBOX_TAG_RESPONSE == 0 {
+            return Err(MailboxError::TagNotAnswered(T::ID));
+        }
+
+        if ((code & !MBOX_TAG_RESPONSE) as usize) < T::RESPONSE_LEN {
+            return Err(MailboxError::TruncatedResponse(T::ID));
+        }
+
+        Ok(T::decode(&words[slot.offset + TAG_HEADER_WORDS..][..T::WORDS]))
+    }
+}
+
+impl<const N: usize> Default for PropertyMessage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends `tag` on its own and returns the answer.
+#[cfg(target_arch = "aarch64")]
+pub fn query<T: Tag>(tag: &T) -> Result<T::Response, MailboxError> {
+    let mut msg = PropertyMessage::<QUERY_WORDS>::new();
+    let slot = msg.push(tag)?;
+    msg.send()?;
+    msg.response(slot)
+}
+
+/// The tags that set up the display and allocate a framebuffer for it.
+struct FrameBufferRequest {
+    msg: PropertyMessage<FRAMEBUFFER_WORDS>,
+    size: TagSlot<SetVirtualSize>,
+    depth: TagSlot<SetDepth>,
+    pixel_order: TagSlot<SetPixelOrder>,
+    allocation: TagSlot<AllocateBuffer>,
+    pitch: TagSlot<GetPitch>
+}
+
+impl FrameBufferRequest {
+    fn new(width: u32, height: u32, depth: u32) -> Result<Self, MailboxError> {
+        let mut msg = PropertyMessage::new();
+        msg.push(&SetPhysicalSize(Size { width, height }))?;
+        let size = msg.push(&SetVirtualSize(Size { width, height }))?;
+        msg.push(&SetVirtualOffset { x: 0, y: 0 })?;
+        let depth = msg.push(&SetDepth(depth))?;
+        let pixel_order = msg.push(&SetPixelOrder(PixelOrder::Rgb))?;
+        let allocation = msg.push(&AllocateBuffer { alignment: FRAMEBUFFER_ALIGNMENT })?;
+        let pitch = msg.push(&GetPitch)?;
+        Ok(Self { msg, size, depth, pixel_order, allocation, pitch })
+    }
+
+    /// Reads the framebuffer out of the answered message.
+    fn info(self) -> Result<FrameBufferInfo, MailboxError> {
+        let msg = &self.msg;
+        let size = msg.response(self.size)?;
+        let allocation = msg.response(self.allocation)?;
+        if allocation.size == 0 {
+            return Err(MailboxError::BadValue(AllocateBuffer::ID));
+        }
+
+        FrameBufferInfo::from_bcm2835(
+            allocation.bus_addr,
+            size.width,
+            size.height,
+            msg.response(self.pitch)?,
+            msg.response(self.depth)?,
+            msg.response(self.pixel_order)? as u32)
+            .ok_or(MailboxError::BadValue(AllocateBuffer::ID))
+    }
+}
+
+/// Asks the VideoCore for a framebuffer of `width` by `height` pixels of `depth` bits.
+///
+/// The firmware can pick another size or depth than asked, the answer has the ones it used.
+#[cfg(target_arch = "aarch64")]
+pub fn allocate_framebuffer(width: u32, height: u32, depth: u32) -> Result<FrameBufferInfo, MailboxError> {
+    let mut request = FrameBufferRequest::new(width, height, depth)?;
+    request.msg.send()?;
+    request.info()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every tag of `msg` the way the firmware does, with the value buffer left as it is.
+    fn answer<const N: usize>(msg: &mut PropertyMessage<N>) {
+        let words = msg.buffer.words_mut();
+        words[1] = MBOX_RESPONSE_SUCCESS;
+        let mut i = 2;
+        while words[i] != MBOX_TAG_LAST {
+            words[i + 2] = MBOX_TAG_RESPONSE | words[i + 1];
+            i += TAG_HEADER_WORDS + words[i + 1] as usize / 4;
+        }
+    }
+
+    fn value<'a, const N: usize, T: Tag>(msg: &'a mut PropertyMessage<N>, slot: &TagSlot<T>) -> &'a mut [u32] {
+        &mut msg.buffer.words_mut()[slot.offset + TAG_HEADER_WORDS..][..T::WORDS]
+    }
+
+    #[test]
+    fn builds_messages() {
+        let mut msg = PropertyMessage::<16>::new();
+        msg.push(&GetBoardSerial).unwrap();
+        msg.push(&SetPowerState { device: Device::UsbHcd, on: true, wait: true }).unwrap();
+        assert_eq!(msg.buffer.words()[..msg.len], [
+            13 * 4, MBOX_REQUEST,
+            GetBoardSerial::ID, 8, MBOX_REQUEST, 0, 0,
+            SetPowerState::ID, 8, MBOX_REQUEST, 3, 3,
+            MBOX_TAG_LAST
+        ]);
+
+        // Another 4 words and the end tag don't fit
+        assert_eq!(msg.push(&GetBoardModel).err(), Some(MailboxError::BufferFull));
+    }
+
+    #[test]
+    fn responses() {
+        let mut msg = PropertyMessage::<32>::new();
+        let serial = msg.push(&GetBoardSerial).unwrap();
+        let mac = msg.push(&GetMacAddress).unwrap();
+        let clock = msg.push(&GetClockRate(Clock::Arm)).unwrap();
+        let power = msg.push(&GetPowerState(Device::Spi)).unwrap();
+        assert_eq!(msg.check(), Err(MailboxError::InvalidResponse(MBOX_REQUEST)));
+
+        answer(&mut msg);
+        value(&mut msg, &serial).copy_from_slice(&[0x89AB_CDEF, 0x0123_4567]);
+        value(&mut msg, &mac).copy_from_slice(&[0x27EB_27B8, 0x3456]);
+        value(&mut msg, &clock)[1] = 1_200_000_000;
+        value(&mut msg, &power)[1] = 0b10;
+        assert_eq!(msg.check(), Ok(()));
+        assert_eq!(msg.response(serial), Ok(0x0123_4567_89AB_CDEF));
+        assert_eq!(msg.response(mac), Ok([0xB8, 0x27, 0xEB, 0x27, 0x56, 0x34]));
+        assert_eq!(msg.response(clock), Ok(1_200_000_000));
+        assert_eq!(msg.response(power), Ok(PowerState { on: false, exists: false }));
+    }
+
+    #[test]
+    fn bad_responses() {
+        let mut msg = PropertyMessage::<32>::new();
+        let model = msg.push(&GetBoardModel).unwrap();
+        let temperature = msg.push(&GetTemperature).unwrap();
+        let revision = msg.push(&GetBoardRevision).unwrap();
+        answer(&mut msg);
+        // Unknown tag
+        msg.buffer.words_mut()[model.offset + 2] = 0;
+        msg.buffer.words_mut()[temperature.offset + 2] = MBOX_TAG_RESPONSE | 4;
+        assert_eq!(msg.response(model), Err(MailboxError::TagNotAnswered(GetBoardModel::ID)));
+        assert_eq!(msg.response(temperature), Err(MailboxError::TruncatedResponse(GetTemperature::ID)));
+        assert!(msg.response(revision).is_ok());
+
+        msg.buffer.words_mut()[1] = MBOX_RESPONSE_ERROR;
+        assert_eq!(msg.check(), Err(MailboxError::RequestFailed));
+    }
+
+    #[test]
+    fn framebuffer() {
+        let mut request = FrameBufferRequest::new(640, 480, 32).unwrap();
+        answer(&mut request.msg);
+        // The firmware went for BGR
+        value(&mut request.msg, &request.pixel_order)[0] = PixelOrder::Bgr as u32;
+        value(&mut request.msg, &request.allocation).copy_from_slice(&[0xC3C0_0000, 640 * 480 * 4]);
+        value(&mut request.msg, &request.pitch)[0] = 640 * 4;
+
+        let info = request.info().unwrap();
+        assert_eq!((info.addr, info.width, info.height, info.pitch, info.bpp), (0x03C0_0000, 640, 480, 2560, 32));
+        assert_eq!(info.format.encode(0x12, 0x34, 0x56), 0x0012_3456);
+    }
+
+    #[test]
+    fn framebuffer_refused() {
+        let mut request = FrameBufferRequest::new(640, 480, 32).unwrap();
+        answer(&mut request.msg);
+        value(&mut request.msg, &request.pitch)[0] = 640 * 4;
+        assert_eq!(request.info().err(), Some(MailboxError::BadValue(AllocateBuffer::ID)));
+
+        let mut request = FrameBufferRequest::new(640, 480, 32).unwrap();
+        answer(&mut request.msg);
+        request.msg.buffer.words_mut()[request.allocation.offset + 2] = 0;
+        assert_eq!(request.info().err(), Some(MailboxError::TagNotAnswered(AllocateBuffer::ID)));
+    }
+}
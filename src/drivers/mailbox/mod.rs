@@ -1,2 +1,4 @@
-#[cfg(target_arch = "aarch64")]
+#[cfg(any(target_arch = "aarch64", test))]
 pub mod bcm2835_mailbox;
+#[cfg(any(target_arch = "aarch64", test))]
+pub mod bcm2835_property;
@@ -1,4 +1,13 @@
+pub mod bus;
+pub mod clk;
+pub mod dma;
+pub mod dram;
 pub mod gpio;
+pub mod i2c;
+pub mod input;
 pub mod mailbox;
+pub mod pci;
 pub mod serial;
+pub mod spi;
 pub mod video;
+pub mod virtio;
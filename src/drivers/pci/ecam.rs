@@ -0,0 +1,83 @@
+//! Enhanced configuration access mechanism of PCI Express, the configuration
+//! space of every function mapped at a fixed place in memory.
+
+use super::{Address, ConfigSpace};
+
+/// Configuration space of a function, the extended part included
+const FUNCTION_SIZE: usize = 4096;
+const BUS_SHIFT: usize = 20;
+const DEVICE_SHIFT: usize = 15;
+const FUNCTION_SHIFT: usize = 12;
+
+pub struct Ecam {
+    base: usize,
+    start_bus: u8,
+    end_bus: u8
+}
+
+impl Ecam {
+    /// # Safety
+    ///
+    /// `base` has to be the mapped address of the region covering `start_bus` to `end_bus`.
+    pub const unsafe fn new(base: usize, start_bus: u8, end_bus: u8) -> Self {
+        Self { base, start_bus, end_bus }
+    }
+
+    /// Size of the region for buses `start_bus` to `end_bus`.
+    pub const fn size(start_bus: u8, end_bus: u8) -> usize {
+        (end_bus as usize - start_bus as usize + 1) << BUS_SHIFT
+    }
+
+    fn ptr(&self, addr: Address, offset: u16) -> Option<*mut u32> {
+        if addr.bus < self.start_bus || addr.bus > self.end_bus || offset as usize >= FUNCTION_SIZE {
+            return None;
+        }
+
+        let offset = ((addr.bus - self.start_bus) as usize) << BUS_SHIFT
+            | (addr.device as usize) << DEVICE_SHIFT
+            | (addr.function as usize) << FUNCTION_SHIFT
+            | (offset as usize & !0x3);
+        Some((self.base + offset) as *mut u32)
+    }
+}
+
+impl ConfigSpace for Ecam {
+    fn read(&self, addr: Address, offset: u16) -> u32 {
+        match self.ptr(addr, offset) {
+            Some(ptr) => unsafe { ptr.read_volatile() },
+            None => !0
+        }
+    }
+
+    fn write(&self, addr: Address, offset: u16, value: u32) {
+        if let Some(ptr) = self.ptr(addr, offset) {
+            unsafe { ptr.write_volatile(value) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+
+    use super::*;
+
+    #[test]
+    fn layout() {
+        // Buses 2 and 3
+        let mut memory = vec![0u32; Ecam::size(2, 3) / 4];
+        let ecam = unsafe { Ecam::new(memory.as_mut_ptr() as usize, 2, 3) };
+
+        ecam.write(Address::new(3, 0x1F, 7), 0x104, 0x1234_5678);
+        ecam.write(Address::new(2, 1, 0), 0x00, 0x1041_1AF4);
+        assert_eq!(ecam.read(Address::new(3, 0x1F, 7), 0x104), 0x1234_5678);
+        assert_eq!(ecam.read(Address::new(1, 0, 0), 0), !0);
+        assert_eq!(ecam.read(Address::new(4, 0, 0), 0), !0);
+        assert_eq!(ecam.read(Address::new(2, 1, 0), 0x1000), !0);
+
+        assert_eq!(memory[(1 << 20 | 0x1F << 15 | 7 << 12 | 0x104) / 4], 0x1234_5678);
+        assert_eq!(memory[(1 << 15) / 4], 0x1041_1AF4);
+    }
+}
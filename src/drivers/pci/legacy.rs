@@ -0,0 +1,75 @@
+//! Configuration mechanism 1 of PCs: an address written to one I/O port
+//! selects the dword the other one reads or writes. Only the first 256 bytes
+//! of each function are reachable this way.
+
+use crate::arch::x86_64::io::{inl, outl};
+use crate::sync::mutex::Mutex;
+
+use super::{Address, ConfigSpace};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const ADDRESS_ENABLE: u32 = 1 << 31;
+
+/// Size of the configuration space reachable through the ports
+const CONFIG_SIZE: u16 = 256;
+
+const fn config_address(addr: Address, offset: u16) -> u32 {
+    ADDRESS_ENABLE
+        | (addr.bus as u32) << 16
+        | (addr.device as u32 & 0x1F) << 11
+        | (addr.function as u32 & 0x7) << 8
+        | (offset as u32 & 0xFC)
+}
+
+pub struct LegacyConfig {
+    /// The address and data ports go together
+    lock: Mutex<()>
+}
+
+impl LegacyConfig {
+    /// # Safety
+    ///
+    /// The configuration ports mustn't be used by anything else.
+    pub const unsafe fn new() -> Self {
+        Self { lock: Mutex::new(()) }
+    }
+}
+
+impl ConfigSpace for LegacyConfig {
+    fn read(&self, addr: Address, offset: u16) -> u32 {
+        if offset >= CONFIG_SIZE {
+            return !0;
+        }
+
+        let _lock = self.lock.lock();
+        unsafe {
+            outl(CONFIG_ADDRESS, config_address(addr, offset));
+            inl(CONFIG_DATA)
+        }
+    }
+
+    fn write(&self, addr: Address, offset: u16, value: u32) {
+        if offset >= CONFIG_SIZE {
+            return;
+        }
+
+        let _lock = self.lock.lock();
+        unsafe {
+            outl(CONFIG_ADDRESS, config_address(addr, offset));
+            outl(CONFIG_DATA, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address() {
+        assert_eq!(config_address(Address::new(0, 0, 0), 0), 0x8000_0000);
+        assert_eq!(config_address(Address::new(0x12, 0x1F, 7), 0x3E), 0x8012_FF3C);
+    }
+}
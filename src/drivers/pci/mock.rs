@@ -0,0 +1,80 @@
+//! Fake configuration space for host tests, with 256 bytes per function.
+
+extern crate std;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use super::{Address, ConfigSpace, BAR0, NUM_BARS};
+
+const DWORDS: usize = 64;
+
+/// Registers of a function and the bits of them that writes don't change.
+struct Space {
+    regs: [u32; DWORDS],
+    read_only: [u32; DWORDS]
+}
+
+/// Functions backed by plain memory, other addresses read as all ones.
+#[derive(Default)]
+pub struct MockConfig {
+    functions: RefCell<BTreeMap<Address, Space>>
+}
+
+impl MockConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a function, `class` has the class, subclass, programming interface and revision from high to low byte.
+    pub fn add_function(&self, addr: Address, vendor: u16, device: u16, class: u32, header_type: u8) {
+        let mut space = Space { regs: [0; DWORDS], read_only: [0; DWORDS] };
+        space.regs[0] = (device as u32) << 16 | vendor as u32;
+        space.regs[2] = class;
+        space.regs[3] = (header_type as u32) << 16;
+
+        // Writing 0s to the status leaves it alone
+        space.read_only[1] = 0xFFFF_0000;
+
+        // Unimplemented BARs are hardwired to 0
+        let bars = BAR0 as usize / 4;
+        space.read_only[bars..bars + NUM_BARS].fill(!0);
+        self.functions.borrow_mut().insert(addr, space);
+    }
+
+    /// Implements BAR `index`, the bits in `read_only` are the size - 1 and the flags of `value`.
+    pub fn add_bar(&self, addr: Address, index: usize, value: u32, read_only: u32) {
+        let reg = BAR0 as usize / 4 + index;
+        let mut functions = self.functions.borrow_mut();
+        let space = functions.get_mut(&addr).expect("no such function");
+        space.regs[reg] = value;
+        space.read_only[reg] = read_only;
+    }
+
+    /// Sets the dword at `offset` regardless of read only bits.
+    pub fn set(&self, addr: Address, offset: u16, value: u32) {
+        self.functions.borrow_mut().get_mut(&addr).expect("no such function").regs[offset as usize / 4] = value;
+    }
+
+    pub fn get(&self, addr: Address, offset: u16) -> u32 {
+        self.functions.borrow().get(&addr).expect("no such function").regs[offset as usize / 4]
+    }
+}
+
+impl ConfigSpace for MockConfig {
+    fn read(&self, addr: Address, offset: u16) -> u32 {
+        let functions = self.functions.borrow();
+        match functions.get(&addr) {
+            Some(space) if (offset as usize) < 4 * DWORDS => space.regs[offset as usize / 4],
+            _ => !0
+        }
+    }
+
+    fn write(&self, addr: Address, offset: u16, value: u32) {
+        let mut functions = self.functions.borrow_mut();
+        if let Some(space) = functions.get_mut(&addr).filter(|_| (offset as usize) < 4 * DWORDS) {
+            let i = offset as usize / 4;
+            space.regs[i] = space.regs[i] & space.read_only[i] | value & !space.read_only[i];
+        }
+    }
+}
@@ -0,0 +1,922 @@
+//! PCI and PCI Express functions, found by walking the buses from the host bridge.
+//!
+//! Configuration space is reached through a [`ConfigSpace`], the legacy I/O
+//! ports on PCs or ECAM where the firmware describes it. Only segment group 0
+//! is looked at, and bus numbers are taken as the firmware assigned them.
+//! Drivers are added with [`register_driver`] and bound by [`probe`] to the
+//! functions matching one of their IDs.
+
+pub mod ecam;
+#[cfg(target_arch = "x86_64")]
+pub mod legacy;
+#[cfg(test)]
+pub mod mock;
+
+use core::fmt::{self, Write};
+
+use crate::sync::mutex::Mutex;
+
+/// Most drivers [`register_driver`] takes
+pub const MAX_DRIVERS: usize = 16;
+
+/// Most functions that can be bound to a driver
+const MAX_BOUND: usize = 64;
+
+const VENDOR_ID: u16 = 0x00;
+const DEVICE_ID: u16 = 0x02;
+const COMMAND: u16 = 0x04;
+const STATUS: u16 = 0x06;
+const REVISION_ID: u16 = 0x08;
+const PROG_IF: u16 = 0x09;
+const SUBCLASS: u16 = 0x0A;
+const CLASS: u16 = 0x0B;
+const HEADER_TYPE: u16 = 0x0E;
+const BAR0: u16 = 0x10;
+const SECONDARY_BUS: u16 = 0x19;
+const CAPABILITIES_PTR: u16 = 0x34;
+const INTERRUPT_LINE: u16 = 0x3C;
+const INTERRUPT_PIN: u16 = 0x3D;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// The function has a list of capabilities
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const HEADER_TYPE_MASK: u8 = 0x7F;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+const HEADER_BRIDGE: u8 = 0x01;
+
+/// BARs of a normal function, bridges have 2
+const NUM_BARS: usize = 6;
+const NUM_BRIDGE_BARS: usize = 2;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_64: u32 = 0x2 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+const BAR_MEMORY_MASK: u32 = !0xF;
+const BAR_IO_MASK: u32 = !0x3;
+
+pub const CAP_PM: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCIE: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// Capabilities followed before giving up on a list that loops
+const MAX_CAPABILITIES: usize = 48;
+
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MMC_SHIFT: u16 = 1;
+const MSI_CONTROL_MME: u16 = 0x7 << 4;
+const MSI_CONTROL_64: u16 = 1 << 7;
+const MSI_CONTROL_MASKABLE: u16 = 1 << 8;
+
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x7FF;
+const MSIX_CONTROL_MASK_ALL: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+const MSIX_BIR_MASK: u32 = 0x7;
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Bus, device and function number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8
+}
+
+impl Address {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device, function }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A way to reach the configuration space of the functions.
+pub trait ConfigSpace {
+    /// Reads the dword at `offset`, which is 4 byte aligned. Functions that don't exist read as all ones.
+    fn read(&self, addr: Address, offset: u16) -> u32;
+
+    fn write(&self, addr: Address, offset: u16, value: u32);
+}
+
+impl<C: ConfigSpace + ?Sized> ConfigSpace for &C {
+    fn read(&self, addr: Address, offset: u16) -> u32 {
+        (**self).read(addr, offset)
+    }
+
+    fn write(&self, addr: Address, offset: u16, value: u32) {
+        (**self).write(addr, offset, value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciError {
+    /// There are already [`MAX_DRIVERS`] drivers
+    TooManyDrivers,
+    /// A driver with the same name exists
+    DuplicateDriver,
+    /// The function doesn't have what the driver needs
+    Unsupported,
+    /// The driver couldn't set the function up
+    ProbeFailed(&'static str)
+}
+
+impl fmt::Display for PciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyDrivers => f.write_str("too many PCI drivers"),
+            Self::DuplicateDriver => f.write_str("PCI driver already registered"),
+            Self::Unsupported => f.write_str("PCI function not supported"),
+            Self::ProbeFailed(reason) => write!(f, "PCI probe failed: {}", reason)
+        }
+    }
+}
+
+/// Class code of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Class {
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8
+}
+
+impl Class {
+    /// Name of the class, as precise as the table here knows it.
+    pub fn name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "network controller",
+            (0x03, 0x00) => "VGA compatible controller",
+            (0x03, _) => "display controller",
+            (0x04, 0x03) => "audio device",
+            (0x04, _) => "multimedia controller",
+            (0x05, _) => "memory controller",
+            (0x06, 0x00) => "host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "bridge",
+            (0x07, _) => "communication controller",
+            (0x08, _) => "system peripheral",
+            (0x09, _) => "input device",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "serial bus controller",
+            _ => "unknown device"
+        }
+    }
+}
+
+/// Base address register, after sizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Takes two registers, and can be above 4 GiB
+        wide: bool
+    },
+    Io {
+        port: u32,
+        size: u32
+    }
+}
+
+impl Bar {
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } => size,
+            Bar::Io { size, .. } => size as u64
+        }
+    }
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Bar::Memory { addr, size, prefetchable, wide } => write!(f, "memory at {:#x} ({}-bit, {}prefetchable) [size={}]",
+                addr, if wide { 64 } else { 32 }, if prefetchable { "" } else { "non-" }, Size(size)),
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#x} [size={}]", port, size)
+        }
+    }
+}
+
+/// A size in bytes, in the largest unit it is a multiple of.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            size if size >= 1 << 30 && size.is_multiple_of(1 << 30) => write!(f, "{}G", size >> 30),
+            size if size >= 1 << 20 && size.is_multiple_of(1 << 20) => write!(f, "{}M", size >> 20),
+            size if size >= 1 << 10 && size.is_multiple_of(1 << 10) => write!(f, "{}K", size >> 10),
+            size => write!(f, "{}", size)
+        }
+    }
+}
+
+/// An entry of the capability list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability in configuration space
+    pub offset: u16
+}
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            CAP_PM => "power management",
+            CAP_MSI => "MSI",
+            CAP_VENDOR => "vendor specific",
+            CAP_PCIE => "PCI Express",
+            CAP_MSIX => "MSI-X",
+            _ => "unknown"
+        }
+    }
+}
+
+pub struct Capabilities<'a> {
+    function: Function<'a>,
+    next: u8,
+    left: usize
+}
+
+impl Iterator for Capabilities<'_> {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        // The bottom two bits are reserved
+        let offset = (self.next & !0x3) as u16;
+        if offset == 0 || self.left == 0 {
+            return None;
+        }
+
+        self.left -= 1;
+        self.next = self.function.read8(offset + 1);
+        Some(Capability { id: self.function.read8(offset), offset })
+    }
+}
+
+/// One function of a device, reached through `config`.
+#[derive(Clone, Copy)]
+pub struct Function<'a> {
+    config: &'a dyn ConfigSpace,
+    addr: Address
+}
+
+impl<'a> Function<'a> {
+    pub fn new(config: &'a dyn ConfigSpace, addr: Address) -> Self {
+        Self { config, addr }
+    }
+
+    pub fn address(&self) -> Address {
+        self.addr
+    }
+
+    pub fn read32(&self, offset: u16) -> u32 {
+        self.config.read(self.addr, offset & !0x3)
+    }
+
+    pub fn read16(&self, offset: u16) -> u16 {
+        (self.read32(offset) >> (8 * (offset & 0x2))) as u16
+    }
+
+    pub fn read8(&self, offset: u16) -> u8 {
+        (self.read32(offset) >> (8 * (offset & 0x3))) as u8
+    }
+
+    pub fn write32(&self, offset: u16, value: u32) {
+        self.config.write(self.addr, offset & !0x3, value)
+    }
+
+    /// Writes half of a dword, the other half is written back as read.
+    pub fn write16(&self, offset: u16, value: u16) {
+        let shift = 8 * (offset & 0x2);
+        let old = self.read32(offset) & !(0xFFFF << shift);
+        self.write32(offset, old | (value as u32) << shift);
+    }
+
+    /// Whether something answers at this address.
+    pub fn exists(&self) -> bool {
+        self.vendor_id() != 0xFFFF
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read16(VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read16(DEVICE_ID)
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.read8(REVISION_ID)
+    }
+
+    pub fn class(&self) -> Class {
+        Class {
+            class: self.read8(CLASS),
+            subclass: self.read8(SUBCLASS),
+            prog_if: self.read8(PROG_IF)
+        }
+    }
+
+    pub fn header_type(&self) -> u8 {
+        self.read8(HEADER_TYPE) & HEADER_TYPE_MASK
+    }
+
+    /// Whether the device has more functions than this one, only meaningful on function 0.
+    pub fn multifunction(&self) -> bool {
+        self.read8(HEADER_TYPE) & HEADER_MULTIFUNCTION != 0
+    }
+
+    pub fn is_bridge(&self) -> bool {
+        self.header_type() == HEADER_BRIDGE
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        self.read8(INTERRUPT_LINE)
+    }
+
+    /// INTA# to INTD# as 1 to 4, 0 without a legacy interrupt.
+    pub fn interrupt_pin(&self) -> u8 {
+        self.read8(INTERRUPT_PIN)
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read16(COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        // The status half is cleared by writing 1, so it gets 0s
+        self.write32(COMMAND, command as u32);
+    }
+
+    /// Sets `bits` in the command register, [`COMMAND_MEMORY`] and [`COMMAND_BUS_MASTER`] for most drivers.
+    pub fn enable(&self, bits: u16) {
+        self.set_command(self.command() | bits);
+    }
+
+    /// Sizes BAR `index`, `None` if it isn't implemented or is the upper half of a 64 bit one.
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        if index >= self.num_bars() {
+            return None;
+        }
+
+        // The upper half of a 64 bit BAR can look like anything, only walking from BAR 0 tells
+        let mut next = 0;
+        while next < index {
+            next += self.bar_slots(next);
+        }
+
+        if next == index { self.size_bar(index) } else { None }
+    }
+
+    /// The implemented BARs with their index.
+    pub fn bars(&self) -> impl Iterator<Item = (usize, Bar)> + 'a {
+        let function = *self;
+        let mut next = 0;
+        (0..self.num_bars()).filter_map(move |i| {
+            if i < next {
+                return None;
+            }
+
+            next = i + function.bar_slots(i);
+            function.size_bar(i).map(|bar| (i, bar))
+        })
+    }
+
+    fn num_bars(&self) -> usize {
+        if self.is_bridge() { NUM_BRIDGE_BARS } else { NUM_BARS }
+    }
+
+    /// How many BAR registers the BAR at `index` takes, 2 for a 64 bit one.
+    fn bar_slots(&self, index: usize) -> usize {
+        let value = self.read32(BAR0 + 4 * index as u16);
+        if value & BAR_IO == 0 && value & BAR_64 != 0 { 2 } else { 1 }
+    }
+
+    /// Sizes the BAR at `index`, which has to start a BAR.
+    fn size_bar(&self, index: usize) -> Option<Bar> {
+        let bars = self.num_bars();
+        let offset = BAR0 + 4 * index as u16;
+        let value = self.read32(offset);
+
+        // The function mustn't decode the all ones address while sizing
+        let command = self.command();
+        self.set_command(command & !(COMMAND_IO | COMMAND_MEMORY));
+        self.write32(offset, !0);
+        let mask = self.read32(offset);
+        self.write32(offset, value);
+
+        let bar = if value & BAR_IO != 0 {
+            let size = (!(mask & BAR_IO_MASK) & 0xFFFF).wrapping_add(1);
+            (mask & BAR_IO_MASK != 0).then_some(Bar::Io { port: value & BAR_IO_MASK, size })
+        } else {
+            let wide = value & BAR_64 != 0;
+            let (addr, mask) = if wide && index + 1 < bars {
+                let high = self.read32(offset + 4);
+                self.write32(offset + 4, !0);
+                let high_mask = self.read32(offset + 4);
+                self.write32(offset + 4, high);
+                ((high as u64) << 32 | (value & BAR_MEMORY_MASK) as u64, (high_mask as u64) << 32 | (mask & BAR_MEMORY_MASK) as u64)
+            } else {
+                ((value & BAR_MEMORY_MASK) as u64, (mask & BAR_MEMORY_MASK) as u64 | 0xFFFF_FFFF_0000_0000)
+            };
+
+            let prefetchable = value & BAR_PREFETCHABLE != 0;
+            (mask & BAR_MEMORY_MASK as u64 != 0).then(|| Bar::Memory { addr, size: (!mask).wrapping_add(1), prefetchable, wide })
+        };
+
+        self.set_command(command);
+        bar
+    }
+
+    pub fn capabilities(&self) -> Capabilities<'a> {
+        let next = if self.read16(STATUS) & STATUS_CAPABILITIES != 0 { self.read8(CAPABILITIES_PTR) } else { 0 };
+        Capabilities { function: *self, next, left: MAX_CAPABILITIES }
+    }
+
+    pub fn find_capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities().find(|c| c.id == id)
+    }
+
+    pub fn msi(&self) -> Option<Msi<'a>> {
+        self.find_capability(CAP_MSI).map(|c| Msi { function: *self, offset: c.offset })
+    }
+
+    pub fn msix(&self) -> Option<MsiX<'a>> {
+        self.find_capability(CAP_MSIX).map(|c| MsiX { function: *self, offset: c.offset })
+    }
+}
+
+/// The MSI capability of a function.
+pub struct Msi<'a> {
+    function: Function<'a>,
+    offset: u16
+}
+
+impl Msi<'_> {
+    fn control(&self) -> u16 {
+        self.function.read16(self.offset + 2)
+    }
+
+    /// Vectors the function asks for.
+    pub fn vectors(&self) -> u8 {
+        1 << ((self.control() >> MSI_CONTROL_MMC_SHIFT) & 0x7)
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.control() & MSI_CONTROL_64 != 0
+    }
+
+    pub fn maskable(&self) -> bool {
+        self.control() & MSI_CONTROL_MASKABLE != 0
+    }
+
+    /// Has the function signal its interrupt by writing `data` to `addr`, with a single vector.
+    pub fn enable(&self, addr: u64, data: u16) -> Result<(), PciError> {
+        let control = self.control();
+        if addr >> 32 != 0 && control & MSI_CONTROL_64 == 0 {
+            return Err(PciError::Unsupported);
+        }
+
+        self.function.write32(self.offset + 4, addr as u32);
+        let data_offset = if control & MSI_CONTROL_64 != 0 {
+            self.function.write32(self.offset + 8, (addr >> 32) as u32);
+            12
+        } else {
+            8
+        };
+
+        self.function.write16(self.offset + data_offset, data);
+        self.function.write16(self.offset + 2, control & !MSI_CONTROL_MME | MSI_CONTROL_ENABLE);
+        Ok(())
+    }
+
+    pub fn disable(&self) {
+        self.function.write16(self.offset + 2, self.control() & !MSI_CONTROL_ENABLE);
+    }
+}
+
+/// The MSI-X capability of a function, whose vectors are in a table in one of its BARs.
+pub struct MsiX<'a> {
+    function: Function<'a>,
+    offset: u16
+}
+
+impl MsiX<'_> {
+    fn control(&self) -> u16 {
+        self.function.read16(self.offset + 2)
+    }
+
+    pub fn table_size(&self) -> u16 {
+        (self.control() & MSIX_CONTROL_TABLE_SIZE) + 1
+    }
+
+    /// BAR and offset in it of the vector table.
+    pub fn table(&self) -> (usize, u32) {
+        let value = self.function.read32(self.offset + 4);
+        ((value & MSIX_BIR_MASK) as usize, value & !MSIX_BIR_MASK)
+    }
+
+    /// BAR and offset in it of the pending bit array.
+    pub fn pending_bits(&self) -> (usize, u32) {
+        let value = self.function.read32(self.offset + 8);
+        ((value & MSIX_BIR_MASK) as usize, value & !MSIX_BIR_MASK)
+    }
+
+    /// Address of the vector table, if its BAR is a memory one.
+    pub fn table_address(&self) -> Option<u64> {
+        let (bar, offset) = self.table();
+        match self.function.bar(bar)? {
+            Bar::Memory { addr, .. } => Some(addr + offset as u64),
+            Bar::Io { .. } => None
+        }
+    }
+
+    /// Sets vector `index` up to write `data` to `addr`, unmasked.
+    ///
+    /// # Safety
+    ///
+    /// `table` has to be the mapped address of the vector table, with memory decoding enabled.
+    pub unsafe fn set_vector(&self, table: usize, index: u16, addr: u64, data: u32) -> Result<(), PciError> {
+        if index >= self.table_size() {
+            return Err(PciError::Unsupported);
+        }
+
+        let entry = (table + index as usize * MSIX_ENTRY_SIZE) as *mut u32;
+        entry.write_volatile(addr as u32);
+        entry.add(1).write_volatile((addr >> 32) as u32);
+        entry.add(2).write_volatile(data);
+        entry.add(3).write_volatile(entry.add(3).read_volatile() & !MSIX_ENTRY_MASKED);
+        Ok(())
+    }
+
+    pub fn enable(&self) {
+        self.function.write16(self.offset + 2, self.control() & !MSIX_CONTROL_MASK_ALL | MSIX_CONTROL_ENABLE);
+    }
+
+    pub fn disable(&self) {
+        self.function.write16(self.offset + 2, self.control() & !MSIX_CONTROL_ENABLE);
+    }
+}
+
+/// Calls `f` with every function behind bus 0, going through bridges.
+pub fn enumerate<F: FnMut(Function<'_>)>(config: &dyn ConfigSpace, mut f: F) {
+    scan_bus(config, 0, &mut f);
+}
+
+fn scan_bus(config: &dyn ConfigSpace, bus: u8, f: &mut dyn FnMut(Function<'_>)) {
+    for device in 0..32 {
+        let first = Function::new(config, Address::new(bus, device, 0));
+        if !first.exists() {
+            continue;
+        }
+
+        let functions = if first.multifunction() { 8 } else { 1 };
+        for function in 0..functions {
+            let function = Function::new(config, Address::new(bus, device, function));
+            if !function.exists() {
+                continue;
+            }
+
+            f(function);
+
+            // Buses behind bridges have higher numbers, anything else would loop
+            let secondary = function.read8(SECONDARY_BUS);
+            if function.is_bridge() && secondary > bus {
+                scan_bus(config, secondary, f);
+            }
+        }
+    }
+}
+
+/// IDs a driver handles, the fields that are `None` match anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>
+}
+
+impl DeviceId {
+    pub const fn device(vendor: u16, device: u16) -> Self {
+        Self { vendor: Some(vendor), device: Some(device), class: None, subclass: None }
+    }
+
+    /// Every device of `vendor`.
+    pub const fn vendor(vendor: u16) -> Self {
+        Self { vendor: Some(vendor), device: None, class: None, subclass: None }
+    }
+
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self { vendor: None, device: None, class: Some(class), subclass: Some(subclass) }
+    }
+
+    pub fn matches(&self, function: &Function<'_>) -> bool {
+        let class = function.class();
+        self.vendor.is_none_or(|v| v == function.vendor_id())
+            && self.device.is_none_or(|d| d == function.device_id())
+            && self.class.is_none_or(|c| c == class.class)
+            && self.subclass.is_none_or(|s| s == class.subclass)
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    /// Sets a matching function up, which is bound to the driver if it succeeds
+    pub probe: fn(Function<'_>) -> Result<(), PciError>
+}
+
+static DRIVERS: Mutex<[Option<&'static Driver>; MAX_DRIVERS]> = Mutex::new([None; MAX_DRIVERS]);
+static BOUND: Mutex<[Option<(Address, &'static Driver)>; MAX_BOUND]> = Mutex::new([None; MAX_BOUND]);
+
+pub fn register_driver(driver: &'static Driver) -> Result<(), PciError> {
+    let mut drivers = DRIVERS.lock();
+    if drivers.iter().flatten().any(|d| d.name == driver.name) {
+        return Err(PciError::DuplicateDriver);
+    }
+
+    let slot = drivers.iter_mut().find(|d| d.is_none()).ok_or(PciError::TooManyDrivers)?;
+    *slot = Some(driver);
+    Ok(())
+}
+
+/// The driver bound to the function at `addr`.
+pub fn driver_of(addr: Address) -> Option<&'static Driver> {
+    BOUND.lock().iter().flatten().find(|(a, _)| *a == addr).map(|(_, driver)| *driver)
+}
+
+/// Offers the functions no driver has yet to the matching drivers, in the order
+/// they were registered, and returns how many got bound. Once `MAX_BOUND`
+/// functions are bound, the others aren't offered.
+pub fn probe(config: &dyn ConfigSpace) -> usize {
+    let drivers = *DRIVERS.lock();
+    let mut bound = 0;
+    enumerate(config, |function| {
+        let addr = function.address();
+        if driver_of(addr).is_some() {
+            return;
+        }
+
+        // A driver that took the function has to be found by driver_of
+        if BOUND.lock().iter().all(Option::is_some) {
+            return;
+        }
+
+        let matching = drivers.iter().flatten().filter(|d| d.ids.iter().any(|id| id.matches(&function)));
+        for driver in matching {
+            if (driver.probe)(function).is_ok() {
+                if let Some(slot) = BOUND.lock().iter_mut().find(|b| b.is_none()) {
+                    *slot = Some((addr, driver));
+                }
+
+                bound += 1;
+                break;
+            }
+        }
+    });
+
+    bound
+}
+
+/// Writes a line per function, and with `verbose` its BARs, interrupt and capabilities.
+pub fn list(config: &dyn ConfigSpace, out: &mut dyn Write, verbose: bool) -> fmt::Result {
+    let mut result = Ok(());
+    enumerate(config, |function| {
+        if result.is_ok() {
+            result = list_function(&function, out, verbose);
+        }
+    });
+
+    result
+}
+
+fn list_function(function: &Function<'_>, out: &mut dyn Write, verbose: bool) -> fmt::Result {
+    let class = function.class();
+    write!(out, "{} {:04x}:{:04x} {} [{:02x}{:02x}]", function.address(), function.vendor_id(), function.device_id(),
+        class.name(), class.class, class.subclass)?;
+    if function.revision() != 0 {
+        write!(out, " (rev {:02x})", function.revision())?;
+    }
+    if let Some(driver) = driver_of(function.address()) {
+        write!(out, ", driver {}", driver.name)?;
+    }
+    writeln!(out)?;
+
+    if !verbose {
+        return Ok(());
+    }
+
+    for (i, bar) in function.bars() {
+        writeln!(out, "    BAR{}: {}", i, bar)?;
+    }
+
+    let pin = function.interrupt_pin();
+    if (1..=4).contains(&pin) {
+        writeln!(out, "    interrupt: pin {}, line {}", (b'A' + pin - 1) as char, function.interrupt_line())?;
+    }
+
+    for cap in function.capabilities() {
+        write!(out, "    capability at {:#04x}: {}", cap.offset, cap.name())?;
+        if let Some(msi) = function.msi().filter(|_| cap.id == CAP_MSI) {
+            write!(out, ", {} vectors{}", msi.vectors(), if msi.is_64() { ", 64-bit" } else { "" })?;
+        }
+        if let Some(msix) = function.msix().filter(|_| cap.id == CAP_MSIX) {
+            let (bar, offset) = msix.table();
+            write!(out, ", {} vectors, table in BAR{} at {:#x}", msix.table_size(), bar, offset)?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::*;
+    use super::mock::MockConfig;
+
+    /// A host bridge, a two function device with BARs and capabilities, and a bridge to a NIC on bus 1.
+    fn machine() -> MockConfig {
+        let config = MockConfig::new();
+        config.add_function(Address::new(0, 0, 0), 0x8086, 0x29C0, 0x06_00_00_02, 0x00);
+        config.add_function(Address::new(0, 1, 0), 0x1AF4, 0x1041, 0x02_00_00_01, HEADER_MULTIFUNCTION);
+        config.add_function(Address::new(0, 1, 1), 0x1AF4, 0x1042, 0x01_00_00_01, 0x00);
+        config.add_function(Address::new(0, 2, 0), 0x1B36, 0x000C, 0x06_04_00_00, HEADER_BRIDGE);
+        config.set(Address::new(0, 2, 0), 0x18, 0x00_01_01_00);
+        config.add_function(Address::new(1, 0, 0), 0x8086, 0x10D3, 0x02_00_00_00, 0x00);
+
+        // A 4 KiB BAR, I/O ports, a 16 KiB 64 bit prefetchable one at 0xC_0000_0000,
+        // whose upper half looks like a 64 bit BAR of its own, and a 32 bit one
+        let nic = Address::new(0, 1, 0);
+        config.add_bar(nic, 0, 0xFEB0_0000, 0xFFF);
+        config.add_bar(nic, 1, 0xC001, 0x1F);
+        config.add_bar(nic, 2, 0x0000_000C, 0x3FFF);
+        config.add_bar(nic, 3, 0xC, 0);
+        config.add_bar(nic, 4, 0xFEB0_1000, 0xFFF);
+
+        // MSI-X at 0x40 followed by 64 bit MSI at 0x50
+        config.set(nic, 0x04, (STATUS_CAPABILITIES as u32) << 16 | COMMAND_MEMORY as u32);
+        config.set(nic, 0x34, 0x40);
+        config.set(nic, 0x40, 0x0003_5011);
+        config.set(nic, 0x44, 0x0000_1000 | 2);
+        config.set(nic, 0x48, 0x0000_1800 | 2);
+        config.set(nic, 0x50, (MSI_CONTROL_64 as u32 | 2 << MSI_CONTROL_MMC_SHIFT) << 16 | 0x00_05);
+        config.set(nic, 0x3C, 0x01_0B);
+        config
+    }
+
+    #[test]
+    fn enumerate_buses() {
+        let config = machine();
+        let mut found = Vec::new();
+        enumerate(&config, |f| found.push((f.address(), f.vendor_id(), f.device_id())));
+        assert_eq!(found, [
+            (Address::new(0, 0, 0), 0x8086, 0x29C0),
+            (Address::new(0, 1, 0), 0x1AF4, 0x1041),
+            (Address::new(0, 1, 1), 0x1AF4, 0x1042),
+            (Address::new(0, 2, 0), 0x1B36, 0x000C),
+            (Address::new(1, 0, 0), 0x8086, 0x10D3)
+        ]);
+
+        let function = Function::new(&config, Address::new(0, 0, 0));
+        assert_eq!(function.class(), Class { class: 0x06, subclass: 0x00, prog_if: 0x00 });
+        assert_eq!(function.revision(), 2);
+        assert!(!Function::new(&config, Address::new(0, 3, 0)).exists());
+    }
+
+    #[test]
+    fn bars() {
+        let config = machine();
+        let function = Function::new(&config, Address::new(0, 1, 0));
+        let bars: Vec<_> = function.bars().collect();
+        assert_eq!(bars, [
+            (0, Bar::Memory { addr: 0xFEB0_0000, size: 0x1000, prefetchable: false, wide: false }),
+            (1, Bar::Io { port: 0xC000, size: 0x20 }),
+            (2, Bar::Memory { addr: 0xC_0000_0000, size: 0x4000, prefetchable: true, wide: true }),
+            (4, Bar::Memory { addr: 0xFEB0_1000, size: 0x1000, prefetchable: false, wide: false })
+        ]);
+
+        // Sizing leaves everything as it was
+        assert_eq!(config.get(function.address(), 0x10), 0xFEB0_0000);
+        assert_eq!(config.get(function.address(), 0x1C), 0xC);
+        assert_eq!(function.command(), COMMAND_MEMORY);
+        assert_eq!(function.bar(3), None);
+        assert_eq!(function.bar(4), bars.last().map(|&(_, bar)| bar));
+        assert_eq!(function.bar(5), None);
+
+        function.enable(COMMAND_BUS_MASTER);
+        assert_eq!(function.command(), COMMAND_MEMORY | COMMAND_BUS_MASTER);
+        // The status isn't written back
+        assert_eq!(config.get(function.address(), 0x04) >> 16, STATUS_CAPABILITIES as u32);
+    }
+
+    #[test]
+    fn capabilities() {
+        let config = machine();
+        let function = Function::new(&config, Address::new(0, 1, 0));
+        let caps: Vec<_> = function.capabilities().collect();
+        assert_eq!(caps, [Capability { id: CAP_MSIX, offset: 0x40 }, Capability { id: CAP_MSI, offset: 0x50 }]);
+        assert!(Function::new(&config, Address::new(0, 0, 0)).capabilities().next().is_none());
+
+        let msi = function.msi().unwrap();
+        assert_eq!((msi.vectors(), msi.is_64(), msi.maskable()), (4, true, false));
+        msi.enable(0xFEE0_0000, 0x41).unwrap();
+        assert_eq!(config.get(function.address(), 0x54), 0xFEE0_0000);
+        assert_eq!(config.get(function.address(), 0x58), 0);
+        assert_eq!(config.get(function.address(), 0x5C), 0x41);
+        assert_eq!(config.get(function.address(), 0x50) >> 16, (MSI_CONTROL_64 | 2 << MSI_CONTROL_MMC_SHIFT | MSI_CONTROL_ENABLE) as u32);
+
+        let msix = function.msix().unwrap();
+        assert_eq!(msix.table_size(), 4);
+        assert_eq!(msix.table(), (2, 0x1000));
+        assert_eq!(msix.pending_bits(), (2, 0x1800));
+        assert_eq!(msix.table_address(), Some(0xC_0000_1000));
+
+        let mut table = [!0u32; 16];
+        unsafe { msix.set_vector(table.as_mut_ptr() as usize, 1, 0xFEE0_0000, 0x42).unwrap() };
+        assert_eq!(table[4..8], [0xFEE0_0000, 0, 0x42, !MSIX_ENTRY_MASKED]);
+        assert_eq!(unsafe { msix.set_vector(table.as_mut_ptr() as usize, 4, 0, 0) }, Err(PciError::Unsupported));
+        msix.enable();
+        assert_eq!(config.get(function.address(), 0x40) >> 16, (MSIX_CONTROL_ENABLE | 3) as u32);
+    }
+
+    #[test]
+    fn driver_binding() {
+        static NET: Driver = Driver {
+            name: "test-net",
+            ids: &[DeviceId::class(0x02, 0x00)],
+            probe: |f| if f.vendor_id() == 0x8086 { Ok(()) } else { Err(PciError::ProbeFailed("not mine")) }
+        };
+        static VIRTIO: Driver = Driver {
+            name: "test-virtio",
+            ids: &[DeviceId::device(0x1AF4, 0x1041), DeviceId::device(0x1AF4, 0x1042)],
+            probe: |_| Ok(())
+        };
+
+        // Addresses of their own, the bound functions are global
+        let config = MockConfig::new();
+        config.add_function(Address::new(0, 4, 0), 0x8086, 0x10D3, 0x02_00_00_00, 0x00);
+        config.add_function(Address::new(0, 5, 0), 0x1AF4, 0x1041, 0x02_00_00_01, HEADER_MULTIFUNCTION);
+        config.add_function(Address::new(0, 5, 1), 0x1AF4, 0x1042, 0x01_00_00_01, 0x00);
+        config.add_function(Address::new(0, 6, 0), 0x10EC, 0x8139, 0x02_00_00_20, 0x00);
+        register_driver(&NET).unwrap();
+        register_driver(&VIRTIO).unwrap();
+        assert_eq!(register_driver(&NET), Err(PciError::DuplicateDriver));
+
+        // The virtio NIC is turned down by the first driver and taken by the second
+        assert_eq!(probe(&config), 3);
+        assert_eq!(driver_of(Address::new(0, 4, 0)).map(|d| d.name), Some("test-net"));
+        assert_eq!(driver_of(Address::new(0, 5, 0)).map(|d| d.name), Some("test-virtio"));
+        assert_eq!(driver_of(Address::new(0, 5, 1)).map(|d| d.name), Some("test-virtio"));
+        assert!(driver_of(Address::new(0, 6, 0)).is_none());
+        assert_eq!(probe(&config), 0);
+
+        let mut out = String::new();
+        list(&config, &mut out, false).unwrap();
+        assert_eq!(out.lines().next(), Some("00:04.0 8086:10d3 Ethernet controller [0200], driver test-net"));
+    }
+
+    #[test]
+    fn listing() {
+        let config = machine();
+        let mut out = String::new();
+        list(&config, &mut out, true).unwrap();
+        let lines: Vec<_> = out.lines().take(9).collect();
+        assert_eq!(lines, [
+            "00:00.0 8086:29c0 host bridge [0600] (rev 02)",
+            "00:01.0 1af4:1041 Ethernet controller [0200] (rev 01)",
+            "    BAR0: memory at 0xfeb00000 (32-bit, non-prefetchable) [size=4K]",
+            "    BAR1: I/O ports at 0xc000 [size=32]",
+            "    BAR2: memory at 0xc00000000 (64-bit, prefetchable) [size=16K]",
+            "    BAR4: memory at 0xfeb01000 (32-bit, non-prefetchable) [size=4K]",
+            "    interrupt: pin A, line 11",
+            "    capability at 0x40: MSI-X, 4 vectors, table in BAR2 at 0x1000",
+            "    capability at 0x50: MSI, 4 vectors, 64-bit"
+        ]);
+    }
+}
@@ -1 +1,33 @@
 pub mod ns16550;
+
+/// A bidirectional stream of bytes, like a serial port.
+pub trait ByteStream {
+    /// Returns the next received byte, if there is one.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    fn write_byte(&mut self, byte: u8);
+
+    /// Reads as many bytes as are available, up to the length of `buf`.
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.read_byte() {
+                Some(byte) => buf[n] = byte,
+                None => break
+            }
+
+            n += 1;
+        }
+
+        n
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Blocks until everything written so far is sent.
+    fn flush(&mut self) {}
+}
@@ -1,153 +1,573 @@
-use core::fmt::{Result, Write};
+use core::fmt::{self, Write};
 use core::hint;
-use core::mem::size_of;
 
-#[cfg(target_arch = "x86_64")]
-use crate::arch::x86_64::io::*;
+use crate::collections::RingBuffer;
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::drivers::dma::{DmaEngine, DmaPort, Flow, Segment, Transfer};
 
-#[cfg(target_arch = "x86_64")]
-type Reg = u8;
-#[cfg(not(target_arch = "x86_64"))]
-type Reg = u32;
+use super::ByteStream;
 
-const RBR: usize = 0;
-const THR: usize = 0;
-const DLL: usize = 0;
-const DLM: usize = size_of::<Reg>();
-const IER: usize = size_of::<Reg>();
-const FCR: usize = 2 * size_of::<Reg>();
-const LCR: usize = 3 * size_of::<Reg>();
-const MCR: usize = 4 * size_of::<Reg>();
-const LSR: usize = 5 * size_of::<Reg>();
+const RBR: Register<ReadOnly> = Register::new(0);
+const THR: Register<WriteOnly> = Register::new(0);
+const DLL: Register<ReadWrite> = Register::new(0);
+const DLM: Register<ReadWrite> = Register::new(1);
+const IER: Register<ReadWrite> = Register::new(1);
+const IIR: Register<ReadOnly> = Register::new(2);
+const FCR: Register<WriteOnly> = Register::new(2);
+const LCR: Register<ReadWrite> = Register::new(3);
+const MCR: Register<ReadWrite> = Register::new(4);
+const LSR: Register<ReadOnly> = Register::new(5);
+const MSR: Register<ReadOnly> = Register::new(6);
 
-const FCR_FIFO_ENABLE: Reg = 1;
-const FCR_RCVR_FIFO_RESET: Reg = 1 << 1;
-const FCR_XMIT_FIFO_RESET: Reg = 1 << 2;
+/// Size of the transmit FIFO
+const FIFO_SIZE: usize = 16;
 
-const FCR_DEFAULT_VAL: Reg = FCR_FIFO_ENABLE | FCR_RCVR_FIFO_RESET | FCR_XMIT_FIFO_RESET;
+/// Size of the software receive and transmit buffers
+const BUFFER_SIZE: usize = 256;
+
+/// Received data available interrupt
+const IER_ERBFI: u32 = 1;
+
+/// Transmitter holding register empty interrupt
+const IER_ETBEI: u32 = 1 << 1;
+
+/// Receiver line status interrupt
+const IER_ELSI: u32 = 1 << 2;
+
+/// Modem status interrupt
+const IER_EDSSI: u32 = 1 << 3;
+
+/// No interrupt pending
+const IIR_NO_INT: u32 = 1;
+const IIR_ID_MASK: u32 = 0b1110;
+const IIR_MODEM_STATUS: u32 = 0b0000;
+const IIR_THR_EMPTY: u32 = 0b0010;
+const IIR_RX_DATA: u32 = 0b0100;
+const IIR_LINE_STATUS: u32 = 0b0110;
+const IIR_RX_TIMEOUT: u32 = 0b1100;
+
+const FCR_FIFO_ENABLE: u32 = 1;
+const FCR_RCVR_FIFO_RESET: u32 = 1 << 1;
+const FCR_XMIT_FIFO_RESET: u32 = 1 << 2;
+
+/// DMA mode 1, the FIFOs ask for DMA whenever they have room or data
+const FCR_DMA_MODE: u32 = 1 << 3;
+
+const FCR_DEFAULT_VAL: u32 = FCR_FIFO_ENABLE | FCR_RCVR_FIFO_RESET | FCR_XMIT_FIFO_RESET;
 
 /// Word length select bit 0
-const LCR_WLS0: Reg = 1;
+const LCR_WLS0: u32 = 1;
 
 /// Word length select bit 1
-const LCR_WLS1: Reg = 1 << 1;
+const LCR_WLS1: u32 = 1 << 1;
 
 /// Number of stop bits
-const LCR_STB: Reg = 1 << 2;
+const LCR_STB: u32 = 1 << 2;
 
 /// Parity enable
-const LCR_PEN: Reg = 1 << 3;
+const LCR_PEN: u32 = 1 << 3;
 
 /// Even parity select
-const LCR_EPS: Reg = 1 << 4;
+const LCR_EPS: u32 = 1 << 4;
+
+/// Stick parity, the parity bit is always set (odd) or cleared (even)
+const LCR_SP: u32 = 1 << 5;
 
 /// Divisor Latch Access Bit
-const LCR_DLAB: Reg = 1 << 7;
+const LCR_DLAB: u32 = 1 << 7;
+
+/// Data terminal ready
+const MCR_DTR: u32 = 1;
+
+/// Request to send
+const MCR_RTS: u32 = 1 << 1;
+
+/// Auxiliary output 2, gates the interrupt line on PCs
+const MCR_OUT2: u32 = 1 << 3;
+
+/// Data ready
+const LSR_DR: u32 = 1;
+
+/// Overrun error
+const LSR_OE: u32 = 1 << 1;
+
+/// Parity error
+const LSR_PE: u32 = 1 << 2;
+
+/// Framing error
+const LSR_FE: u32 = 1 << 3;
+
+/// Break interrupt
+const LSR_BI: u32 = 1 << 4;
 
 /// Transmitter holding register
-const LSR_THRE: Reg = 1 << 5;
+const LSR_THRE: u32 = 1 << 5;
 
-macro_rules! read_registers {
-    ($($name:ident: ($reg:ident),)*) => {
-        $(
-            unsafe fn $name(&self) -> Reg {
-                #[cfg(target_arch = "x86_64")]
-                let r = inb((self.base + $reg) as u16);
+/// Transmitter empty
+const LSR_TEMT: u32 = 1 << 6;
 
-                #[cfg(not(target_arch = "x86_64"))]
-                let r = ((self.base + $reg) as *const Reg).read_volatile();
+/// Largest accepted deviation from the requested baud rate, in hundredths of a percent
+const MAX_BAUD_ERROR: i32 = 200;
 
-                r
-            }
-        )*
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// Parity bit always set
+    Mark,
+    /// Parity bit always cleared
+    Space
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// 1.5 stop bits with 5 data bits
+    Two
+}
+
+/// Line settings of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits
+}
+
+impl SerialConfig {
+    /// 8 data bits, no parity and 1 stop bit at `baud_rate`.
+    pub const fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One
+        }
+    }
+
+    const fn lcr(&self) -> u32 {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 0,
+            DataBits::Six => LCR_WLS0,
+            DataBits::Seven => LCR_WLS1,
+            DataBits::Eight => LCR_WLS0 | LCR_WLS1
+        };
+
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Odd => LCR_PEN,
+            Parity::Even => LCR_PEN | LCR_EPS,
+            Parity::Mark => LCR_PEN | LCR_SP,
+            Parity::Space => LCR_PEN | LCR_EPS | LCR_SP
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => LCR_STB
+        };
+
+        data_bits | parity | stop_bits
     }
 }
 
-macro_rules! write_registers {
-    ($($name:ident: ($reg:ident),)*) => {
-        $(
-            unsafe fn $name(&self, v: Reg) {
-                #[cfg(target_arch = "x86_64")]
-                outb((self.base + $reg) as u16, v);
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self::new(115200)
+    }
+}
 
-                #[cfg(not(target_arch = "x86_64"))]
-                ((self.base + $reg) as *mut Reg).write_volatile(v)
-            }
-        )*
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    ZeroBaudRate,
+    /// The divisor would be 0
+    BaudRateTooHigh,
+    /// The divisor doesn't fit into 16 bits
+    BaudRateTooLow,
+    /// The closest baud rate the clock allows is off by more than [`MAX_BAUD_ERROR`]
+    BaudRateMismatch {
+        requested: u32,
+        actual: u32,
+        /// In hundredths of a percent
+        error: i32
     }
 }
 
-pub struct NS16550 {
-    base: usize
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroBaudRate => f.write_str("baud rate is 0"),
+            Self::BaudRateTooHigh => f.write_str("baud rate too high for the input clock"),
+            Self::BaudRateTooLow => f.write_str("baud rate too low for the input clock"),
+            Self::BaudRateMismatch { requested, actual, error } => write!(
+                f,
+                "baud rate {} not possible, closest is {} ({}{}.{:02}% off)",
+                requested,
+                actual,
+                if *error < 0 { "-" } else { "" },
+                error.unsigned_abs() / 100,
+                error.unsigned_abs() % 100)
+        }
+    }
+}
+
+/// Baud rate divisor as programmed into DLL and DLM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaudDivisor {
+    pub divisor: u16,
+    pub actual_baud_rate: u32,
+    /// Deviation of the actual from the requested baud rate, in hundredths of a percent
+    pub error: i32
+}
+
+/// Receive errors counted since they were last taken with [`NS16550::take_errors`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineErrors {
+    pub overrun: usize,
+    pub parity: usize,
+    pub framing: usize,
+    pub breaks: usize,
+    /// Bytes dropped because the receive buffer was full
+    pub dropped: usize
 }
 
-impl NS16550 {
-    pub const fn new(base: usize) -> Self {
+impl LineErrors {
+    const fn new() -> Self {
         Self {
-            base
+            overrun: 0,
+            parity: 0,
+            framing: 0,
+            breaks: 0,
+            dropped: 0
         }
     }
 
-    const fn calc_divisor(clock: u32, baud_rate: u32) -> u16 {
-        (clock / (16 * baud_rate)) as u16
+    pub fn any(&self) -> bool {
+        *self != Self::new()
     }
 
-    pub unsafe fn init(&self, clock: u32, baud_rate: u32) {
-        self.set_fcr(FCR_DEFAULT_VAL);
+    fn record(&mut self, lsr: u32) {
+        if lsr & LSR_OE != 0 {
+            self.overrun += 1;
+        }
+
+        if lsr & LSR_PE != 0 {
+            self.parity += 1;
+        }
+
+        if lsr & LSR_FE != 0 {
+            self.framing += 1;
+        }
+
+        if lsr & LSR_BI != 0 {
+            self.breaks += 1;
+        }
+    }
+}
+
+/// Contents of the modem status register.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ModemStatus(u8);
 
-        // TODO: don't hard code this
-        self.set_lcr(LCR_WLS0 | LCR_WLS1); // 8 data bits, 1 stop bit, no parity
-        self.set_ier(0);
+impl ModemStatus {
+    /// Clear to send
+    pub fn cts(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    /// Data set ready
+    pub fn dsr(&self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
+
+    /// Ring indicator
+    pub fn ri(&self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+
+    /// Data carrier detect
+    pub fn dcd(&self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+}
+
+/// Finds the divisor giving the baud rate closest to `baud_rate` with an input clock of `clock` Hz.
+const fn calc_divisor(clock: u32, baud_rate: u32) -> Result<BaudDivisor, ConfigError> {
+    if baud_rate == 0 {
+        return Err(ConfigError::ZeroBaudRate);
+    }
+
+    // Rounded to the nearest divisor, in 64 bits so 16 * baud_rate can't overflow
+    let divisor = (clock as u64 + 8 * baud_rate as u64) / (16 * baud_rate as u64);
+    if divisor == 0 {
+        return Err(ConfigError::BaudRateTooHigh);
+    }
+
+    if divisor > u16::MAX as u64 {
+        return Err(ConfigError::BaudRateTooLow);
+    }
+
+    let actual = (clock as u64 / (16 * divisor)) as u32;
+    let error = ((actual as i64 - baud_rate as i64) * 10000 / baud_rate as i64) as i32;
+    if error.abs() > MAX_BAUD_ERROR {
+        return Err(ConfigError::BaudRateMismatch { requested: baud_rate, actual, error });
+    }
+
+    Ok(BaudDivisor {
+        divisor: divisor as u16,
+        actual_baud_rate: actual,
+        error
+    })
+}
+
+pub struct NS16550<B: Bus> {
+    regs: RegisterBlock<B>,
+    interrupts: bool,
+    rx: RingBuffer<u8, BUFFER_SIZE>,
+    tx: RingBuffer<u8, BUFFER_SIZE>,
+    errors: LineErrors,
+    modem_status: ModemStatus,
+    /// Engine and port for writes longer than the FIFO
+    dma: Option<(&'static (dyn DmaEngine + Sync), DmaPort)>
+}
+
+impl<B: Bus> NS16550<B> {
+    pub const fn new(regs: RegisterBlock<B>) -> Self {
+        Self {
+            regs,
+            interrupts: false,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            errors: LineErrors::new(),
+            modem_status: ModemStatus(0),
+            dma: None
+        }
+    }
+
+    /// Programs the line settings, leaving the port untouched if the baud rate isn't possible.
+    pub fn init(&self, clock: u32, config: SerialConfig) -> Result<BaudDivisor, ConfigError> {
+        let divisor = calc_divisor(clock, config.baud_rate)?;
+
+        self.regs.write(FCR, self.fcr());
+        self.regs.write(LCR, config.lcr());
+        self.regs.write(IER, 0);
 
         // Set baud rate
-        let divisor = Self::calc_divisor(clock, baud_rate);
-        self.set_lcr(self.lcr() | LCR_DLAB);
-        self.set_dll(((divisor) as u8).into());
-        self.set_dlm(((divisor >> 8) as u8).into());
-        self.set_lcr(self.lcr() & !LCR_DLAB);
+        self.regs.set_bits(LCR, LCR_DLAB);
+        self.regs.write(DLL, divisor.divisor as u32 & 0xFF);
+        self.regs.write(DLM, (divisor.divisor >> 8) as u32);
+        self.regs.clear_bits(LCR, LCR_DLAB);
+
+        Ok(divisor)
+    }
+
+    /// Sends polled writes longer than the FIFO through `dma`, `port` being the transmit holding register.
+    pub fn set_dma(&mut self, dma: &'static (dyn DmaEngine + Sync), port: DmaPort) {
+        // Changing the FIFO mode resets the FIFOs
+        self.flush();
+        self.dma = Some((dma, port));
+        self.regs.write(FCR, self.fcr());
     }
 
-    unsafe fn tx(&mut self, c: Reg) {
-        while (self.lsr() & LSR_THRE) == 0 {
+    fn fcr(&self) -> u32 {
+        if self.dma.is_some() {
+            FCR_DEFAULT_VAL | FCR_DMA_MODE
+        } else {
+            FCR_DEFAULT_VAL
+        }
+    }
+
+    /// Switches from polling to interrupt driven, buffered operation.
+    ///
+    /// The caller is responsible for routing the interrupt to [`NS16550::handle_interrupt`].
+    pub fn enable_interrupts(&mut self) {
+        self.interrupts = true;
+        self.modem_status = ModemStatus(self.regs.read(MSR) as u8);
+        self.regs.write(MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+        self.regs.write(IER, IER_ERBFI | IER_ELSI | IER_EDSSI);
+    }
+
+    pub fn disable_interrupts(&mut self) {
+        self.regs.write(IER, 0);
+        self.interrupts = false;
+        self.flush();
+    }
+
+    /// Services all pending interrupt conditions of the UART.
+    pub fn handle_interrupt(&mut self) {
+        loop {
+            let iir = self.regs.read(IIR);
+            if iir & IIR_NO_INT != 0 {
+                break;
+            }
+
+            match iir & IIR_ID_MASK {
+                IIR_LINE_STATUS => {
+                    let lsr = self.regs.read(LSR);
+                    self.errors.record(lsr);
+                },
+                IIR_RX_DATA | IIR_RX_TIMEOUT => self.receive(),
+                IIR_THR_EMPTY => self.transmit(),
+                IIR_MODEM_STATUS => self.modem_status = ModemStatus(self.regs.read(MSR) as u8),
+                _ => break
+            }
+        }
+    }
+
+    /// Moves all received bytes from the FIFO to the receive buffer.
+    fn receive(&mut self) {
+        loop {
+            let lsr = self.regs.read(LSR);
+            if lsr & LSR_DR == 0 {
+                break;
+            }
+
+            self.errors.record(lsr);
+            let byte = self.regs.read(RBR) as u8;
+            if self.rx.push(byte).is_err() {
+                self.errors.dropped += 1;
+            }
+        }
+    }
+
+    /// Refills the transmit FIFO from the transmit buffer, has to be called with the FIFO empty.
+    fn transmit(&mut self) {
+        for _ in 0..FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => self.regs.write(THR, byte as u32),
+                None => break
+            }
+        }
+
+        if self.tx.is_empty() {
+            self.regs.clear_bits(IER, IER_ETBEI);
+        } else {
+            self.regs.set_bits(IER, IER_ETBEI);
+        }
+    }
+
+    fn tx(&mut self, c: u32) {
+        while (self.regs.read(LSR) & LSR_THRE) == 0 {
             hint::spin_loop();
         }
 
-        self.set_thr(c);
+        self.regs.write(THR, c);
     }
 
-    read_registers! {
-        rbr: (RBR),
-        ier: (IER),
-        lcr: (LCR),
-        lsr: (LSR),
+    /// Returns and resets the receive errors seen so far.
+    pub fn take_errors(&mut self) -> LineErrors {
+        let errors = self.errors;
+        self.errors = LineErrors::new();
+        errors
     }
 
-    write_registers! {
-        set_thr: (THR),
-        set_dll: (DLL),
-        set_dlm: (DLM),
-        set_ier: (IER),
-        set_fcr: (FCR),
-        set_lcr: (LCR),
-        set_lsr: (LSR),
+    /// Returns the modem status, only updated on interrupts if those are enabled.
+    pub fn modem_status(&mut self) -> ModemStatus {
+        if !self.interrupts {
+            self.modem_status = ModemStatus(self.regs.read(MSR) as u8);
+        }
+
+        self.modem_status
     }
 }
 
-impl Write for NS16550 {
-    fn write_str(&mut self, s: &str) -> Result {
-        for c in s.chars() {
-            if c == '\n' {
-                self.write_char('\r')?;
+impl<B: Bus> ByteStream for NS16550<B> {
+    fn read_byte(&mut self) -> Option<u8> {
+        if self.interrupts {
+            return self.rx.pop();
+        }
+
+        let lsr = self.regs.read(LSR);
+        if lsr & LSR_DR == 0 {
+            return None;
+        }
+
+        self.errors.record(lsr);
+        Some(self.regs.read(RBR) as u8)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if !self.interrupts {
+            self.tx(byte as u32);
+            return;
+        }
+
+        while self.tx.push(byte).is_err() {
+            // Interrupts might be masked, so make room by hand
+            while self.regs.read(LSR) & LSR_THRE == 0 {
+                hint::spin_loop();
+            }
+
+            self.transmit();
+        }
+
+        if self.regs.read(LSR) & LSR_THRE != 0 {
+            self.transmit();
+        } else {
+            self.regs.set_bits(IER, IER_ETBEI);
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        if let Some((dma, port)) = self.dma {
+            if !self.interrupts && buf.len() > FIFO_SIZE {
+                let segment = Segment { src: buf.as_ptr() as usize, dst: 0, len: buf.len() };
+                // How much of it went out before an error or a timeout isn't known,
+                // so all of it goes out the slow way rather than losing the rest
+                if dma.run(&Transfer::new(Flow::MemToDev(port), &[segment])).is_ok() {
+                    return;
+                }
+            }
+        }
+
+        for &byte in buf {
+            self.write_byte(byte);
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            while self.regs.read(LSR) & LSR_THRE == 0 {
+                hint::spin_loop();
             }
 
-            self.write_char(c)?;
+            self.transmit();
+        }
+
+        while self.regs.read(LSR) & LSR_TEMT == 0 {
+            hint::spin_loop();
+        }
+    }
+}
+
+impl<B: Bus> Write for NS16550<B> {
+    /// Writes every line in one go, so long ones can be sent with DMA.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(line) = lines.next() {
+            self.write(line.as_bytes());
+        }
+
+        for line in lines {
+            self.write(b"\r\n");
+            self.write(line.as_bytes());
         }
 
         Ok(())
     }
 
-    fn write_char(&mut self, c: char) -> Result {
-        unsafe { self.tx(c as Reg); }
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.write(c.encode_utf8(&mut [0; 4]).as_bytes());
         Ok(())
     }
 }
@@ -155,29 +575,186 @@ impl Write for NS16550 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::drivers::bus::mock::{Access, MockBus};
+    use crate::drivers::dma::mock::MockDma;
+    use crate::drivers::dma::{DmaError, Width};
+
+    fn uart(bus: &MockBus) -> NS16550<&MockBus> {
+        NS16550::new(RegisterBlock::new(bus, 1))
+    }
 
     macro_rules! calc_divisor_tests {
-        ($($name:ident: ($clock:literal, $input:literal, $expected:literal),)*) => {
+        ($($name:ident: ($clock:literal, $input:literal, $expected:expr),)*) => {
             $(
                 #[test]
                 fn $name() {
-                    assert_eq!(NS16550::calc_divisor($clock, $input), $expected);
+                    assert_eq!(calc_divisor($clock, $input).map(|d| d.divisor), $expected);
                 }
             )*
         }
     }
 
     calc_divisor_tests! {
-        calc_divisor_1843200_50: (1843200, 50, 2304),
-        calc_divisor_1843200_300: (1843200, 300, 384),
-        calc_divisor_1843200_1200: (1843200, 1200, 96),
-        calc_divisor_1843200_2400: (1843200, 2400, 48),
-        calc_divisor_1843200_4800: (1843200, 4800, 24),
-        calc_divisor_1843200_9600: (1843200, 9600, 12),
-        calc_divisor_1843200_19200: (1843200, 19200, 6),
-        calc_divisor_1843200_38400: (1843200, 38400, 3),
-        calc_divisor_1843200_57600: (1843200, 57600, 2),
-        calc_divisor_1843200_115200: (1843200, 115200, 1),
-        calc_divisor_24000000_115200: (24000000, 115200, 13),
+        calc_divisor_1843200_50: (1843200, 50, Ok(2304)),
+        calc_divisor_1843200_300: (1843200, 300, Ok(384)),
+        calc_divisor_1843200_1200: (1843200, 1200, Ok(96)),
+        calc_divisor_1843200_2400: (1843200, 2400, Ok(48)),
+        calc_divisor_1843200_4800: (1843200, 4800, Ok(24)),
+        calc_divisor_1843200_9600: (1843200, 9600, Ok(12)),
+        calc_divisor_1843200_19200: (1843200, 19200, Ok(6)),
+        calc_divisor_1843200_38400: (1843200, 38400, Ok(3)),
+        calc_divisor_1843200_57600: (1843200, 57600, Ok(2)),
+        calc_divisor_1843200_115200: (1843200, 115200, Ok(1)),
+        calc_divisor_24000000_115200: (24000000, 115200, Ok(13)),
+        calc_divisor_24000000_9600: (24000000, 9600, Ok(156)),
+        calc_divisor_1843200_0: (1843200, 0, Err(ConfigError::ZeroBaudRate)),
+        calc_divisor_1843200_1000000: (1843200, 1000000, Err(ConfigError::BaudRateTooHigh)),
+        calc_divisor_24000000_10: (24000000, 10, Err(ConfigError::BaudRateTooLow)),
+        calc_divisor_1843200_230400: (1843200, 230400, Err(ConfigError::BaudRateMismatch {
+            requested: 230400,
+            actual: 115200,
+            error: -5000
+        })),
+    }
+
+    #[test]
+    fn calc_divisor_error() {
+        let divisor = calc_divisor(24000000, 115200).unwrap();
+        assert_eq!(divisor.actual_baud_rate, 115384);
+        assert_eq!(divisor.error, 15);
+    }
+
+    #[test]
+    fn config_lcr() {
+        assert_eq!(SerialConfig::new(9600).lcr(), LCR_WLS0 | LCR_WLS1);
+        let config = SerialConfig {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two
+        };
+        assert_eq!(config.lcr(), LCR_WLS1 | LCR_PEN | LCR_EPS | LCR_STB);
+    }
+
+    #[test]
+    fn line_errors_record() {
+        let mut errors = LineErrors::new();
+        assert!(!errors.any());
+        errors.record(LSR_DR | LSR_THRE);
+        assert!(!errors.any());
+        errors.record(LSR_OE | LSR_FE);
+        errors.record(LSR_OE);
+        assert_eq!(errors, LineErrors { overrun: 2, framing: 1, ..LineErrors::new() });
+    }
+
+    #[test]
+    fn init_programs_line() {
+        let bus = MockBus::new();
+        let config = SerialConfig {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two
+        };
+        uart(&bus).init(1843200, config).unwrap();
+
+        let lcr = config.lcr();
+        assert_eq!(bus.log(), [
+            Access::Write(FCR.index(), FCR_DEFAULT_VAL),
+            Access::Write(LCR.index(), lcr),
+            Access::Write(IER.index(), 0),
+            Access::Read(LCR.index(), lcr),
+            Access::Write(LCR.index(), lcr | LCR_DLAB),
+            Access::Write(DLL.index(), 12),
+            Access::Write(DLM.index(), 0),
+            Access::Read(LCR.index(), lcr | LCR_DLAB),
+            Access::Write(LCR.index(), lcr),
+        ]);
+    }
+
+    #[test]
+    fn init_rejects_baud_rate() {
+        let bus = MockBus::new();
+        assert_eq!(uart(&bus).init(1843200, SerialConfig::new(0)), Err(ConfigError::ZeroBaudRate));
+        assert!(bus.log().is_empty());
+    }
+
+    #[test]
+    fn polled_write() {
+        let bus = MockBus::new();
+        bus.script(LSR.index(), [0, 0, LSR_THRE]);
+        bus.set_read_bits(LSR.index(), LSR_THRE);
+        uart(&bus).write_str("a\n").unwrap();
+        assert_eq!(bus.writes(THR.index()), [b'a' as u32, b'\r' as u32, b'\n' as u32]);
+    }
+
+    #[test]
+    fn interrupt_receive() {
+        let bus = MockBus::new();
+        let mut uart = uart(&bus);
+        uart.enable_interrupts();
+        assert_eq!(bus.get(IER.index()), IER_ERBFI | IER_ELSI | IER_EDSSI);
+
+        bus.script(IIR.index(), [IIR_RX_DATA, IIR_NO_INT]);
+        bus.script(LSR.index(), [LSR_DR, LSR_DR | LSR_PE, 0]);
+        bus.script(RBR.index(), [b'h' as u32, b'i' as u32]);
+        uart.handle_interrupt();
+
+        assert_eq!(uart.read_byte(), Some(b'h'));
+        assert_eq!(uart.read_byte(), Some(b'i'));
+        assert_eq!(uart.read_byte(), None);
+        assert_eq!(uart.take_errors(), LineErrors { parity: 1, ..LineErrors::new() });
+    }
+
+    #[test]
+    fn interrupt_transmit() {
+        let bus = MockBus::new();
+        let mut uart = uart(&bus);
+        uart.enable_interrupts();
+
+        // The FIFO is busy, so the bytes wait for the THR empty interrupt
+        uart.write(b"ok");
+        assert!(bus.writes(THR.index()).is_empty());
+        assert_ne!(bus.get(IER.index()) & IER_ETBEI, 0);
+
+        bus.script(IIR.index(), [IIR_THR_EMPTY, IIR_NO_INT]);
+        uart.handle_interrupt();
+        assert_eq!(bus.writes(THR.index()), [b'o' as u32, b'k' as u32]);
+        assert_eq!(bus.get(IER.index()) & IER_ETBEI, 0);
+    }
+
+    #[test]
+    fn dma_write() {
+        static DMA: MockDma = MockDma::new();
+
+        let bus = MockBus::new();
+        bus.set_read_bits(LSR.index(), LSR_THRE | LSR_TEMT);
+        let mut uart = uart(&bus);
+        let port = DmaPort { addr: 0x02500000, drq: 14, width: Width::Byte };
+        uart.set_dma(&DMA, port);
+        assert_eq!(bus.writes(FCR.index()), [FCR_DEFAULT_VAL | FCR_DMA_MODE]);
+
+        // Short writes still go through THR
+        let data = [b'x'; 40];
+        uart.write(&data[..FIFO_SIZE]);
+        uart.write(&data);
+        assert_eq!(bus.writes(THR.index()).len(), FIFO_SIZE);
+        assert_eq!(DMA.transfers(), [(Flow::MemToDev(port), Segment { src: data.as_ptr() as usize, dst: 0, len: 40 })]);
+
+        // Long lines printed with write_str too
+        DMA.clear();
+        let text = "a line longer than the FIFO\nshort\n";
+        uart.write_str(text).unwrap();
+        assert_eq!(DMA.transfers(), [(Flow::MemToDev(port), Segment { src: text.as_ptr() as usize, dst: 0, len: 27 })]);
+        assert_eq!(bus.writes(THR.index())[FIFO_SIZE..], b"\r\nshort\r\n".map(|b| b as u32));
+
+        // A failed transfer is sent again through THR
+        DMA.clear();
+        DMA.fail_with(Some(DmaError::Timeout));
+        let before = bus.writes(THR.index()).len();
+        uart.write(&data);
+        DMA.fail_with(None);
+        assert_eq!(DMA.transfers().len(), 1);
+        assert_eq!(bus.writes(THR.index())[before..], data.map(|b| b as u32));
     }
 }
@@ -0,0 +1,90 @@
+//! SPI controllers behind the [`SpiBus`] trait, and the devices on them.
+
+pub mod spi_nor;
+#[cfg(any(target_arch = "riscv64", test))]
+pub mod sun20i_spi;
+
+use core::fmt;
+
+use crate::drivers::dma::DmaError;
+
+/// Clock polarity and phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Clock idles low, data is sampled on the rising edge
+    Mode0,
+    Mode1,
+    /// Clock idles high, data is sampled on the falling edge
+    Mode2,
+    Mode3
+}
+
+impl Mode {
+    /// Clock polarity, whether the clock idles high
+    pub const fn cpol(&self) -> bool {
+        matches!(self, Mode::Mode2 | Mode::Mode3)
+    }
+
+    /// Clock phase, whether data is sampled on the second edge
+    pub const fn cpha(&self) -> bool {
+        matches!(self, Mode::Mode1 | Mode::Mode3)
+    }
+}
+
+/// Number of data lines a transfer uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Single,
+    Dual,
+    Quad
+}
+
+/// One step of a [`SpiBus::transaction`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Op<'a> {
+    Write(&'a [u8]),
+    /// Only the fast read commands of flash chips read on more than one line
+    Read(Width, &'a mut [u8])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpiError {
+    NoSuchChipSelect,
+    /// The controller can't transfer on that many lines
+    Unsupported,
+    /// A transfer didn't complete
+    Timeout,
+    /// A bulk transfer through DMA failed
+    Dma(DmaError)
+}
+
+impl From<DmaError> for SpiError {
+    fn from(e: DmaError) -> Self {
+        Self::Dma(e)
+    }
+}
+
+impl fmt::Display for SpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchChipSelect => f.write_str("no such chip select"),
+            Self::Unsupported => f.write_str("transfer width not supported"),
+            Self::Timeout => f.write_str("SPI transfer timed out"),
+            Self::Dma(e) => write!(f, "SPI: {}", e)
+        }
+    }
+}
+
+/// An SPI controller in master mode.
+pub trait SpiBus {
+    /// Runs `ops` in order with chip select `cs` asserted throughout.
+    ///
+    /// The data coming in during writes is dropped.
+    fn transaction(&self, cs: u8, ops: &mut [Op<'_>]) -> Result<(), SpiError>;
+}
+
+impl<S: SpiBus> SpiBus for &S {
+    fn transaction(&self, cs: u8, ops: &mut [Op<'_>]) -> Result<(), SpiError> {
+        (**self).transaction(cs, ops)
+    }
+}
@@ -0,0 +1,487 @@
+//! NOR flash chips on an SPI bus, using the commands the 25 series chips of
+//! all vendors understand.
+//!
+//! Chips of up to 16 MiB are addressed with 3 bytes, bigger ones with the
+//! 4 byte address variants of the commands, so the address mode of the chip
+//! never has to be switched.
+//!
+//! None of the boards noros runs on in QEMU has an SPI controller with a flash chip,
+//! so the tests run it against a fake chip behind [`SpiBus`] instead.
+
+use core::{fmt, hint};
+
+use super::{Op, SpiBus, SpiError, Width};
+
+const CMD_READ_ID: u8 = 0x9F;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+
+/// Commands that take an address, which differ by address length
+struct Opcodes {
+    read: u8,
+    read_dual: u8,
+    read_quad: u8,
+    program: u8,
+    sector_erase: u8,
+    block_erase: u8
+}
+
+const OPCODES_3B: Opcodes = Opcodes {
+    read: 0x03,
+    read_dual: 0x3B,
+    read_quad: 0x6B,
+    program: 0x02,
+    sector_erase: 0x20,
+    block_erase: 0xD8
+};
+
+const OPCODES_4B: Opcodes = Opcodes {
+    read: 0x13,
+    read_dual: 0x3C,
+    read_quad: 0x6C,
+    program: 0x12,
+    sector_erase: 0x21,
+    block_erase: 0xDC
+};
+
+/// A program or erase is in progress
+const STATUS_BUSY: u8 = 1 << 0;
+const STATUS_WEL: u8 = 1 << 1;
+
+/// A program can't cross a page boundary
+pub const PAGE_SIZE: u64 = 256;
+/// Smallest unit of erase
+pub const SECTOR_SIZE: u64 = 4096;
+pub const BLOCK_SIZE: u64 = 65536;
+
+/// Largest chip addressed with 3 bytes
+const MAX_3B_SIZE: u64 = 16 << 20;
+
+/// Polls of the status register before giving up
+const BUSY_TIMEOUT: usize = 1_000_000;
+
+/// Manufacturer, memory type and capacity as read by the JEDEC ID command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8
+}
+
+impl JedecId {
+    /// Size in bytes, the capacity byte is its log2 on the chips of the big vendors
+    pub const fn size(&self) -> Option<u64> {
+        match self.capacity {
+            0x10..=0x1F => Some(1 << self.capacity),
+            _ => None
+        }
+    }
+
+    pub const fn vendor(&self) -> &'static str {
+        match self.manufacturer {
+            0x01 => "Spansion",
+            0x0B => "XTX",
+            0x1C => "EON",
+            0x20 => "Micron",
+            0x68 => "Boya",
+            0x9D => "ISSI",
+            0xC2 => "Macronix",
+            0xC8 => "GigaDevice",
+            0xEF => "Winbond",
+            _ => "unknown"
+        }
+    }
+}
+
+impl fmt::Display for JedecId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:02x}{:02x}{:02x}", self.vendor(), self.manufacturer, self.memory_type, self.capacity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    Spi(SpiError),
+    /// Nothing answered the JEDEC ID command
+    NoDevice,
+    /// The size of the chip can't be told from its ID
+    Unsupported(JedecId),
+    OutOfRange,
+    /// Erases have to start and end at a sector boundary
+    Unaligned,
+    /// The chip didn't enable writes, the write protect pin might be active
+    WriteProtected,
+    /// A program or erase didn't finish
+    Timeout
+}
+
+impl From<SpiError> for FlashError {
+    fn from(e: SpiError) -> Self {
+        Self::Spi(e)
+    }
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spi(e) => write!(f, "flash: {}", e),
+            Self::NoDevice => f.write_str("no flash found"),
+            Self::Unsupported(id) => write!(f, "unsupported flash {}", id),
+            Self::OutOfRange => f.write_str("out of the flash"),
+            Self::Unaligned => f.write_str("not aligned to a flash sector"),
+            Self::WriteProtected => f.write_str("flash is write protected"),
+            Self::Timeout => f.write_str("flash timed out")
+        }
+    }
+}
+
+/// An SPI NOR flash chip at a chip select of `S`.
+pub struct SpiNor<S: SpiBus> {
+    bus: S,
+    cs: u8,
+    id: JedecId,
+    size: u64,
+    read_width: Width
+}
+
+impl<S: SpiBus> SpiNor<S> {
+    /// Finds the chip at chip select `cs` and its size.
+    pub fn probe(bus: S, cs: u8) -> Result<Self, FlashError> {
+        let mut id = [0; 3];
+        bus.transaction(cs, &mut [Op::Write(&[CMD_READ_ID]), Op::Read(Width::Single, &mut id)])?;
+        if id[0] == 0x00 || id[0] == 0xFF {
+            return Err(FlashError::NoDevice);
+        }
+
+        let id = JedecId { manufacturer: id[0], memory_type: id[1], capacity: id[2] };
+        let size = id.size().ok_or(FlashError::Unsupported(id))?;
+        Ok(Self { bus, cs, id, size, read_width: Width::Single })
+    }
+
+    pub fn id(&self) -> JedecId {
+        self.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads on `width` data lines from now on.
+    ///
+    /// Quad reads only work once the quad enable bit of the chip is set, which is vendor specific.
+    pub fn set_read_width(&mut self, width: Width) {
+        self.read_width = width;
+    }
+
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), FlashError> {
+        self.check_range(offset, buf.len() as u64)?;
+
+        // The fast reads wait 8 clocks between address and data
+        let opcodes = self.opcodes();
+        let (opcode, dummy) = match self.read_width {
+            Width::Single => (opcodes.read, false),
+            Width::Dual => (opcodes.read_dual, true),
+            Width::Quad => (opcodes.read_quad, true)
+        };
+
+        let (cmd, len) = self.command(opcode, offset, dummy);
+        self.bus.transaction(self.cs, &mut [Op::Write(&cmd[..len]), Op::Read(self.read_width, buf)])?;
+        Ok(())
+    }
+
+    /// Erases whole sectors to all ones, using block erases where possible.
+    pub fn erase(&self, offset: u64, len: u64) -> Result<(), FlashError> {
+        if !offset.is_multiple_of(SECTOR_SIZE) || !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+
+        self.check_range(offset, len)?;
+
+        let end = offset + len;
+        let mut addr = offset;
+        while addr < end {
+            let (opcode, size) = if addr.is_multiple_of(BLOCK_SIZE) && end - addr >= BLOCK_SIZE {
+                (self.opcodes().block_erase, BLOCK_SIZE)
+            } else {
+                (self.opcodes().sector_erase, SECTOR_SIZE)
+            };
+
+            let (cmd, len) = self.command(opcode, addr, false);
+            self.write_enable()?;
+            self.bus.transaction(self.cs, &mut [Op::Write(&cmd[..len])])?;
+            self.wait_ready()?;
+            addr += size;
+        }
+
+        Ok(())
+    }
+
+    /// Programs `data` at `offset`, which only clears bits, so the range has to be erased first.
+    pub fn program(&self, offset: u64, data: &[u8]) -> Result<(), FlashError> {
+        self.check_range(offset, data.len() as u64)?;
+
+        let mut addr = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let n = ((PAGE_SIZE - addr % PAGE_SIZE) as usize).min(data.len());
+            let (page, rest) = data.split_at(n);
+
+            let (cmd, len) = self.command(self.opcodes().program, addr, false);
+            self.write_enable()?;
+            self.bus.transaction(self.cs, &mut [Op::Write(&cmd[..len]), Op::Write(page)])?;
+            self.wait_ready()?;
+
+            addr += n as u64;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> Result<(), FlashError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(FlashError::OutOfRange)
+        }
+    }
+
+    fn opcodes(&self) -> &'static Opcodes {
+        if self.size > MAX_3B_SIZE { &OPCODES_4B } else { &OPCODES_3B }
+    }
+
+    /// `opcode` followed by the address and a dummy byte if needed, and the length of that.
+    fn command(&self, opcode: u8, addr: u64, dummy: bool) -> ([u8; 6], usize) {
+        let addr_len = if self.size > MAX_3B_SIZE { 4 } else { 3 };
+        let mut cmd = [0; 6];
+        cmd[0] = opcode;
+        cmd[1..1 + addr_len].copy_from_slice(&(addr as u32).to_be_bytes()[4 - addr_len..]);
+        (cmd, 1 + addr_len + dummy as usize)
+    }
+
+    fn status(&self) -> Result<u8, FlashError> {
+        let mut status = [0];
+        self.bus.transaction(self.cs, &mut [Op::Write(&[CMD_READ_STATUS]), Op::Read(Width::Single, &mut status)])?;
+        Ok(status[0])
+    }
+
+    fn write_enable(&self) -> Result<(), FlashError> {
+        self.bus.transaction(self.cs, &mut [Op::Write(&[CMD_WRITE_ENABLE])])?;
+        if self.status()? & STATUS_WEL == 0 {
+            return Err(FlashError::WriteProtected);
+        }
+
+        Ok(())
+    }
+
+    fn wait_ready(&self) -> Result<(), FlashError> {
+        for _ in 0..BUSY_TIMEOUT {
+            if self.status()? & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(FlashError::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// A flash chip in memory that checks the commands it gets like a real one would.
+    struct FakeFlash {
+        id: [u8; 3],
+        protected: bool,
+        /// Status reads that report busy after each program or erase
+        busy_polls: usize,
+        state: RefCell<FakeState>
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        /// Bytes that aren't erased
+        cells: BTreeMap<u64, u8>,
+        wel: bool,
+        busy: usize,
+        /// Opcodes that took an address, and the address
+        log: Vec<(u8, u64)>
+    }
+
+    impl FakeState {
+        fn get(&self, addr: u64) -> u8 {
+            self.cells.get(&addr).copied().unwrap_or(0xFF)
+        }
+    }
+
+    impl FakeFlash {
+        fn new(id: [u8; 3]) -> Self {
+            Self { id, protected: false, busy_polls: 2, state: RefCell::default() }
+        }
+
+        fn get(&self, addr: u64) -> u8 {
+            self.state.borrow().get(addr)
+        }
+
+        fn log(&self) -> Vec<(u8, u64)> {
+            self.state.borrow().log.clone()
+        }
+    }
+
+    impl SpiBus for FakeFlash {
+        fn transaction(&self, cs: u8, ops: &mut [Op<'_>]) -> Result<(), SpiError> {
+            if cs != 0 {
+                return Err(SpiError::NoSuchChipSelect);
+            }
+
+            let mut tx = Vec::new();
+            let mut rx = None;
+            for op in ops.iter_mut() {
+                match op {
+                    Op::Write(data) => tx.extend_from_slice(data),
+                    Op::Read(width, buf) => rx = Some((*width, &mut **buf))
+                }
+            }
+
+            let mut state = self.state.borrow_mut();
+            let addr_len = if self.id[2] > 0x18 { 4 } else { 3 };
+            let addr = |tx: &[u8]| tx[1..1 + addr_len].iter().fold(0, |a, &b| a << 8 | b as u64);
+            let opcode = tx[0];
+            match opcode {
+                CMD_READ_ID => rx.unwrap().1.copy_from_slice(&self.id),
+                CMD_READ_STATUS => {
+                    let busy = state.busy > 0;
+                    state.busy = state.busy.saturating_sub(1);
+                    rx.unwrap().1[0] = if busy { STATUS_BUSY } else { 0 } | if state.wel { STATUS_WEL } else { 0 };
+                },
+                CMD_WRITE_ENABLE => state.wel = !self.protected,
+                0x03 | 0x3B | 0x6B | 0x13 | 0x3C | 0x6C => {
+                    let start = addr(&tx);
+                    let (width, buf) = rx.unwrap();
+                    let (expected, dummy) = match opcode {
+                        0x03 | 0x13 => (Width::Single, 0),
+                        0x3B | 0x3C => (Width::Dual, 1),
+                        _ => (Width::Quad, 1)
+                    };
+                    assert_eq!((width, tx.len()), (expected, 1 + addr_len + dummy));
+                    state.log.push((opcode, start));
+                    for (i, byte) in buf.iter_mut().enumerate() {
+                        *byte = state.get(start + i as u64);
+                    }
+                },
+                0x02 | 0x12 | 0x20 | 0xD8 | 0x21 | 0xDC => {
+                    assert!(state.wel, "write without write enable");
+                    state.wel = false;
+                    state.busy = self.busy_polls;
+                    let start = addr(&tx);
+                    state.log.push((opcode, start));
+                    let data = &tx[1 + addr_len..];
+                    match opcode {
+                        // Like the real thing, wraps around within the page
+                        0x02 | 0x12 => for (i, &byte) in data.iter().enumerate() {
+                            let a = start & !(PAGE_SIZE - 1) | ((start + i as u64) % PAGE_SIZE);
+                            let old = state.get(a);
+                            state.cells.insert(a, old & byte);
+                        },
+                        _ => {
+                            let size = if opcode == 0x20 || opcode == 0x21 { SECTOR_SIZE } else { BLOCK_SIZE };
+                            let base = start & !(size - 1);
+                            state.cells.retain(|&a, _| a < base || a >= base + size);
+                        }
+                    }
+                },
+                _ => panic!("unknown opcode {:#x}", opcode)
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn probe() {
+        let flash = SpiNor::probe(FakeFlash::new([0xEF, 0x40, 0x18]), 0).unwrap();
+        assert_eq!(flash.size(), 16 << 20);
+        assert_eq!(std::format!("{}", flash.id()), "Winbond ef4018");
+
+        assert_eq!(SpiNor::probe(FakeFlash::new([0; 3]), 0).err(), Some(FlashError::NoDevice));
+        let id = JedecId { manufacturer: 0xC2, memory_type: 0x20, capacity: 0x3A };
+        assert_eq!(SpiNor::probe(FakeFlash::new([0xC2, 0x20, 0x3A]), 0).err(), Some(FlashError::Unsupported(id)));
+        assert_eq!(SpiNor::probe(FakeFlash::new([0xEF, 0x40, 0x18]), 1).err(), Some(FlashError::Spi(SpiError::NoSuchChipSelect)));
+    }
+
+    #[test]
+    fn program_and_read() {
+        let mut flash = SpiNor::probe(FakeFlash::new([0xC8, 0x40, 0x17]), 0).unwrap();
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        flash.program(0x1F0, &data).unwrap();
+
+        // Split at the page boundaries
+        assert_eq!(flash.bus.log(), [(0x02, 0x1F0), (0x02, 0x200), (0x02, 0x300)]);
+        assert_eq!(flash.bus.get(0x1EF), 0xFF);
+        assert_eq!(flash.bus.get(0x31B), 43);
+
+        let mut buf = [0; 300];
+        flash.read(0x1F0, &mut buf).unwrap();
+        assert_eq!(buf[..], data[..]);
+
+        flash.set_read_width(Width::Dual);
+        let mut buf = [0; 4];
+        flash.read(0x200, &mut buf).unwrap();
+        assert_eq!(buf, [16, 17, 18, 19]);
+        assert_eq!(flash.bus.log().last(), Some(&(0x3B, 0x200)));
+
+        assert_eq!(flash.read(8 << 20, &mut buf), Err(FlashError::OutOfRange));
+        assert_eq!(flash.program(u64::MAX, &buf), Err(FlashError::OutOfRange));
+    }
+
+    #[test]
+    fn erase() {
+        let flash = SpiNor::probe(FakeFlash::new([0xEF, 0x40, 0x18]), 0).unwrap();
+        flash.program(0xF000, &[0; 16]).unwrap();
+        flash.program(0x20FF0, &[0; 32]).unwrap();
+        flash.bus.state.borrow_mut().log.clear();
+
+        flash.erase(0xF000, 0x12000).unwrap();
+        assert_eq!(flash.bus.log(), [(0x20, 0xF000), (0xD8, 0x10000), (0x20, 0x20000)]);
+        assert_eq!(flash.bus.get(0xF000), 0xFF);
+        assert_eq!(flash.bus.get(0x20FFF), 0xFF);
+        assert_eq!(flash.bus.get(0x21000), 0);
+
+        assert_eq!(flash.erase(0x800, 0x1000), Err(FlashError::Unaligned));
+        assert_eq!(flash.erase(0xFFF000, 0x2000), Err(FlashError::OutOfRange));
+    }
+
+    #[test]
+    fn four_byte_addresses() {
+        let mut flash = SpiNor::probe(FakeFlash::new([0xC2, 0x20, 0x19]), 0).unwrap();
+        assert_eq!(flash.size(), 32 << 20);
+        flash.set_read_width(Width::Quad);
+        flash.erase(0x1FF_0000, BLOCK_SIZE).unwrap();
+        flash.program(0x1FF_FFFE, &[1, 2]).unwrap();
+
+        let mut buf = [0; 2];
+        flash.read(0x1FF_FFFE, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(flash.bus.log(), [(0xDC, 0x1FF_0000), (0x12, 0x1FF_FFFE), (0x6C, 0x1FF_FFFE)]);
+    }
+
+    #[test]
+    fn write_failures() {
+        let mut chip = FakeFlash::new([0xEF, 0x40, 0x18]);
+        chip.protected = true;
+        let flash = SpiNor::probe(chip, 0).unwrap();
+        assert_eq!(flash.program(0, &[0]), Err(FlashError::WriteProtected));
+
+        let mut chip = FakeFlash::new([0xEF, 0x40, 0x18]);
+        chip.busy_polls = usize::MAX;
+        let flash = SpiNor::probe(chip, 0).unwrap();
+        assert_eq!(flash.erase(0, SECTOR_SIZE), Err(FlashError::Timeout));
+    }
+}
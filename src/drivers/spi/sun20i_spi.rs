@@ -0,0 +1,357 @@
+//! SPI controller of the Allwinner D1, the sun6i variant.
+//!
+//! Transfers go through the 64 byte FIFOs one burst at a time, with the chip
+//! select driven by software so it stays asserted between the bursts of a
+//! transaction. The FIFOs only work with byte accesses, so they get a bus of
+//! their own. With a DMA engine attached, transfers that don't fit into the
+//! FIFO go through it in a single burst instead.
+//!
+//! QEMU emulates neither the D1 nor this controller, so the driver has only been
+//! validated against the mock registers of the host tests below, not on a board
+//! yet. The flash layer on top only sees [`SpiBus`], which is where it gets tested.
+
+use core::hint;
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::drivers::clk::d1_ccu::{Ccu, Clock, ClockError, Reset};
+use crate::drivers::dma::sun6i_dma::DRQ_SPI0;
+use crate::drivers::dma::{self, DmaEngine, DmaPort, Flow, Segment, Transfer};
+
+use super::{Mode, Op, SpiBus, SpiError, Width};
+
+pub const D1_SPI0_BASE: usize = 0x04025000;
+pub const D1_SPI1_BASE: usize = 0x04026000;
+
+const SPI_GCR: Register<ReadWrite> = Register::new(0x04);
+const SPI_TCR: Register<ReadWrite> = Register::new(0x08);
+
+/// Interrupt status, write 1 to clear
+const SPI_ISR: Register<ReadWrite> = Register::new(0x14);
+const SPI_FCR: Register<ReadWrite> = Register::new(0x18);
+const SPI_CCR: Register<ReadWrite> = Register::new(0x24);
+
+/// Bytes in the burst, of which the first `SPI_MTC` are sent
+const SPI_MBC: Register<ReadWrite> = Register::new(0x30);
+const SPI_MTC: Register<ReadWrite> = Register::new(0x34);
+
+/// Bytes sent on a single line, and the width of the rest
+const SPI_BCC: Register<ReadWrite> = Register::new(0x38);
+
+const SPI_TXD: Register<WriteOnly> = Register::new(0x200);
+const SPI_RXD: Register<ReadOnly> = Register::new(0x300);
+
+const GCR_EN: u32 = 1 << 0;
+const GCR_MASTER: u32 = 1 << 1;
+/// Stops the clock while the RX FIFO is full
+const GCR_TP_EN: u32 = 1 << 7;
+
+const TCR_CPHA: u32 = 1 << 0;
+const TCR_CPOL: u32 = 1 << 1;
+/// Chip select is active low
+const TCR_SPOL: u32 = 1 << 2;
+const TCR_SS_SHIFT: u32 = 4;
+const TCR_SS_SEL: u32 = 0x3 << TCR_SS_SHIFT;
+/// The chip select follows `TCR_SS_LEVEL` instead of the bursts
+const TCR_SS_OWNER: u32 = 1 << 6;
+const TCR_SS_LEVEL: u32 = 1 << 7;
+/// Drops what is received while sending
+const TCR_DHB: u32 = 1 << 8;
+/// Starts a burst, cleared by the controller once it's done
+const TCR_XCH: u32 = 1 << 31;
+
+const ISR_TC: u32 = 1 << 12;
+
+/// The receive FIFO asks for DMA when it has data
+const FCR_RF_DRQ_EN: u32 = 1 << 8;
+const FCR_RF_RST: u32 = 1 << 15;
+/// The transmit FIFO asks for DMA when it has room
+const FCR_TF_DRQ_EN: u32 = 1 << 24;
+const FCR_TF_RST: u32 = 1 << 31;
+
+const CCR_CDR2_MAX: u64 = 0xFF;
+/// Divides by `2 * (CDR2 + 1)` instead of a power of two
+const CCR_DRS: u32 = 1 << 12;
+
+const BCC_DUAL: u32 = 1 << 28;
+const BCC_QUAD: u32 = 1 << 29;
+
+const FIFO_SIZE: usize = 64;
+
+/// Longest burst, the counters have 24 bits
+const MAX_BURST: usize = 0xFF_FFFF;
+
+/// Number of chip selects per controller
+const NUM_CHIP_SELECTS: u8 = 4;
+
+/// Polls of the transfer complete bit before giving up, enough for a FIFO full
+const TIMEOUT: usize = 1_000_000;
+
+pub struct Spi<B: Bus, F: Bus> {
+    regs: RegisterBlock<B>,
+    /// The same registers with byte accesses, for the FIFOs
+    fifo: RegisterBlock<F>,
+    /// Physical address of the registers, the DMA controller needs it for the FIFOs
+    base: usize,
+    /// Which of the two controllers this is, for the clocks and DMA requests
+    index: u8,
+    dma: Option<&'static (dyn DmaEngine + Sync)>
+}
+
+impl<B: Bus, F: Bus> Spi<B, F> {
+    /// Transfers longer than the FIFO go through `dma` if there is one.
+    pub const fn new(regs: RegisterBlock<B>, fifo: RegisterBlock<F>, base: usize, index: u8, dma: Option<&'static (dyn DmaEngine + Sync)>) -> Self {
+        Self { regs, fifo, base, index, dma }
+    }
+
+    /// Sets the controller up as master in `mode` and returns the SPI clock,
+    /// as close to `rate` as it gets without going above it.
+    pub fn init<C: Bus>(&self, ccu: &Ccu<C>, mode: Mode, rate: u64) -> Result<u64, ClockError> {
+        ccu.assert_reset(Reset::Spi(self.index))?;
+        ccu.enable(Clock::SpiBus(self.index))?;
+        ccu.deassert_reset(Reset::Spi(self.index))?;
+
+        // The module clock gets divided by 2 at least
+        let clock = Clock::Spi(self.index);
+        ccu.disable(clock)?;
+        let module_rate = ccu.set_rate(clock, 2 * rate)?;
+        ccu.enable(clock)?;
+
+        let div = module_rate.div_ceil(2 * rate).clamp(1, CCR_CDR2_MAX + 1);
+        self.regs.write(SPI_CCR, CCR_DRS | (div - 1) as u32);
+
+        self.regs.write(SPI_GCR, GCR_EN | GCR_MASTER | GCR_TP_EN);
+        let mut tcr = TCR_SPOL | TCR_SS_OWNER | TCR_SS_LEVEL;
+        if mode.cpol() {
+            tcr |= TCR_CPOL;
+        }
+        if mode.cpha() {
+            tcr |= TCR_CPHA;
+        }
+        self.regs.write(SPI_TCR, tcr);
+        self.regs.write(SPI_ISR, !0);
+
+        Ok(module_rate / (2 * div))
+    }
+
+    /// Sends `data`, which has to fit in the FIFO.
+    fn write_burst(&self, data: &[u8]) -> Result<(), SpiError> {
+        self.start_burst(data.len(), data.len(), 0);
+        self.regs.set_bits(SPI_TCR, TCR_DHB);
+        for &byte in data {
+            self.fifo.write(SPI_TXD, byte as u32);
+        }
+
+        self.run()
+    }
+
+    /// Fills `buf`, which has to fit in the FIFO.
+    fn read_burst(&self, width: Width, buf: &mut [u8]) -> Result<(), SpiError> {
+        self.start_burst(buf.len(), 0, Self::bcc(width));
+        self.regs.clear_bits(SPI_TCR, TCR_DHB);
+        self.run()?;
+
+        for byte in buf {
+            *byte = self.fifo.read(SPI_RXD) as u8;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `data` through DMA in one burst.
+    fn write_dma(&self, dma: &dyn DmaEngine, data: &[u8]) -> Result<(), SpiError> {
+        self.start_burst(data.len(), data.len(), 0);
+        self.regs.set_bits(SPI_TCR, TCR_DHB);
+        let segment = Segment { src: data.as_ptr() as usize, dst: 0, len: data.len() };
+        self.dma_burst(dma, Flow::MemToDev(self.port(SPI_TXD.index())), segment, FCR_TF_DRQ_EN)
+    }
+
+    /// Fills `buf` through DMA in one burst.
+    fn read_dma(&self, dma: &dyn DmaEngine, width: Width, buf: &mut [u8]) -> Result<(), SpiError> {
+        self.start_burst(buf.len(), 0, Self::bcc(width));
+        self.regs.clear_bits(SPI_TCR, TCR_DHB);
+        let segment = Segment { src: 0, dst: buf.as_mut_ptr() as usize, len: buf.len() };
+        self.dma_burst(dma, Flow::DevToMem(self.port(SPI_RXD.index())), segment, FCR_RF_DRQ_EN)
+    }
+
+    /// Runs the burst set up with `dma` feeding or draining the FIFO, `drq` enables its requests.
+    ///
+    /// Bursts can be far longer than the FIFO, so the DMA engine, which has a timeout
+    /// meant for that, is waited for before the end of the burst.
+    fn dma_burst(&self, dma: &dyn DmaEngine, flow: Flow, segment: Segment, drq: u32) -> Result<(), SpiError> {
+        self.regs.set_bits(SPI_FCR, drq);
+        let channel = dma.request_channel()?;
+        let result = dma.start(channel, &Transfer::new(flow, &[segment]), None)
+            .and_then(|()| {
+                self.regs.set_bits(SPI_TCR, TCR_XCH);
+                dma.wait(channel)
+            })
+            .map_err(SpiError::from)
+            .and_then(|()| self.wait_complete());
+        dma.release_channel(channel);
+        self.regs.clear_bits(SPI_FCR, drq);
+        result
+    }
+
+    /// The FIFO at `offset` as seen by the DMA controller.
+    fn port(&self, offset: usize) -> DmaPort {
+        DmaPort {
+            addr: self.base + offset,
+            drq: DRQ_SPI0 + self.index,
+            width: dma::Width::Byte
+        }
+    }
+
+    fn bcc(width: Width) -> u32 {
+        match width {
+            Width::Single => 0,
+            Width::Dual => BCC_DUAL,
+            Width::Quad => BCC_QUAD
+        }
+    }
+
+    fn start_burst(&self, len: usize, sent: usize, bcc: u32) {
+        self.regs.set_bits(SPI_FCR, FCR_RF_RST | FCR_TF_RST);
+        self.regs.write(SPI_MBC, len as u32);
+        self.regs.write(SPI_MTC, sent as u32);
+        self.regs.write(SPI_BCC, bcc | sent as u32);
+    }
+
+    /// Runs the burst set up and waits for it.
+    fn run(&self) -> Result<(), SpiError> {
+        self.regs.set_bits(SPI_TCR, TCR_XCH);
+        self.wait_complete()
+    }
+
+    /// Waits for the end of a burst that has at most a FIFO full left to go.
+    fn wait_complete(&self) -> Result<(), SpiError> {
+        for _ in 0..TIMEOUT {
+            if self.regs.read(SPI_ISR) & ISR_TC != 0 {
+                self.regs.write(SPI_ISR, ISR_TC);
+                return Ok(());
+            }
+
+            hint::spin_loop();
+        }
+
+        Err(SpiError::Timeout)
+    }
+}
+
+impl<B: Bus, F: Bus> SpiBus for Spi<B, F> {
+    fn transaction(&self, cs: u8, ops: &mut [Op<'_>]) -> Result<(), SpiError> {
+        if cs >= NUM_CHIP_SELECTS {
+            return Err(SpiError::NoSuchChipSelect);
+        }
+
+        self.regs.modify(SPI_TCR, |v| v & !(TCR_SS_SEL | TCR_SS_LEVEL) | (cs as u32) << TCR_SS_SHIFT);
+        let result = ops.iter_mut().try_for_each(|op| match (op, self.dma) {
+            (Op::Write(data), Some(dma)) if data.len() > FIFO_SIZE =>
+                data.chunks(MAX_BURST).try_for_each(|chunk| self.write_dma(dma, chunk)),
+            (Op::Read(width, buf), Some(dma)) if buf.len() > FIFO_SIZE =>
+                buf.chunks_mut(MAX_BURST).try_for_each(|chunk| self.read_dma(dma, *width, chunk)),
+            (Op::Write(data), _) => data.chunks(FIFO_SIZE).try_for_each(|chunk| self.write_burst(chunk)),
+            (Op::Read(width, buf), _) => buf.chunks_mut(FIFO_SIZE).try_for_each(|chunk| self.read_burst(*width, chunk))
+        });
+        self.regs.set_bits(SPI_TCR, TCR_SS_LEVEL);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+    use crate::drivers::dma::mock::MockDma;
+
+    fn no_delay(_: u64) {}
+
+    #[test]
+    fn init() {
+        let regs = MockBus::new();
+        let ccu = MockBus::new();
+        // PLL_PERI0 at its reset value of 600 MHz
+        ccu.set(0x020, 0x48216300);
+        let spi = Spi::new(RegisterBlock::new(&regs, 1), RegisterBlock::new(MockBus::new(), 1), D1_SPI0_BASE, 0, None);
+
+        // 600 MHz / 15 for the module clock, halved by the controller
+        let ccu = Ccu::new(RegisterBlock::new(&ccu, 1), no_delay);
+        assert_eq!(spi.init(&ccu, Mode::Mode3, 20_000_000), Ok(20_000_000));
+        assert_eq!(regs.get(SPI_CCR.index()), CCR_DRS);
+        assert_eq!(regs.get(SPI_GCR.index()), GCR_EN | GCR_MASTER | GCR_TP_EN);
+        assert_eq!(regs.get(SPI_TCR.index()), TCR_CPOL | TCR_CPHA | TCR_SPOL | TCR_SS_OWNER | TCR_SS_LEVEL);
+
+        // 24 MHz / 8 / 15 from the crystal
+        assert_eq!(spi.init(&ccu, Mode::Mode0, 100_000), Ok(100_000));
+        assert_eq!(regs.get(SPI_TCR.index()), TCR_SPOL | TCR_SS_OWNER | TCR_SS_LEVEL);
+
+        let spi = Spi::new(RegisterBlock::new(&regs, 1), RegisterBlock::new(MockBus::new(), 1), D1_SPI0_BASE, 2, None);
+        assert_eq!(spi.init(&ccu, Mode::Mode0, 1_000_000), Err(ClockError::NoSuchClock));
+    }
+
+    #[test]
+    fn transaction() {
+        let regs = MockBus::new();
+        regs.set_read_bits(SPI_ISR.index(), ISR_TC);
+        let fifo = MockBus::new();
+        fifo.script(SPI_RXD.index(), [0xEF, 0x40, 0x18]);
+        let spi = Spi::new(RegisterBlock::new(&regs, 1), RegisterBlock::new(&fifo, 1), D1_SPI0_BASE, 0, None);
+        regs.set(SPI_TCR.index(), TCR_SS_OWNER | TCR_SS_LEVEL);
+
+        let data = [0x5A; 70];
+        let mut id = [0; 3];
+        spi.transaction(1, &mut [Op::Write(&data), Op::Read(Width::Quad, &mut id)]).unwrap();
+        assert_eq!(id, [0xEF, 0x40, 0x18]);
+        assert_eq!(fifo.writes(SPI_TXD.index()), [0x5A; 70]);
+
+        // Two bursts for the write, one for the read
+        assert_eq!(regs.writes(SPI_MBC.index()), [64, 6, 3]);
+        assert_eq!(regs.writes(SPI_MTC.index()), [64, 6, 0]);
+        assert_eq!(regs.writes(SPI_BCC.index()), [64, 6, BCC_QUAD]);
+
+        // Chip select 1 is asserted until the end
+        let tcr = regs.writes(SPI_TCR.index());
+        assert_eq!(tcr[0], TCR_SS_OWNER | 1 << TCR_SS_SHIFT);
+        assert!(tcr[..tcr.len() - 1].iter().all(|v| v & TCR_SS_LEVEL == 0));
+        assert_eq!(tcr.last().unwrap() & TCR_SS_LEVEL, TCR_SS_LEVEL);
+
+        assert_eq!(spi.transaction(4, &mut []), Err(SpiError::NoSuchChipSelect));
+    }
+
+    #[test]
+    fn timeout() {
+        let regs = MockBus::new();
+        let spi = Spi::new(RegisterBlock::new(&regs, 1), RegisterBlock::new(MockBus::new(), 1), D1_SPI0_BASE, 0, None);
+        assert_eq!(spi.transaction(0, &mut [Op::Write(&[0x06])]), Err(SpiError::Timeout));
+        assert_eq!(regs.get(SPI_TCR.index()) & TCR_SS_LEVEL, TCR_SS_LEVEL);
+    }
+
+    #[test]
+    fn dma() {
+        static DMA: MockDma = MockDma::new();
+
+        let regs = MockBus::new();
+        regs.set_read_bits(SPI_ISR.index(), ISR_TC);
+        let fifo = MockBus::new();
+        fifo.script(SPI_RXD.index(), [0xEF, 0x40, 0x18]);
+        let spi = Spi::new(RegisterBlock::new(&regs, 1), RegisterBlock::new(&fifo, 1), D1_SPI1_BASE, 1, Some(&DMA));
+
+        // Only transfers longer than the FIFO go through DMA
+        let data = [0x5A; 100];
+        let mut id = [0; 3];
+        let mut buf = [0; 300];
+        spi.transaction(0, &mut [Op::Write(&data), Op::Read(Width::Single, &mut id), Op::Read(Width::Quad, &mut buf)]).unwrap();
+        assert_eq!(id, [0xEF, 0x40, 0x18]);
+        assert!(fifo.writes(SPI_TXD.index()).is_empty());
+        assert_eq!(regs.writes(SPI_MBC.index()), [100, 3, 300]);
+        assert_eq!(regs.writes(SPI_BCC.index()), [100, 0, BCC_QUAD]);
+        assert_eq!(regs.get(SPI_FCR.index()) & (FCR_TF_DRQ_EN | FCR_RF_DRQ_EN), 0);
+
+        let tx = DmaPort { addr: 0x04026200, drq: 23, width: dma::Width::Byte };
+        let rx = DmaPort { addr: 0x04026300, ..tx };
+        assert_eq!(DMA.transfers(), [
+            (Flow::MemToDev(tx), Segment { src: data.as_ptr() as usize, dst: 0, len: 100 }),
+            (Flow::DevToMem(rx), Segment { src: 0, dst: buf.as_ptr() as usize, len: 300 })
+        ]);
+    }
+}
@@ -0,0 +1,196 @@
+//! Code page 437, the character set of the VGA text mode font.
+
+/// Glyph shown for characters the font doesn't have, a small square
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// Characters of the glyphs 0x80 to 0xFF
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}'
+];
+
+/// Characters of the glyphs 0x01 to 0x1F, which are control characters in ASCII
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼'
+];
+
+/// Glyph 0x7F
+const HOUSE: char = '⌂';
+
+/// Characters that look like a glyph meant for another character
+const ALIASES: [(char, u8); 4] = [
+    // Greek small letter beta, the font has one glyph for both
+    ('\u{3B2}', 0xE1),
+    // Greek small letter mu, next to the micro sign
+    ('\u{3BC}', 0xE6),
+    // Ohm sign
+    ('\u{2126}', 0xEA),
+    // N-ary summation
+    ('\u{2211}', 0xE4)
+];
+
+/// Returns the glyph of `c`, or `None` if the font doesn't have it.
+///
+/// ASCII control characters map to themselves, they're not glyphs.
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+
+    if c == HOUSE {
+        return Some(0x7F);
+    }
+
+    HIGH.iter().position(|&h| h == c).map(|i| 0x80 + i as u8)
+        .or_else(|| LOW.iter().position(|&l| l == c).map(|i| 0x01 + i as u8))
+        .or_else(|| ALIASES.iter().find(|&&(a, _)| a == c).map(|&(_, glyph)| glyph))
+}
+
+/// Returns the character glyph `glyph` shows, glyphs below 0x20 and 0x7F
+/// give their ASCII control characters.
+pub fn to_char(glyph: u8) -> char {
+    match glyph {
+        0x80..=0xFF => HIGH[glyph as usize - 0x80],
+        _ => glyph as char
+    }
+}
+
+/// Like [`to_char`], but gives the pictures shown for the control characters,
+/// to find the same glyphs in other fonts.
+pub fn to_picture(glyph: u8) -> char {
+    match glyph {
+        0x01..=0x1F => LOW[glyph as usize - 0x01],
+        0x7F => HOUSE,
+        _ => to_char(glyph)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidUtf8;
+
+/// Decodes UTF-8 one byte at a time, for output that doesn't come in whole strings.
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    code: u32,
+    /// Continuation bytes still to come
+    remaining: u8,
+    /// Smallest code point the sequence may encode, to reject overlong ones
+    min: u32
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Self {
+            code: 0,
+            remaining: 0,
+            min: 0
+        }
+    }
+
+    /// True if in the middle of a sequence.
+    pub const fn pending(&self) -> bool {
+        self.remaining != 0
+    }
+
+    pub fn reset(&mut self) {
+        self.remaining = 0;
+    }
+
+    /// Handles one byte, returns the character once a sequence is complete.
+    ///
+    /// A byte that isn't a continuation byte while [`Utf8Decoder::pending`] breaks
+    /// the sequence, callers should check for that first so the byte isn't lost.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<char, InvalidUtf8>> {
+        if self.remaining == 0 {
+            let (code, remaining, min) = match byte {
+                0x00..=0x7F => return Some(Ok(byte as char)),
+                0xC2..=0xDF => (byte & 0x1F, 1, 0x80),
+                0xE0..=0xEF => (byte & 0x0F, 2, 0x800),
+                0xF0..=0xF4 => (byte & 0x07, 3, 0x10000),
+                _ => return Some(Err(InvalidUtf8))
+            };
+
+            self.code = code as u32;
+            self.remaining = remaining;
+            self.min = min;
+            return None;
+        }
+
+        if byte & 0xC0 != 0x80 {
+            self.remaining = 0;
+            return Some(Err(InvalidUtf8));
+        }
+
+        self.code = self.code << 6 | (byte & 0x3F) as u32;
+        self.remaining -= 1;
+        if self.remaining != 0 {
+            return None;
+        }
+
+        // Rejects surrogates and code points above U+10FFFF
+        Some(char::from_u32(self.code).filter(|_| self.code >= self.min).ok_or(InvalidUtf8))
+    }
+}
+
+/// True for the bytes that continue a UTF-8 sequence.
+pub const fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn table_round_trips() {
+        for glyph in 0x80..=0xFF {
+            assert_eq!(from_char(to_char(glyph)), Some(glyph), "glyph {:#x}", glyph);
+        }
+
+        for (i, &c) in LOW.iter().enumerate() {
+            assert_eq!(from_char(c), Some(i as u8 + 1));
+        }
+    }
+
+    #[test]
+    fn mapping() {
+        assert_eq!(from_char('A'), Some(b'A'));
+        assert_eq!(from_char('\n'), Some(b'\n'));
+        assert_eq!(from_char('°'), Some(0xF8));
+        assert_eq!(from_char('─'), Some(0xC4));
+        assert_eq!(from_char('§'), Some(0x15));
+        assert_eq!(from_char('⌂'), Some(0x7F));
+        assert_eq!(from_char('\u{3B2}'), from_char('ß'));
+        assert_eq!(from_char('€'), None);
+        assert_eq!(from_char('😀'), None);
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<Result<char, InvalidUtf8>> {
+        let mut decoder = Utf8Decoder::new();
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    #[test]
+    fn decoder() {
+        assert_eq!(decode("aé€😀".as_bytes()), [Ok('a'), Ok('é'), Ok('€'), Ok('😀')]);
+        // Lone continuation byte, overlong '/', encoded surrogate and a truncated sequence
+        assert_eq!(decode(&[0x80, 0xC0, 0xE0, 0x80, 0xAF, 0xED, 0xA0, 0x80, 0xC3, b'a']), [
+            Err(InvalidUtf8),
+            Err(InvalidUtf8),
+            Err(InvalidUtf8),
+            Err(InvalidUtf8),
+            Err(InvalidUtf8)
+        ]);
+    }
+}
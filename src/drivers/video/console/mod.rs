@@ -1 +1,3 @@
+pub mod cp437;
+pub mod scrollback;
 pub mod vga;
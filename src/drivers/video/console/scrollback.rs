@@ -0,0 +1,187 @@
+//! History of the lines that scrolled off a text console.
+
+/// Lines that scrolled off the top of a screen, oldest first, and the view into them.
+///
+/// The storage holds the history followed by room for one screen, where the live
+/// screen is kept while an earlier part is shown.
+pub struct Scrollback<T: 'static> {
+    storage: &'static mut [T],
+    columns: usize,
+    /// Number of lines the history can hold
+    depth: usize,
+    /// Ring index of the oldest line
+    start: usize,
+    len: usize,
+    /// Lines the view is scrolled back, 0 shows the live screen
+    offset: usize
+}
+
+impl<T: Copy> Scrollback<T> {
+    /// Uses `storage` for as many lines of history as fit next to a copy of the screen.
+    pub fn new(storage: &'static mut [T], columns: usize, screen_lines: usize) -> Self {
+        let depth = (storage.len() / columns).saturating_sub(screen_lines);
+        Self {
+            storage,
+            columns,
+            depth,
+            start: 0,
+            len: 0,
+            offset: 0
+        }
+    }
+
+    /// Number of lines the history can hold.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Number of lines in the history.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of lines the view is scrolled back.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Adds a line that scrolled off the screen, dropping the oldest one if full.
+    pub fn push(&mut self, line: &[T]) {
+        if self.depth == 0 {
+            return;
+        }
+
+        let index = (self.start + self.len) % self.depth;
+        self.storage[index * self.columns..][..self.columns].copy_from_slice(&line[..self.columns]);
+        if self.len == self.depth {
+            self.start = (self.start + 1) % self.depth;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Returns a line of the history, 0 being the one that scrolled off last.
+    pub fn line(&self, back: usize) -> Option<&[T]> {
+        if back >= self.len {
+            return None;
+        }
+
+        Some(self.ring_line(self.len - 1 - back))
+    }
+
+    /// Forgets the history, the view has to be on the live screen.
+    pub fn clear(&mut self) {
+        debug_assert_eq!(self.offset, 0);
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Line `i` of the history, oldest first
+    fn ring_line(&self, i: usize) -> &[T] {
+        &self.storage[(self.start + i) % self.depth * self.columns..][..self.columns]
+    }
+
+    fn saved_screen(&mut self) -> &mut [T] {
+        &mut self.storage[self.depth * self.columns..]
+    }
+
+    /// Shows `screen` scrolled back by `offset` lines, clamped to the history.
+    ///
+    /// The live contents of `screen` are saved when leaving offset 0 and put back
+    /// when returning to it, nothing should be written to `screen` in between.
+    pub fn scroll_to(&mut self, screen: &mut [T], offset: usize) {
+        let offset = offset.min(self.len);
+        if offset == self.offset {
+            return;
+        }
+
+        let screen_len = screen.len();
+        if self.offset == 0 {
+            self.saved_screen()[..screen_len].copy_from_slice(screen);
+        }
+
+        self.offset = offset;
+        if offset == 0 {
+            screen.copy_from_slice(&self.saved_screen()[..screen_len]);
+            return;
+        }
+
+        // The history and the saved screen form one long list of lines, show the right window of it
+        for (i, line) in screen.chunks_exact_mut(self.columns).enumerate() {
+            let j = self.len - offset + i;
+            if j < self.len {
+                line.copy_from_slice(self.ring_line(j));
+            } else {
+                let saved = (j - self.len) * self.columns;
+                line.copy_from_slice(&self.storage[self.depth * self.columns + saved..][..self.columns]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec;
+
+    use super::*;
+
+    const COLUMNS: usize = 2;
+    const LINES: usize = 2;
+
+    fn scrollback(depth: usize) -> Scrollback<u8> {
+        let storage = Box::leak(vec![0; (depth + LINES) * COLUMNS].into_boxed_slice());
+        Scrollback::new(storage, COLUMNS, LINES)
+    }
+
+    #[test]
+    fn ring() {
+        let mut history = scrollback(3);
+        assert_eq!(history.depth(), 3);
+        assert_eq!(history.line(0), None);
+
+        for line in [b"aa", b"bb", b"cc", b"dd"] {
+            history.push(line);
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.line(0), Some(&b"dd"[..]));
+        assert_eq!(history.line(2), Some(&b"bb"[..]));
+        assert_eq!(history.line(3), None);
+
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn view() {
+        let mut history = scrollback(3);
+        history.push(b"aa");
+        history.push(b"bb");
+        let mut screen = *b"ccdd";
+
+        history.scroll_to(&mut screen, 1);
+        assert_eq!(&screen, b"bbcc");
+        history.scroll_to(&mut screen, 5);
+        assert_eq!(history.offset(), 2);
+        assert_eq!(&screen, b"aabb");
+        history.scroll_to(&mut screen, 0);
+        assert_eq!(&screen, b"ccdd");
+    }
+
+    #[test]
+    fn no_room() {
+        let mut history = scrollback(0);
+        history.push(b"aa");
+        let mut screen = *b"ccdd";
+        history.scroll_to(&mut screen, 1);
+        assert_eq!(&screen, b"ccdd");
+        assert_eq!(history.offset(), 0);
+    }
+}
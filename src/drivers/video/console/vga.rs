@@ -1,4 +1,9 @@
-use core::fmt::{Result, Write};
+use core::fmt::{self, Write};
+
+use crate::drivers::bus::{Bus, ReadWrite, Register, RegisterBlock, WriteOnly};
+
+use super::cp437::{self, is_continuation, Utf8Decoder, REPLACEMENT};
+use super::scrollback::Scrollback;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,15 +27,56 @@ pub enum Color {
     White = 15
 }
 
+impl Color {
+    const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::Pink,
+        Color::Yellow,
+        Color::White
+    ];
+
+    const fn from_u8(value: u8) -> Color {
+        Self::ALL[(value & 0x0f) as usize]
+    }
+
+    /// Returns the bright variant, the dark colors only have 3 bits.
+    pub const fn bright(self) -> Color {
+        Self::from_u8(self as u8 | 0x08)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct ColorCode(u8);
 
+/// Color of text written before any is chosen, and of erased characters
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::White, Color::Black);
+
 impl ColorCode {
-    pub fn new(foreground: Color, background: Color) -> ColorCode {
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
 
+    pub const fn fg(self) -> Color {
+        Color::from_u8(self.0)
+    }
+
+    pub const fn bg(self) -> Color {
+        Color::from_u8(self.0 >> 4)
+    }
+
     pub fn set_fg_color(&mut self, color: Color) {
         self.0 = self.0 & 0xf0 | color as u8;
     }
@@ -38,6 +84,19 @@ impl ColorCode {
     pub fn set_bg_color(&mut self, color: Color) {
         self.0 = (color as u8) << 4 | self.0 & 0x0f;
     }
+
+    /// Writes the control sequence that selects this color, with bold off.
+    pub fn write_sgr(self, out: &mut dyn Write) -> fmt::Result {
+        if self == DEFAULT_COLOR {
+            return out.write_str("\x1b[0m");
+        }
+
+        let ansi = |color: Color| ANSI_COLORS.iter().position(|&c| c as u8 == color as u8 & 0x07).unwrap_or(0);
+        let bright = |color: Color| color as u8 & 0x08 != 0;
+        let fg = if bright(self.fg()) { 90 } else { 30 } + ansi(self.fg());
+        let bg = if bright(self.bg()) { 100 } else { 40 } + ansi(self.bg());
+        write!(out, "\x1b[0;{};{}m", fg, bg)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -47,45 +106,476 @@ pub struct ScreenChar {
     color_code: ColorCode,
 }
 
+impl ScreenChar {
+    pub const fn new(glyph: u8, color_code: ColorCode) -> Self {
+        Self {
+            ascii_character: glyph,
+            color_code
+        }
+    }
+
+    /// Code page 437 glyph
+    pub fn glyph(&self) -> u8 {
+        self.ascii_character
+    }
+
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+}
+
+/// Number of columns between tab stops
+const TAB_WIDTH: usize = 8;
+
+/// Most parameters an escape sequence can have, the rest are ignored
+const MAX_PARAMS: usize = 4;
+
+const ESC: u8 = 0x1B;
+
+const CRTC_INDEX: Register<WriteOnly> = Register::new(0);
+const CRTC_DATA: Register<ReadWrite> = Register::new(1);
+
+const CRTC_CURSOR_START: u32 = 0x0A;
+const CRTC_CURSOR_END: u32 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u32 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u32 = 0x0F;
+
+/// Hides the cursor when set in the cursor start register
+const CURSOR_DISABLE: u32 = 1 << 5;
+const CURSOR_SCANLINE_MASK: u32 = 0x1F;
+
+/// Colors in the order of the ANSI color numbers
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray
+];
+
+/// Select graphic rendition, only colors and bold are supported.
+fn sgr(color_code: &mut ColorCode, bold: &mut bool, param: u16) {
+    let ansi_fg = |color: u16, bright: bool| {
+        let color = ANSI_COLORS[color as usize];
+        if bright { color.bright() } else { color }
+    };
+
+    match param {
+        0 => {
+            *color_code = DEFAULT_COLOR;
+            *bold = false;
+        },
+        1 => {
+            *bold = true;
+            color_code.set_fg_color(color_code.fg().bright());
+        },
+        22 => {
+            if *bold {
+                color_code.set_fg_color(Color::from_u8(color_code.fg() as u8 & 0x07));
+            }
+
+            *bold = false;
+        },
+        30..=37 => color_code.set_fg_color(ansi_fg(param - 30, *bold)),
+        39 => color_code.set_fg_color(DEFAULT_COLOR.fg()),
+        40..=47 => color_code.set_bg_color(ANSI_COLORS[(param - 40) as usize]),
+        49 => color_code.set_bg_color(DEFAULT_COLOR.bg()),
+        90..=97 => color_code.set_fg_color(ansi_fg(param - 90, true)),
+        100..=107 => color_code.set_bg_color(ANSI_COLORS[(param - 100) as usize].bright()),
+        _ => ()
+    }
+}
+
+/// Follows the colors selected in a stream of text, for consoles like serial
+/// ports that pass it on to a terminal and can't ask it for its color.
 #[derive(Debug)]
-pub struct Writer {
+pub struct ColorTracker {
+    color_code: ColorCode,
+    bold: bool,
+    escape: Escape,
+    params: [u16; MAX_PARAMS],
+    num_params: usize
+}
+
+impl ColorTracker {
+    pub const fn new() -> Self {
+        Self {
+            color_code: DEFAULT_COLOR,
+            bold: false,
+            escape: Escape::None,
+            params: [0; MAX_PARAMS],
+            num_params: 0
+        }
+    }
+
+    /// Color of the text after the bytes fed so far.
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    /// Takes a byte of the stream, the control sequences are parsed like [`Writer`] does.
+    pub fn feed(&mut self, byte: u8) {
+        match self.escape {
+            Escape::None if byte == ESC => self.escape = Escape::Esc,
+            Escape::None => (),
+            Escape::Esc if byte == b'[' => {
+                self.escape = Escape::Csi;
+                self.params = [0; MAX_PARAMS];
+                self.num_params = 0;
+            },
+            Escape::Esc => self.escape = Escape::None,
+            Escape::Csi => match byte {
+                b'0'..=b'9' => {
+                    if let Some(param) = self.params.get_mut(self.num_params) {
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    }
+                },
+                b';' => self.num_params = (self.num_params + 1).min(MAX_PARAMS),
+                b'?' => (),
+                _ => {
+                    self.escape = Escape::None;
+                    if byte == b'm' {
+                        for i in 0..(self.num_params + 1).min(MAX_PARAMS) {
+                            sgr(&mut self.color_code, &mut self.bold, self.params[i]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for ColorTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRT controller, only used to move the hardware cursor.
+pub struct Crtc<B: Bus> {
+    regs: RegisterBlock<B>
+}
+
+impl<B: Bus> Crtc<B> {
+    /// `regs` has the index register at index 0 and the data register at index 1, 0x3D4 and 0x3D5 on a PC.
+    pub const fn new(regs: RegisterBlock<B>) -> Self {
+        Self { regs }
+    }
+
+    fn write_reg(&self, index: u32, value: u32) {
+        self.regs.write(CRTC_INDEX, index);
+        self.regs.write(CRTC_DATA, value);
+    }
+
+    fn modify_reg(&self, index: u32, f: impl FnOnce(u32) -> u32) {
+        self.regs.write(CRTC_INDEX, index);
+        self.regs.modify(CRTC_DATA, f);
+    }
+
+    /// Shows the cursor between scanlines `start` and `end` of a character cell.
+    pub fn enable_cursor(&self, start: u8, end: u8) {
+        self.modify_reg(CRTC_CURSOR_START, |v| (v & !(CURSOR_DISABLE | CURSOR_SCANLINE_MASK)) | start as u32 & CURSOR_SCANLINE_MASK);
+        self.modify_reg(CRTC_CURSOR_END, |v| (v & !CURSOR_SCANLINE_MASK) | end as u32 & CURSOR_SCANLINE_MASK);
+    }
+
+    pub fn disable_cursor(&self) {
+        self.write_reg(CRTC_CURSOR_START, CURSOR_DISABLE);
+    }
+
+    /// Moves the cursor to character `pos` of the screen.
+    pub fn move_cursor(&self, pos: usize) {
+        self.write_reg(CRTC_CURSOR_LOCATION_LOW, pos as u32 & 0xFF);
+        self.write_reg(CRTC_CURSOR_LOCATION_HIGH, (pos >> 8) as u32 & 0xFF);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Got ESC
+    Esc,
+    /// Inside a control sequence, after ESC [
+    Csi
+}
+
+/// Text mode console, understands the control characters and the subset of
+/// ANSI escape sequences needed for colored output and line editing.
+///
+/// Text is UTF-8, characters the font doesn't have show up as [`REPLACEMENT`].
+pub struct Writer<B: Bus> {
     buffer: &'static mut [ScreenChar],
     num_columns: usize,
     pos: usize,
     color_code: ColorCode,
+    /// Bright foreground, set by SGR 1
+    bold: bool,
+    saved_pos: usize,
+    escape: Escape,
+    params: [u16; MAX_PARAMS],
+    num_params: usize,
+    utf8: Utf8Decoder,
+    scrollback: Option<Scrollback<ScreenChar>>,
+    crtc: Option<Crtc<B>>
 }
 
-impl Writer {
-    pub fn new(buffer: &'static mut [ScreenChar], num_lines: usize, num_columns: usize) -> Self {
+impl<B: Bus> Writer<B> {
+    /// `crtc` is used to move the hardware cursor along, if there is one.
+    pub fn new(buffer: &'static mut [ScreenChar], num_lines: usize, num_columns: usize, crtc: Option<Crtc<B>>) -> Self {
         debug_assert_eq!(buffer.len(), num_lines * num_columns);
 
         Self {
             num_columns,
             pos: 0,
             buffer,
-            color_code: ColorCode::new(Color::White, Color::Black)
+            color_code: DEFAULT_COLOR,
+            bold: false,
+            saved_pos: 0,
+            escape: Escape::None,
+            params: [0; MAX_PARAMS],
+            num_params: 0,
+            utf8: Utf8Decoder::new(),
+            scrollback: None,
+            crtc
         }
     }
 
+    /// Keeps the lines that scroll off the top in `storage`, the history is as
+    /// deep as what's left of it after a copy of the screen.
+    pub fn set_scrollback(&mut self, storage: &'static mut [ScreenChar]) {
+        let num_lines = self.buffer.len() / self.num_columns;
+        self.scrollback = Some(Scrollback::new(storage, self.num_columns, num_lines));
+    }
+
+    pub fn scrollback(&self) -> Option<&Scrollback<ScreenChar>> {
+        self.scrollback.as_ref()
+    }
+
+    /// Shows the screen `lines` further back in the history.
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        let offset = self.view_offset() + lines;
+        self.scroll_view_to(offset);
+    }
+
+    /// Shows the screen `lines` further forward, back to the live screen at most.
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        let offset = self.view_offset().saturating_sub(lines);
+        self.scroll_view_to(offset);
+    }
+
+    /// Number of lines the screen is scrolled back.
+    pub fn view_offset(&self) -> usize {
+        self.scrollback.as_ref().map_or(0, |s| s.offset())
+    }
+
+    fn scroll_view_to(&mut self, offset: usize) {
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.scroll_to(self.buffer, offset);
+        }
+    }
+
+    pub fn num_lines(&self) -> usize {
+        self.buffer.len() / self.num_columns
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.num_columns
+    }
+
+    /// Characters on the screen, line after line.
+    pub fn buffer(&self) -> &[ScreenChar] {
+        self.buffer
+    }
+
+    /// Position of the cursor in [`Writer::buffer`].
+    pub fn cursor(&self) -> usize {
+        self.pos.min(self.buffer.len() - 1)
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
+        self.put(byte);
+        self.update_cursor();
+    }
+
+    fn put(&mut self, byte: u8) {
         debug_assert!(self.pos <= self.buffer.len());
 
+        // Output always goes to the live screen
+        self.scroll_view_to(0);
+
+        if self.utf8.pending() && !is_continuation(byte) {
+            self.utf8.reset();
+            self.put_glyph(REPLACEMENT);
+        }
+
+        match self.escape {
+            Escape::None => (),
+            Escape::Esc => {
+                self.escape = Escape::None;
+                match byte {
+                    b'[' => {
+                        self.escape = Escape::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.num_params = 0;
+                    },
+                    b'7' => self.saved_pos = self.pos,
+                    b'8' => self.pos = self.saved_pos,
+                    _ => ()
+                }
+
+                return;
+            },
+            Escape::Csi => {
+                self.csi(byte);
+                return;
+            }
+        }
+
+        if !byte.is_ascii() {
+            match self.utf8.feed(byte) {
+                Some(Ok(c)) => self.put_glyph(cp437::from_char(c).unwrap_or(REPLACEMENT)),
+                Some(Err(_)) => self.put_glyph(REPLACEMENT),
+                None => ()
+            }
+
+            return;
+        }
+
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                self.buffer[self.pos] = ScreenChar {
-                    ascii_character: byte,
-                    color_code: self.color_code,
-                };
-                self.pos += 1;
-            }
+            b'\r' => self.pos -= self.column(),
+            b'\t' => {
+                let column = self.column();
+                let tab_stop = ((column / TAB_WIDTH + 1) * TAB_WIDTH).min(self.num_columns - 1);
+                self.pos += tab_stop - column;
+            },
+            // Backspace only moves the cursor, "\x08 \x08" erases
+            0x08 => {
+                if self.column() > 0 {
+                    self.pos -= 1;
+                }
+            },
+            ESC => self.escape = Escape::Esc,
+            // Bell
+            0x07 => (),
+            byte => self.put_glyph(byte)
         }
+    }
+
+    fn put_glyph(&mut self, glyph: u8) {
+        self.buffer[self.pos] = ScreenChar {
+            ascii_character: glyph,
+            color_code: self.color_code,
+        };
+        self.pos += 1;
 
         if self.pos == self.buffer.len() {
             self.scroll();
         }
     }
 
+    /// Handles a byte of a control sequence.
+    fn csi(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' => {
+                if let Some(param) = self.params.get_mut(self.num_params) {
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+
+                return;
+            },
+            b';' => {
+                self.num_params = (self.num_params + 1).min(MAX_PARAMS);
+                return;
+            },
+            // Private sequences like ESC [ ? 25 h aren't supported, but their parameters are skipped all the same
+            b'?' => return,
+            _ => ()
+        }
+
+        self.escape = Escape::None;
+        // The last parameter isn't followed by a ;
+        let num_params = (self.num_params + 1).min(MAX_PARAMS);
+        // Most sequences default to 1 when the parameter is 0 or missing
+        let n = (self.params[0] as usize).max(1);
+        let (line, column) = (self.line(), self.column());
+        match byte {
+            b'm' => {
+                for i in 0..num_params {
+                    self.sgr(self.params[i]);
+                }
+            },
+            b'A' => self.move_to(line.saturating_sub(n), column),
+            b'B' => self.move_to(line + n, column),
+            b'C' => self.move_to(line, column + n),
+            b'D' => self.move_to(line, column.saturating_sub(n)),
+            b'G' => self.move_to(line, n - 1),
+            b'H' | b'f' => self.move_to(n - 1, (self.params[1] as usize).max(1) - 1),
+            b'J' => match self.params[0] {
+                0 => self.erase(self.pos, self.buffer.len()),
+                1 => self.erase(0, self.pos + 1),
+                2 => self.erase(0, self.buffer.len()),
+                // Like xterm, 3 erases the history too
+                _ => {
+                    self.erase(0, self.buffer.len());
+                    if let Some(scrollback) = &mut self.scrollback {
+                        scrollback.clear();
+                    }
+                }
+            },
+            b'K' => {
+                let start = self.pos - column;
+                match self.params[0] {
+                    0 => self.erase(self.pos, start + self.num_columns),
+                    1 => self.erase(start, self.pos + 1),
+                    _ => self.erase(start, start + self.num_columns)
+                }
+            },
+            b's' => self.saved_pos = self.pos,
+            b'u' => self.pos = self.saved_pos,
+            _ => ()
+        }
+    }
+
+    fn sgr(&mut self, param: u16) {
+        sgr(&mut self.color_code, &mut self.bold, param);
+    }
+
+    fn line(&self) -> usize {
+        self.pos / self.num_columns
+    }
+
+    fn column(&self) -> usize {
+        self.pos % self.num_columns
+    }
+
+    fn move_to(&mut self, line: usize, column: usize) {
+        let num_lines = self.buffer.len() / self.num_columns;
+        self.pos = line.min(num_lines - 1) * self.num_columns + column.min(self.num_columns - 1);
+    }
+
+    /// Blanks the characters from `start` up to `end`, in the current background color.
+    fn erase(&mut self, start: usize, end: usize) {
+        let end = end.min(self.buffer.len());
+        let blank = self.blank();
+        self.buffer[start.min(end)..end].fill(blank);
+    }
+
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
+            ascii_character: b' ',
+            color_code: ColorCode::new(DEFAULT_COLOR.fg(), self.color_code.bg())
+        }
+    }
+
+    fn update_cursor(&self) {
+        if let Some(crtc) = &self.crtc {
+            crtc.move_cursor(self.cursor());
+        }
+    }
+
     fn new_line(&mut self) {
         debug_assert!(self.pos <= self.buffer.len());
 
@@ -100,14 +590,16 @@ impl Writer {
     fn scroll(&mut self) {
         debug_assert!(self.pos <= self.buffer.len());
 
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.push(&self.buffer[..self.num_columns]);
+        }
+
         self.buffer.copy_within(self.num_columns..self.buffer.len(), 0);
 
         let new_cursor_pos = self.buffer.len() - self.num_columns;
 
-        self.buffer[new_cursor_pos..].fill(ScreenChar {
-            ascii_character: b' ',
-            color_code: ColorCode::new(Color::White, Color::Black)
-        });
+        let blank = self.blank();
+        self.buffer[new_cursor_pos..].fill(blank);
 
         self.pos = new_cursor_pos;
     }
@@ -115,12 +607,13 @@ impl Writer {
     pub fn clear(&mut self) {
         debug_assert!(self.pos <= self.buffer.len());
 
-        self.buffer.fill(ScreenChar {
-            ascii_character: b' ',
-            color_code: ColorCode::new(Color::White, Color::Black)
-        });
+        self.scroll_view_to(0);
+
+        let blank = self.blank();
+        self.buffer.fill(blank);
 
         self.pos = 0;
+        self.update_cursor();
     }
 
     pub fn color_code(&self) -> ColorCode {
@@ -140,11 +633,249 @@ impl Writer {
     }
 }
 
-impl Write for Writer {
-    fn write_str(&mut self, s: &str) -> Result {
+impl<B: Bus> fmt::Debug for Writer<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("num_columns", &self.num_columns)
+            .field("pos", &self.pos)
+            .field("color_code", &self.color_code)
+            .finish()
+    }
+}
+
+impl<B: Bus> Write for Writer<B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
         for byte in s.bytes() {
-            self.write_byte(byte);
+            self.put(byte);
         }
+
+        self.update_cursor();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use super::*;
+    use crate::drivers::bus::mock::{Access, MockBus};
+
+    const LINES: usize = 3;
+    const COLUMNS: usize = 4;
+
+    fn writer() -> Writer<&'static MockBus> {
+        let buffer = Box::leak(Box::new([ScreenChar { ascii_character: 0, color_code: ColorCode(0) }; LINES * COLUMNS]));
+        let mut writer = Writer::new(buffer, LINES, COLUMNS, None);
+        writer.clear();
+        writer
+    }
+
+    fn line(writer: &Writer<&MockBus>, line: usize) -> [u8; COLUMNS] {
+        let mut text = [0; COLUMNS];
+        for (i, c) in writer.buffer[line * COLUMNS..][..COLUMNS].iter().enumerate() {
+            text[i] = c.ascii_character;
+        }
+
+        text
+    }
+
+    #[test]
+    fn write_and_new_line() {
+        let mut writer = writer();
+        writer.write_str("ab\ncd").unwrap();
+        assert_eq!(&line(&writer, 0), b"ab  ");
+        assert_eq!(&line(&writer, 1), b"cd  ");
+        assert_eq!(writer.pos, COLUMNS + 2);
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let mut writer = writer();
+        writer.write_str("abcdef").unwrap();
+        assert_eq!(&line(&writer, 0), b"abcd");
+        assert_eq!(&line(&writer, 1), b"ef  ");
+    }
+
+    #[test]
+    fn scrolls_at_the_bottom() {
+        let mut writer = writer();
+        writer.write_str("1\n2\n3\n4").unwrap();
+        assert_eq!(&line(&writer, 0), b"2   ");
+        assert_eq!(&line(&writer, 1), b"3   ");
+        assert_eq!(&line(&writer, 2), b"4   ");
+    }
+
+    #[test]
+    fn color() {
+        let mut writer = writer();
+        writer.set_fg_color(Color::Red);
+        writer.write_str("x").unwrap();
+        assert_eq!(writer.buffer[0].color_code, ColorCode::new(Color::Red, Color::Black));
+        assert_eq!(writer.buffer[1].color_code, ColorCode::new(Color::White, Color::Black));
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut writer = writer();
+        writer.write_str("abc\rx\t").unwrap();
+        assert_eq!(&line(&writer, 0), b"xbc ");
+        assert_eq!(writer.pos, 3);
+
+        writer.write_str("\n12\x08\x08 ").unwrap();
+        assert_eq!(&line(&writer, 1), b" 2  ");
+        assert_eq!(writer.pos, COLUMNS + 1);
+
+        // Backspace stops at the start of the line
+        writer.write_str("\x08\x08\x08").unwrap();
+        assert_eq!(writer.pos, COLUMNS);
+    }
+
+    #[test]
+    fn sgr_colors() {
+        let mut writer = writer();
+        writer.write_str("\x1b[31ma\x1b[1;44mb\x1b[22;39mc\x1b[0md").unwrap();
+        assert_eq!(writer.buffer[0].color_code, ColorCode::new(Color::Red, Color::Black));
+        assert_eq!(writer.buffer[1].color_code, ColorCode::new(Color::LightRed, Color::Blue));
+        assert_eq!(writer.buffer[2].color_code, ColorCode::new(Color::White, Color::Blue));
+        assert_eq!(writer.buffer[3].color_code, ColorCode::new(Color::White, Color::Black));
+        assert_eq!(&line(&writer, 0), b"abcd");
+    }
+
+    #[test]
+    fn color_tracker() {
+        let mut tracker = ColorTracker::new();
+        let mut writer = writer();
+        let mut out = std::string::String::new();
+        ColorCode::new(Color::LightRed, Color::Blue).write_sgr(&mut out).unwrap();
+        out.push_str("a\x1b[Kb\x1b[1;2;3;4;45m");
+        for b in out.bytes() {
+            tracker.feed(b);
+            writer.write_byte(b);
+        }
+
+        assert_eq!(tracker.color_code(), ColorCode::new(Color::LightRed, Color::Blue));
+        assert_eq!(tracker.color_code(), writer.color_code());
+
+        out.clear();
+        DEFAULT_COLOR.write_sgr(&mut out).unwrap();
+        assert_eq!(out, "\x1b[0m");
+        out.bytes().for_each(|b| tracker.feed(b));
+        assert_eq!(tracker.color_code(), DEFAULT_COLOR);
+    }
+
+    #[test]
+    fn too_many_params() {
+        let mut writer = writer();
+        // The fifth parameter would make 45, a magenta background, if it ran into the fourth
+        writer.write_str("\x1b[1;2;3;4;5ma").unwrap();
+        assert_eq!(writer.buffer[0].color_code.bg(), Color::Black);
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let mut writer = writer();
+        writer.write_str("\x1b[2;3Hx\x1b[Hy\x1b[2Bz\x1b[9;9H").unwrap();
+        assert_eq!(&line(&writer, 0), b"y   ");
+        assert_eq!(&line(&writer, 1), b"  x ");
+        assert_eq!(&line(&writer, 2), b" z  ");
+        assert_eq!(writer.pos, LINES * COLUMNS - 1);
+
+        writer.write_str("\x1b[2;2H\x1b[3D\x1b[C\x1b[A").unwrap();
+        assert_eq!(writer.pos, 1);
+    }
+
+    #[test]
+    fn erase() {
+        let mut writer = writer();
+        writer.write_str("abcdefghijkl\x1b[2;3H\x1b[K").unwrap();
+        assert_eq!(&line(&writer, 0), b"efgh");
+        assert_eq!(&line(&writer, 1), b"ij  ");
+
+        writer.write_str("\x1b[1K").unwrap();
+        assert_eq!(&line(&writer, 1), b"    ");
+
+        writer.write_str("\x1b[1;2H\x1b[J").unwrap();
+        assert_eq!(&line(&writer, 0), b"e   ");
+        assert_eq!(&line(&writer, 2), b"    ");
+
+        writer.write_str("\x1b[2J").unwrap();
+        assert_eq!(&line(&writer, 0), b"    ");
+    }
+
+    #[test]
+    fn hardware_cursor() {
+        let bus = Box::leak(Box::new(MockBus::new()));
+        let buffer = Box::leak(Box::new([ScreenChar { ascii_character: 0, color_code: ColorCode(0) }; 80 * 25]));
+        let mut writer = Writer::new(buffer, 25, 80, Some(Crtc::new(RegisterBlock::new(&*bus, 1))));
+        writer.write_str("\x1b[5;1H").unwrap();
+        assert_eq!(bus.log(), [
+            Access::Write(0, CRTC_CURSOR_LOCATION_LOW),
+            Access::Write(1, 320 & 0xFF),
+            Access::Write(0, CRTC_CURSOR_LOCATION_HIGH),
+            Access::Write(1, 320 >> 8)
+        ]);
+
+        bus.clear_log();
+        writer.crtc.as_ref().unwrap().disable_cursor();
+        assert_eq!(bus.writes(1), [CURSOR_DISABLE]);
+    }
+
+    #[test]
+    fn utf8() {
+        let mut writer = writer();
+        writer.write_str("é°€").unwrap();
+        assert_eq!(&line(&writer, 0), &[0x82, 0xF8, REPLACEMENT, b' ']);
+
+        // Bytes written one at a time, the way the shell console does
+        for &byte in "\nµ─²".as_bytes() {
+            writer.write_byte(byte);
+        }
+
+        assert_eq!(&line(&writer, 1), &[0xE6, 0xC4, 0xFD, b' ']);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut writer = writer();
+        // Truncated sequence followed by ASCII, then a lone continuation byte
+        for &byte in &[0xC3, b'a', 0xA9] {
+            writer.write_byte(byte);
+        }
+
+        assert_eq!(&line(&writer, 0), &[REPLACEMENT, b'a', REPLACEMENT, b' ']);
+    }
+
+    #[test]
+    fn scrollback() {
+        let mut writer = writer();
+        let storage = Box::leak(Box::new([ScreenChar { ascii_character: 0, color_code: ColorCode(0) }; (LINES + 2) * COLUMNS]));
+        writer.set_scrollback(storage);
+        writer.write_str("1\n2\n3\n4\n5").unwrap();
+        let history = writer.scrollback().unwrap();
+        assert_eq!(history.depth(), 2);
+        assert_eq!(history.line(0).unwrap()[0].glyph(), b'2');
+        assert_eq!(history.line(1).unwrap()[0].glyph(), b'1');
+
+        writer.scroll_view_up(1);
+        assert_eq!(&line(&writer, 0), b"2   ");
+        assert_eq!(&line(&writer, 2), b"4   ");
+        writer.scroll_view_up(10);
+        assert_eq!(writer.view_offset(), 2);
+        assert_eq!(&line(&writer, 0), b"1   ");
+
+        // Writing goes back to the live screen
+        writer.write_str("6").unwrap();
+        assert_eq!(writer.view_offset(), 0);
+        assert_eq!(&line(&writer, 2), b"56  ");
+
+        writer.scroll_view_up(1);
+        writer.scroll_view_down(1);
+        assert_eq!(&line(&writer, 0), b"3   ");
+
+        writer.write_str("\x1b[3J").unwrap();
+        assert!(writer.scrollback().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+//! Text console drawn on a framebuffer with a PSF font.
+
+use core::fmt::{self, Write};
+
+use crate::drivers::bus::Mmio8;
+use crate::drivers::video::console::cp437::{self, REPLACEMENT};
+use crate::drivers::video::console::vga::{ColorCode, ScreenChar, Writer};
+
+use super::psf::Font;
+use super::FrameBuffer;
+
+/// Colors of the VGA text mode palette, in the order of [`Color`](crate::drivers::video::console::vga::Color)
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0x00, 0xAA, 0x00),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00),
+    (0xAA, 0x00, 0xAA),
+    (0xAA, 0x55, 0x00),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0x55, 0xFF, 0x55),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55),
+    (0xFF, 0x55, 0xFF),
+    (0xFF, 0xFF, 0x55),
+    (0xFF, 0xFF, 0xFF)
+];
+
+/// Scanlines at the bottom of a character cell covered by the cursor
+const CURSOR_HEIGHT: usize = 2;
+
+/// Text console on a framebuffer.
+///
+/// The text is kept by a VGA [`Writer`], so it understands the same control
+/// characters and escape sequences, and cells are redrawn when they change.
+pub struct FrameBufferConsole {
+    fb: FrameBuffer,
+    font: Font<'static>,
+    /// Glyph of the font for every code page 437 glyph
+    glyphs: [u16; 256],
+    /// There is no CRT controller, the cursor is drawn
+    text: Writer<Mmio8>,
+    /// Characters as they are drawn on the framebuffer
+    shown: &'static mut [ScreenChar],
+    /// Character the cursor is drawn on
+    shown_cursor: Option<usize>,
+    /// Pixel values of the palette colors
+    palette: [u32; 16]
+}
+
+impl FrameBufferConsole {
+    /// Uses `storage` for two copies of the text, the console gets as many lines
+    /// as fit on the screen and in half of `storage`.
+    ///
+    /// Returns `None` if not even one line fits.
+    pub fn new(mut fb: FrameBuffer, font: Font<'static>, storage: &'static mut [ScreenChar]) -> Option<Self> {
+        let num_columns = fb.width() / font.width();
+        let num_lines = (fb.height() / font.height()).min((storage.len() / 2).checked_div(num_columns)?);
+        if num_lines == 0 {
+            return None;
+        }
+
+        let (text, shown) = storage.split_at_mut(num_lines * num_columns);
+        let mut text = Writer::new(text, num_lines, num_columns, None);
+        text.clear();
+        let shown = &mut shown[..num_lines * num_columns];
+        shown.copy_from_slice(text.buffer());
+
+        let palette = PALETTE.map(|(r, g, b)| fb.color(r, g, b));
+        // Blank characters are all background, which starts out black
+        fb.clear(palette[0]);
+
+        let mut console = Self {
+            fb,
+            glyphs: glyph_map(&font),
+            font,
+            text,
+            shown,
+            shown_cursor: None,
+            palette
+        };
+        console.render();
+        Some(console)
+    }
+
+    /// Keeps the lines that scroll off the top in `storage`, see [`Writer::set_scrollback`].
+    pub fn set_scrollback(&mut self, storage: &'static mut [ScreenChar]) {
+        self.text.set_scrollback(storage);
+    }
+
+    pub fn num_lines(&self) -> usize {
+        self.text.num_lines()
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.text.num_columns()
+    }
+
+    /// Characters on the screen, line after line.
+    pub fn buffer(&self) -> &[ScreenChar] {
+        self.text.buffer()
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.text.write_byte(byte);
+        self.render();
+    }
+
+    pub fn color_code(&self) -> ColorCode {
+        self.text.color_code()
+    }
+
+    pub fn set_color_code(&mut self, color: ColorCode) {
+        self.text.set_color_code(color);
+    }
+
+    /// Shows the screen `lines` further back in the history.
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        self.text.scroll_view_up(lines);
+        self.render();
+    }
+
+    /// Shows the screen `lines` further forward, back to the live screen at most.
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        self.text.scroll_view_down(lines);
+        self.render();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.render();
+    }
+
+    /// Draws the characters that changed since the last time, and the cursor.
+    fn render(&mut self) {
+        // The cursor only shows on the live screen
+        let cursor = (self.text.view_offset() == 0).then(|| self.text.cursor());
+        for i in 0..self.shown.len() {
+            let c = self.text.buffer()[i];
+            let is_cursor = cursor == Some(i);
+            if c != self.shown[i] || is_cursor != (self.shown_cursor == Some(i)) {
+                self.draw(i, c, is_cursor);
+                self.shown[i] = c;
+            }
+        }
+
+        self.shown_cursor = cursor;
+    }
+
+    /// Draws `c` as character `i` of the screen.
+    fn draw(&mut self, i: usize, c: ScreenChar, cursor: bool) {
+        let (width, height) = (self.font.width(), self.font.height());
+        let (x0, y0) = (i % self.num_columns() * width, i / self.num_columns() * height);
+        let fg = self.palette[c.color_code().fg() as usize];
+        let bg = self.palette[c.color_code().bg() as usize];
+        let glyph = self.font.glyph(self.glyphs[c.glyph() as usize] as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let set = glyph.is_some_and(|g| self.font.pixel(g, x, y)) || (cursor && y >= height - CURSOR_HEIGHT);
+                self.fb.put_pixel(x0 + x, y0 + y, if set { fg } else { bg });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FrameBufferConsole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameBufferConsole")
+            .field("info", self.fb.info())
+            .field("text", &self.text)
+            .finish()
+    }
+}
+
+impl Write for FrameBufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.text.write_str(s)?;
+        self.render();
+        Ok(())
+    }
+}
+
+/// Finds the glyphs of `font` that show the code page 437 glyphs.
+///
+/// Fonts without a unicode table are taken to be in code page 437 order.
+fn glyph_map(font: &Font) -> [u16; 256] {
+    let find = |glyph: u8| {
+        font.lookup(cp437::to_picture(glyph))
+            .or_else(|| (!font.has_unicode_table() && (glyph as usize) < font.num_glyphs()).then_some(glyph as usize))
+    };
+
+    let fallback = find(REPLACEMENT).or_else(|| font.lookup('?')).unwrap_or(0);
+    let mut glyphs = [0; 256];
+    for (i, glyph) in glyphs.iter_mut().enumerate() {
+        *glyph = find(i as u8).unwrap_or(fallback) as u16;
+    }
+
+    glyphs
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec;
+
+    use super::*;
+    use crate::drivers::video::console::vga::Color;
+    use crate::drivers::video::framebuffer::psf::DEFAULT_FONT;
+    use crate::drivers::video::framebuffer::{FrameBufferInfo, PixelFormat};
+
+    const WIDTH: usize = 4 * 8;
+    const HEIGHT: usize = 3 * 16;
+
+    fn console() -> FrameBufferConsole {
+        let info = FrameBufferInfo { addr: 1, width: WIDTH, height: HEIGHT, pitch: WIDTH * 4, bpp: 32, format: PixelFormat::XRGB8888 };
+        let fb = FrameBuffer::from_slice(Box::leak(vec![0xAB; info.size()].into_boxed_slice()), info);
+        let storage = Box::leak(Box::new([ScreenChar::new(0, ColorCode::new(Color::Black, Color::Black)); 2 * 4 * 3]));
+        FrameBufferConsole::new(fb, Font::parse(DEFAULT_FONT).unwrap(), storage).unwrap()
+    }
+
+    fn pixel(console: &FrameBufferConsole, x: usize, y: usize) -> u32 {
+        u32::from_le_bytes(console.fb.buffer[(y * WIDTH + x) * 4..][..4].try_into().unwrap())
+    }
+
+    /// Pixels of the character cell at `line`, `column` that have color `color`.
+    fn count(console: &FrameBufferConsole, line: usize, column: usize, color: u32) -> usize {
+        (0..16).flat_map(|y| (0..8).map(move |x| (x, y)))
+            .filter(|&(x, y)| pixel(console, column * 8 + x, line * 16 + y) == color)
+            .count()
+    }
+
+    #[test]
+    fn draws_text() {
+        let mut console = console();
+        assert_eq!((console.num_lines(), console.num_columns()), (3, 4));
+        // The whole screen is cleared, only the cursor shows
+        assert_eq!(count(&console, 0, 1, 0), 8 * 16);
+
+        write!(console, "\u{2588}\x1b[31m\u{2588}\x1b[0m").unwrap();
+        assert_eq!(count(&console, 0, 0, 0xFF_FFFF), 8 * 16);
+        assert_eq!(count(&console, 0, 1, 0xAA_0000), 8 * 16);
+        // Cursor
+        assert_eq!(count(&console, 0, 2, 0xFF_FFFF), 8 * CURSOR_HEIGHT);
+
+        console.write_byte(b'\n');
+        assert_eq!(count(&console, 0, 2, 0), 8 * 16);
+        assert_eq!(count(&console, 1, 0, 0xFF_FFFF), 8 * CURSOR_HEIGHT);
+    }
+
+    #[test]
+    fn color_code() {
+        let mut console = console();
+        let red = ColorCode::new(Color::Red, Color::Black);
+        console.set_color_code(red);
+        write!(console, "\u{2588}").unwrap();
+        assert_eq!(console.color_code(), red);
+        assert_eq!(count(&console, 0, 0, 0xAA_0000), 8 * 16);
+    }
+
+    #[test]
+    fn scrolls() {
+        let mut console = console();
+        write!(console, "\u{2588}\n\n\n").unwrap();
+        // The block scrolled off
+        assert_eq!(count(&console, 0, 0, 0), 8 * 16);
+        assert_eq!(console.buffer()[0].glyph(), b' ');
+    }
+
+    #[test]
+    fn needs_room_for_a_line() {
+        let info = FrameBufferInfo { addr: 1, width: 7, height: 16, pitch: 28, bpp: 32, format: PixelFormat::XRGB8888 };
+        let fb = FrameBuffer::from_slice(Box::leak(vec![0; info.size()].into_boxed_slice()), info);
+        let storage = Box::leak(Box::new([ScreenChar::new(0, ColorCode::new(Color::Black, Color::Black)); 16]));
+        assert!(FrameBufferConsole::new(fb, Font::parse(DEFAULT_FONT).unwrap(), storage).is_none());
+    }
+}
@@ -0,0 +1,429 @@
+//! Linear framebuffers set up by the firmware or boot loader.
+
+pub mod console;
+pub mod psf;
+
+use core::ptr;
+use core::slice;
+
+use crate::fdt::{Fdt, Token};
+
+/// Framebuffer type of direct RGB color, the others are indexed color and EGA text
+const MULTIBOOT2_FRAMEBUFFER_RGB: u8 = 1;
+
+/// The VideoCore hands out bus addresses, with the cache alias in the top bits
+const BCM2835_BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
+/// Pixel order of the BCM2835 property mailbox where red is in the low bits
+const BCM2835_PIXEL_ORDER_RGB: u32 = 1;
+
+/// Deepest device tree nesting tracked while looking for a framebuffer
+const MAX_DEPTH: usize = 16;
+
+/// Position and width of a color component within a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorField {
+    pub shift: u8,
+    pub size: u8
+}
+
+impl ColorField {
+    pub const fn new(shift: u8, size: u8) -> Self {
+        Self { shift, size }
+    }
+
+    /// Scales the 8 bit `value` to the field and shifts it into place.
+    const fn encode(self, value: u8) -> u32 {
+        match self.size {
+            0 => 0,
+            size @ 1..=8 => ((value as u32) >> (8 - size)) << self.shift,
+            // Wider fields get the value in their top bits
+            size => (value as u32) << (self.shift + size - 8)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub red: ColorField,
+    pub green: ColorField,
+    pub blue: ColorField
+}
+
+impl PixelFormat {
+    pub const RGB565: Self = Self::new(ColorField::new(11, 5), ColorField::new(5, 6), ColorField::new(0, 5));
+    /// 8 bits per component with blue in the low byte, XRGB8888 in little endian
+    pub const XRGB8888: Self = Self::new(ColorField::new(16, 8), ColorField::new(8, 8), ColorField::new(0, 8));
+    /// 8 bits per component with red in the low byte, XBGR8888 in little endian
+    pub const XBGR8888: Self = Self::new(ColorField::new(0, 8), ColorField::new(8, 8), ColorField::new(16, 8));
+
+    pub const fn new(red: ColorField, green: ColorField, blue: ColorField) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// Returns the pixel value of an 8 bit per component color.
+    pub const fn encode(&self, r: u8, g: u8, b: u8) -> u32 {
+        self.red.encode(r) | self.green.encode(g) | self.blue.encode(b)
+    }
+}
+
+/// Pixel formats of the `simple-framebuffer` device tree binding, by name
+const SIMPLE_FRAMEBUFFER_FORMATS: [(&str, u8, PixelFormat); 7] = [
+    ("r5g6b5", 16, PixelFormat::RGB565),
+    ("r8g8b8", 24, PixelFormat::new(ColorField::new(16, 8), ColorField::new(8, 8), ColorField::new(0, 8))),
+    ("x8r8g8b8", 32, PixelFormat::XRGB8888),
+    ("a8r8g8b8", 32, PixelFormat::XRGB8888),
+    ("x8b8g8r8", 32, PixelFormat::XBGR8888),
+    ("a8b8g8r8", 32, PixelFormat::XBGR8888),
+    ("x2r10g10b10", 32, PixelFormat::new(ColorField::new(20, 10), ColorField::new(10, 10), ColorField::new(0, 10)))
+];
+
+/// Where a framebuffer is and how its pixels are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    /// Physical address of the top left pixel
+    pub addr: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Bytes from the start of one line to the next
+    pub pitch: usize,
+    /// Bits per pixel
+    pub bpp: u8,
+    pub format: PixelFormat
+}
+
+impl FrameBufferInfo {
+    /// Size of the framebuffer in bytes.
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        (self.bpp as usize).div_ceil(8)
+    }
+
+    /// Reads a multiboot2 framebuffer tag, header included, the boot information
+    /// code finds it by its type.
+    ///
+    /// Returns `None` for indexed color and text mode framebuffers, only direct RGB is supported.
+    pub fn from_multiboot2(tag: &[u8]) -> Option<Self> {
+        let u32_at = |offset: usize| tag.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+        if tag.len() < 38 || tag[29] != MULTIBOOT2_FRAMEBUFFER_RGB {
+            return None;
+        }
+
+        let addr = u64::from_le_bytes(tag[8..16].try_into().unwrap());
+        Some(Self {
+            addr: addr.try_into().ok()?,
+            width: u32_at(20)? as usize,
+            height: u32_at(24)? as usize,
+            pitch: u32_at(16)? as usize,
+            bpp: tag[28],
+            format: PixelFormat::new(
+                ColorField::new(tag[32], tag[33]),
+                ColorField::new(tag[34], tag[35]),
+                ColorField::new(tag[36], tag[37]))
+        }).filter(Self::valid)
+    }
+
+    /// Builds the description of a framebuffer allocated through the BCM2835 property mailbox.
+    ///
+    /// `bus_addr` is the address the VideoCore answered with and `pixel_order` the value of
+    /// the pixel order tag, 1 for RGB and 0 for BGR.
+    pub fn from_bcm2835(bus_addr: u32, width: u32, height: u32, pitch: u32, depth: u32, pixel_order: u32) -> Option<Self> {
+        let rgb = pixel_order == BCM2835_PIXEL_ORDER_RGB;
+        let format = match depth {
+            16 if rgb => PixelFormat::RGB565,
+            16 => PixelFormat::new(ColorField::new(0, 5), ColorField::new(5, 6), ColorField::new(11, 5)),
+            24 | 32 if rgb => PixelFormat::XBGR8888,
+            24 | 32 => PixelFormat::XRGB8888,
+            _ => return None
+        };
+
+        Some(Self {
+            addr: (bus_addr & BCM2835_BUS_ADDRESS_MASK) as usize,
+            width: width as usize,
+            height: height as usize,
+            pitch: pitch as usize,
+            bpp: depth as u8,
+            format
+        }).filter(Self::valid)
+    }
+
+    /// Finds the first enabled `simple-framebuffer` node of the device tree.
+    pub fn from_fdt(fdt: &Fdt) -> Option<Self> {
+        // #address-cells of the nodes on the path to the current one, reg only needs the address
+        let mut address_cells = [2; MAX_DEPTH];
+        let mut depth = 0;
+        let mut node = SimpleFrameBufferNode::default();
+        for token in fdt.tokens() {
+            match token.ok()? {
+                Token::BeginNode(_) => {
+                    depth += 1;
+                    if depth >= MAX_DEPTH {
+                        return None;
+                    }
+
+                    // Children use the default unless the node says otherwise
+                    address_cells[depth] = 2;
+                    node = SimpleFrameBufferNode::default();
+                },
+                Token::EndNode => {
+                    if let Some(info) = node.info() {
+                        return Some(info);
+                    }
+
+                    node = SimpleFrameBufferNode::default();
+                    depth = depth.checked_sub(1)?;
+                },
+                Token::Property { name, value } => {
+                    match name {
+                        "#address-cells" => address_cells[depth] = be32(value)?,
+                        "compatible" => node.compatible = value.split(|&b| b == 0).any(|c| c == b"simple-framebuffer"),
+                        "status" => node.disabled = !matches!(value, b"okay\0" | b"ok\0"),
+                        "reg" => node.addr = be_cells(value, address_cells[depth.saturating_sub(1)] as usize),
+                        "width" => node.width = be32(value),
+                        "height" => node.height = be32(value),
+                        "stride" => node.stride = be32(value),
+                        "format" => node.format = value.strip_suffix(&[0]).and_then(|f| {
+                            SIMPLE_FRAMEBUFFER_FORMATS.iter().find(|(name, ..)| name.as_bytes() == f)
+                        }).map(|&(_, bpp, format)| (bpp, format)),
+                        _ => ()
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn valid(&self) -> bool {
+        self.addr != 0
+            && self.width != 0
+            && self.height != 0
+            && matches!(self.bpp, 8 | 15 | 16 | 24 | 32)
+            && self.pitch >= self.width * self.bytes_per_pixel()
+    }
+}
+
+/// Properties of a device tree node that could be a `simple-framebuffer`.
+#[derive(Debug, Default)]
+struct SimpleFrameBufferNode {
+    compatible: bool,
+    disabled: bool,
+    addr: Option<u64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    stride: Option<u32>,
+    format: Option<(u8, PixelFormat)>
+}
+
+impl SimpleFrameBufferNode {
+    fn info(&self) -> Option<FrameBufferInfo> {
+        if !self.compatible || self.disabled {
+            return None;
+        }
+
+        let (bpp, format) = self.format?;
+        Some(FrameBufferInfo {
+            addr: self.addr?.try_into().ok()?,
+            width: self.width? as usize,
+            height: self.height? as usize,
+            pitch: self.stride? as usize,
+            bpp,
+            format
+        }).filter(FrameBufferInfo::valid)
+    }
+}
+
+fn be32(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.get(..4)?.try_into().unwrap()))
+}
+
+/// Reads the first number of a property, `cells` 32 bit words long.
+fn be_cells(value: &[u8], cells: usize) -> Option<u64> {
+    if cells == 0 || cells > 2 {
+        return None;
+    }
+
+    value.get(..cells * 4)?.chunks_exact(4).try_fold(0u64, |v, cell| Some(v << 32 | be32(cell)? as u64))
+}
+
+/// Pixels in memory, drawn with volatile stores of the pixel size so the
+/// framebuffer can be uncached device memory.
+pub struct FrameBuffer {
+    buffer: &'static mut [u8],
+    info: FrameBufferInfo
+}
+
+impl FrameBuffer {
+    /// # Safety
+    ///
+    /// `info.addr` has to be the mapped address of a framebuffer that is `info.size()`
+    /// bytes long and nothing else may use.
+    pub unsafe fn new(info: FrameBufferInfo) -> Self {
+        Self::from_slice(slice::from_raw_parts_mut(info.addr as *mut u8, info.size()), info)
+    }
+
+    /// Draws into `buffer` instead of the memory at `info.addr`.
+    pub fn from_slice(buffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        assert!(buffer.len() >= info.size());
+
+        Self { buffer, info }
+    }
+
+    pub fn info(&self) -> &FrameBufferInfo {
+        &self.info
+    }
+
+    pub fn width(&self) -> usize {
+        self.info.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.info.height
+    }
+
+    /// Returns the pixel value of an 8 bit per component color.
+    pub fn color(&self, r: u8, g: u8, b: u8) -> u32 {
+        self.info.format.encode(r, g, b)
+    }
+
+    /// Sets pixel `x`, `y` to `color`, which is a value returned by [`FrameBuffer::color`].
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+
+        let offset = y * self.info.pitch + x * self.info.bytes_per_pixel();
+        let pixel = &mut self.buffer[offset..offset + self.info.bytes_per_pixel()];
+        unsafe {
+            match pixel.len() {
+                4 if pixel.as_ptr().align_offset(4) == 0 => ptr::write_volatile(pixel.as_mut_ptr() as *mut u32, color.to_le()),
+                2 if pixel.as_ptr().align_offset(2) == 0 => ptr::write_volatile(pixel.as_mut_ptr() as *mut u16, (color as u16).to_le()),
+                _ => {
+                    for (i, byte) in pixel.iter_mut().enumerate() {
+                        ptr::write_volatile(byte, (color >> (i * 8)) as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills the rectangle of `width` by `height` pixels at `x`, `y`, clipped to the screen.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let x_end = x.saturating_add(width).min(self.info.width);
+        let y_end = y.saturating_add(height).min(self.info.height);
+        for y in y..y_end {
+            for x in x..x_end {
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.fill_rect(0, 0, self.info.width, self.info.height, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::fdt::builder::FdtBuilder;
+
+    /// Builds a device tree with a framebuffer node under `/chosen`, which has one address and size cell.
+    fn fdt_blob(status: &str) -> Vec<u8> {
+        FdtBuilder::new()
+            .begin_node("")
+            .cells("#address-cells", &[2])
+            .begin_node("chosen")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1])
+            .begin_node("framebuffer@3c000000")
+            .string_property("compatible", "simple-framebuffer")
+            .cells("reg", &[0x3C00_0000, 0x0030_0000])
+            .cells("width", &[640])
+            .cells("height", &[480])
+            .cells("stride", &[2560])
+            .string_property("format", "a8r8g8b8")
+            .string_property("status", status)
+            .end_node()
+            .end_node()
+            .end_node()
+            .build()
+    }
+
+    #[test]
+    fn simple_framebuffer() {
+        let blob = fdt_blob("okay");
+        assert_eq!(FrameBufferInfo::from_fdt(&Fdt::new(&blob).unwrap()), Some(FrameBufferInfo {
+            addr: 0x3C00_0000,
+            width: 640,
+            height: 480,
+            pitch: 2560,
+            bpp: 32,
+            format: PixelFormat::XRGB8888
+        }));
+
+        let blob = fdt_blob("disabled");
+        assert_eq!(FrameBufferInfo::from_fdt(&Fdt::new(&blob).unwrap()), None);
+    }
+
+    #[test]
+    fn multiboot2() {
+        let mut tag = Vec::new();
+        for word in [8, 38, 0xFD00_0000, 0, 4096, 1024, 768] {
+            tag.extend_from_slice(&u32::to_le_bytes(word));
+        }
+
+        tag.extend_from_slice(&[32, MULTIBOOT2_FRAMEBUFFER_RGB, 0, 0, 16, 8, 8, 8, 0, 8]);
+        let info = FrameBufferInfo::from_multiboot2(&tag).unwrap();
+        assert_eq!((info.addr, info.width, info.height, info.pitch, info.bpp), (0xFD00_0000, 1024, 768, 4096, 32));
+        assert_eq!(info.format, PixelFormat::XRGB8888);
+
+        // EGA text
+        tag[29] = 2;
+        assert_eq!(FrameBufferInfo::from_multiboot2(&tag), None);
+    }
+
+    #[test]
+    fn bcm2835() {
+        let info = FrameBufferInfo::from_bcm2835(0xC3C0_0000, 640, 480, 2560, 32, 1).unwrap();
+        assert_eq!(info.addr, 0x03C0_0000);
+        assert_eq!(info.format.encode(0x12, 0x34, 0x56), 0x0056_3412);
+        assert_eq!(FrameBufferInfo::from_bcm2835(0xC3C0_0000, 640, 480, 2560, 12, 1), None);
+        assert_eq!(FrameBufferInfo::from_bcm2835(0xC3C0_0000, 640, 480, 640, 32, 1), None);
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(PixelFormat::XRGB8888.encode(0xFF, 0x80, 0x01), 0x00FF_8001);
+        assert_eq!(PixelFormat::RGB565.encode(0xFF, 0xFF, 0), 0xFFE0);
+        assert_eq!(PixelFormat::RGB565.encode(0x08, 0x04, 0x08), 0x0821);
+    }
+
+    #[test]
+    fn draw() {
+        for (bpp, format) in [(16, PixelFormat::RGB565), (24, SIMPLE_FRAMEBUFFER_FORMATS[1].2), (32, PixelFormat::XRGB8888)] {
+            let bytes = bpp / 8;
+            let info = FrameBufferInfo { addr: 1, width: 4, height: 3, pitch: 4 * bytes + 2, bpp: bpp as u8, format };
+            let buffer = Box::leak(vec![0; info.size()].into_boxed_slice());
+            let mut fb = FrameBuffer::from_slice(buffer, info);
+            let white = fb.color(0xFF, 0xFF, 0xFF);
+            fb.fill_rect(1, 1, 10, 10, white);
+            fb.put_pixel(4, 0, white);
+
+            let pixel = |buffer: &[u8], x: usize, y: usize| buffer[y * info.pitch + x * bytes..][..bytes] == white.to_le_bytes()[..bytes];
+            let buffer = &*fb.buffer;
+            assert!(!pixel(buffer, 0, 0) && !pixel(buffer, 3, 0) && !pixel(buffer, 0, 2), "{} bpp", bpp);
+            assert!(pixel(buffer, 1, 1) && pixel(buffer, 3, 2), "{} bpp", bpp);
+            // The padding at the end of a line stays untouched
+            assert_eq!(buffer[4 * bytes..info.pitch], [0, 0]);
+        }
+    }
+}
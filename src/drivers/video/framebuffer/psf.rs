@@ -0,0 +1,229 @@
+//! PC Screen Font, the bitmap font format of the Linux console, both version 1 and 2.
+
+use core::str;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+/// The font has 512 glyphs instead of 256
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_MODESEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_STARTSEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_STARTSEQ: u8 = 0xFE;
+
+/// 8x16 font covering code page 437, rendered from DejaVu Sans Mono with
+/// the box drawing and block characters drawn to line up between cells.
+pub static DEFAULT_FONT: &[u8] = include_bytes!("font8x16.psf");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+    BadMagic,
+    /// The data is smaller than its header says
+    Truncated
+}
+
+/// Maps characters to glyphs, in the encoding of the font version.
+#[derive(Debug, Clone, Copy)]
+enum UnicodeTable<'a> {
+    /// Little endian UCS-2 code points
+    Psf1(&'a [u8]),
+    /// UTF-8
+    Psf2(&'a [u8])
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    num_glyphs: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+    unicode: Option<UnicodeTable<'a>>
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, PsfError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            Err(PsfError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: &'a [u8]) -> Result<Self, PsfError> {
+        if data.len() < PSF1_HEADER_SIZE {
+            return Err(PsfError::Truncated);
+        }
+
+        let (mode, height) = (data[2], data[3] as usize);
+        let num_glyphs = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+        let end = PSF1_HEADER_SIZE + num_glyphs * height;
+        let glyphs = data.get(PSF1_HEADER_SIZE..end).ok_or(PsfError::Truncated)?;
+        let unicode = (mode & (PSF1_MODEHASTAB | PSF1_MODESEQ) != 0).then(|| UnicodeTable::Psf1(&data[end..]));
+
+        Ok(Self {
+            glyphs,
+            num_glyphs,
+            bytes_per_glyph: height,
+            width: 8,
+            height,
+            unicode
+        })
+    }
+
+    fn parse_psf2(data: &'a [u8]) -> Result<Self, PsfError> {
+        if data.len() < PSF2_HEADER_SIZE {
+            return Err(PsfError::Truncated);
+        }
+
+        let header = |field: usize| u32::from_le_bytes(data[field * 4..field * 4 + 4].try_into().unwrap()) as usize;
+        let (header_size, flags, num_glyphs) = (header(2), header(3), header(4));
+        let (bytes_per_glyph, height, width) = (header(5), header(6), header(7));
+        if width == 0 || bytes_per_glyph < height * width.div_ceil(8) {
+            return Err(PsfError::Truncated);
+        }
+
+        let end = num_glyphs.checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(PsfError::Truncated)?;
+        let glyphs = data.get(header_size..end).ok_or(PsfError::Truncated)?;
+        let unicode = (flags as u32 & PSF2_HAS_UNICODE_TABLE != 0).then(|| UnicodeTable::Psf2(&data[end..]));
+
+        Ok(Self {
+            glyphs,
+            num_glyphs,
+            bytes_per_glyph,
+            width,
+            height,
+            unicode
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn num_glyphs(&self) -> usize {
+        self.num_glyphs
+    }
+
+    pub fn has_unicode_table(&self) -> bool {
+        self.unicode.is_some()
+    }
+
+    /// Bitmap of glyph `index`, rows of `(width + 7) / 8` bytes with the leftmost pixel in the top bit.
+    pub fn glyph(&self, index: usize) -> Option<&'a [u8]> {
+        if index >= self.num_glyphs {
+            return None;
+        }
+
+        Some(&self.glyphs[index * self.bytes_per_glyph..][..self.bytes_per_glyph])
+    }
+
+    /// True if pixel `x`, `y` of `glyph` is set.
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        glyph[y * self.width.div_ceil(8) + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// Returns the glyph the unicode table gives `c`, or `None` if there
+    /// is no table or `c` isn't in it.
+    ///
+    /// Sequences of characters sharing one glyph are skipped, only single characters count.
+    pub fn lookup(&self, c: char) -> Option<usize> {
+        match self.unicode? {
+            UnicodeTable::Psf1(table) => {
+                let mut index = 0;
+                let mut in_sequence = false;
+                for entry in table.chunks_exact(2).map(|e| u16::from_le_bytes([e[0], e[1]])) {
+                    match entry {
+                        PSF1_SEPARATOR => {
+                            index += 1;
+                            in_sequence = false;
+                        },
+                        PSF1_STARTSEQ => in_sequence = true,
+                        entry if !in_sequence && entry as u32 == c as u32 => return Some(index),
+                        _ => ()
+                    }
+                }
+            },
+            UnicodeTable::Psf2(table) => {
+                for (index, entry) in table.split(|&b| b == PSF2_SEPARATOR).enumerate() {
+                    let singles = entry.split(|&b| b == PSF2_STARTSEQ).next().unwrap_or_default();
+                    let Ok(singles) = str::from_utf8(singles) else {
+                        continue;
+                    };
+
+                    if singles.chars().any(|s| s == c) {
+                        return Some(index);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn default_font() {
+        let font = Font::parse(DEFAULT_FONT).unwrap();
+        assert_eq!((font.width(), font.height(), font.num_glyphs()), (8, 16, 256));
+        assert_eq!(font.lookup('A'), Some(0x41));
+        assert_eq!(font.lookup('é'), Some(0x82));
+        assert_eq!(font.lookup('☺'), Some(0x01));
+        assert_eq!(font.lookup('\u{3B2}'), Some(0xE1));
+        assert_eq!(font.lookup('€'), None);
+
+        // Full block
+        let block = font.glyph(0xDB).unwrap();
+        assert!((0..8).all(|x| (0..16).all(|y| font.pixel(block, x, y))));
+        assert!(font.glyph(b' ' as usize).unwrap().iter().all(|&row| row == 0));
+        assert_eq!(font.glyph(256), None);
+    }
+
+    #[test]
+    fn psf1() {
+        // Two glyphs 2 rows high, 'a' is glyph 1 and so is 'b' followed by a sequence
+        let mut data = Vec::from([0x36, 0x04, PSF1_MODEHASTAB, 2]);
+        data.resize(PSF1_HEADER_SIZE + 256 * 2, 0);
+        data[PSF1_HEADER_SIZE + 2] = 0x81;
+        for entry in [0xFFFF, 0x61, 0x62, 0xFFFE, 0x63, 0x64, 0xFFFF] {
+            data.extend_from_slice(&u16::to_le_bytes(entry));
+        }
+
+        let font = Font::parse(&data).unwrap();
+        assert_eq!((font.width(), font.height(), font.num_glyphs()), (8, 2, 256));
+        let glyph = font.glyph(1).unwrap();
+        assert!(font.pixel(glyph, 0, 0) && font.pixel(glyph, 7, 0) && !font.pixel(glyph, 1, 0));
+        assert_eq!(font.lookup('a'), Some(1));
+        assert_eq!(font.lookup('b'), Some(1));
+        assert_eq!(font.lookup('c'), None);
+    }
+
+    #[test]
+    fn bad_fonts() {
+        assert_eq!(Font::parse(b"not a font").err(), Some(PsfError::BadMagic));
+        assert_eq!(Font::parse(&DEFAULT_FONT[..1000]).err(), Some(PsfError::Truncated));
+        assert_eq!(Font::parse(&[0x36, 0x04, 0, 16]).err(), Some(PsfError::Truncated));
+    }
+}
@@ -0,0 +1,233 @@
+//! Device for host tests, which works on the queues in the memory of the test.
+
+extern crate std;
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+use super::{DeviceType, Transport, INTERRUPT_QUEUE, STATUS_FEATURES_OK};
+
+const DESC_SIZE: u64 = 16;
+const DESC_F_NEXT: u16 = 1 << 0;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// Answers a request, given what the driver wrote, and returns how many bytes it wrote back.
+type Responder = Box<dyn FnMut(&[u8], &mut [u8]) -> usize>;
+
+#[derive(Default)]
+struct Queue {
+    max: u16,
+    size: u16,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    ready: bool,
+    last_avail: u16
+}
+
+#[derive(Default)]
+struct Inner {
+    status: u8,
+    device_features: u64,
+    driver_features: u64,
+    /// Features the device doesn't accept although it offers them
+    rejected: u64,
+    queues: BTreeMap<u16, Queue>,
+    notified: Vec<u16>,
+    interrupt: u32,
+    config: Vec<u8>,
+    generation: u32,
+    responder: Option<Responder>
+}
+
+pub struct FakeDevice {
+    device_type: DeviceType,
+    inner: RefCell<Inner>
+}
+
+unsafe fn read<T>(addr: u64) -> T {
+    (addr as *const T).read_volatile()
+}
+
+unsafe fn write<T>(addr: u64, value: T) {
+    (addr as *mut T).write_volatile(value)
+}
+
+impl FakeDevice {
+    pub fn new(device_type: DeviceType, features: u64) -> Self {
+        Self { device_type, inner: RefCell::new(Inner { device_features: features, ..Inner::default() }) }
+    }
+
+    pub fn add_queue(&self, queue: u16, max: u16) {
+        self.inner.borrow_mut().queues.insert(queue, Queue { max, ..Queue::default() });
+    }
+
+    pub fn reject_features(&self, features: u64) {
+        self.inner.borrow_mut().rejected = features;
+    }
+
+    pub fn driver_features(&self) -> u64 {
+        self.inner.borrow().driver_features
+    }
+
+    pub fn set_config(&self, config: &[u8]) {
+        let mut inner = self.inner.borrow_mut();
+        inner.config = config.to_vec();
+        inner.generation += 1;
+    }
+
+    /// Size and addresses of the rings of a queue the driver set up.
+    pub fn queue(&self, queue: u16) -> Option<(u16, u64, u64, u64)> {
+        let inner = self.inner.borrow();
+        inner.queues.get(&queue).filter(|q| q.ready).map(|q| (q.size, q.desc, q.avail, q.used))
+    }
+
+    /// The queues the driver notified, in order.
+    pub fn notified(&self) -> Vec<u16> {
+        self.inner.borrow().notified.clone()
+    }
+
+    /// Makes notifications answer the requests at once with `responder`.
+    pub fn respond_with(&self, responder: impl FnMut(&[u8], &mut [u8]) -> usize + 'static) {
+        self.inner.borrow_mut().responder = Some(Box::new(responder));
+    }
+
+    pub fn set_no_notify(&self, queue: u16, no_notify: bool) {
+        let used = self.queue(queue).expect("queue not set up").3;
+        unsafe { write::<u16>(used, no_notify as u16) };
+    }
+
+    pub fn wants_interrupts(&self, queue: u16) -> bool {
+        let avail = self.queue(queue).expect("queue not set up").2;
+        unsafe { read::<u16>(avail) & 1 == 0 }
+    }
+
+    /// Answers the new requests of `queue` with `f`, like [`Self::respond_with`],
+    /// and returns how many there were.
+    pub fn process(&self, queue: u16, mut f: impl FnMut(&[u8], &mut [u8]) -> usize) -> usize {
+        let mut inner = self.inner.borrow_mut();
+        let q = inner.queues.get_mut(&queue).filter(|q| q.ready).expect("queue not set up");
+        let mut count = 0;
+        unsafe {
+            let avail_idx = read::<u16>(q.avail + 2);
+            while q.last_avail != avail_idx {
+                let head = read::<u16>(q.avail + 4 + 2 * (q.last_avail % q.size) as u64);
+                q.last_avail = q.last_avail.wrapping_add(1);
+
+                // Gathers the chain, the readable buffers come first
+                let mut readable = Vec::new();
+                let mut writable = Vec::new();
+                let mut index = head;
+                loop {
+                    let desc = q.desc + DESC_SIZE * index as u64;
+                    let (addr, len, flags) = (read::<u64>(desc), read::<u32>(desc + 8), read::<u16>(desc + 12));
+                    let buffer = (addr, len as usize);
+                    if flags & DESC_F_WRITE != 0 { writable.push(buffer) } else { readable.push(buffer) }
+                    if flags & DESC_F_NEXT == 0 {
+                        break;
+                    }
+                    index = read::<u16>(desc + 14);
+                }
+
+                let input: Vec<u8> = readable.iter()
+                    .flat_map(|&(addr, len)| core::slice::from_raw_parts(addr as *const u8, len).iter().copied())
+                    .collect();
+                let mut output = std::vec![0; writable.iter().map(|b| b.1).sum()];
+                let written = f(&input, &mut output);
+
+                // Scatters the answer back over the writable buffers
+                let mut rest = &output[..written];
+                for &(addr, len) in &writable {
+                    let n = len.min(rest.len());
+                    core::slice::from_raw_parts_mut(addr as *mut u8, n).copy_from_slice(&rest[..n]);
+                    rest = &rest[n..];
+                }
+
+                let used_idx = read::<u16>(q.used + 2);
+                let elem = q.used + 4 + 8 * (used_idx % q.size) as u64;
+                write::<u32>(elem, head as u32);
+                write::<u32>(elem + 4, written as u32);
+                write::<u16>(q.used + 2, used_idx.wrapping_add(1));
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            inner.interrupt |= INTERRUPT_QUEUE;
+        }
+
+        count
+    }
+}
+
+impl Transport for FakeDevice {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn status(&self) -> u8 {
+        self.inner.borrow().status
+    }
+
+    fn set_status(&self, status: u8) {
+        let mut inner = self.inner.borrow_mut();
+        if status == 0 {
+            inner.driver_features = 0;
+            inner.queues.values_mut().for_each(|q| q.ready = false);
+        }
+
+        inner.status = if inner.driver_features & inner.rejected != 0 { status & !STATUS_FEATURES_OK } else { status };
+    }
+
+    fn device_features(&self) -> u64 {
+        self.inner.borrow().device_features
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.inner.borrow_mut().driver_features = features;
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        self.inner.borrow().queues.get(&queue).map_or(0, |q| q.max)
+    }
+
+    fn queue_ready(&self, queue: u16) -> bool {
+        self.inner.borrow().queues.get(&queue).is_some_and(|q| q.ready)
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: u64, avail: u64, used: u64) {
+        let mut inner = self.inner.borrow_mut();
+        let q = inner.queues.get_mut(&queue).expect("no such queue");
+        assert!(size <= q.max);
+        *q = Queue { max: q.max, size, desc, avail, used, ready: true, last_avail: 0 };
+    }
+
+    fn notify(&self, queue: u16) {
+        self.inner.borrow_mut().notified.push(queue);
+        let responder = self.inner.borrow_mut().responder.take();
+        if let Some(mut responder) = responder {
+            self.process(queue, &mut responder);
+            self.inner.borrow_mut().responder = Some(responder);
+        }
+    }
+
+    fn ack_interrupt(&self) -> u32 {
+        core::mem::take(&mut self.inner.borrow_mut().interrupt)
+    }
+
+    fn config_generation(&self) -> u32 {
+        self.inner.borrow().generation
+    }
+
+    fn read_config(&self, offset: usize) -> u8 {
+        self.inner.borrow().config.get(offset).copied().unwrap_or(0)
+    }
+
+    fn write_config(&self, offset: usize, value: u8) {
+        if let Some(byte) = self.inner.borrow_mut().config.get_mut(offset) {
+            *byte = value;
+        }
+    }
+}
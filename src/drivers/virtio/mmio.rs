@@ -0,0 +1,249 @@
+//! virtio-mmio, the transport of the QEMU virt machines: a 512 byte window of
+//! registers per device, found in the device tree.
+
+use crate::drivers::bus::{Bus, ReadOnly, ReadWrite, Register, RegisterBlock, WriteOnly};
+use crate::fdt::Fdt;
+use crate::sync::mutex::Mutex;
+
+use super::{DeviceType, Transport, VirtioError};
+
+/// "virt" in little endian
+const MAGIC: u32 = 0x74726976;
+/// Version of the modern register layout, 1 is the legacy one
+const VERSION_MODERN: u32 = 2;
+
+/// Size of the register window of a device
+pub const WINDOW_SIZE: usize = 0x200;
+
+const MAGIC_VALUE: Register<ReadOnly> = Register::new(0x000);
+const VERSION: Register<ReadOnly> = Register::new(0x004);
+const DEVICE_ID: Register<ReadOnly> = Register::new(0x008);
+const DEVICE_FEATURES: Register<ReadOnly> = Register::new(0x010);
+const DEVICE_FEATURES_SEL: Register<WriteOnly> = Register::new(0x014);
+const DRIVER_FEATURES: Register<WriteOnly> = Register::new(0x020);
+const DRIVER_FEATURES_SEL: Register<WriteOnly> = Register::new(0x024);
+const QUEUE_SEL: Register<WriteOnly> = Register::new(0x030);
+const QUEUE_NUM_MAX: Register<ReadOnly> = Register::new(0x034);
+const QUEUE_NUM: Register<WriteOnly> = Register::new(0x038);
+const QUEUE_READY: Register<ReadWrite> = Register::new(0x044);
+const QUEUE_NOTIFY: Register<WriteOnly> = Register::new(0x050);
+const INTERRUPT_STATUS: Register<ReadOnly> = Register::new(0x060);
+const INTERRUPT_ACK: Register<WriteOnly> = Register::new(0x064);
+const STATUS: Register<ReadWrite> = Register::new(0x070);
+const QUEUE_DESC_LOW: Register<WriteOnly> = Register::new(0x080);
+const QUEUE_DESC_HIGH: Register<WriteOnly> = Register::new(0x084);
+const QUEUE_DRIVER_LOW: Register<WriteOnly> = Register::new(0x090);
+const QUEUE_DRIVER_HIGH: Register<WriteOnly> = Register::new(0x094);
+const QUEUE_DEVICE_LOW: Register<WriteOnly> = Register::new(0x0A0);
+const QUEUE_DEVICE_HIGH: Register<WriteOnly> = Register::new(0x0A4);
+const CONFIG_GENERATION: Register<ReadOnly> = Register::new(0x0FC);
+
+/// The device configuration follows the registers
+const CONFIG: usize = 0x100;
+
+pub struct MmioTransport<B: Bus> {
+    regs: RegisterBlock<B>,
+    device_type: DeviceType,
+    /// Held while a queue is selected
+    select: Mutex<()>
+}
+
+impl<B: Bus> MmioTransport<B> {
+    /// Checks there is a modern device behind `regs`, the virt machines have
+    /// windows without one.
+    pub fn probe(regs: RegisterBlock<B>) -> Result<Self, VirtioError> {
+        if regs.read(MAGIC_VALUE) != MAGIC {
+            return Err(VirtioError::NotVirtio);
+        }
+
+        let version = regs.read(VERSION);
+        if version != VERSION_MODERN {
+            return Err(VirtioError::UnsupportedVersion(version));
+        }
+
+        match regs.read(DEVICE_ID) {
+            0 => Err(VirtioError::NoDevice),
+            id => Ok(Self { regs, device_type: DeviceType::from_id(id), select: Mutex::new(()) })
+        }
+    }
+
+    fn config_reg(offset: usize) -> Register<ReadWrite> {
+        Register::new(CONFIG + (offset & !0x3))
+    }
+}
+
+impl<B: Bus> Transport for MmioTransport<B> {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn status(&self) -> u8 {
+        self.regs.read(STATUS) as u8
+    }
+
+    fn set_status(&self, status: u8) {
+        self.regs.write(STATUS, status as u32);
+    }
+
+    fn device_features(&self) -> u64 {
+        let _select = self.select.lock();
+        self.regs.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.regs.read(DEVICE_FEATURES);
+        self.regs.write(DEVICE_FEATURES_SEL, 1);
+        (self.regs.read(DEVICE_FEATURES) as u64) << 32 | low as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        let _select = self.select.lock();
+        self.regs.write(DRIVER_FEATURES_SEL, 0);
+        self.regs.write(DRIVER_FEATURES, features as u32);
+        self.regs.write(DRIVER_FEATURES_SEL, 1);
+        self.regs.write(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        let _select = self.select.lock();
+        self.regs.write(QUEUE_SEL, queue as u32);
+        self.regs.read(QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    fn queue_ready(&self, queue: u16) -> bool {
+        let _select = self.select.lock();
+        self.regs.write(QUEUE_SEL, queue as u32);
+        self.regs.read(QUEUE_READY) & 1 != 0
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: u64, avail: u64, used: u64) {
+        let _select = self.select.lock();
+        self.regs.write(QUEUE_SEL, queue as u32);
+        self.regs.write(QUEUE_NUM, size as u32);
+        self.regs.write(QUEUE_DESC_LOW, desc as u32);
+        self.regs.write(QUEUE_DESC_HIGH, (desc >> 32) as u32);
+        self.regs.write(QUEUE_DRIVER_LOW, avail as u32);
+        self.regs.write(QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+        self.regs.write(QUEUE_DEVICE_LOW, used as u32);
+        self.regs.write(QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+        self.regs.write(QUEUE_READY, 1);
+    }
+
+    fn notify(&self, queue: u16) {
+        self.regs.write(QUEUE_NOTIFY, queue as u32);
+    }
+
+    fn ack_interrupt(&self) -> u32 {
+        let status = self.regs.read(INTERRUPT_STATUS);
+        self.regs.write(INTERRUPT_ACK, status);
+        status
+    }
+
+    fn config_generation(&self) -> u32 {
+        self.regs.read(CONFIG_GENERATION)
+    }
+
+    /// The configuration is read in words, which every device allows.
+    fn read_config(&self, offset: usize) -> u8 {
+        (self.regs.read(Self::config_reg(offset)) >> (8 * (offset & 0x3))) as u8
+    }
+
+    fn write_config(&self, offset: usize, value: u8) {
+        let shift = 8 * (offset & 0x3);
+        self.regs.modify(Self::config_reg(offset), |v| v & !(0xFF << shift) | (value as u32) << shift);
+    }
+}
+
+/// Calls `f` with the address of every enabled `virtio,mmio` node of the device tree.
+pub fn devices(fdt: &Fdt, f: impl FnMut(u64)) {
+    fdt.compatible_nodes(&["virtio,mmio"], f);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::drivers::bus::mock::MockBus;
+    use crate::drivers::virtio::{negotiate, F_VERSION_1};
+    use crate::fdt::builder::FdtBuilder;
+
+    fn device(id: u32) -> MockBus {
+        let bus = MockBus::new();
+        bus.set(MAGIC_VALUE.index(), MAGIC);
+        bus.set(VERSION.index(), VERSION_MODERN);
+        bus.set(DEVICE_ID.index(), id);
+        bus
+    }
+
+    #[test]
+    fn probe() {
+        let blank = MockBus::new();
+        assert!(matches!(MmioTransport::probe(RegisterBlock::new(&blank, 1)), Err(VirtioError::NotVirtio)));
+        let empty = device(0);
+        assert!(matches!(MmioTransport::probe(RegisterBlock::new(&empty, 1)), Err(VirtioError::NoDevice)));
+        let legacy = device(2);
+        legacy.set(VERSION.index(), 1);
+        assert!(matches!(MmioTransport::probe(RegisterBlock::new(&legacy, 1)), Err(VirtioError::UnsupportedVersion(1))));
+
+        let bus = device(2);
+        let transport = MmioTransport::probe(RegisterBlock::new(&bus, 1)).unwrap();
+        assert_eq!(transport.device_type(), DeviceType::Block);
+
+        bus.set(CONFIG + 4, 0x1234_5678);
+        assert_eq!(transport.read_config(5), 0x56);
+        transport.write_config(6, 0xAB);
+        assert_eq!(bus.get(CONFIG + 4), 0x12AB_5678);
+    }
+
+    #[test]
+    fn queues() {
+        let bus = device(1);
+        let transport = MmioTransport::probe(RegisterBlock::new(&bus, 1)).unwrap();
+
+        // Feature words come in the order they are selected
+        bus.script(DEVICE_FEATURES.index(), [1 << 5, 1]);
+        bus.set(STATUS.index(), 0);
+        assert_eq!(transport.device_features(), F_VERSION_1 | 1 << 5);
+        bus.script(DEVICE_FEATURES.index(), [1 << 5, 1]);
+        bus.clear_log();
+        let _ = negotiate(&transport, 1 << 5);
+        assert_eq!(bus.writes(DRIVER_FEATURES.index()), [1 << 5, 1]);
+        assert_eq!(bus.writes(DRIVER_FEATURES_SEL.index()), [0, 1]);
+
+        bus.set(QUEUE_NUM_MAX.index(), 256);
+        assert_eq!(transport.max_queue_size(1), 256);
+        transport.setup_queue(1, 128, 0x1_2345_6000, 0x8000_1000, 0x8000_2000);
+        assert_eq!(bus.get(QUEUE_SEL.index()), 1);
+        assert_eq!(bus.get(QUEUE_NUM.index()), 128);
+        assert_eq!((bus.get(QUEUE_DESC_LOW.index()), bus.get(QUEUE_DESC_HIGH.index())), (0x2345_6000, 1));
+        assert_eq!((bus.get(QUEUE_DRIVER_LOW.index()), bus.get(QUEUE_DEVICE_LOW.index())), (0x8000_1000, 0x8000_2000));
+        assert!(transport.queue_ready(1));
+
+        bus.set(INTERRUPT_STATUS.index(), 3);
+        assert_eq!(transport.ack_interrupt(), 3);
+        assert_eq!(bus.writes(INTERRUPT_ACK.index()), [3]);
+    }
+
+    /// Builds a device tree with an enabled and a disabled virtio-mmio node and a UART, all with two address cells.
+    fn fdt_blob() -> Vec<u8> {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("").cells("#address-cells", &[2]);
+        for (addr, compatible, status) in [(0x1000_8000, "virtio,mmio", "okay"), (0x1000_7000, "virtio,mmio", "disabled"),
+            (0x1000_0000, "ns16550a", "okay")] {
+            fdt.begin_node("node")
+                .string_property("compatible", compatible)
+                .string_property("status", status)
+                .cells("reg", &[0, addr, 0, 0x200])
+                .end_node();
+        }
+        fdt.end_node().build()
+    }
+
+    #[test]
+    fn device_tree() {
+        let blob = fdt_blob();
+        let mut found = Vec::new();
+        devices(&Fdt::new(&blob).unwrap(), |addr| found.push(addr));
+        assert_eq!(found, [0x1000_8000]);
+    }
+}
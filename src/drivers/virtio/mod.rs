@@ -0,0 +1,321 @@
+//! VirtIO devices, the paravirtualised devices of QEMU, behind a [`Transport`].
+//!
+//! The transport is virtio-mmio on the virt machines without PCI and the
+//! modern virtio-pci one elsewhere; legacy devices aren't supported.
+//! Requests go through split [`queue::VirtQueue`]s, whose rings and buffers
+//! are handed to the device by address, so they have to be identity mapped
+//! and coherent with it, as for DMA.
+//!
+//! A driver brings its device up with [`negotiate`], sets up its queues and
+//! calls [`driver_ok`]. An interrupt handler would call [`ack_interrupt`] and
+//! then take what the device is done with off the queues, but no interrupt of
+//! a VirtIO device is routed yet: virtio-pci devices get no MSI-X vector and
+//! their INTx line isn't unmasked in the PIC, and the virtio-mmio interrupts
+//! aren't enabled in the interrupt controller. Requests are completed by
+//! polling the used ring, as [`queue::VirtQueue::submit`] does.
+
+pub mod mmio;
+pub mod pci;
+pub mod queue;
+#[cfg(test)]
+pub mod fake;
+
+use core::{fmt, hint};
+
+/// The driver has seen the device
+pub const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+/// The driver knows how to drive the device
+pub const STATUS_DRIVER: u8 = 1 << 1;
+pub const STATUS_DRIVER_OK: u8 = 1 << 2;
+pub const STATUS_FEATURES_OK: u8 = 1 << 3;
+/// The device ran into an error it can only recover from with a reset
+pub const STATUS_NEEDS_RESET: u8 = 1 << 6;
+/// The driver gave up on the device
+pub const STATUS_FAILED: u8 = 1 << 7;
+
+/// The device follows VirtIO 1.0 or later, which every non legacy device offers
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// A queue has buffers in its used ring
+pub const INTERRUPT_QUEUE: u32 = 1 << 0;
+/// The device configuration changed
+pub const INTERRUPT_CONFIG: u32 = 1 << 1;
+
+/// Polls of the used ring before [`queue::VirtQueue::submit`] gives up on the device
+const TIMEOUT: usize = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Net,
+    Block,
+    Console,
+    Entropy,
+    Balloon,
+    Scsi,
+    Gpu,
+    Input,
+    Socket,
+    Unknown(u32)
+}
+
+impl DeviceType {
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            1 => Self::Net,
+            2 => Self::Block,
+            3 => Self::Console,
+            4 => Self::Entropy,
+            5 => Self::Balloon,
+            8 => Self::Scsi,
+            16 => Self::Gpu,
+            18 => Self::Input,
+            19 => Self::Socket,
+            id => Self::Unknown(id)
+        }
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Net => f.write_str("network"),
+            Self::Block => f.write_str("block"),
+            Self::Console => f.write_str("console"),
+            Self::Entropy => f.write_str("entropy"),
+            Self::Balloon => f.write_str("memory balloon"),
+            Self::Scsi => f.write_str("SCSI host"),
+            Self::Gpu => f.write_str("GPU"),
+            Self::Input => f.write_str("input"),
+            Self::Socket => f.write_str("socket"),
+            Self::Unknown(id) => write!(f, "unknown ({})", id)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The registers don't belong to a VirtIO device
+    NotVirtio,
+    /// A legacy device, or one newer than the driver
+    UnsupportedVersion(u32),
+    /// The slot exists but has no device behind it
+    NoDevice,
+    /// A PCI device lacks one of the capabilities of the modern interface, or
+    /// one of them is too small for what it describes
+    MissingCapability,
+    /// The device didn't accept the features the driver chose
+    FeaturesRejected,
+    NoSuchQueue,
+    /// The queue was already set up
+    QueueInUse,
+    /// There aren't enough free descriptors for the buffers
+    QueueFull,
+    /// A request needs at least one buffer
+    NoBuffers,
+    /// [`queue::VirtQueue::submit`] needs the queue to itself
+    Busy,
+    /// The device didn't answer, and was reset
+    Timeout
+}
+
+impl fmt::Display for VirtioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotVirtio => f.write_str("not a VirtIO device"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported VirtIO version {}", version),
+            Self::NoDevice => f.write_str("no VirtIO device"),
+            Self::MissingCapability => f.write_str("VirtIO PCI capability missing"),
+            Self::FeaturesRejected => f.write_str("VirtIO features rejected"),
+            Self::NoSuchQueue => f.write_str("no such virtqueue"),
+            Self::QueueInUse => f.write_str("virtqueue already set up"),
+            Self::QueueFull => f.write_str("virtqueue full"),
+            Self::NoBuffers => f.write_str("no buffers"),
+            Self::Busy => f.write_str("virtqueue busy"),
+            Self::Timeout => f.write_str("VirtIO device timed out")
+        }
+    }
+}
+
+/// How the registers of a device are reached.
+pub trait Transport {
+    fn device_type(&self) -> DeviceType;
+
+    fn status(&self) -> u8;
+
+    /// Writing 0 resets the device.
+    fn set_status(&self, status: u8);
+
+    fn device_features(&self) -> u64;
+
+    fn set_driver_features(&self, features: u64);
+
+    /// Largest size of queue `queue`, 0 if the device doesn't have it.
+    fn max_queue_size(&self, queue: u16) -> u16;
+
+    fn queue_ready(&self, queue: u16) -> bool;
+
+    /// Hands queue `queue` with `size` entries to the device, the addresses are
+    /// those of the descriptor table, the available ring and the used ring.
+    fn setup_queue(&self, queue: u16, size: u16, desc: u64, avail: u64, used: u64);
+
+    /// Tells the device there are new buffers in queue `queue`.
+    fn notify(&self, queue: u16);
+
+    /// Acknowledges the interrupt and returns why it was raised, as `INTERRUPT_*` bits.
+    fn ack_interrupt(&self) -> u32;
+
+    /// Changes whenever the device configuration does.
+    fn config_generation(&self) -> u32;
+
+    fn read_config(&self, offset: usize) -> u8;
+
+    fn write_config(&self, offset: usize, value: u8);
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn device_type(&self) -> DeviceType {
+        (**self).device_type()
+    }
+
+    fn status(&self) -> u8 {
+        (**self).status()
+    }
+
+    fn set_status(&self, status: u8) {
+        (**self).set_status(status)
+    }
+
+    fn device_features(&self) -> u64 {
+        (**self).device_features()
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        (**self).set_driver_features(features)
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        (**self).max_queue_size(queue)
+    }
+
+    fn queue_ready(&self, queue: u16) -> bool {
+        (**self).queue_ready(queue)
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: u64, avail: u64, used: u64) {
+        (**self).setup_queue(queue, size, desc, avail, used)
+    }
+
+    fn notify(&self, queue: u16) {
+        (**self).notify(queue)
+    }
+
+    fn ack_interrupt(&self) -> u32 {
+        (**self).ack_interrupt()
+    }
+
+    fn config_generation(&self) -> u32 {
+        (**self).config_generation()
+    }
+
+    fn read_config(&self, offset: usize) -> u8 {
+        (**self).read_config(offset)
+    }
+
+    fn write_config(&self, offset: usize, value: u8) {
+        (**self).write_config(offset, value)
+    }
+}
+
+/// Resets the device and agrees on the features both sides know, of those in
+/// `supported`. [`F_VERSION_1`] is always asked for, as only modern devices are driven.
+pub fn negotiate(transport: &dyn Transport, supported: u64) -> Result<u64, VirtioError> {
+    transport.set_status(0);
+    transport.set_status(STATUS_ACKNOWLEDGE);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let features = transport.device_features() & (supported | F_VERSION_1);
+    if features & F_VERSION_1 == 0 {
+        transport.set_status(STATUS_FAILED);
+        return Err(VirtioError::UnsupportedVersion(0));
+    }
+
+    transport.set_driver_features(features);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+    if transport.status() & STATUS_FEATURES_OK == 0 {
+        transport.set_status(STATUS_FAILED);
+        return Err(VirtioError::FeaturesRejected);
+    }
+
+    Ok(features)
+}
+
+/// Lets the device go once its queues are set up.
+pub fn driver_ok(transport: &dyn Transport) {
+    transport.set_status(transport.status() | STATUS_DRIVER_OK);
+}
+
+/// Gives up on the device after an error.
+pub fn fail(transport: &dyn Transport) {
+    transport.set_status(transport.status() | STATUS_FAILED);
+}
+
+pub fn ack_interrupt(transport: &dyn Transport) -> u32 {
+    transport.ack_interrupt()
+}
+
+/// Reads `buf.len()` bytes of the device configuration from `offset`, again
+/// until the device didn't change it while they were read.
+pub fn read_config(transport: &dyn Transport, offset: usize, buf: &mut [u8]) {
+    loop {
+        let generation = transport.config_generation();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = transport.read_config(offset + i);
+        }
+
+        if transport.config_generation() == generation {
+            return;
+        }
+
+        hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+    use super::fake::FakeDevice;
+
+    #[test]
+    fn negotiation() {
+        let device = FakeDevice::new(DeviceType::Block, F_VERSION_1 | 1 << 5 | 1 << 9);
+        assert_eq!(negotiate(&device, 1 << 9 | 1 << 10), Ok(F_VERSION_1 | 1 << 9));
+        assert_eq!(device.driver_features(), F_VERSION_1 | 1 << 9);
+        assert_eq!(device.status(), STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+
+        driver_ok(&device);
+        assert_eq!(device.status() & STATUS_DRIVER_OK, STATUS_DRIVER_OK);
+
+        let legacy = FakeDevice::new(DeviceType::Net, 1 << 5);
+        assert_eq!(negotiate(&legacy, !0), Err(VirtioError::UnsupportedVersion(0)));
+        assert_eq!(legacy.status(), STATUS_FAILED);
+
+        let picky = FakeDevice::new(DeviceType::Net, F_VERSION_1 | 1 << 5);
+        picky.reject_features(1 << 5);
+        assert_eq!(negotiate(&picky, 1 << 5), Err(VirtioError::FeaturesRejected));
+        assert_eq!(picky.status() & STATUS_FAILED, STATUS_FAILED);
+    }
+
+    #[test]
+    fn config() {
+        let device = FakeDevice::new(DeviceType::Block, F_VERSION_1);
+        device.set_config(&[0x00, 0x20, 0, 0, 0, 0, 0, 0]);
+        let mut capacity = [0; 8];
+        read_config(&device, 0, &mut capacity);
+        assert_eq!(u64::from_le_bytes(capacity), 0x2000);
+        assert_eq!(device.device_type().to_string(), "block");
+    }
+}
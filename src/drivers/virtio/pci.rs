@@ -0,0 +1,385 @@
+//! virtio-pci, the modern interface: vendor specific capabilities point at the
+//! structures of the transport in the memory BARs of the function.
+//!
+//! Transitional devices are driven through their modern interface too. The
+//! BARs have to be mapped at their physical address.
+//!
+//! There is no MSI-X support, the configuration and the queues get no vector,
+//! so drivers poll the used rings instead of waiting for interrupts.
+
+use core::ptr;
+
+use crate::drivers::pci::{Bar, Function, CAP_VENDOR, COMMAND_BUS_MASTER, COMMAND_MEMORY};
+use crate::sync::mutex::Mutex;
+
+use super::{DeviceType, Transport, VirtioError};
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+/// Transitional devices, which tell their type in the subsystem ID
+pub const TRANSITIONAL_DEVICE_IDS: core::ops::RangeInclusive<u16> = 0x1000..=0x103F;
+/// Modern devices, 0x1040 plus the device type
+pub const MODERN_DEVICE_IDS: core::ops::RangeInclusive<u16> = 0x1040..=0x107F;
+const MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+const SUBSYSTEM_ID: u16 = 0x2E;
+
+/// Fields of a `virtio_pci_cap`
+const CAP_CFG_TYPE: u16 = 3;
+const CAP_BAR: u16 = 4;
+const CAP_OFFSET: u16 = 8;
+const CAP_LENGTH: u16 = 12;
+const CAP_NOTIFY_OFF_MULTIPLIER: u16 = 16;
+
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR: u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+/// Fields of the common configuration structure
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const MSIX_CONFIG: usize = 0x10;
+const NUM_QUEUES: usize = 0x12;
+const DEVICE_STATUS: usize = 0x14;
+const CONFIG_GENERATION: usize = 0x15;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+const COMMON_SIZE: u32 = 0x38;
+
+/// MSI-X vector that never interrupts
+const NO_VECTOR: u16 = 0xFFFF;
+
+/// A structure of the transport in memory, whose fields are accessed with their own width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    base: usize,
+    len: usize
+}
+
+impl Region {
+    fn read8(&self, offset: usize) -> u8 {
+        assert!(offset < self.len);
+        unsafe { ptr::read_volatile((self.base + offset) as *const u8) }
+    }
+
+    fn read16(&self, offset: usize) -> u16 {
+        assert!(offset + 2 <= self.len);
+        unsafe { ptr::read_volatile((self.base + offset) as *const u16) }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        assert!(offset + 4 <= self.len);
+        unsafe { ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write8(&self, offset: usize, value: u8) {
+        assert!(offset < self.len);
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u8, value) }
+    }
+
+    fn write16(&self, offset: usize, value: u16) {
+        assert!(offset + 2 <= self.len);
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u16, value) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        assert!(offset + 4 <= self.len);
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// 64 bit fields are written in two halves, which the specification allows.
+    fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+pub struct PciTransport {
+    device_type: DeviceType,
+    common: Region,
+    notify: Region,
+    notify_multiplier: u32,
+    isr: Region,
+    /// Devices without configuration don't have it
+    device: Option<Region>,
+    /// Held while a queue or feature word is selected
+    select: Mutex<()>
+}
+
+impl PciTransport {
+    pub fn is_virtio(function: &Function<'_>) -> bool {
+        function.vendor_id() == VENDOR_ID
+            && (TRANSITIONAL_DEVICE_IDS.contains(&function.device_id()) || MODERN_DEVICE_IDS.contains(&function.device_id()))
+    }
+
+    /// Finds the structures of the modern interface and turns on memory decoding
+    /// and bus mastering. Configuration changes don't interrupt.
+    ///
+    /// # Safety
+    ///
+    /// The memory BARs of `function` have to be mapped at their physical address.
+    pub unsafe fn new(function: &Function<'_>) -> Result<Self, VirtioError> {
+        if !Self::is_virtio(function) {
+            return Err(VirtioError::NotVirtio);
+        }
+
+        let device_id = function.device_id();
+        let id = if MODERN_DEVICE_IDS.contains(&device_id) {
+            device_id - MODERN_DEVICE_ID_BASE
+        } else {
+            function.read16(SUBSYSTEM_ID)
+        };
+
+        // The first capability of a type is the one to use
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for cap in function.capabilities().filter(|c| c.id == CAP_VENDOR) {
+            let found = || region(function, cap.offset);
+            match function.read8(cap.offset + CAP_CFG_TYPE) {
+                CFG_TYPE_COMMON if common.is_none() => common = found(),
+                CFG_TYPE_NOTIFY if notify.is_none() => {
+                    notify = found();
+                    notify_multiplier = function.read32(cap.offset + CAP_NOTIFY_OFF_MULTIPLIER);
+                },
+                CFG_TYPE_ISR if isr.is_none() => isr = found(),
+                CFG_TYPE_DEVICE if device.is_none() => device = found(),
+                _ => ()
+            }
+        }
+
+        let (Some(common), Some(notify), Some(isr)) = (common, notify, isr) else {
+            return Err(VirtioError::MissingCapability);
+        };
+        if common.len < COMMON_SIZE as usize {
+            return Err(VirtioError::MissingCapability);
+        }
+
+        function.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+        // Notifying a queue mustn't write past the notification structure
+        for queue in 0..common.read16(NUM_QUEUES) {
+            common.write16(QUEUE_SELECT, queue);
+            let offset = common.read16(QUEUE_NOTIFY_OFF) as usize * notify_multiplier as usize;
+            if offset + 2 > notify.len {
+                return Err(VirtioError::MissingCapability);
+            }
+        }
+
+        common.write16(MSIX_CONFIG, NO_VECTOR);
+        Ok(Self {
+            device_type: DeviceType::from_id(id as u32),
+            common,
+            notify,
+            notify_multiplier,
+            isr,
+            device,
+            select: Mutex::new(())
+        })
+    }
+}
+
+/// Where the capability at `offset` points, if it is in a memory BAR.
+fn region(function: &Function<'_>, offset: u16) -> Option<Region> {
+    let bar = function.read8(offset + CAP_BAR) as usize;
+    let (start, len) = (function.read32(offset + CAP_OFFSET) as u64, function.read32(offset + CAP_LENGTH) as u64);
+    match function.bar(bar)? {
+        Bar::Memory { addr, size, .. } if start + len <= size => Some(Region { base: (addr + start) as usize, len: len as usize }),
+        _ => None
+    }
+}
+
+impl Transport for PciTransport {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn status(&self) -> u8 {
+        self.common.read8(DEVICE_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.common.write8(DEVICE_STATUS, status);
+    }
+
+    fn device_features(&self) -> u64 {
+        let _select = self.select.lock();
+        self.common.write32(DEVICE_FEATURE_SELECT, 0);
+        let low = self.common.read32(DEVICE_FEATURE);
+        self.common.write32(DEVICE_FEATURE_SELECT, 1);
+        (self.common.read32(DEVICE_FEATURE) as u64) << 32 | low as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        let _select = self.select.lock();
+        self.common.write32(DRIVER_FEATURE_SELECT, 0);
+        self.common.write32(DRIVER_FEATURE, features as u32);
+        self.common.write32(DRIVER_FEATURE_SELECT, 1);
+        self.common.write32(DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        let _select = self.select.lock();
+        self.common.write16(QUEUE_SELECT, queue);
+        self.common.read16(QUEUE_SIZE)
+    }
+
+    fn queue_ready(&self, queue: u16) -> bool {
+        let _select = self.select.lock();
+        self.common.write16(QUEUE_SELECT, queue);
+        self.common.read16(QUEUE_ENABLE) != 0
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: u64, avail: u64, used: u64) {
+        let _select = self.select.lock();
+        self.common.write16(QUEUE_SELECT, queue);
+        self.common.write16(QUEUE_SIZE, size);
+        self.common.write64(QUEUE_DESC, desc);
+        self.common.write64(QUEUE_DRIVER, avail);
+        self.common.write64(QUEUE_DEVICE, used);
+        self.common.write16(QUEUE_MSIX_VECTOR, NO_VECTOR);
+        self.common.write16(QUEUE_ENABLE, 1);
+    }
+
+    fn notify(&self, queue: u16) {
+        let offset = {
+            let _select = self.select.lock();
+            self.common.write16(QUEUE_SELECT, queue);
+            self.common.read16(QUEUE_NOTIFY_OFF) as usize * self.notify_multiplier as usize
+        };
+        self.notify.write16(offset, queue);
+    }
+
+    /// Reading the ISR status acknowledges the interrupt.
+    fn ack_interrupt(&self) -> u32 {
+        self.isr.read8(0) as u32
+    }
+
+    fn config_generation(&self) -> u32 {
+        self.common.read8(CONFIG_GENERATION) as u32
+    }
+
+    fn read_config(&self, offset: usize) -> u8 {
+        self.device.map_or(0, |device| device.read8(offset))
+    }
+
+    fn write_config(&self, offset: usize, value: u8) {
+        if let Some(device) = self.device {
+            device.write8(offset, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use super::*;
+    use crate::drivers::pci::Address;
+    use crate::drivers::pci::mock::MockConfig;
+    use crate::drivers::virtio::{INTERRUPT_QUEUE, STATUS_ACKNOWLEDGE};
+
+    const BAR_SIZE: usize = 0x4000;
+
+    /// BAR 4 of the device, where the structures are, aligned to its size like a real BAR.
+    #[repr(C, align(16384))]
+    struct Bar4([u8; BAR_SIZE]);
+
+    const ADDR: Address = Address::new(0, 3, 0);
+
+    /// A modern network device with its structures at 0, 0x1000, 0x2000 and 0x3000 of a 64 bit BAR 4.
+    fn device(bar: *mut Bar4) -> MockConfig {
+        let config = MockConfig::new();
+        config.add_function(ADDR, VENDOR_ID, 0x1041, 0x02_00_00_01, 0x00);
+        let addr = bar as u64;
+        config.add_bar(ADDR, 4, addr as u32 | 0xC, BAR_SIZE as u32 - 1);
+        config.add_bar(ADDR, 5, (addr >> 32) as u32, 0);
+
+        // Status says there is a capability list
+        config.set(ADDR, 0x04, 1 << 20);
+        config.set(ADDR, 0x34, 0x40);
+        for (i, cfg_type) in [CFG_TYPE_COMMON, CFG_TYPE_NOTIFY, CFG_TYPE_ISR, CFG_TYPE_DEVICE].into_iter().enumerate() {
+            let offset = 0x40 + 0x14 * i as u16;
+            let next = if i < 3 { offset + 0x14 } else { 0 };
+            config.set(ADDR, offset, (cfg_type as u32) << 24 | 0x14 << 16 | (next as u32) << 8 | CAP_VENDOR as u32);
+            config.set(ADDR, offset + CAP_BAR, 4);
+            config.set(ADDR, offset + CAP_OFFSET, 0x1000 * i as u32);
+            config.set(ADDR, offset + CAP_LENGTH, 0x1000);
+            config.set(ADDR, offset + CAP_NOTIFY_OFF_MULTIPLIER, 4);
+        }
+        config
+    }
+
+    fn peek(bar: *mut Bar4, offset: usize, len: usize) -> u64 {
+        (0..len).rev().fold(0, |v, i| v << 8 | unsafe { (*bar).0[offset + i] } as u64)
+    }
+
+    fn poke(bar: *mut Bar4, offset: usize, value: u8) {
+        unsafe { (*bar).0[offset] = value };
+    }
+
+    #[test]
+    fn transport() {
+        let bar = Box::into_raw(Box::new(Bar4([0; BAR_SIZE])));
+        let config = device(bar);
+        let function = Function::new(&config, ADDR);
+        assert!(PciTransport::is_virtio(&function));
+        let transport = unsafe { PciTransport::new(&function) }.unwrap();
+        assert_eq!(transport.device_type(), DeviceType::Net);
+        assert_eq!(function.command(), COMMAND_MEMORY | COMMAND_BUS_MASTER);
+        assert_eq!(peek(bar, MSIX_CONFIG, 2), NO_VECTOR as u64);
+
+        transport.set_status(STATUS_ACKNOWLEDGE);
+        assert_eq!(peek(bar, DEVICE_STATUS, 1), STATUS_ACKNOWLEDGE as u64);
+        transport.setup_queue(2, 64, 0x1_0000_1000, 0x2000, 0x3000);
+        assert_eq!(peek(bar, QUEUE_SELECT, 2), 2);
+        assert_eq!(peek(bar, QUEUE_SIZE, 2), 64);
+        assert_eq!(peek(bar, QUEUE_DESC, 8), 0x1_0000_1000);
+        assert_eq!(peek(bar, QUEUE_DRIVER, 8), 0x2000);
+        assert_eq!(peek(bar, QUEUE_DEVICE, 8), 0x3000);
+        assert_eq!(peek(bar, QUEUE_MSIX_VECTOR, 2), NO_VECTOR as u64);
+        assert!(transport.queue_ready(2));
+
+        // Queue 2 is notified at its offset times the multiplier
+        poke(bar, QUEUE_NOTIFY_OFF, 3);
+        transport.notify(2);
+        assert_eq!(peek(bar, 0x1000 + 12, 2), 2);
+
+        poke(bar, 0x2000, INTERRUPT_QUEUE as u8);
+        assert_eq!(transport.ack_interrupt(), INTERRUPT_QUEUE);
+        poke(bar, 0x3000 + 6, 0x52);
+        assert_eq!(transport.read_config(6), 0x52);
+
+        drop(unsafe { Box::from_raw(bar) });
+    }
+
+    #[test]
+    fn missing_capability() {
+        let bar = Box::into_raw(Box::new(Bar4([0; BAR_SIZE])));
+        let config = device(bar);
+        // The ISR capability goes past the end of the BAR
+        config.set(ADDR, 0x40 + 2 * 0x14 + CAP_OFFSET, 0x3800);
+        let function = Function::new(&config, ADDR);
+        assert_eq!(unsafe { PciTransport::new(&function) }.err(), Some(VirtioError::MissingCapability));
+
+        // A queue is notified past the end of the notification structure
+        config.set(ADDR, 0x40 + 2 * 0x14 + CAP_OFFSET, 0x2000);
+        poke(bar, NUM_QUEUES, 1);
+        poke(bar, QUEUE_NOTIFY_OFF + 1, 0x04);
+        assert_eq!(unsafe { PciTransport::new(&function) }.err(), Some(VirtioError::MissingCapability));
+        poke(bar, QUEUE_NOTIFY_OFF + 1, 0x03);
+        assert!(unsafe { PciTransport::new(&function) }.is_ok());
+
+        config.set(ADDR, 0x00, 0x1000_8086);
+        assert_eq!(unsafe { PciTransport::new(&function) }.err(), Some(VirtioError::NotVirtio));
+        drop(unsafe { Box::from_raw(bar) });
+    }
+}
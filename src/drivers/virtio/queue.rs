@@ -0,0 +1,414 @@
+//! Split virtqueues: a descriptor table, a ring of requests for the device
+//! and a ring of the ones it is done with.
+//!
+//! The rings live in the queue, so it has to stay where it is once it is set
+//! up, which a static does. A request is a chain of descriptors, the buffers
+//! the device reads followed by those it writes, and is known by the index of
+//! its first descriptor.
+
+use core::cell::UnsafeCell;
+use core::hint;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{self, Ordering};
+
+use crate::sync::mutex::Mutex;
+
+use super::{Transport, VirtioError, TIMEOUT};
+
+/// Largest queue the split layout allows
+pub const MAX_QUEUE_SIZE: usize = 32768;
+
+/// The chain goes on at `next`
+const DESC_F_NEXT: u16 = 1 << 0;
+/// The device writes the buffer
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// Set by the driver when it doesn't want interrupts
+const AVAIL_F_NO_INTERRUPT: u16 = 1 << 0;
+/// Set by the device when it doesn't need to be notified
+const USED_F_NO_NOTIFY: u16 = 1 << 0;
+
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16
+}
+
+#[repr(C, align(2))]
+struct AvailRing<const N: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [u16; N],
+    used_event: u16
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32
+}
+
+#[repr(C, align(4))]
+struct UsedRing<const N: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; N],
+    avail_event: u16
+}
+
+/// What the device reads and writes, each part aligned as the specification asks.
+#[repr(C)]
+struct Rings<const N: usize> {
+    desc: [Descriptor; N],
+    avail: AvailRing<N>,
+    used: UsedRing<N>
+}
+
+/// Bookkeeping of the driver side.
+struct State {
+    /// Index of the queue on the device, `None` until it is set up
+    queue: Option<u16>,
+    /// Entries in use, at most `N`
+    size: u16,
+    free_head: u16,
+    num_free: u16,
+    /// Next index of the available ring, wraps around at 65536
+    avail_idx: u16,
+    last_used: u16
+}
+
+impl State {
+    const UNUSED: Self = Self { queue: None, size: 0, free_head: 0, num_free: 0, avail_idx: 0, last_used: 0 };
+}
+
+pub struct VirtQueue<const N: usize> {
+    rings: UnsafeCell<Rings<N>>,
+    state: Mutex<State>
+}
+
+/// The rings are only written with the state locked, or by the device.
+unsafe impl<const N: usize> Sync for VirtQueue<N> {}
+
+impl<const N: usize> VirtQueue<N> {
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two() && N <= MAX_QUEUE_SIZE, "queue size has to be a power of two up to 32768");
+        Self {
+            rings: UnsafeCell::new(Rings {
+                desc: [Descriptor { addr: 0, len: 0, flags: 0, next: 0 }; N],
+                avail: AvailRing { flags: 0, idx: 0, ring: [0; N], used_event: 0 },
+                used: UsedRing { flags: 0, idx: 0, ring: [UsedElem { id: 0, len: 0 }; N], avail_event: 0 }
+            }),
+            state: Mutex::new(State::UNUSED)
+        }
+    }
+
+    /// Hands the queue to the device as queue `queue`, with as many entries as
+    /// both allow. Has to be done between [`super::negotiate`] and [`super::driver_ok`].
+    pub fn setup(&self, transport: &dyn Transport, queue: u16) -> Result<(), VirtioError> {
+        let mut state = self.state.lock();
+        if state.queue.is_some() || transport.queue_ready(queue) {
+            return Err(VirtioError::QueueInUse);
+        }
+
+        let max = transport.max_queue_size(queue);
+        if max == 0 {
+            return Err(VirtioError::NoSuchQueue);
+        }
+
+        // Split queues are a power of two long
+        let size = (N as u16).min(max);
+        let size = 1u16 << (u16::BITS - 1 - size.leading_zeros());
+
+        let rings = self.rings.get();
+        unsafe {
+            for i in 0..size {
+                (*rings).desc[i as usize] = Descriptor { addr: 0, len: 0, flags: 0, next: (i + 1) % size };
+            }
+            (*rings).avail.flags = 0;
+            (*rings).avail.idx = 0;
+            (*rings).used.flags = 0;
+            (*rings).used.idx = 0;
+        }
+
+        *state = State { queue: Some(queue), size, free_head: 0, num_free: size, avail_idx: 0, last_used: 0 };
+        let (desc, avail, used) = unsafe {
+            (addr_of!((*rings).desc) as u64, addr_of!((*rings).avail) as u64, addr_of!((*rings).used) as u64)
+        };
+        transport.setup_queue(queue, size, desc, avail, used);
+        Ok(())
+    }
+
+    /// Forgets the setup and the requests in flight once the device was reset,
+    /// so the queue can be set up again. Tokens of earlier requests are void.
+    pub fn reset(&self) {
+        *self.state.lock() = State::UNUSED;
+    }
+
+    /// Entries of the queue, 0 before it is set up.
+    pub fn size(&self) -> u16 {
+        self.state.lock().size
+    }
+
+    pub fn num_free(&self) -> u16 {
+        self.state.lock().num_free
+    }
+
+    /// Puts a request in the available ring, `outputs` for the device to read
+    /// and `inputs` for it to write, and returns its token. The device only
+    /// sees it after [`Self::notify`].
+    ///
+    /// # Safety
+    ///
+    /// The buffers have to stay where they are, and `inputs` unread, until
+    /// [`Self::pop_used`] returns the token or the device is reset.
+    pub unsafe fn add(&self, outputs: &[&[u8]], inputs: &mut [&mut [u8]]) -> Result<u16, VirtioError> {
+        let mut state = self.state.lock();
+        let count = outputs.len() + inputs.len();
+        if state.queue.is_none() {
+            return Err(VirtioError::NoSuchQueue);
+        } else if count == 0 {
+            return Err(VirtioError::NoBuffers);
+        } else if count > state.num_free as usize {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let rings = self.rings.get();
+        let buffers = outputs.iter().map(|b| (b.as_ptr(), b.len(), 0))
+            .chain(inputs.iter_mut().map(|b| (b.as_mut_ptr() as *const u8, b.len(), DESC_F_WRITE)));
+        let head = state.free_head;
+        for (i, (addr, len, flags)) in buffers.enumerate() {
+            // The free list already links the descriptors, which the chain keeps
+            let desc = &mut (*rings).desc[state.free_head as usize];
+            desc.addr = addr as u64;
+            desc.len = len as u32;
+            desc.flags = flags | if i + 1 < count { DESC_F_NEXT } else { 0 };
+            state.free_head = desc.next;
+        }
+
+        state.num_free -= count as u16;
+
+        let slot = (state.avail_idx % state.size) as usize;
+        addr_of_mut!((*rings).avail.ring[slot]).write_volatile(head);
+
+        // The device mustn't see the index before the ring entry
+        atomic::fence(Ordering::SeqCst);
+        state.avail_idx = state.avail_idx.wrapping_add(1);
+        addr_of_mut!((*rings).avail.idx).write_volatile(state.avail_idx);
+        Ok(head)
+    }
+
+    /// Tells the device about new requests, unless it said it doesn't need to be.
+    pub fn notify(&self, transport: &dyn Transport) {
+        let Some(queue) = self.state.lock().queue else {
+            return;
+        };
+
+        atomic::fence(Ordering::SeqCst);
+        let flags = unsafe { addr_of!((*self.rings.get()).used.flags).read_volatile() };
+        if flags & USED_F_NO_NOTIFY == 0 {
+            transport.notify(queue);
+        }
+    }
+
+    /// Asks the device not to interrupt when it is done with requests, which it may ignore.
+    pub fn set_interrupts(&self, enabled: bool) {
+        let _state = self.state.lock();
+        let flags = if enabled { 0 } else { AVAIL_F_NO_INTERRUPT };
+        unsafe { addr_of_mut!((*self.rings.get()).avail.flags).write_volatile(flags) };
+    }
+
+    pub fn can_pop(&self) -> bool {
+        let state = self.state.lock();
+        state.queue.is_some() && self.used_idx() != state.last_used
+    }
+
+    /// Takes the next request the device is done with off the used ring, and
+    /// returns its token and the number of bytes the device wrote.
+    pub fn pop_used(&self) -> Option<(u16, u32)> {
+        let mut state = self.state.lock();
+        if state.queue.is_none() || self.used_idx() == state.last_used {
+            return None;
+        }
+
+        // The entry is only valid once the index says so
+        atomic::fence(Ordering::SeqCst);
+        let rings = self.rings.get();
+        let slot = (state.last_used % state.size) as usize;
+        let elem = unsafe { addr_of!((*rings).used.ring[slot]).read_volatile() };
+        state.last_used = state.last_used.wrapping_add(1);
+
+        // Puts the chain back at the head of the free list
+        let head = elem.id as u16;
+        let mut last = head;
+        let mut count = 1;
+        unsafe {
+            while (*rings).desc[last as usize].flags & DESC_F_NEXT != 0 {
+                last = (*rings).desc[last as usize].next;
+                count += 1;
+            }
+            (*rings).desc[last as usize].next = state.free_head;
+        }
+
+        state.free_head = head;
+        state.num_free += count;
+        Some((head, elem.len))
+    }
+
+    /// Runs one request and waits for the device to be done with it, the queue
+    /// mustn't have others. Returns how many bytes the device wrote to `inputs`.
+    pub fn submit(&self, transport: &dyn Transport, outputs: &[&[u8]], inputs: &mut [&mut [u8]]) -> Result<u32, VirtioError> {
+        {
+            let state = self.state.lock();
+            if state.num_free != state.size {
+                return Err(VirtioError::Busy);
+            }
+        }
+
+        let token = unsafe { self.add(outputs, inputs)? };
+        self.notify(transport);
+        for _ in 0..TIMEOUT {
+            if let Some((done, len)) = self.pop_used() {
+                debug_assert_eq!(done, token);
+                return Ok(len);
+            }
+
+            hint::spin_loop();
+        }
+
+        // Only a reset makes sure the device leaves the buffers alone
+        transport.set_status(0);
+        self.reset();
+        Err(VirtioError::Timeout)
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { addr_of!((*self.rings.get()).used.idx).read_volatile() }
+    }
+}
+
+impl<const N: usize> Default for VirtQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use super::super::DeviceType;
+    use super::super::fake::FakeDevice;
+
+    #[test]
+    fn setup() {
+        let device = FakeDevice::new(DeviceType::Block, 0);
+        device.add_queue(0, 4);
+        let queue = VirtQueue::<8>::new();
+        assert_eq!(queue.setup(&device, 1), Err(VirtioError::NoSuchQueue));
+        queue.setup(&device, 0).unwrap();
+        assert_eq!(queue.size(), 4);
+        assert_eq!(queue.num_free(), 4);
+        assert_eq!(queue.setup(&device, 0), Err(VirtioError::QueueInUse));
+
+        let (size, desc, avail, used) = device.queue(0).unwrap();
+        assert_eq!(size, 4);
+        assert!(desc.is_multiple_of(16) && avail.is_multiple_of(2) && used.is_multiple_of(4));
+        assert_eq!(avail, desc + 16 * 8);
+    }
+
+    #[test]
+    fn requests() {
+        let device = FakeDevice::new(DeviceType::Block, 0);
+        device.add_queue(0, 4);
+        let queue = VirtQueue::<4>::new();
+        queue.setup(&device, 0).unwrap();
+
+        let request = [1, 2, 3];
+        let mut response = [0; 4];
+        let mut status = [0xFF];
+        let token = unsafe { queue.add(&[&request], &mut [&mut response, &mut status]).unwrap() };
+        assert_eq!(queue.num_free(), 1);
+        assert!(unsafe { queue.add(&[&request, &request], &mut []) } == Err(VirtioError::QueueFull));
+        assert!(!queue.can_pop());
+
+        // The device sees the chain once notified, and answers with the request reversed
+        queue.notify(&device);
+        assert_eq!(device.notified(), [0]);
+        let chains = device.process(0, |readable, writable| {
+            let data: Vec<u8> = readable.iter().rev().copied().collect();
+            writable[..data.len()].copy_from_slice(&data);
+            writable[writable.len() - 1] = 0;
+            writable.len()
+        });
+        assert_eq!(chains, 1);
+        assert_eq!(queue.pop_used(), Some((token, 5)));
+        assert_eq!(queue.pop_used(), None);
+        assert_eq!(response, [3, 2, 1, 0]);
+        assert_eq!(status, [0]);
+        assert_eq!(queue.num_free(), 4);
+
+        // The rings wrap around
+        device.respond_with(|readable, writable| {
+            writable[0] = readable[0];
+            1
+        });
+        for i in 0..10u8 {
+            let mut out = [0];
+            assert_eq!(queue.submit(&device, &[&[i]], &mut [&mut out]), Ok(1));
+            assert_eq!(out, [i]);
+        }
+
+        assert_eq!(unsafe { queue.add(&[], &mut []) }, Err(VirtioError::NoBuffers));
+        unsafe { queue.add(&[&request], &mut []).unwrap() };
+        assert_eq!(queue.submit(&device, &[&request], &mut []), Err(VirtioError::Busy));
+    }
+
+    #[test]
+    fn reset() {
+        let device = FakeDevice::new(DeviceType::Block, 0);
+        device.add_queue(0, 4);
+        let queue = VirtQueue::<4>::new();
+        queue.setup(&device, 0).unwrap();
+        unsafe { queue.add(&[&[1, 2, 3]], &mut []).unwrap() };
+
+        device.set_status(0);
+        queue.reset();
+        assert_eq!(queue.size(), 0);
+        assert_eq!(unsafe { queue.add(&[&[1]], &mut []) }, Err(VirtioError::NoSuchQueue));
+
+        queue.setup(&device, 0).unwrap();
+        assert_eq!(queue.num_free(), 4);
+        device.respond_with(|readable, writable| {
+            writable[0] = readable[0];
+            1
+        });
+        let mut out = [0];
+        assert_eq!(queue.submit(&device, &[&[7]], &mut [&mut out]), Ok(1));
+        assert_eq!(out, [7]);
+    }
+
+    #[test]
+    fn suppression() {
+        let device = FakeDevice::new(DeviceType::Net, 0);
+        device.add_queue(1, 8);
+        let queue = VirtQueue::<8>::new();
+        queue.setup(&device, 1).unwrap();
+
+        device.set_no_notify(1, true);
+        unsafe { queue.add(&[&[0]], &mut []).unwrap() };
+        queue.notify(&device);
+        assert!(device.notified().is_empty());
+
+        queue.set_interrupts(false);
+        assert!(!device.wants_interrupts(1));
+        queue.set_interrupts(true);
+        assert!(device.wants_interrupts(1));
+    }
+}
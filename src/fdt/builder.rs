@@ -0,0 +1,114 @@
+//! Device tree blobs for host tests, built node by node.
+
+extern crate std;
+
+use std::vec::Vec;
+
+use super::{FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_MAGIC, FDT_NOP, FDT_PROP, HEADER_SIZE};
+
+const VERSION: u32 = 17;
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+
+#[derive(Default)]
+pub struct FdtBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>
+}
+
+impl FdtBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_node(&mut self, name: &str) -> &mut Self {
+        self.push(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self
+    }
+
+    pub fn end_node(&mut self) -> &mut Self {
+        self.push(FDT_END_NODE);
+        self
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.push(FDT_NOP);
+        self
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        let offset = self.string(name);
+        self.push(FDT_PROP);
+        self.push(value.len() as u32);
+        self.push(offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+        self
+    }
+
+    /// A property made of big endian cells.
+    pub fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value)
+    }
+
+    /// A property holding a string, which gets its terminating null.
+    pub fn string_property(&mut self, name: &str, value: &str) -> &mut Self {
+        let mut bytes = Vec::from(value.as_bytes());
+        bytes.push(0);
+        self.property(name, &bytes)
+    }
+
+    /// The blob with everything added so far, which has to close all nodes.
+    pub fn build(&self) -> Vec<u8> {
+        let mut structure = self.structure.clone();
+        structure.extend_from_slice(&FDT_END.to_be_bytes());
+
+        let strings_offset = HEADER_SIZE + structure.len();
+        let header = [
+            FDT_MAGIC,
+            (strings_offset + self.strings.len()) as u32,
+            HEADER_SIZE as u32,
+            strings_offset as u32,
+            HEADER_SIZE as u32,
+            VERSION,
+            LAST_COMPATIBLE_VERSION,
+            0,
+            self.strings.len() as u32,
+            structure.len() as u32
+        ];
+
+        let mut blob: Vec<u8> = header.iter().flat_map(|word| word.to_be_bytes()).collect();
+        blob.extend_from_slice(&structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+
+    /// Offset of `name` in the strings block, which only gets each name once.
+    fn string(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for existing in self.strings.split(|&b| b == 0) {
+            if existing == name.as_bytes() && offset < self.strings.len() {
+                return offset as u32;
+            }
+
+            offset += existing.len() + 1;
+        }
+
+        let offset = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    fn push(&mut self, word: u32) {
+        self.structure.extend_from_slice(&word.to_be_bytes());
+    }
+
+    /// Tokens start on 4 byte boundaries.
+    fn pad(&mut self) {
+        self.structure.resize(self.structure.len().next_multiple_of(4), 0);
+    }
+}
@@ -0,0 +1,345 @@
+//! Flattened device tree parsing, just enough to walk the tree.
+
+#[cfg(test)]
+pub mod builder;
+
+use core::slice;
+use core::str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const FDT_MAGIC: u32 = 0xD00DFEED;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Deepest node [`Fdt::compatible_nodes`] looks into
+const MAX_DEPTH: usize = 8;
+
+/// Address of the device tree handed over by the firmware, 0 if there is none
+static BLOB: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    /// The blob is smaller than its header says
+    Truncated,
+    BadStructure
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// Start of a node, the root node has an empty name
+    BeginNode(&'a str),
+    EndNode,
+    Property {
+        name: &'a str,
+        value: &'a [u8]
+    }
+}
+
+pub struct Fdt<'a> {
+    blob: &'a [u8]
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(blob: &'a [u8]) -> Result<Self, FdtError> {
+        if blob.len() < HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+
+        let mut fdt = Self { blob };
+        if fdt.header(0) != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+
+        if fdt.total_size() > blob.len() {
+            return Err(FdtError::Truncated);
+        }
+
+        fdt.blob = &blob[..fdt.total_size()];
+        let (struct_offset, struct_size) = (fdt.header(2) as usize, fdt.header(9) as usize);
+        let (strings_offset, strings_size) = (fdt.header(3) as usize, fdt.header(8) as usize);
+        if struct_offset + struct_size > fdt.blob.len() || strings_offset + strings_size > fdt.blob.len() {
+            return Err(FdtError::Truncated);
+        }
+
+        Ok(fdt)
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to a device tree that stays valid and unmodified.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Fdt<'static>, FdtError> {
+        let header = slice::from_raw_parts(ptr, 8);
+        if be32(header, 0) != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+
+        Fdt::new(slice::from_raw_parts(ptr, be32(header, 4) as usize))
+    }
+
+    fn header(&self, field: usize) -> u32 {
+        be32(self.blob, field * 4)
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.header(1) as usize
+    }
+
+    pub fn version(&self) -> u32 {
+        self.header(5)
+    }
+
+    /// Physical id of the CPU that booted
+    pub fn boot_cpu(&self) -> u32 {
+        self.header(7)
+    }
+
+    /// Calls `f` with the address of every enabled node compatible with one of `compatible`,
+    /// the first number of its `reg` property.
+    pub fn compatible_nodes(&self, compatible: &[&str], mut f: impl FnMut(u64)) {
+        // #address-cells of the nodes on the path to the current one
+        let mut address_cells = [2; MAX_DEPTH];
+        let mut depth = 0;
+        let (mut matches, mut disabled, mut addr) = (false, false, None);
+        for token in self.tokens() {
+            let Ok(token) = token else {
+                return;
+            };
+
+            match token {
+                Token::BeginNode(_) => {
+                    depth += 1;
+                    if depth >= MAX_DEPTH {
+                        return;
+                    }
+
+                    address_cells[depth] = 2;
+                    (matches, disabled, addr) = (false, false, None);
+                },
+                Token::EndNode => {
+                    if let Some(addr) = addr.filter(|_| matches && !disabled) {
+                        f(addr);
+                    }
+
+                    (matches, disabled, addr) = (false, false, None);
+                    depth = depth.saturating_sub(1);
+                },
+                Token::Property { name, value } => {
+                    match name {
+                        "#address-cells" => address_cells[depth] = cell(value).unwrap_or(2),
+                        "compatible" => matches = value.split(|&b| b == 0).any(|c| compatible.iter().any(|m| m.as_bytes() == c)),
+                        "status" => disabled = !matches!(value, b"okay\0" | b"ok\0"),
+                        "reg" => addr = cells(value, address_cells[depth.saturating_sub(1)] as usize),
+                        _ => ()
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tokens(&self) -> Tokens<'a> {
+        let struct_offset = self.header(2) as usize;
+        let strings_offset = self.header(3) as usize;
+        Tokens {
+            structure: &self.blob[struct_offset..struct_offset + self.header(9) as usize],
+            strings: &self.blob[strings_offset..strings_offset + self.header(8) as usize],
+            pos: 0,
+            done: false
+        }
+    }
+}
+
+/// Iterator over the structure block of a device tree.
+pub struct Tokens<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+    pos: usize,
+    done: bool
+}
+
+impl<'a> Tokens<'a> {
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, FdtError> {
+        loop {
+            if self.pos + 4 > self.structure.len() {
+                return Err(FdtError::BadStructure);
+            }
+
+            let token = be32(self.structure, self.pos);
+            self.pos += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(&self.structure[self.pos..])?;
+                    self.pos = align4(self.pos + name.len() + 1);
+                    return Ok(Some(Token::BeginNode(name)));
+                },
+                FDT_END_NODE => return Ok(Some(Token::EndNode)),
+                FDT_PROP => {
+                    if self.pos + 8 > self.structure.len() {
+                        return Err(FdtError::BadStructure);
+                    }
+
+                    let len = be32(self.structure, self.pos) as usize;
+                    let name_offset = be32(self.structure, self.pos + 4) as usize;
+                    let start = self.pos + 8;
+                    let value = self.structure.get(start..start + len).ok_or(FdtError::BadStructure)?;
+                    let name = c_str(self.strings.get(name_offset..).ok_or(FdtError::BadStructure)?)?;
+                    self.pos = align4(start + len);
+                    return Ok(Some(Token::Property { name, value }));
+                },
+                FDT_NOP => continue,
+                FDT_END => return Ok(None),
+                _ => return Err(FdtError::BadStructure)
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token<'a>, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let token = self.next_token();
+        if !matches!(token, Ok(Some(_))) {
+            self.done = true;
+        }
+
+        token.transpose()
+    }
+}
+
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn cell(value: &[u8]) -> Option<u32> {
+    Some(be32(value.get(..4)?, 0))
+}
+
+/// Reads the first number of a property, `count` cells long.
+fn cells(value: &[u8], count: usize) -> Option<u64> {
+    if count == 0 || count > 2 {
+        return None;
+    }
+
+    value.get(..count * 4)?.chunks_exact(4).try_fold(0u64, |v, c| Some(v << 32 | cell(c)? as u64))
+}
+
+const fn align4(v: usize) -> usize {
+    (v + 3) & !3
+}
+
+/// Returns the NUL terminated string at the start of `bytes`.
+fn c_str(bytes: &[u8]) -> Result<&str, FdtError> {
+    let len = bytes.iter().position(|&b| b == 0).ok_or(FdtError::BadStructure)?;
+    str::from_utf8(&bytes[..len]).map_err(|_| FdtError::BadStructure)
+}
+
+/// Remembers where the firmware put the device tree.
+pub fn set_blob(addr: usize) {
+    BLOB.store(addr, Ordering::Relaxed);
+}
+
+/// Returns the device tree handed over by the firmware, if any.
+pub fn blob() -> Option<Result<Fdt<'static>, FdtError>> {
+    match BLOB.load(Ordering::Relaxed) {
+        0 => None,
+        addr => Some(unsafe { Fdt::from_ptr(addr as *const u8) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use super::builder::FdtBuilder;
+
+    /// Builds a blob with a root node holding `model` and a `cpus` child node.
+    fn build() -> Vec<u8> {
+        FdtBuilder::new()
+            .begin_node("")
+            .string_property("model", "noe")
+            .nop()
+            .begin_node("cpus")
+            .cells("#size-cells", &[0])
+            .end_node()
+            .end_node()
+            .build()
+    }
+
+    #[test]
+    fn walk() {
+        let blob = build();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.version(), 17);
+        let tokens: Vec<_> = fdt.tokens().map(Result::unwrap).collect();
+        assert_eq!(tokens, [
+            Token::BeginNode(""),
+            Token::Property { name: "model", value: b"noe\0" },
+            Token::BeginNode("cpus"),
+            Token::Property { name: "#size-cells", value: &[0, 0, 0, 0] },
+            Token::EndNode,
+            Token::EndNode
+        ]);
+    }
+
+    #[test]
+    fn compatible_nodes() {
+        let blob = FdtBuilder::new()
+            .begin_node("")
+            .cells("#address-cells", &[1])
+            .begin_node("clint@2000000")
+            .property("compatible", b"sifive,clint0\0riscv,clint0\0")
+            .cells("reg", &[0x0200_0000, 0x10000])
+            .end_node()
+            .begin_node("soc")
+            .cells("#address-cells", &[2])
+            .begin_node("clint@14000000")
+            .string_property("compatible", "thead,c900-clint")
+            .cells("reg", &[0, 0x1400_0000, 0, 0x10000])
+            .end_node()
+            .begin_node("clint@15000000")
+            .string_property("compatible", "riscv,clint0")
+            .string_property("status", "disabled")
+            .cells("reg", &[0, 0x1500_0000, 0, 0x10000])
+            .end_node()
+            .end_node()
+            .end_node()
+            .build();
+
+        let mut found = Vec::new();
+        Fdt::new(&blob).unwrap().compatible_nodes(&["riscv,clint0", "thead,c900-clint"], |addr| found.push(addr));
+        assert_eq!(found, [0x0200_0000, 0x1400_0000]);
+    }
+
+    #[test]
+    fn bad_blobs() {
+        let mut blob = build();
+        assert_eq!(Fdt::new(&blob[..HEADER_SIZE - 1]).err(), Some(FdtError::Truncated));
+        assert_eq!(Fdt::new(&blob[..blob.len() - 1]).err(), Some(FdtError::Truncated));
+        blob[0] = 0;
+        assert_eq!(Fdt::new(&blob).err(), Some(FdtError::BadMagic));
+    }
+
+    #[test]
+    fn bad_structure() {
+        let mut blob = build();
+        // Turn the first token into garbage
+        blob[HEADER_SIZE + 3] = 0x42;
+        let fdt = Fdt::new(&blob).unwrap();
+        let mut tokens = fdt.tokens();
+        assert_eq!(tokens.next(), Some(Err(FdtError::BadStructure)));
+        assert_eq!(tokens.next(), None);
+    }
+}
@@ -0,0 +1,82 @@
+//! Interrupt statistics, shown by the `irqstat` shell command.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::smp::{PerCpu, MAX_CPUS};
+use crate::sync::mutex::Mutex;
+
+/// Maximum number of interrupt sources that can be registered.
+pub const MAX_IRQ_STATS: usize = 32;
+
+/// Per-CPU count of one interrupt source.
+pub struct IrqStat {
+    name: &'static str,
+    counts: PerCpu<AtomicUsize>
+}
+
+impl IrqStat {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            counts: PerCpu::new([const { AtomicUsize::new(0) }; MAX_CPUS])
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Counts one interrupt on the current CPU.
+    pub fn inc(&self) {
+        self.counts.get().fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self, cpu: usize) -> usize {
+        self.counts.get_cpu(cpu).load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+}
+
+static STATS: Mutex<[Option<&'static IrqStat>; MAX_IRQ_STATS]> = Mutex::new([None; MAX_IRQ_STATS]);
+
+/// Makes `stat` show up in the statistics, returns false if there is no room left.
+pub fn register(stat: &'static IrqStat) -> bool {
+    let mut stats = STATS.lock();
+    if stats.iter().flatten().any(|s| core::ptr::eq(*s, stat)) {
+        return true;
+    }
+
+    match stats.iter_mut().find(|s| s.is_none()) {
+        Some(slot) => {
+            *slot = Some(stat);
+            true
+        },
+        None => false
+    }
+}
+
+/// Returns all registered interrupt sources.
+pub fn stats() -> [Option<&'static IrqStat>; MAX_IRQ_STATS] {
+    *STATS.lock()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_and_register() {
+        static STAT: IrqStat = IrqStat::new("test");
+        STAT.inc();
+        STAT.inc();
+        assert_eq!(STAT.count(0), 2);
+        assert_eq!(STAT.total(), 2);
+
+        assert!(register(&STAT));
+        assert!(register(&STAT));
+        assert_eq!(stats().iter().flatten().filter(|s| s.name() == "test").count(), 1);
+    }
+}
@@ -2,12 +2,27 @@
 #![allow(dead_code)]
 
 mod arch;
+mod collections;
 mod drivers;
+mod fdt;
+mod irq;
 mod prelude;
+mod sched;
+mod shell;
+mod smp;
 mod sync;
+mod tty;
 
 #[cfg(not(test))]
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if the kernel is panicking.
+pub fn panicking() -> bool {
+    PANICKING.load(Ordering::Relaxed)
+}
 
 #[macro_export]
 macro_rules! print {
@@ -34,6 +49,11 @@ macro_rules! eprintln {
 #[panic_handler]
 #[cfg(not(test))]
 fn panic(info: &PanicInfo) -> ! {
-    eprintln!("{}", info);
+    // Don't try to print anything if printing is what panicked
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        smp::ipi::broadcast_ipi(smp::ipi::IpiKind::Stop);
+        eprintln!("{}", info);
+    }
+
     loop {}
 }
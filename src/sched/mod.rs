@@ -0,0 +1,140 @@
+use core::fmt;
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use crate::smp::{PerCpu, MAX_CPUS};
+
+/// Maximum number of tasks the kernel can track.
+pub const MAX_TASKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    /// The task that runs `kernel_main` on the boot core.
+    pub const BOOT: Self = Self(0);
+
+    pub const fn new(id: usize) -> Self {
+        debug_assert!(id < MAX_TASKS);
+        Self(id)
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TaskState {
+    Unused = 0,
+    Runnable = 1,
+    Running = 2,
+    Blocked = 3
+}
+
+impl From<u8> for TaskState {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => TaskState::Unused,
+            1 => TaskState::Runnable,
+            2 => TaskState::Running,
+            3 => TaskState::Blocked,
+            v => panic!("Invalid Task State: {}", v)
+        }
+    }
+}
+
+// TODO: replace once a context switch exists
+// Index 0 is the boot task, it's running from the moment kernel_main is entered
+static TASK_STATES: [AtomicU8; MAX_TASKS] = {
+    let mut states = [const { AtomicU8::new(TaskState::Unused as u8) }; MAX_TASKS];
+    states[0] = AtomicU8::new(TaskState::Running as u8);
+    states
+};
+
+static CURRENT: PerCpu<AtomicUsize> = PerCpu::new([const { AtomicUsize::new(0) }; MAX_CPUS]);
+static NEED_RESCHED: PerCpu<AtomicBool> = PerCpu::new([const { AtomicBool::new(false) }; MAX_CPUS]);
+
+/// Sets up the initial task of a CPU, the first `MAX_CPUS` task ids are reserved for them.
+pub fn init_cpu(cpu: usize) {
+    TASK_STATES[cpu].store(TaskState::Running as u8, Ordering::Relaxed);
+    CURRENT.get_cpu(cpu).store(cpu, Ordering::Relaxed);
+}
+
+/// Returns the id of the task running on this core.
+pub fn current() -> TaskId {
+    TaskId(CURRENT.get().load(Ordering::Relaxed))
+}
+
+/// Asks this CPU to call [`schedule`] at the next opportunity.
+pub fn set_need_resched() {
+    NEED_RESCHED.get().store(true, Ordering::Relaxed);
+}
+
+pub fn need_resched() -> bool {
+    NEED_RESCHED.get().load(Ordering::Relaxed)
+}
+
+pub fn state(task: TaskId) -> TaskState {
+    TASK_STATES[task.0].load(Ordering::Acquire).into()
+}
+
+/// Marks the current task as blocked.
+///
+/// The task keeps running until it calls [`schedule`], this allows the caller to
+/// publish itself on a wait queue before giving up the CPU without losing a wake up.
+pub fn block_current() {
+    TASK_STATES[current().0].store(TaskState::Blocked as u8, Ordering::Release);
+}
+
+/// Makes a blocked task runnable again.
+///
+/// Returns `false` if the task wasn't blocked.
+pub fn wake(task: TaskId) -> bool {
+    TASK_STATES[task.0].compare_exchange(
+        TaskState::Blocked as u8,
+        TaskState::Runnable as u8,
+        Ordering::AcqRel,
+        Ordering::Relaxed).is_ok()
+}
+
+/// Gives up the CPU until the current task is runnable again.
+///
+/// Returns right away if the task isn't blocked and no reschedule was asked for
+/// with [`set_need_resched`], the request is cleared either way.
+pub fn schedule() {
+    #[cfg(debug_assertions)]
+    crate::sync::lockdep::assert_no_locks_held();
+
+    let requested = NEED_RESCHED.get().swap(false, Ordering::Relaxed);
+    let state = &TASK_STATES[current().0];
+    if !requested && state.load(Ordering::Acquire) != TaskState::Blocked as u8 {
+        return;
+    }
+
+    // TODO: switch to another runnable task instead of spinning
+    while state.load(Ordering::Acquire) == TaskState::Blocked as u8 {
+        hint::spin_loop();
+    }
+
+    state.store(TaskState::Running as u8, Ordering::Relaxed);
+}
+
+/// Calls `f` for every task that is in use.
+pub fn for_each_task<F: FnMut(TaskId, TaskState)>(mut f: F) {
+    for (i, state) in TASK_STATES.iter().enumerate() {
+        let state = state.load(Ordering::Relaxed).into();
+        if state != TaskState::Unused {
+            f(TaskId(i), state);
+        }
+    }
+}
+
@@ -0,0 +1,267 @@
+//! Commands the shell always has.
+
+use core::fmt::Write;
+use core::ptr::{self, addr_of};
+use core::str;
+
+use crate::arch;
+use crate::fdt::{self, Token};
+use crate::irq;
+use crate::sched;
+use crate::smp::{self, MAX_CPUS, STACK_SIZE};
+
+use super::command::{commands, parse_number};
+use super::{register, Command, CommandError};
+
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Longest property value `dtb` prints in full.
+const MAX_PROP_BYTES: usize = 32;
+
+static BUILTINS: [Command; 9] = [
+    Command { name: "help", usage: "", help: "list the commands", run: help },
+    Command { name: "meminfo", usage: "", help: "show the memory used by the kernel", run: meminfo },
+    Command { name: "irqstat", usage: "", help: "show interrupt counts per CPU", run: irqstat },
+    Command { name: "tasks", usage: "", help: "list the tasks and their state", run: tasks },
+    Command { name: "dtb", usage: "", help: "dump the device tree passed by the firmware", run: dtb },
+    Command { name: "acpi", usage: "", help: "list the ACPI tables", run: acpi },
+    Command { name: "peek", usage: "<addr> [count]", help: "read 32 bit words from memory", run: peek },
+    Command { name: "poke", usage: "<addr> <value>", help: "write a 32 bit word to memory", run: poke },
+    Command { name: "reboot", usage: "", help: "restart the machine", run: reboot }
+];
+
+pub fn register_builtins() {
+    for builtin in &BUILTINS {
+        // Only fails if called twice
+        let _ = register(builtin);
+    }
+}
+
+fn help(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    for command in commands().iter().flatten() {
+        writeln!(out, "{:<8} {:<16} {}", command.name, command.usage, command.help)?;
+    }
+
+    Ok(())
+}
+
+fn meminfo(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let (start, end) = (addr_of!(__kernel_start) as usize, addr_of!(__kernel_end) as usize);
+    writeln!(out, "kernel:     {:#x}..{:#x} ({} KiB)", start, end, (end - start) / 1024)?;
+    writeln!(out, "cpu stacks: {} x {} KiB", MAX_CPUS, STACK_SIZE / 1024)?;
+    Ok(())
+}
+
+fn irqstat(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let cpus = (0..MAX_CPUS).filter(|&id| smp::cpu(id).online());
+    write!(out, "{:<8}", "")?;
+    for id in cpus.clone() {
+        // There are fewer than 10 CPUs, so this lines up with the counts
+        write!(out, " {:>9}{}", "CPU", id)?;
+    }
+    writeln!(out)?;
+
+    for stat in irq::stats().iter().flatten() {
+        write!(out, "{:<8}", stat.name())?;
+        for id in cpus.clone() {
+            write!(out, " {:>10}", stat.count(id))?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn tasks(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let mut result = Ok(());
+    sched::for_each_task(|id, state| {
+        if result.is_ok() {
+            result = writeln!(out, "{:>4} {:?}", id, state);
+        }
+    });
+
+    Ok(result?)
+}
+
+fn dtb(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let fdt = match fdt::blob() {
+        Some(Ok(fdt)) => fdt,
+        Some(Err(_)) => return Err(CommandError::Failed("invalid device tree")),
+        None => return Err(CommandError::Failed("no device tree"))
+    };
+
+    writeln!(out, "version {}, {} bytes, boot cpu {}", fdt.version(), fdt.total_size(), fdt.boot_cpu())?;
+    let mut depth = 0;
+    for token in fdt.tokens() {
+        match token.map_err(|_| CommandError::Failed("invalid device tree"))? {
+            Token::BeginNode(name) => {
+                writeln!(out, "{:indent$}{} {{", "", if name.is_empty() { "/" } else { name }, indent = depth * 2)?;
+                depth += 1;
+            },
+            Token::EndNode => {
+                depth = depth.saturating_sub(1);
+                writeln!(out, "{:indent$}}};", "", indent = depth * 2)?;
+            },
+            Token::Property { name, value } => {
+                write!(out, "{:indent$}{}", "", name, indent = depth * 2)?;
+                write_value(value, out)?;
+                writeln!(out, ";")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a property value as a string if it looks like one, in bytes otherwise.
+fn write_value(value: &[u8], out: &mut dyn Write) -> Result<(), CommandError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+
+    let text = value.strip_suffix(&[0]).and_then(|v| str::from_utf8(v).ok());
+    if let Some(text) = text.filter(|t| t.chars().all(|c| c == '\0' || (' '..='~').contains(&c))) {
+        write!(out, " = \"")?;
+        for c in text.chars() {
+            match c {
+                '\0' => write!(out, "\", \"")?,
+                c => out.write_char(c)?
+            }
+        }
+
+        write!(out, "\"")?;
+        return Ok(());
+    }
+
+    write!(out, " = [")?;
+    for byte in value.iter().take(MAX_PROP_BYTES) {
+        write!(out, " {:02x}", byte)?;
+    }
+
+    if value.len() > MAX_PROP_BYTES {
+        write!(out, " ...")?;
+    }
+
+    write!(out, " ]")?;
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn acpi(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    use crate::arch::x86_64::acpi::Rsdp;
+
+    let rsdp = Rsdp::find().ok_or(CommandError::Failed("no RSDP found"))?;
+    let oem = str::from_utf8(rsdp.oem_id()).unwrap_or("?");
+    writeln!(out, "RSDP at {:#x}, revision {}, OEM {}", rsdp.addr(), rsdp.revision(), oem)?;
+
+    let mut result = Ok(());
+    rsdp.for_each_table(|table| {
+        if result.is_ok() {
+            let signature = table.signature();
+            let checksum = if table.valid() { "" } else { " (bad checksum)" };
+            result = writeln!(out, "{} at {:#x}, {} bytes{}",
+                str::from_utf8(&signature).unwrap_or("????"), table.addr(), table.length(), checksum);
+        }
+    });
+
+    Ok(result?)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn acpi(_args: &[&str], _out: &mut dyn Write) -> Result<(), CommandError> {
+    Err(CommandError::Failed("not available on this architecture"))
+}
+
+/// Parses a word aligned address.
+fn parse_addr(s: &str) -> Result<usize, CommandError> {
+    let addr = parse_number(s).ok_or(CommandError::Usage)?;
+    if addr % 4 != 0 {
+        return Err(CommandError::Failed("address not aligned"));
+    }
+
+    Ok(addr)
+}
+
+fn peek(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    let (addr, count) = match args {
+        [_, addr] => (parse_addr(addr)?, 1),
+        [_, addr, count] => (parse_addr(addr)?, parse_number(count).ok_or(CommandError::Usage)?),
+        _ => return Err(CommandError::Usage)
+    };
+
+    for i in 0..count {
+        let addr = addr + i * 4;
+        if i % 4 == 0 {
+            if i != 0 {
+                writeln!(out)?;
+            }
+
+            write!(out, "{:#010x}:", addr)?;
+        }
+
+        write!(out, " {:08x}", unsafe { ptr::read_volatile(addr as *const u32) })?;
+    }
+
+    writeln!(out)?;
+    Ok(())
+}
+
+fn poke(args: &[&str], _out: &mut dyn Write) -> Result<(), CommandError> {
+    let [_, addr, value] = args else {
+        return Err(CommandError::Usage);
+    };
+
+    let addr = parse_addr(addr)?;
+    let value = parse_number(value).ok_or(CommandError::Usage)?;
+    let value = u32::try_from(value).map_err(|_| CommandError::Failed("value too large"))?;
+    unsafe { ptr::write_volatile(addr as *mut u32, value) };
+    Ok(())
+}
+
+fn reboot(_args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+    writeln!(out, "rebooting...")?;
+    arch::reboot()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    #[test]
+    fn peek_poke() {
+        let mut words = [0u32; 6];
+        let addr = words.as_mut_ptr() as usize;
+        let mut out = String::new();
+        poke(&["poke", &std::format!("{:#x}", addr + 4), "0xCAFE"], &mut out).unwrap();
+        assert_eq!(unsafe { ptr::read_volatile(addr_of!(words[1])) }, 0xCAFE);
+
+        peek(&["peek", &std::format!("{:#x}", addr), "5"], &mut out).unwrap();
+        let expected = std::format!(
+            "{:#010x}: 00000000 0000cafe 00000000 00000000\n{:#010x}: 00000000\n", addr, addr + 16);
+        assert_eq!(out, expected);
+
+        assert_eq!(peek(&["peek"], &mut out), Err(CommandError::Usage));
+        assert_eq!(poke(&["poke", &std::format!("{:#x}", addr + 1), "0"], &mut out),
+            Err(CommandError::Failed("address not aligned")));
+        assert_eq!(poke(&["poke", &std::format!("{:#x}", addr), "0x100000000"], &mut out),
+            Err(CommandError::Failed("value too large")));
+    }
+
+    #[test]
+    fn property_values() {
+        let mut out = String::new();
+        write_value(b"arm,pl011\0arm,primecell\0", &mut out).unwrap();
+        assert_eq!(out, " = \"arm,pl011\", \"arm,primecell\"");
+
+        out.clear();
+        write_value(&[0, 0, 0x10, 0], &mut out).unwrap();
+        assert_eq!(out, " = [ 00 00 10 00 ]");
+    }
+}
@@ -0,0 +1,174 @@
+//! Command registry of the shell.
+
+use core::fmt::{self, Write};
+
+use crate::sync::mutex::Mutex;
+
+use super::line_editor::Completer;
+
+/// Maximum number of commands that can be registered.
+pub const MAX_COMMANDS: usize = 32;
+
+/// Maximum number of words in a command line, including the command name.
+pub const MAX_ARGS: usize = 16;
+
+/// Runs a command, `args[0]` is the name of the command.
+pub type CommandFn = fn(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError>;
+
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments, shown after the name by `help`
+    pub usage: &'static str,
+    /// One line description
+    pub help: &'static str,
+    pub run: CommandFn
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// The arguments are wrong, the shell prints the usage of the command
+    Usage,
+    Failed(&'static str)
+}
+
+impl From<fmt::Error> for CommandError {
+    fn from(_: fmt::Error) -> Self {
+        Self::Failed("output error")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// There are already [`MAX_COMMANDS`] commands
+    Full,
+    /// A command with the same name exists
+    Duplicate
+}
+
+static COMMANDS: Mutex<[Option<&'static Command>; MAX_COMMANDS]> = Mutex::new([None; MAX_COMMANDS]);
+
+/// Adds a command to the shell.
+pub fn register(command: &'static Command) -> Result<(), RegisterError> {
+    let mut commands = COMMANDS.lock();
+    if commands.iter().flatten().any(|c| c.name == command.name) {
+        return Err(RegisterError::Duplicate);
+    }
+
+    let slot = commands.iter_mut().find(|c| c.is_none()).ok_or(RegisterError::Full)?;
+    *slot = Some(command);
+    Ok(())
+}
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.lock().iter().flatten().find(|c| c.name == name).copied()
+}
+
+/// Returns all registered commands, in the order they were registered.
+pub fn commands() -> [Option<&'static Command>; MAX_COMMANDS] {
+    *COMMANDS.lock()
+}
+
+/// Splits `line` into words and runs the command it names.
+pub fn execute(line: &str, out: &mut dyn Write) -> fmt::Result {
+    let mut args = [""; MAX_ARGS];
+    let mut argc = 0;
+    for word in line.split_whitespace() {
+        if argc == MAX_ARGS {
+            return writeln!(out, "too many arguments");
+        }
+
+        args[argc] = word;
+        argc += 1;
+    }
+
+    if argc == 0 {
+        return Ok(());
+    }
+
+    let Some(command) = find(args[0]) else {
+        return writeln!(out, "{}: command not found", args[0]);
+    };
+
+    match (command.run)(&args[..argc], out) {
+        Ok(()) => Ok(()),
+        Err(CommandError::Usage) => writeln!(out, "usage: {} {}", command.name, command.usage),
+        Err(CommandError::Failed(reason)) => writeln!(out, "{}: {}", command.name, reason)
+    }
+}
+
+/// Parses a number, in hex if it starts with `0x`.
+pub fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+/// Completes command names.
+pub struct CommandCompleter;
+
+impl Completer for CommandCompleter {
+    fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str)) {
+        commands().iter().flatten().filter(|c| c.name.starts_with(prefix)).for_each(|c| f(c.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    fn echo(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+        match args {
+            [_] => Err(CommandError::Usage),
+            [_, "fail"] => Err(CommandError::Failed("asked to")),
+            [_, rest @ ..] => {
+                for arg in rest {
+                    write!(out, "{} ", arg)?;
+                }
+
+                Ok(())
+            },
+            [] => unreachable!()
+        }
+    }
+
+    static ECHO: Command = Command {
+        name: "test-echo",
+        usage: "<word>...",
+        help: "prints its arguments",
+        run: echo
+    };
+
+    fn run(line: &str) -> String {
+        let mut out = String::new();
+        execute(line, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn register_and_execute() {
+        let _ = register(&ECHO);
+        assert_eq!(register(&ECHO), Err(RegisterError::Duplicate));
+        assert_eq!(run("  test-echo a  b "), "a b ");
+        assert_eq!(run("test-echo"), "usage: test-echo <word>...\n");
+        assert_eq!(run("test-echo fail"), "test-echo: asked to\n");
+        assert_eq!(run("nope"), "nope: command not found\n");
+        assert_eq!(run("   "), "");
+
+        let mut names = String::new();
+        CommandCompleter.complete("test-e", &mut |name| names.push_str(name));
+        assert_eq!(names, "test-echo");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("0x1F"), Some(0x1F));
+        assert_eq!(parse_number("0x"), None);
+        assert_eq!(parse_number("-1"), None);
+    }
+}
@@ -0,0 +1,442 @@
+//! Line editing for the shell: cursor movement, history and completion.
+//!
+//! Understands the usual emacs style control keys and the ANSI escape sequences
+//! terminals send for the arrow, home, end and delete keys.
+
+use core::fmt::{self, Write};
+use core::str;
+
+use crate::collections::RingBuffer;
+
+/// Longest line that can be entered.
+pub const MAX_LINE: usize = 128;
+
+/// Number of lines kept in the history.
+const HISTORY_SIZE: usize = 16;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_B: u8 = 0x02;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const CTRL_F: u8 = 0x06;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = b'\t';
+const CTRL_K: u8 = 0x0B;
+const CTRL_N: u8 = 0x0E;
+const CTRL_P: u8 = 0x10;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1B;
+const DEL: u8 = 0x7F;
+
+#[derive(Clone, Copy)]
+struct Line {
+    buf: [u8; MAX_LINE],
+    len: usize
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAX_LINE],
+            len: 0
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII gets in
+        str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+/// Supplies candidates for tab completion.
+pub trait Completer {
+    /// Calls `f` with every candidate for the first word of the line that starts with `prefix`.
+    fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str));
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Enter was pressed
+    Line(&'a str),
+    /// Ctrl-C was pressed, the line is discarded
+    Cancel
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Esc,
+    /// Inside a control sequence, with the numeric parameter read so far
+    Csi(u8)
+}
+
+pub struct LineEditor {
+    prompt: &'static str,
+    line: Line,
+    cursor: usize,
+    history: RingBuffer<Line, HISTORY_SIZE>,
+    /// Entry of the history shown, `history.len()` is the line being edited
+    browse: usize,
+    /// The line being edited while browsing the history
+    edited: Line,
+    escape: Escape,
+    last_cr: bool
+}
+
+impl LineEditor {
+    pub const fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: Line::new(),
+            cursor: 0,
+            history: RingBuffer::new(),
+            browse: 0,
+            edited: Line::new(),
+            escape: Escape::None,
+            last_cr: false
+        }
+    }
+
+    /// Starts a new line and prints the prompt.
+    pub fn start(&mut self, out: &mut dyn Write) -> fmt::Result {
+        self.line = Line::new();
+        self.cursor = 0;
+        self.browse = self.history.len();
+        out.write_str(self.prompt)
+    }
+
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Handles one byte of input, echoing to `out`.
+    ///
+    /// Returns the line once it's complete, [`LineEditor::start`] has to be called before feeding more input.
+    pub fn feed(&mut self, byte: u8, out: &mut dyn Write, completer: &dyn Completer) -> Result<Option<Event<'_>>, fmt::Error> {
+        let last_cr = self.last_cr;
+        self.last_cr = byte == b'\r';
+
+        match self.escape {
+            Escape::None => (),
+            Escape::Esc => {
+                self.escape = if byte == b'[' { Escape::Csi(0) } else { Escape::None };
+                return Ok(None);
+            },
+            Escape::Csi(param) => {
+                self.escape = Escape::None;
+                match byte {
+                    b'0'..=b'9' => self.escape = Escape::Csi(param.saturating_mul(10).saturating_add(byte - b'0')),
+                    b'A' => self.history_prev(out)?,
+                    b'B' => self.history_next(out)?,
+                    b'C' => self.move_to(self.cursor + 1, out)?,
+                    b'D' => self.move_to(self.cursor.saturating_sub(1), out)?,
+                    b'H' => self.move_to(0, out)?,
+                    b'F' => self.move_to(self.line.len, out)?,
+                    b'~' => match param {
+                        1 | 7 => self.move_to(0, out)?,
+                        3 => self.delete(out)?,
+                        4 | 8 => self.move_to(self.line.len, out)?,
+                        _ => ()
+                    },
+                    _ => ()
+                }
+
+                return Ok(None);
+            }
+        }
+
+        match byte {
+            b'\n' if last_cr => (),
+            b'\r' | b'\n' => {
+                out.write_str("\r\n")?;
+                let line = self.line;
+                if line.len != 0 && self.history.iter().next_back().is_none_or(|l| l.as_str() != line.as_str()) {
+                    self.history.push_overwrite(line);
+                }
+
+                return Ok(Some(Event::Line(self.line.as_str())));
+            },
+            CTRL_C => {
+                out.write_str("^C\r\n")?;
+                self.line = Line::new();
+                self.cursor = 0;
+                return Ok(Some(Event::Cancel));
+            },
+            ESC => self.escape = Escape::Esc,
+            BACKSPACE | DEL if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.delete(out)?;
+            },
+            CTRL_A => self.move_to(0, out)?,
+            CTRL_E => self.move_to(self.line.len, out)?,
+            CTRL_B => self.move_to(self.cursor.saturating_sub(1), out)?,
+            CTRL_F => self.move_to(self.cursor + 1, out)?,
+            CTRL_P => self.history_prev(out)?,
+            CTRL_N => self.history_next(out)?,
+            CTRL_U => {
+                self.line.buf.copy_within(self.cursor..self.line.len, 0);
+                self.line.len -= self.cursor;
+                self.cursor = 0;
+                self.refresh(out)?;
+            },
+            CTRL_K => {
+                self.line.len = self.cursor;
+                self.refresh(out)?;
+            },
+            TAB => self.complete(out, completer)?,
+            0x20..=0x7E => self.insert(&[byte], out)?,
+            _ => ()
+        }
+
+        Ok(None)
+    }
+
+    fn insert(&mut self, bytes: &[u8], out: &mut dyn Write) -> fmt::Result {
+        let n = bytes.len().min(MAX_LINE - self.line.len);
+        if n == 0 {
+            return out.write_char('\x07');
+        }
+
+        let at_end = self.cursor == self.line.len;
+        self.line.buf.copy_within(self.cursor..self.line.len, self.cursor + n);
+        self.line.buf[self.cursor..self.cursor + n].copy_from_slice(&bytes[..n]);
+        self.line.len += n;
+        self.cursor += n;
+
+        if at_end {
+            out.write_str(str::from_utf8(&bytes[..n]).unwrap_or(""))
+        } else {
+            self.refresh(out)
+        }
+    }
+
+    /// Deletes the character under the cursor.
+    fn delete(&mut self, out: &mut dyn Write) -> fmt::Result {
+        if self.cursor >= self.line.len {
+            return self.refresh(out);
+        }
+
+        self.line.buf.copy_within(self.cursor + 1..self.line.len, self.cursor);
+        self.line.len -= 1;
+        self.refresh(out)
+    }
+
+    fn move_to(&mut self, pos: usize, out: &mut dyn Write) -> fmt::Result {
+        self.cursor = pos.min(self.line.len);
+        self.refresh(out)
+    }
+
+    fn history_prev(&mut self, out: &mut dyn Write) -> fmt::Result {
+        if self.browse == 0 {
+            return Ok(());
+        }
+
+        if self.browse == self.history.len() {
+            self.edited = self.line;
+        }
+
+        self.browse -= 1;
+        self.show_history()?;
+        self.refresh(out)
+    }
+
+    fn history_next(&mut self, out: &mut dyn Write) -> fmt::Result {
+        if self.browse >= self.history.len() {
+            return Ok(());
+        }
+
+        self.browse += 1;
+        self.show_history()?;
+        self.refresh(out)
+    }
+
+    fn show_history(&mut self) -> fmt::Result {
+        self.line = self.history.get(self.browse).unwrap_or(self.edited);
+        self.cursor = self.line.len;
+        Ok(())
+    }
+
+    /// Completes the first word of the line, the only one there are candidates for.
+    fn complete(&mut self, out: &mut dyn Write, completer: &dyn Completer) -> fmt::Result {
+        let before = &self.line.buf[..self.cursor];
+        if before.contains(&b' ') {
+            return out.write_char('\x07');
+        }
+
+        let prefix = str::from_utf8(before).unwrap_or("");
+        let mut common = [0; MAX_LINE];
+        let mut common_len = 0;
+        let mut count = 0;
+        completer.complete(prefix, &mut |candidate| {
+            let candidate = candidate.as_bytes();
+            if count == 0 {
+                common_len = candidate.len().min(MAX_LINE);
+                common[..common_len].copy_from_slice(&candidate[..common_len]);
+            } else {
+                common_len = common[..common_len].iter()
+                    .zip(candidate)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            }
+
+            count += 1;
+        });
+
+        let prefix_len = prefix.len();
+        match count {
+            0 => out.write_char('\x07'),
+            1 => {
+                let mut completion = [0; MAX_LINE];
+                let n = common_len - prefix_len;
+                completion[..n].copy_from_slice(&common[prefix_len..common_len]);
+                completion[n] = b' ';
+                self.insert(&completion[..n + 1], out)
+            },
+            _ if common_len > prefix_len => self.insert(&common[prefix_len..common_len], out),
+            _ => {
+                out.write_str("\r\n")?;
+                let mut result = Ok(());
+                completer.complete(prefix, &mut |candidate| {
+                    if result.is_ok() {
+                        result = write!(out, "{}  ", candidate);
+                    }
+                });
+                result?;
+                out.write_str("\r\n")?;
+                self.refresh(out)
+            }
+        }
+    }
+
+    /// Redraws the whole line and puts the terminal cursor where it belongs.
+    fn refresh(&self, out: &mut dyn Write) -> fmt::Result {
+        write!(out, "\r{}{}\x1b[K", self.prompt, self.line.as_str())?;
+        if self.cursor < self.line.len {
+            write!(out, "\x1b[{}D", self.line.len - self.cursor)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    struct Words(&'static [&'static str]);
+
+    impl Completer for Words {
+        fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str)) {
+            self.0.iter().filter(|w| w.starts_with(prefix)).for_each(|w| f(w));
+        }
+    }
+
+    const WORDS: Words = Words(&["help", "history", "peek", "poke"]);
+
+    /// Feeds `input` and returns the last complete line.
+    fn feed(editor: &mut LineEditor, input: &[u8]) -> Option<String> {
+        let mut out = String::new();
+        let mut line = None;
+        for &byte in input {
+            if let Some(Event::Line(l)) = editor.feed(byte, &mut out, &WORDS).unwrap() {
+                line = Some(l.into());
+                editor.start(&mut out).unwrap();
+            }
+        }
+
+        line
+    }
+
+    fn editor() -> LineEditor {
+        let mut editor = LineEditor::new("> ");
+        editor.start(&mut String::new()).unwrap();
+        editor
+    }
+
+    #[test]
+    fn plain_line() {
+        let mut editor = editor();
+        assert_eq!(feed(&mut editor, b"peek 0x10\r\n").as_deref(), Some("peek 0x10"));
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn backspace_and_cursor() {
+        let mut editor = editor();
+        // Type "hxlp", fix the typo in the middle, then delete the last char and retype it
+        feed(&mut editor, b"hxlp\x1b[D\x1b[D\x7fe\x1b[F\x08p");
+        assert_eq!(editor.line(), "help");
+        feed(&mut editor, b"\x01\x1b[3~");
+        assert_eq!(editor.line(), "elp");
+        assert_eq!(editor.cursor(), 0);
+    }
+
+    #[test]
+    fn kill() {
+        let mut editor = editor();
+        feed(&mut editor, b"abcdef\x02\x02\x0B");
+        assert_eq!(editor.line(), "abcd");
+        feed(&mut editor, b"\x02\x15");
+        assert_eq!(editor.line(), "d");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = editor();
+        feed(&mut editor, b"one\rtwo\rtwo\r");
+        feed(&mut editor, b"thr\x1b[A");
+        assert_eq!(editor.line(), "two");
+        feed(&mut editor, b"\x1b[A\x1b[A");
+        assert_eq!(editor.line(), "one");
+        feed(&mut editor, b"\x1b[B\x1b[B");
+        assert_eq!(editor.line(), "thr");
+    }
+
+    #[test]
+    fn cancel() {
+        let mut editor = editor();
+        let mut out = String::new();
+        feed(&mut editor, b"abc");
+        assert_eq!(editor.feed(CTRL_C, &mut out, &WORDS), Ok(Some(Event::Cancel)));
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn complete_unique() {
+        let mut editor = editor();
+        feed(&mut editor, b"he\t");
+        assert_eq!(editor.line(), "help ");
+    }
+
+    #[test]
+    fn complete_common_prefix() {
+        let mut editor = editor();
+        feed(&mut editor, b"h\t");
+        assert_eq!(editor.line(), "h");
+        feed(&mut editor, b"i\t");
+        assert_eq!(editor.line(), "history ");
+
+        let mut editor = LineEditor::new("> ");
+        let mut out = String::new();
+        for &byte in b"p\t" {
+            editor.feed(byte, &mut out, &WORDS).unwrap();
+        }
+
+        assert!(out.contains("peek  poke"));
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut editor = editor();
+        feed(&mut editor, &[b'x'; MAX_LINE + 10]);
+        assert_eq!(editor.line().len(), MAX_LINE);
+    }
+}
@@ -0,0 +1,64 @@
+//! Interactive shell on the console.
+//!
+//! Subsystems add their own commands with [`register`], see [`builtins`] for examples.
+
+pub mod builtins;
+pub mod command;
+pub mod line_editor;
+
+use core::fmt::{self, Write};
+
+use crate::arch;
+
+use self::command::CommandCompleter;
+use self::line_editor::{Event, LineEditor};
+
+pub use self::command::{register, Command, CommandError};
+
+const PROMPT: &str = "noros> ";
+
+/// Writes to the console, turning `\n` into `\r\n`.
+struct ConsoleWriter {
+    last: u8
+}
+
+impl Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        arch::with_console(|console| {
+            for &byte in s.as_bytes() {
+                if byte == b'\n' && self.last != b'\r' {
+                    console.write_byte(b'\r');
+                }
+
+                console.write_byte(byte);
+                self.last = byte;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Runs the shell on the boot CPU, interrupts are taken while it waits for input.
+pub fn run() -> ! {
+    let mut out = ConsoleWriter { last: 0 };
+    let mut editor = LineEditor::new(PROMPT);
+    let _ = editor.start(&mut out);
+
+    loop {
+        let Some(byte) = arch::with_console(|console| console.read_byte()) else {
+            arch::wait_for_input();
+            continue;
+        };
+
+        match editor.feed(byte, &mut out, &CommandCompleter) {
+            Ok(Some(Event::Line(line))) => {
+                let _ = command::execute(line, &mut out);
+            },
+            Ok(Some(Event::Cancel)) => (),
+            Ok(None) | Err(_) => continue
+        }
+
+        let _ = editor.start(&mut out);
+    }
+}
@@ -0,0 +1,129 @@
+use core::hint;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::irq::IrqStat;
+use crate::sched;
+use crate::sync::mutex::Mutex;
+
+use super::{cpu, cpu_id, PerCpu, MAX_CPUS};
+
+/// Kinds of inter-processor interrupts, several can be pending at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum IpiKind {
+    /// Ask the CPU to call into the scheduler
+    Reschedule = 1 << 0,
+    /// Flush the TLB entries of [`tlb_shootdown`]
+    TlbShootdown = 1 << 1,
+    /// Stop the CPU, used when panicking
+    Stop = 1 << 2
+}
+
+static PENDING: PerCpu<AtomicU32> = PerCpu::new([const { AtomicU32::new(0) }; MAX_CPUS]);
+/// Number of inter-processor interrupts handled
+pub static IPI_STAT: IrqStat = IrqStat::new("ipi");
+
+/// Only one shootdown can be in flight at a time
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+/// Address to flush, `usize::MAX` flushes the whole TLB
+static SHOOTDOWN_ADDR: AtomicUsize = AtomicUsize::new(usize::MAX);
+static SHOOTDOWN_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends an inter-processor interrupt to `cpu`.
+pub fn send_ipi(cpu_id: usize, kind: IpiKind) {
+    PENDING.get_cpu(cpu_id).fetch_or(kind as u32, Ordering::Release);
+    arch::send_ipi(cpu(cpu_id).hw_id());
+}
+
+/// Sends an inter-processor interrupt to all other online CPUs.
+///
+/// Returns the number of CPUs it was sent to.
+pub fn broadcast_ipi(kind: IpiKind) -> usize {
+    let this = cpu_id();
+    let mut sent = 0;
+    for id in (0..MAX_CPUS).filter(|&id| id != this && cpu(id).online()) {
+        send_ipi(id, kind);
+        sent += 1;
+    }
+
+    sent
+}
+
+/// Flushes the TLB entry of `addr`, or the whole TLB if `None`, on all online CPUs.
+///
+/// Returns once every CPU has done so.
+pub fn tlb_shootdown(addr: Option<usize>) {
+    // Interrupts are masked here, so the shootdown of the CPU holding the lock
+    // has to be handled by polling or it would wait for this one forever
+    let _lock = loop {
+        if let Ok(lock) = SHOOTDOWN.try_lock() {
+            break lock;
+        }
+
+        poll_ipi();
+        hint::spin_loop();
+    };
+
+    let addr = addr.unwrap_or(usize::MAX);
+    SHOOTDOWN_ADDR.store(addr, Ordering::Relaxed);
+
+    let this = cpu_id();
+    let targets = (0..MAX_CPUS).filter(|&id| id != this && cpu(id).online()).count();
+    SHOOTDOWN_PENDING.store(targets, Ordering::Release);
+
+    broadcast_ipi(IpiKind::TlbShootdown);
+    flush_tlb(addr);
+
+    while SHOOTDOWN_PENDING.load(Ordering::Acquire) != 0 {
+        poll_ipi();
+        hint::spin_loop();
+    }
+}
+
+/// Handles the inter-processor interrupts pending for this CPU without waiting
+/// for the interrupt, for code that spins with interrupts masked.
+pub fn poll_ipi() {
+    arch::ack_ipi();
+    handle_ipi();
+}
+
+fn flush_tlb(addr: usize) {
+    if addr == usize::MAX {
+        arch::flush_tlb_all();
+    } else {
+        arch::flush_tlb(addr);
+    }
+}
+
+/// Handles the inter-processor interrupts pending for this CPU.
+///
+/// Called from the interrupt handler of every architecture, or [`poll_ipi`].
+pub fn handle_ipi() {
+    let pending = PENDING.get().swap(0, Ordering::Acquire);
+    if pending == 0 {
+        return;
+    }
+
+    IPI_STAT.inc();
+
+    if pending & IpiKind::TlbShootdown as u32 != 0 {
+        flush_tlb(SHOOTDOWN_ADDR.load(Ordering::Relaxed));
+        SHOOTDOWN_PENDING.fetch_sub(1, Ordering::Release);
+    }
+
+    if pending & IpiKind::Reschedule as u32 != 0 {
+        sched::set_need_resched();
+    }
+
+    if pending & IpiKind::Stop as u32 != 0 {
+        loop {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Returns the number of inter-processor interrupts `cpu` has handled.
+pub fn received(cpu: usize) -> usize {
+    IPI_STAT.count(cpu)
+}
@@ -0,0 +1,166 @@
+pub mod ipi;
+pub mod percpu;
+
+use core::hint;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::irq;
+use crate::prelude::*;
+use crate::sched;
+
+pub use self::percpu::PerCpu;
+
+/// Maximum number of CPUs that are brought up.
+pub const MAX_CPUS: usize = 4;
+
+/// Size of the stack of every secondary CPU, must match the boot code.
+pub const STACK_SIZE: usize = 0x4000;
+
+#[repr(C, align(16))]
+pub struct CpuStacks([[u8; STACK_SIZE]; MAX_CPUS]);
+
+/// Stacks of the secondary CPUs, CPU `n` uses the stack ending at `(n + 1) * STACK_SIZE`.
+/// The stack of the boot CPU is set up by the boot code.
+#[no_mangle]
+pub static mut CPU_STACKS: CpuStacks = CpuStacks([[0; STACK_SIZE]; MAX_CPUS]);
+
+/// Secondary CPUs spin until this is cleared, it's not in .bss because
+/// they start waiting before the boot CPU zeroes it.
+#[no_mangle]
+pub static SECONDARY_HOLD: AtomicU64 = AtomicU64::new(1);
+
+/// Size of [`CpuData`], must match the boot code.
+pub const CPU_DATA_SIZE: usize = 32;
+
+/// Data kept for every CPU, the per-CPU register of the architecture
+/// (GS base, TPIDR_EL1 or tp) points to the entry of the CPU it runs on.
+///
+/// The boot code sets that register before entering Rust, so it's valid
+/// from the first lock or print on.
+#[repr(C)]
+pub struct CpuData {
+    /// Pointer to itself, has to be the first field so x86_64 can load it with `mov reg, gs:[0]`
+    this: AtomicPtr<CpuData>,
+    id: usize,
+    online: AtomicBool,
+    /// Architecture specific id: APIC ID, MPIDR affinity or hart id
+    hw_id: AtomicUsize
+}
+
+const _: () = assert!(mem::size_of::<CpuData>() == CPU_DATA_SIZE);
+
+impl CpuData {
+    const fn new(this: *mut CpuData, id: usize) -> Self {
+        Self {
+            this: AtomicPtr::new(this),
+            id,
+            online: AtomicBool::new(false),
+            hw_id: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn hw_id(&self) -> usize {
+        self.hw_id.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-CPU data, CPU `n` uses the entry at `CPUS + n * CPU_DATA_SIZE`.
+#[no_mangle]
+pub static CPUS: [CpuData; MAX_CPUS] = {
+    let mut cpus = [const { CpuData::new(ptr::null_mut(), 0) }; MAX_CPUS];
+    let mut i = 0;
+    while i < MAX_CPUS {
+        cpus[i] = CpuData::new(unsafe { (&raw const CPUS as *mut CpuData).add(i) }, i);
+        i += 1;
+    }
+
+    cpus
+};
+
+/// Returns the id of the CPU this runs on, between 0 and [`MAX_CPUS`].
+#[inline]
+pub fn cpu_id() -> usize {
+    this_cpu().id
+}
+
+pub fn cpu(id: usize) -> &'static CpuData {
+    &CPUS[id]
+}
+
+/// Returns the data of the CPU this runs on.
+#[cfg(not(test))]
+#[inline]
+pub fn this_cpu() -> &'static CpuData {
+    unsafe { &*arch::cpu_data() }
+}
+
+#[cfg(test)]
+pub fn this_cpu() -> &'static CpuData {
+    &CPUS[0]
+}
+
+/// Returns the number of CPUs that are up and running.
+pub fn online_cpus() -> usize {
+    CPUS.iter().filter(|c| c.online()).count()
+}
+
+/// Looks up the CPU id belonging to an architecture specific id.
+pub fn cpu_id_from_hw_id(hw_id: usize) -> Option<usize> {
+    CPUS.iter().position(|c| c.online() && c.hw_id() == hw_id)
+}
+
+/// Marks the calling CPU as online, called once by every CPU before doing anything else.
+pub fn cpu_online(id: usize, hw_id: usize) {
+    let cpu = &CPUS[id];
+    debug_assert!(ptr::eq(this_cpu(), cpu));
+    cpu.hw_id.store(hw_id, Ordering::Relaxed);
+    sched::init_cpu(id);
+    cpu.online.store(true, Ordering::Release);
+}
+
+/// Starts all secondary CPUs and waits for them to come online.
+pub fn init() {
+    irq::register(&ipi::IPI_STAT);
+    arch::start_secondary_cpus();
+
+    // There is no way to know how many CPUs there are yet, wait until no new ones show up
+    let mut online = online_cpus();
+    let mut spins = 0;
+    while online < MAX_CPUS && spins < 10_000_000 {
+        hint::spin_loop();
+        let now = online_cpus();
+        if now != online {
+            online = now;
+            spins = 0;
+        }
+
+        spins += 1;
+    }
+
+    println!("{} CPU(s) online", online);
+}
+
+/// Entry point of the secondary CPUs once the architecture specific setup is done.
+pub fn secondary_main(id: usize) -> ! {
+    println!("CPU {} online", id);
+
+    idle()
+}
+
+/// Waits for interrupts forever, inter-processor interrupts are handled by the interrupt handler.
+pub fn idle() -> ! {
+    loop {
+        arch::wait_for_interrupt();
+    }
+}
@@ -0,0 +1,56 @@
+use core::slice;
+
+use super::{cpu_id, MAX_CPUS};
+
+/// A variable with a separate instance for every CPU.
+///
+/// Only hands out shared references, so `T` needs interior mutability to be
+/// of any use. Other CPUs can read an instance with [`PerCpu::get_cpu`], which
+/// is why `T` has to be `Sync` for the variable to be `Sync`.
+pub struct PerCpu<T>([T; MAX_CPUS]);
+
+impl<T> PerCpu<T> {
+    pub const fn new(values: [T; MAX_CPUS]) -> Self {
+        Self(values)
+    }
+
+    /// Returns the instance of the CPU this runs on.
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.0[cpu_id()]
+    }
+
+    pub fn get_cpu(&self, cpu: usize) -> &T {
+        &self.0[cpu]
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn get_this_cpu() {
+        let var = PerCpu::new([const { AtomicUsize::new(0) }; MAX_CPUS]);
+        var.get().store(5, Ordering::Relaxed);
+        assert_eq!(var.get_cpu(cpu_id()).load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn instances_are_separate() {
+        let var = PerCpu::new([const { AtomicUsize::new(0) }; MAX_CPUS]);
+        for (i, v) in var.iter().enumerate() {
+            v.store(i, Ordering::Relaxed);
+        }
+
+        for i in 0..MAX_CPUS {
+            assert_eq!(var.get_cpu(i).load(Ordering::Relaxed), i);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use super::WaitQueue;
+use super::sleep_mutex::SleepMutexGuard;
+
+/// A condition variable to be used together with a [`SleepMutex`](super::sleep_mutex::SleepMutex).
+pub struct Condvar {
+    waiters: WaitQueue
+}
+
+impl Condvar {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new()
+        }
+    }
+
+    /// Releases the lock and blocks the current task until it's notified,
+    /// the lock is reacquired before returning.
+    ///
+    /// Like most condition variables, spurious wake ups are possible.
+    pub fn wait<'a, T>(&self, guard: SleepMutexGuard<'a, T>) -> SleepMutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // Queue up before unlocking so a notify right after can't be missed
+        self.waiters.wait_with(|| drop(guard));
+        mutex.lock()
+    }
+
+    /// Blocks the current task until `condition` returns `false`.
+    pub fn wait_while<'a, T, F>(&self, mut guard: SleepMutexGuard<'a, T>, mut condition: F) -> SleepMutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
+
+impl Default for Condvar {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
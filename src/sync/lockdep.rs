@@ -0,0 +1,386 @@
+//! Lock validator for debug builds.
+//!
+//! Spin locks are grouped into classes by the place that creates them, so all the
+//! locks made by the same constructor call share a class. The validator keeps track
+//! of which locks every CPU holds and of the order in which classes have been
+//! acquired, and panics on:
+//! - recursive locking, which would otherwise spin forever
+//! - lock order inversion, two paths taking the same locks in a different order
+//! - spin locks held across a context switch
+
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::prelude::*;
+use crate::sched;
+#[cfg(not(test))]
+use crate::smp::{self, PerCpu, MAX_CPUS};
+
+/// Maximum number of lock classes that are checked for ordering issues, locks
+/// created at other places after this still get checked for recursion.
+pub const MAX_LOCK_CLASSES: usize = 64;
+
+/// Maximum number of spin locks a single CPU can hold at once.
+const MAX_HELD: usize = 16;
+
+const NO_CLASS: usize = 0;
+const UNTRACKED: usize = usize::MAX;
+
+#[cfg(not(test))]
+fn cpu_id() -> usize {
+    smp::cpu_id()
+}
+
+// Every test thread acts as its own CPU
+#[cfg(test)]
+fn cpu_id() -> usize {
+    extern crate std;
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    std::thread_local!(static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed));
+    ID.with(|id| *id)
+}
+
+/// Owner of a lock as stored in [`LockState`], 0 means the lock is free.
+fn current_owner() -> usize {
+    (cpu_id() << 32 | sched::current().as_usize()) + 1
+}
+
+/// Debug information attached to every spin lock.
+pub struct LockState {
+    /// Where the lock was created, which decides its class
+    site: &'static Location<'static>,
+    class: AtomicUsize,
+    owner: AtomicUsize
+}
+
+impl LockState {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            site: Location::caller(),
+            class: AtomicUsize::new(NO_CLASS),
+            owner: AtomicUsize::new(0)
+        }
+    }
+
+    fn class(&self) -> usize {
+        let class = self.class.load(Ordering::Relaxed);
+        if class != NO_CLASS {
+            return class;
+        }
+
+        let new = ORDER.register(self.site);
+        match self.class.compare_exchange(NO_CLASS, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => new,
+            Err(class) => class
+        }
+    }
+
+    /// Checks that taking the lock can't deadlock, called before trying to acquire it.
+    pub fn acquire(&self, location: &'static Location<'static>) {
+        let owner = self.owner.load(Ordering::Relaxed);
+        if owner == current_owner() {
+            panic!(
+                "lockdep: recursive locking at {} of lock {} already held by cpu {} task {}",
+                location,
+                ORDER.describe(self.class()),
+                (owner - 1) >> 32,
+                sched::current());
+        }
+
+        let class = self.class();
+        for held in held_locks().iter() {
+            let held = held.load(Ordering::Relaxed);
+            // Locks of the same class have no order between them
+            if held == NO_CLASS || held == UNTRACKED || class == UNTRACKED || held == class {
+                continue;
+            }
+
+            if let Err(()) = ORDER.add(held, class) {
+                panic!(
+                    "lockdep: lock order inversion at {}: acquiring lock {} while holding lock {}, \
+                    the opposite order has been seen before",
+                    location,
+                    ORDER.describe(class),
+                    ORDER.describe(held));
+            }
+        }
+    }
+
+    /// Records the lock as held by the current CPU and task.
+    pub fn acquired(&self) {
+        self.owner.store(current_owner(), Ordering::Relaxed);
+        let class = self.class();
+        let slot = held_locks().iter()
+            .find(|s| s.compare_exchange(NO_CLASS, class, Ordering::Relaxed, Ordering::Relaxed).is_ok());
+        if slot.is_none() {
+            panic!("lockdep: more than {} locks held on cpu {}", MAX_HELD, cpu_id());
+        }
+    }
+
+    pub fn released(&self) {
+        self.owner.store(0, Ordering::Relaxed);
+        let class = self.class.load(Ordering::Relaxed);
+        // Locks don't have to be released in the order they were acquired
+        let _ = held_locks().iter()
+            .rev()
+            .find(|s| s.compare_exchange(class, NO_CLASS, Ordering::Relaxed, Ordering::Relaxed).is_ok());
+    }
+}
+
+impl Default for LockState {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(test))]
+static HELD: PerCpu<[AtomicUsize; MAX_HELD]> = PerCpu::new([const { [const { AtomicUsize::new(NO_CLASS) }; MAX_HELD] }; MAX_CPUS]);
+
+#[cfg(not(test))]
+fn held_locks() -> &'static [AtomicUsize; MAX_HELD] {
+    HELD.get()
+}
+
+#[cfg(test)]
+fn held_locks() -> &'static [AtomicUsize; MAX_HELD] {
+    extern crate std;
+    use std::boxed::Box;
+
+    std::thread_local!(static HELD: &'static [AtomicUsize; MAX_HELD] =
+        Box::leak(Box::new([const { AtomicUsize::new(NO_CLASS) }; MAX_HELD])));
+    HELD.with(|held| *held)
+}
+
+/// Panics if the current CPU holds any spin lock, called before switching tasks.
+pub fn assert_no_locks_held() {
+    let held = held_locks().iter()
+        .map(|s| s.load(Ordering::Relaxed))
+        .find(|&s| s != NO_CLASS);
+    if let Some(class) = held {
+        panic!(
+            "lockdep: task {} is switched out while holding spin lock {}",
+            sched::current(),
+            ORDER.describe(class));
+    }
+
+    // Printing takes the console lock, which is only safe with no locks held
+    if ORDER.exhausted.load(Ordering::Relaxed) && !ORDER.reported.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "lockdep: more than {} lock classes, locks created at other places aren't checked for ordering",
+            MAX_LOCK_CLASSES);
+    }
+}
+
+/// Graph of the order in which lock classes have been acquired.
+struct LockOrder {
+    next_class: AtomicUsize,
+    /// Set once a lock didn't get a class, reported the first time no lock is held
+    exhausted: AtomicBool,
+    reported: AtomicBool,
+    /// Where the locks of every class are created
+    locations: [AtomicPtr<Location<'static>>; MAX_LOCK_CLASSES],
+    /// Bit `b` of `after[a]` is set if class `b` has been acquired while holding class `a`
+    after: [AtomicU64; MAX_LOCK_CLASSES]
+}
+
+static ORDER: LockOrder = LockOrder::new();
+
+impl LockOrder {
+    const fn new() -> Self {
+        Self {
+            next_class: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+            reported: AtomicBool::new(false),
+            locations: [const { AtomicPtr::new(ptr::null_mut()) }; MAX_LOCK_CLASSES],
+            after: [const { AtomicU64::new(0) }; MAX_LOCK_CLASSES]
+        }
+    }
+
+    /// Returns the class of the locks created at `location`, allocating it for the first one.
+    fn register(&self, location: &'static Location<'static>) -> usize {
+        let registered = self.next_class.load(Ordering::Relaxed).min(MAX_LOCK_CLASSES);
+        let existing = self.locations[..registered].iter()
+            .position(|l| unsafe { l.load(Ordering::Relaxed).as_ref() } == Some(location));
+        match existing {
+            Some(index) => index + 1,
+            None => self.allocate(location)
+        }
+    }
+
+    /// Allocates a new class, classes start at 1 so 0 can mean unassigned.
+    fn allocate(&self, location: &'static Location<'static>) -> usize {
+        let index = self.next_class.fetch_add(1, Ordering::Relaxed);
+        if index >= MAX_LOCK_CLASSES {
+            self.exhausted.store(true, Ordering::Relaxed);
+            return UNTRACKED;
+        }
+
+        self.locations[index].store(location as *const _ as *mut _, Ordering::Relaxed);
+        index + 1
+    }
+
+    /// Records that `class` is acquired while holding `held`.
+    ///
+    /// Returns an error if `held` has been acquired while holding `class`, directly or through other locks.
+    fn add(&self, held: usize, class: usize) -> Result<(), ()> {
+        if self.after[held - 1].load(Ordering::Relaxed) & (1 << (class - 1)) != 0 {
+            // Already known
+            return Ok(());
+        }
+
+        if self.reachable(class, held) {
+            return Err(());
+        }
+
+        self.after[held - 1].fetch_or(1 << (class - 1), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns true if `to` has been acquired after `from`.
+    fn reachable(&self, from: usize, to: usize) -> bool {
+        let mut seen = 0u64;
+        let mut frontier = 1u64 << (from - 1);
+        while frontier != 0 {
+            seen |= frontier;
+            let mut next = 0u64;
+            for i in 0..MAX_LOCK_CLASSES {
+                if frontier & (1 << i) != 0 {
+                    next |= self.after[i].load(Ordering::Relaxed);
+                }
+            }
+
+            frontier = next & !seen;
+        }
+
+        seen & (1 << (to - 1)) != 0
+    }
+
+    fn describe(&self, class: usize) -> LockDescription {
+        let location = match class {
+            NO_CLASS | UNTRACKED => None,
+            class => unsafe { self.locations[class - 1].load(Ordering::Relaxed).as_ref() }
+        };
+
+        LockDescription { class, location }
+    }
+}
+
+struct LockDescription {
+    class: usize,
+    location: Option<&'static Location<'static>>
+}
+
+impl core::fmt::Display for LockDescription {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "#{} (created at {})", self.class, location),
+            None => f.write_str("#?")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::mutex::Mutex;
+
+    #[test]
+    fn order_consistent() {
+        let order = LockOrder::new();
+        let a = order.allocate(Location::caller());
+        let b = order.allocate(Location::caller());
+        let c = order.allocate(Location::caller());
+        assert!(order.add(a, b).is_ok());
+        assert!(order.add(b, c).is_ok());
+        assert!(order.add(a, c).is_ok());
+        assert!(order.add(a, b).is_ok());
+    }
+
+    #[test]
+    fn order_inversion() {
+        let order = LockOrder::new();
+        let a = order.allocate(Location::caller());
+        let b = order.allocate(Location::caller());
+        assert!(order.add(a, b).is_ok());
+        assert!(order.add(b, a).is_err());
+    }
+
+    #[test]
+    fn order_inversion_transitive() {
+        let order = LockOrder::new();
+        let a = order.allocate(Location::caller());
+        let b = order.allocate(Location::caller());
+        let c = order.allocate(Location::caller());
+        assert!(order.add(a, b).is_ok());
+        assert!(order.add(b, c).is_ok());
+        assert!(order.add(c, a).is_err());
+    }
+
+    #[test]
+    fn order_untracked() {
+        let order = LockOrder::new();
+        for _ in 0..MAX_LOCK_CLASSES {
+            assert_ne!(order.allocate(Location::caller()), UNTRACKED);
+        }
+
+        assert!(!order.exhausted.load(Ordering::Relaxed));
+        assert_eq!(order.allocate(Location::caller()), UNTRACKED);
+        assert!(order.exhausted.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn class_per_site() {
+        let order = LockOrder::new();
+        let a = order.register(Location::caller());
+        let b = order.register(Location::caller());
+        assert_ne!(a, b);
+        let classes: [usize; 2 * MAX_LOCK_CLASSES] = core::array::from_fn(|_| order.register(Location::caller()));
+        assert!(classes.iter().all(|&class| class == classes[0] && class != UNTRACKED));
+        assert!(!order.exhausted.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "recursive locking")]
+    fn recursive_lock() {
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock();
+        let _guard2 = mutex.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "while holding spin lock")]
+    fn lock_held_across_switch() {
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock();
+        assert_no_locks_held();
+    }
+
+    #[test]
+    fn nested_locks() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        let guard_a = a.lock();
+        let guard_b = b.lock();
+        drop(guard_a);
+        drop(guard_b);
+        assert_no_locks_held();
+    }
+
+    #[test]
+    fn nested_locks_same_site() {
+        let locks: [Mutex<()>; 2] = core::array::from_fn(|_| Mutex::new(()));
+        let guard_a = locks[0].lock();
+        let guard_b = locks[1].lock();
+        drop(guard_b);
+        drop(guard_a);
+        let guard_b = locks[1].lock();
+        let guard_a = locks[0].lock();
+        drop(guard_a);
+        drop(guard_b);
+        assert_no_locks_held();
+    }
+}
@@ -1,4 +1,11 @@
+pub mod condvar;
+#[cfg(debug_assertions)]
+pub mod lockdep;
 pub mod mutex;
+pub mod sleep_mutex;
+pub mod wait_queue;
+
+pub use self::wait_queue::WaitQueue;
 
 #[derive(Debug)]
 pub enum TryLockError {
@@ -2,33 +2,61 @@ use core::cell::UnsafeCell;
 use core::hint;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+#[cfg(debug_assertions)]
+use core::panic::Location;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+#[cfg(debug_assertions)]
+use super::lockdep::LockState;
 use super::{TryLockResult, TryLockError};
 
 struct MovableMutex {
-    inner: AtomicBool
+    inner: AtomicBool,
+    #[cfg(debug_assertions)]
+    lockdep: LockState
 }
 
 impl MovableMutex {
     #[inline]
+    #[track_caller]
     pub const fn new() -> Self {
         Self {
-            inner: AtomicBool::new(false)
+            inner: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            lockdep: LockState::new()
         }
     }
 
     #[inline]
-    pub fn try_lock(&self) -> bool {
+    fn try_acquire(&self) -> bool {
         self.inner.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
     }
 
     #[inline]
+    #[track_caller]
+    pub fn try_lock(&self) -> bool {
+        let locked = self.try_acquire();
+        #[cfg(debug_assertions)]
+        if locked {
+            self.lockdep.acquired();
+        }
+
+        locked
+    }
+
+    #[inline]
+    #[track_caller]
     pub fn lock(&self) -> bool {
-        while !self.try_lock() {
+        #[cfg(debug_assertions)]
+        self.lockdep.acquire(Location::caller());
+
+        while !self.try_acquire() {
             self.lock_contended();
         }
 
+        #[cfg(debug_assertions)]
+        self.lockdep.acquired();
+
         true
     }
 
@@ -41,11 +69,15 @@ impl MovableMutex {
 
     #[inline]
     pub fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        self.lockdep.released();
+
         self.inner.store(false, Ordering::Release);
     }
 }
 
 impl Default for MovableMutex {
+    #[track_caller]
     fn default() -> Self {
         Self::new()
     }
@@ -57,6 +89,7 @@ pub struct Mutex<T> {
 }
 
 impl<T> Mutex<T> {
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             inner: MovableMutex::new(),
@@ -64,11 +97,13 @@ impl<T> Mutex<T> {
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
         self.inner.lock();
         MutexGuard::new(self)
     }
 
+    #[track_caller]
     pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
         if self.inner.try_lock() {
             Ok(MutexGuard::new(self))
@@ -76,6 +111,16 @@ impl<T> Mutex<T> {
             Err(TryLockError::WouldBlock)
         }
     }
+
+    /// Unlocks the mutex regardless of who holds it.
+    ///
+    /// # Safety
+    ///
+    /// Only meant for the panic path, where the panicking core might already hold
+    /// the lock, any outstanding guard must never be used again.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.unlock();
+    }
 }
 
 
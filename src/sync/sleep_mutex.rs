@@ -0,0 +1,119 @@
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{TryLockResult, TryLockError, WaitQueue};
+
+/// A mutex that puts the waiting task to sleep instead of spinning.
+///
+/// Meant for locks that can be held for a long time, like the ones around disk
+/// I/O or the console. Must not be used from interrupt context, use
+/// [`Mutex`](super::mutex::Mutex) there instead.
+pub struct SleepMutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>
+}
+
+impl<T> SleepMutex<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data)
+        }
+    }
+
+    #[inline]
+    fn try_acquire(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    pub fn lock(&self) -> SleepMutexGuard<'_, T> {
+        while !self.try_acquire() {
+            self.waiters.wait_until(|| !self.locked.load(Ordering::Relaxed));
+        }
+
+        SleepMutexGuard::new(self)
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<SleepMutexGuard<'_, T>> {
+        if self.try_acquire() {
+            Ok(SleepMutexGuard::new(self))
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+unsafe impl<T: Send> Send for SleepMutex<T> {}
+unsafe impl<T: Send> Sync for SleepMutex<T> {}
+
+#[must_use = "if unused the SleepMutex will immediately unlock"]
+pub struct SleepMutexGuard<'a, T: 'a> {
+    lock: &'a SleepMutex<T>,
+    // PhantomData so Send doesn't get auto implemented
+    // TODO: replace when negative trait bounds are implemented #68318
+    _a: PhantomData<*const u8>
+}
+
+impl<'a, T> SleepMutexGuard<'a, T> {
+    const fn new(lock: &'a SleepMutex<T>) -> Self {
+        Self {
+            lock,
+            _a: PhantomData
+        }
+    }
+
+    pub(super) fn mutex(&self) -> &'a SleepMutex<T> {
+        self.lock
+    }
+}
+
+impl<T> Deref for SleepMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_unlock() {
+        let mutex = SleepMutex::new(1);
+        *mutex.lock() += 1;
+        assert_eq!(*mutex.lock(), 2);
+    }
+
+    #[test]
+    fn try_lock_while_locked() {
+        let mutex = SleepMutex::new(());
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_err());
+        drop(guard);
+        assert!(mutex.try_lock().is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::sched::{self, TaskId, MAX_TASKS};
+
+use super::mutex::Mutex;
+
+/// FIFO of task ids, every task can only wait on a single queue at a time
+/// so it never needs to hold more than `MAX_TASKS` entries.
+struct TaskQueue {
+    tasks: [TaskId; MAX_TASKS],
+    head: usize,
+    len: usize
+}
+
+impl TaskQueue {
+    const fn new() -> Self {
+        Self {
+            tasks: [TaskId::BOOT; MAX_TASKS],
+            head: 0,
+            len: 0
+        }
+    }
+
+    fn push(&mut self, task: TaskId) {
+        assert!(self.len < MAX_TASKS, "wait queue overflow");
+        self.tasks[(self.head + self.len) % MAX_TASKS] = task;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let task = self.tasks[self.head];
+        self.head = (self.head + 1) % MAX_TASKS;
+        self.len -= 1;
+        Some(task)
+    }
+}
+
+/// A queue of tasks that are blocked until some condition holds.
+///
+/// The queue itself is protected by a spin lock, so waking tasks is allowed from
+/// interrupt context. Waiting is not.
+pub struct WaitQueue {
+    queue: Mutex<TaskQueue>
+}
+
+impl WaitQueue {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            queue: Mutex::new(TaskQueue::new())
+        }
+    }
+
+    /// Blocks the current task until `condition` returns `true`.
+    ///
+    /// `condition` is checked with the queue locked, so a waker that changes the
+    /// state before calling [`WaitQueue::wake_one`] or [`WaitQueue::wake_all`] can't be missed.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) {
+        loop {
+            {
+                let mut queue = self.queue.lock();
+                if condition() {
+                    return;
+                }
+
+                queue.push(sched::current());
+                sched::block_current();
+            }
+
+            sched::schedule();
+        }
+    }
+
+    /// Blocks the current task until it's woken, `before_sleep` runs after the
+    /// task is queued but before it gives up the CPU.
+    pub(super) fn wait_with<F: FnOnce()>(&self, before_sleep: F) {
+        {
+            let mut queue = self.queue.lock();
+            queue.push(sched::current());
+            sched::block_current();
+        }
+
+        before_sleep();
+        sched::schedule();
+    }
+
+    /// Wakes the task that has been waiting the longest.
+    ///
+    /// Returns `true` if a task was woken.
+    pub fn wake_one(&self) -> bool {
+        let mut queue = self.queue.lock();
+        while let Some(task) = queue.pop() {
+            if sched::wake(task) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Wakes all waiting tasks and returns how many were woken.
+    pub fn wake_all(&self) -> usize {
+        let mut queue = self.queue.lock();
+        let mut woken = 0;
+        while let Some(task) = queue.pop() {
+            if sched::wake(task) {
+                woken += 1;
+            }
+        }
+
+        woken
+    }
+}
+
+impl Default for WaitQueue {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_queue_fifo() {
+        let mut queue = TaskQueue::new();
+        queue.push(TaskId::new(3));
+        queue.push(TaskId::new(1));
+        queue.push(TaskId::new(2));
+        assert_eq!(queue.pop(), Some(TaskId::new(3)));
+        assert_eq!(queue.pop(), Some(TaskId::new(1)));
+        assert_eq!(queue.pop(), Some(TaskId::new(2)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn task_queue_wraps() {
+        let mut queue = TaskQueue::new();
+        for i in 0..(MAX_TASKS * 3) {
+            queue.push(TaskId::new(i % MAX_TASKS));
+            assert_eq!(queue.pop(), Some(TaskId::new(i % MAX_TASKS)));
+        }
+
+        assert_eq!(queue.len, 0);
+    }
+
+    #[test]
+    fn wake_without_waiters() {
+        let queue = WaitQueue::new();
+        assert!(!queue.wake_one());
+        assert_eq!(queue.wake_all(), 0);
+    }
+
+    #[test]
+    fn wait_until_satisfied() {
+        let queue = WaitQueue::new();
+        queue.wait_until(|| true);
+    }
+}
@@ -0,0 +1,337 @@
+//! Line discipline between a keyboard and whatever reads from the console.
+//!
+//! Key presses are turned into the bytes a terminal would send, in canonical
+//! mode they're held back until a whole line is entered, in raw mode the
+//! reader gets them straight away and does its own editing, like the shell.
+
+use core::fmt::Write;
+
+use crate::collections::RingBuffer;
+use crate::drivers::input::keyboard::Key;
+use crate::drivers::input::KeyCode;
+
+/// Longest line that can be entered in canonical mode.
+pub const MAX_LINE: usize = 256;
+
+/// Size of the buffer of bytes waiting to be read
+const INPUT_SIZE: usize = 256;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1B;
+const DEL: u8 = 0x7F;
+
+/// Where in an escape sequence the last byte was, in canonical mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    /// Inside a control sequence, which ends with a byte from 0x40 to 0x7E
+    Csi
+}
+
+pub struct LineDiscipline {
+    /// Bytes ready to be read
+    input: RingBuffer<u8, INPUT_SIZE>,
+    /// Line being entered in canonical mode
+    line: [u8; MAX_LINE],
+    line_len: usize,
+    escape: Escape,
+    /// Bytes of a UTF-8 character echoed in raw mode, which is echoed once complete
+    raw_char: [u8; 4],
+    raw_char_len: usize,
+    canonical: bool,
+    echo: bool
+}
+
+impl LineDiscipline {
+    /// Starts in canonical mode with echo on.
+    pub const fn new() -> Self {
+        Self {
+            input: RingBuffer::new(),
+            line: [0; MAX_LINE],
+            line_len: 0,
+            escape: Escape::None,
+            raw_char: [0; 4],
+            raw_char_len: 0,
+            canonical: true,
+            echo: true
+        }
+    }
+
+    pub fn set_canonical(&mut self, canonical: bool) {
+        if !canonical {
+            // Whatever was typed so far becomes readable
+            for i in 0..self.line_len {
+                let _ = self.input.push(self.line[i]);
+            }
+
+            self.line_len = 0;
+        }
+
+        self.escape = Escape::None;
+        self.canonical = canonical;
+    }
+
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Handles a key press, echoing to `echo` if enabled.
+    pub fn receive_key(&mut self, key: &Key, echo: &mut dyn Write) {
+        let mut buf = [0; 4];
+        for &byte in encode_key(key, &mut buf) {
+            self.receive(byte, echo);
+        }
+    }
+
+    /// Handles a byte coming from the terminal, echoing to `echo` if enabled.
+    pub fn receive(&mut self, byte: u8, echo: &mut dyn Write) {
+        if !self.canonical {
+            if self.input.push(byte).is_ok() && self.echo {
+                self.echo_raw(byte, echo);
+            }
+
+            return;
+        }
+
+        match self.escape {
+            Escape::None => (),
+            Escape::Esc => {
+                self.escape = if byte == b'[' { Escape::Csi } else { Escape::None };
+                return;
+            },
+            Escape::Csi => {
+                if (0x40..=0x7E).contains(&byte) {
+                    self.escape = Escape::None;
+                }
+
+                return;
+            }
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if self.input.capacity() - self.input.len() <= self.line_len {
+                    // No room for the whole line, wait until the reader catches up
+                    return;
+                }
+
+                for i in 0..self.line_len {
+                    let _ = self.input.push(self.line[i]);
+                }
+
+                let _ = self.input.push(b'\n');
+                self.line_len = 0;
+                self.echo_str("\n", echo);
+            },
+            BACKSPACE | DEL => {
+                if self.line_len == 0 {
+                    return;
+                }
+
+                // Remove a whole UTF-8 sequence
+                self.line_len -= 1;
+                while self.line_len > 0 && self.line[self.line_len] & 0xC0 == 0x80 {
+                    self.line_len -= 1;
+                }
+
+                self.echo_str("\x08 \x08", echo);
+            },
+            CTRL_U => {
+                while self.line_len > 0 {
+                    self.receive(DEL, echo);
+                }
+            },
+            CTRL_C => {
+                self.line_len = 0;
+                self.echo_str("^C\n", echo);
+            },
+            // Control characters and escape sequences mean nothing to a line
+            ESC => self.escape = Escape::Esc,
+            0x00..=0x1F => (),
+            _ => {
+                if self.line_len == MAX_LINE {
+                    return;
+                }
+
+                self.line[self.line_len] = byte;
+                self.line_len += 1;
+                if self.echo {
+                    self.echo_last_char(echo);
+                }
+            }
+        }
+    }
+
+    /// Returns the next byte ready to be read.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.input.pop()
+    }
+
+    fn echo_str(&self, s: &str, echo: &mut dyn Write) {
+        if self.echo {
+            let _ = echo.write_str(s);
+        }
+    }
+
+    /// Echoes `byte` as it's read in raw mode, multibyte characters once all their bytes are there.
+    fn echo_raw(&mut self, byte: u8, echo: &mut dyn Write) {
+        if byte & 0xC0 != 0x80 {
+            self.raw_char_len = 0;
+        }
+
+        if self.raw_char_len == self.raw_char.len() {
+            return;
+        }
+
+        self.raw_char[self.raw_char_len] = byte;
+        self.raw_char_len += 1;
+        if let Ok(s) = core::str::from_utf8(&self.raw_char[..self.raw_char_len]) {
+            let _ = echo.write_str(s);
+            self.raw_char_len = 0;
+        }
+    }
+
+    /// Echoes the character at the end of the line, multibyte ones once all their bytes are there.
+    fn echo_last_char(&self, echo: &mut dyn Write) {
+        let start = (0..self.line_len).rev().find(|&i| self.line[i] & 0xC0 != 0x80).unwrap_or(0);
+        if let Ok(s) = core::str::from_utf8(&self.line[start..self.line_len]) {
+            let _ = echo.write_str(s);
+        }
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the bytes a terminal sends for `key`.
+pub fn encode_key<'a>(key: &Key, buf: &'a mut [u8; 4]) -> &'a [u8] {
+    let sequence: &[u8] = match key.code {
+        KeyCode::Enter | KeyCode::KpEnter => b"\r",
+        KeyCode::Backspace => &[DEL],
+        KeyCode::Tab => b"\t",
+        KeyCode::Escape => &[ESC],
+        KeyCode::Up => b"\x1b[A",
+        KeyCode::Down => b"\x1b[B",
+        KeyCode::Right => b"\x1b[C",
+        KeyCode::Left => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::Insert => b"\x1b[2~",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        _ => match key.ch {
+            // Ctrl turns @, letters and [\]^_ into control characters
+            Some(c @ ('@'..='_' | 'a'..='z')) if key.modifiers.ctrl() => {
+                buf[0] = c.to_ascii_uppercase() as u8 & 0x1F;
+                return &buf[..1];
+            },
+            Some(c) => return c.encode_utf8(buf).as_bytes(),
+            None => b""
+        }
+    };
+
+    buf[..sequence.len()].copy_from_slice(sequence);
+    &buf[..sequence.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::drivers::input::Modifiers;
+
+    fn read_all(tty: &mut LineDiscipline) -> Vec<u8> {
+        core::iter::from_fn(|| tty.read_byte()).collect()
+    }
+
+    fn key(code: KeyCode, ch: Option<char>, ctrl: bool) -> Key {
+        Key { code, modifiers: Modifiers { left_ctrl: ctrl, ..Default::default() }, ch }
+    }
+
+    #[test]
+    fn canonical() {
+        let mut tty = LineDiscipline::new();
+        let mut echo = String::new();
+        for &b in b"lsx\x7f -l" {
+            tty.receive(b, &mut echo);
+        }
+
+        assert_eq!(tty.read_byte(), None);
+        tty.receive(b'\r', &mut echo);
+        assert_eq!(read_all(&mut tty), b"ls -l\n");
+        assert_eq!(echo, "lsx\x08 \x08 -l\n");
+
+        for &b in b"abc\x15d\x03" {
+            tty.receive(b, &mut echo);
+        }
+
+        assert_eq!(read_all(&mut tty), b"");
+    }
+
+    #[test]
+    fn utf8() {
+        let mut tty = LineDiscipline::new();
+        let mut echo = String::new();
+        for &b in "é€".as_bytes() {
+            tty.receive(b, &mut echo);
+        }
+
+        assert_eq!(echo, "é€");
+        tty.receive(DEL, &mut echo);
+        tty.receive(b'\n', &mut echo);
+        assert_eq!(read_all(&mut tty), "é\n".as_bytes());
+    }
+
+    #[test]
+    fn raw() {
+        let mut tty = LineDiscipline::new();
+        let mut echo = String::new();
+        tty.receive(b'a', &mut echo);
+        tty.set_canonical(false);
+        tty.set_echo(false);
+        tty.receive_key(&key(KeyCode::Up, None, false), &mut echo);
+        tty.receive_key(&key(KeyCode::C, Some('c'), true), &mut echo);
+        assert_eq!(read_all(&mut tty), b"a\x1b[A\x03");
+        assert_eq!(echo, "a");
+
+        tty.set_echo(true);
+        for &b in "é€".as_bytes() {
+            tty.receive(b, &mut echo);
+        }
+
+        assert_eq!(echo, "aé€");
+    }
+
+    #[test]
+    fn canonical_escapes() {
+        let mut tty = LineDiscipline::new();
+        let mut echo = String::new();
+        for &b in b"\x1b[A\x1b[3~\x1bO" {
+            tty.receive(b, &mut echo);
+        }
+
+        tty.receive_key(&key(KeyCode::PageUp, None, false), &mut echo);
+        tty.receive(b'\n', &mut echo);
+        assert_eq!(read_all(&mut tty), b"\n");
+        assert_eq!(echo, "\n");
+    }
+
+    #[test]
+    fn encode() {
+        let mut buf = [0; 4];
+        assert_eq!(encode_key(&key(KeyCode::Key2, Some('é'), false), &mut buf), "é".as_bytes());
+        assert_eq!(encode_key(&key(KeyCode::LeftBracket, Some('['), true), &mut buf), [ESC]);
+        assert_eq!(encode_key(&key(KeyCode::F1, None, false), &mut buf), b"");
+    }
+}